@@ -0,0 +1,77 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use relay_core::audit::{self, NewAuditEntry};
+use relay_core::RelayContext;
+use tracing;
+use crate::auth::AuthenticatedUser;
+use crate::error::ErrorCode;
+use crate::rate_limit::client_ip;
+
+/// Write an audit record; failing to is logged rather than failing the request it describes
+pub async fn record(ctx: &RelayContext, entry: NewAuditEntry) {
+    let written = match ctx.db_pool.get().await {
+        Ok(mut conn) => audit::record(&mut conn, &entry).await,
+        Err(e) => Err(anyhow::anyhow!("Failed to get DB connection: {}", e)),
+    };
+    if let Err(e) = written {
+        tracing::error!("Failed to write audit record {:?}: {}", entry, e);
+    }
+}
+
+/// `success`, or `failure` with the `ApiError` code (or the status when there is none)
+fn outcome(action: String, response: &Response) -> NewAuditEntry {
+    match response.extensions().get::<ErrorCode>() {
+        Some(ErrorCode(code)) => NewAuditEntry::failure(action, *code),
+        None if response.status().is_success() => NewAuditEntry::success(action),
+        None => NewAuditEntry::failure(action, response.status().as_str()),
+    }
+}
+
+/// Axum middleware for admin routes that records every change (any method but GET), including
+/// attempts `require_admin` refused; runs outside it so those reach the log
+pub async fn audit_admin(req: Request, next: Next) -> Response {
+    if req.method() == Method::GET || req.method() == Method::HEAD {
+        return next.run(req).await;
+    }
+
+    let ctx = req.extensions().get::<RelayContext>().cloned();
+    let actor = req.extensions().get::<AuthenticatedUser>().map(|u| u.user_address.clone());
+    let route = req.extensions().get::<MatchedPath>().map(|p| p.as_str().to_string());
+    let action = format!("admin:{} {}", req.method(), route.as_deref().unwrap_or(req.uri().path()));
+    let target = req.uri().path().to_string();
+    let ip = client_ip(&req);
+
+    let response = next.run(req).await;
+
+    let mut entry = outcome(action, &response).target(target).ip(ip);
+    entry.actor = actor;
+    match ctx {
+        Some(ctx) => record(&ctx, entry).await,
+        None => tracing::error!("No RelayContext to write audit record {:?}", entry),
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, response::IntoResponse};
+    use crate::error::ApiError;
+
+    #[test]
+    fn test_outcome_uses_error_code() {
+        let denied = ApiError::forbidden("admin_required", "Admin role required").into_response();
+        let entry = outcome("admin:PUT /x".to_string(), &denied);
+        assert_eq!((entry.result, entry.reason.as_deref()), (audit::FAILURE, Some("admin_required")));
+
+        let entry = outcome("admin:PUT /x".to_string(), &StatusCode::UNPROCESSABLE_ENTITY.into_response());
+        assert_eq!((entry.result, entry.reason.as_deref()), (audit::FAILURE, Some("422")));
+
+        let entry = outcome("admin:PUT /x".to_string(), &StatusCode::OK.into_response());
+        assert_eq!((entry.result, entry.reason), (audit::SUCCESS, None));
+    }
+}
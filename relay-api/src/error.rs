@@ -0,0 +1,92 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use std::fmt::Display;
+use tracing;
+
+/// API error returned to clients as `{"error": code, "message": message}`
+/// `code` is a stable machine-readable identifier; `message` is for humans and may change
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    pub details: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    pub fn unauthorized(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, code, message)
+    }
+
+    pub fn forbidden(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, code, message)
+    }
+
+    pub fn not_found(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, code, message)
+    }
+
+    pub fn internal(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, code, message)
+    }
+
+    /// Attach extra structured context (e.g. `retry_after`) to the response body
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    /// Failed to check out a connection from the database pool
+    pub fn database_unavailable(err: impl Display) -> Self {
+        tracing::error!("Failed to get DB connection: {}", err);
+        Self::internal("database_unavailable", "Database is unavailable")
+    }
+
+    /// A database query failed; the underlying error is logged, not returned
+    pub fn database(err: impl Display) -> Self {
+        tracing::error!("Database error: {}", err);
+        Self::internal("database_error", "Database error")
+    }
+
+    /// Failed to get a Redis connection
+    pub fn cache_unavailable(err: impl Display) -> Self {
+        tracing::error!("Failed to get Redis connection: {}", err);
+        Self::internal("cache_unavailable", "Cache is unavailable")
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut body = serde_json::json!({
+            "error": self.code,
+            "message": self.message,
+        });
+
+        if let Some(details) = self.details {
+            body["details"] = details;
+        }
+
+        let mut response = (self.status, Json(body)).into_response();
+        response.extensions_mut().insert(ErrorCode(self.code));
+        response
+    }
+}
+
+/// The `code` of an `ApiError` response, kept in its extensions for middleware such as the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCode(pub &'static str);
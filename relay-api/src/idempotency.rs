@@ -0,0 +1,130 @@
+use axum::http::HeaderMap;
+use relay_core::{RelayContext, redis::get_connection};
+use crate::error::ApiError;
+
+pub const IDEMPOTENCY_HEADER: &str = "idempotency-key";
+
+/// How long a completed request's result is remembered
+const IDEMPOTENCY_TTL_SECONDS: u64 = 24 * 60 * 60;
+
+/// Placeholder stored while the first request with a key is still being processed
+const PENDING: &str = "pending";
+
+const MAX_KEY_LENGTH: usize = 255;
+
+/// State of an idempotency key after trying to claim it
+#[derive(Debug)]
+pub enum Reservation {
+    /// First time this key was seen; the caller should process the request
+    New,
+    /// Another request with this key is still being processed
+    InProgress,
+    /// The key was already used; replay this stored response
+    Completed(serde_json::Value),
+}
+
+/// Read and validate the `Idempotency-Key` header, if present
+pub fn key_from_headers(headers: &HeaderMap) -> Result<Option<String>, ApiError> {
+    let value = match headers.get(IDEMPOTENCY_HEADER) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    let key = value
+        .to_str()
+        .map(|s| s.trim())
+        .map_err(|_| ApiError::bad_request("invalid_idempotency_key", "Idempotency-Key must be ASCII"))?;
+
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        return Err(ApiError::bad_request(
+            "invalid_idempotency_key",
+            format!("Idempotency-Key must be 1-{} characters", MAX_KEY_LENGTH),
+        ));
+    }
+
+    Ok(Some(key.to_string()))
+}
+
+/// Claim `key` for `user_address`, or report the state left by an earlier request
+pub async fn reserve(ctx: &RelayContext, user_address: &str, key: &str) -> anyhow::Result<Reservation> {
+    let mut conn = get_connection(&ctx.redis_pool).await?;
+    let redis_key = ctx.config.redis.keys().idempotency(user_address, key);
+
+    // Retry once if the key expires between the SET NX and the GET
+    for _ in 0..2 {
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(&redis_key)
+            .arg(PENDING)
+            .arg("NX")
+            .arg("EX")
+            .arg(IDEMPOTENCY_TTL_SECONDS)
+            .query_async(&mut conn)
+            .await?;
+
+        if claimed.is_some() {
+            return Ok(Reservation::New);
+        }
+
+        let stored: Option<String> = redis::cmd("GET")
+            .arg(&redis_key)
+            .query_async(&mut conn)
+            .await?;
+
+        match stored.as_deref() {
+            None => continue,
+            Some(PENDING) => return Ok(Reservation::InProgress),
+            Some(json) => return Ok(Reservation::Completed(serde_json::from_str(json)?)),
+        }
+    }
+
+    Ok(Reservation::InProgress)
+}
+
+/// Store the response for `key` so retries replay it
+pub async fn complete(
+    ctx: &RelayContext,
+    user_address: &str,
+    key: &str,
+    response: &serde_json::Value,
+) -> anyhow::Result<()> {
+    let mut conn = get_connection(&ctx.redis_pool).await?;
+
+    redis::cmd("SET")
+        .arg(ctx.config.redis.keys().idempotency(user_address, key))
+        .arg(serde_json::to_string(response)?)
+        .arg("EX")
+        .arg(IDEMPOTENCY_TTL_SECONDS)
+        .query_async::<()>(&mut conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Release a claimed key after a failed request so the client can retry with it
+pub async fn release(ctx: &RelayContext, user_address: &str, key: &str) -> anyhow::Result<()> {
+    let mut conn = get_connection(&ctx.redis_pool).await?;
+
+    redis::cmd("DEL")
+        .arg(ctx.config.redis.keys().idempotency(user_address, key))
+        .query_async::<()>(&mut conn)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert!(key_from_headers(&headers).unwrap().is_none());
+
+        headers.insert(IDEMPOTENCY_HEADER, " retry-123 ".parse().unwrap());
+        assert_eq!(key_from_headers(&headers).unwrap().as_deref(), Some("retry-123"));
+
+        headers.insert(IDEMPOTENCY_HEADER, "x".repeat(MAX_KEY_LENGTH + 1).parse().unwrap());
+        assert!(key_from_headers(&headers).is_err());
+    }
+}
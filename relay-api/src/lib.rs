@@ -1,7 +1,18 @@
+pub mod audit;
 pub mod auth;
+pub mod error;
 pub mod server;
 pub mod handlers;
+pub mod idempotency;
+pub mod media;
+pub mod pagination;
+pub mod presence;
+pub mod rate_limit;
+pub mod tls;
 pub mod websocket;
+pub mod ws_cursor;
+pub mod ws_deflate;
+pub mod ws_outbox;
 
 pub use server::run;
 
@@ -0,0 +1,130 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 100;
+
+/// `limit`/`offset` of a list request, with the default page size and cap applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl Page {
+    pub fn new(limit: Option<i64>, offset: Option<i64>) -> Self {
+        Self {
+            limit: limit.unwrap_or(DEFAULT_LIMIT).clamp(0, MAX_LIMIT),
+            offset: offset.unwrap_or(0).max(0),
+        }
+    }
+
+    /// List response body; `total` counts every match across all pages, not just `items`
+    pub fn envelope<T: Serialize>(&self, items: Vec<T>, total: i64) -> serde_json::Value {
+        serde_json::json!({
+            "items": items,
+            "total": total,
+            "limit": self.limit,
+            "offset": self.offset,
+            "has_more": self.offset + self.limit < total,
+        })
+    }
+}
+
+/// The blocks a conversation listing is made of, in listing order; each is sorted most recent first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConversationGroup {
+    /// Conversations the caller pinned
+    Pinned,
+    /// With `sort=unread`, the unpinned ones with unread messages
+    Unread,
+    Rest,
+}
+
+impl ConversationGroup {
+    fn code(self) -> &'static str {
+        match self {
+            Self::Pinned => "p",
+            Self::Unread => "u",
+            Self::Rest => "a",
+        }
+    }
+}
+
+/// Position after the last conversation of a page, passed back as `GET /api/v1/conversations?cursor=`
+/// Unlike an offset it doesn't shift when conversations get new messages between requests
+/// Encoded as `{group}.{last_message_at in micros, or -}.{id}`, where `group` is `p`, `u` or `a` for the
+/// [`ConversationGroup`] it points into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConversationCursor {
+    pub group: ConversationGroup,
+    pub last_message_at: Option<DateTime<Utc>>,
+    pub id: i64,
+}
+
+impl ConversationCursor {
+    pub fn encode(&self) -> String {
+        let last_message_at = self
+            .last_message_at
+            .map(|t| t.timestamp_micros().to_string())
+            .unwrap_or_else(|| "-".to_string());
+        format!("{}.{}.{}", self.group.code(), last_message_at, self.id)
+    }
+
+    pub fn parse(cursor: &str) -> Option<Self> {
+        let mut parts = cursor.trim().splitn(3, '.');
+        let group = match parts.next()? {
+            "p" => ConversationGroup::Pinned,
+            "u" => ConversationGroup::Unread,
+            "a" => ConversationGroup::Rest,
+            _ => return None,
+        };
+        let last_message_at = match parts.next()? {
+            "-" => None,
+            micros => Some(DateTime::from_timestamp_micros(micros.parse().ok()?)?),
+        };
+        let id = parts.next()?.parse().ok()?;
+
+        Some(Self { group, last_message_at, id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_defaults_and_bounds() {
+        assert_eq!(Page::new(None, None), Page { limit: 50, offset: 0 });
+        assert_eq!(Page::new(Some(500), Some(20)), Page { limit: 100, offset: 20 });
+        assert_eq!(Page::new(Some(-1), Some(-5)), Page { limit: 0, offset: 0 });
+    }
+
+    #[test]
+    fn test_envelope_has_more() {
+        let page = Page::new(Some(2), Some(2));
+
+        let middle = page.envelope(vec![3, 4], 5);
+        assert_eq!(middle, serde_json::json!({"items": [3, 4], "total": 5, "limit": 2, "offset": 2, "has_more": true}));
+
+        assert_eq!(page.envelope(vec![3, 4], 4)["has_more"], false);
+        assert_eq!(page.envelope(Vec::<i32>::new(), 0)["has_more"], false);
+    }
+
+    #[test]
+    fn test_conversation_cursor_round_trip() {
+        let at = DateTime::from_timestamp_micros(1_760_000_000_123_456).unwrap();
+        for cursor in [
+            ConversationCursor { group: ConversationGroup::Unread, last_message_at: Some(at), id: 42 },
+            ConversationCursor { group: ConversationGroup::Rest, last_message_at: None, id: 7 },
+            ConversationCursor { group: ConversationGroup::Pinned, last_message_at: Some(at), id: 3 },
+        ] {
+            assert_eq!(ConversationCursor::parse(&cursor.encode()), Some(cursor));
+        }
+
+        assert_eq!(ConversationCursor::parse("u.1760000000123456.42").unwrap().last_message_at, Some(at));
+        for invalid in ["", "x.-.1", "a.-", "a.soon.1", "a.-.one"] {
+            assert_eq!(ConversationCursor::parse(invalid), None);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use chrono::{DateTime, TimeZone, Utc};
+use relay_core::{RelayContext, redis::{get_connection, RedisConnection, RedisKeys}};
+
+/// A user is online if any of their connections has heartbeated within `timeout_seconds`
+fn is_online(latest_heartbeat: Option<i64>, now: i64, timeout_seconds: i64) -> bool {
+    latest_heartbeat.is_some_and(|ts| now - ts < timeout_seconds)
+}
+
+fn timestamp(seconds: i64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(seconds, 0).single()
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Presence {
+    pub address: String,
+    pub online: bool,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// Record a heartbeat for `connection_id`; also used when the connection is first opened
+pub async fn heartbeat(ctx: &RelayContext, user_address: &str, connection_id: &str) -> anyhow::Result<()> {
+    let mut conn = get_connection(&ctx.redis_pool).await?;
+    record_heartbeat(&mut conn, ctx.config.redis.keys(), user_address, connection_id, ctx.config.server.ws_pong_timeout_seconds as i64).await
+}
+
+pub async fn disconnect(ctx: &RelayContext, user_address: &str, connection_id: &str) -> anyhow::Result<()> {
+    let mut conn = get_connection(&ctx.redis_pool).await?;
+    remove_connection(&mut conn, ctx.config.redis.keys(), user_address, connection_id).await
+}
+
+pub async fn lookup(ctx: &RelayContext, addresses: &[String]) -> anyhow::Result<Vec<Presence>> {
+    let mut conn = get_connection(&ctx.redis_pool).await?;
+    lookup_with(&mut conn, ctx.config.redis.keys(), addresses, ctx.config.server.ws_pong_timeout_seconds as i64).await
+}
+
+/// Each user's live connections are kept in `PRESENCE:{user}`, a sorted set of connection ids
+/// scored by their last heartbeat, so the user stays online until the last connection drops
+async fn record_heartbeat(
+    conn: &mut RedisConnection,
+    keys: RedisKeys<'_>,
+    user_address: &str,
+    connection_id: &str,
+    timeout: i64,
+) -> anyhow::Result<()> {
+    let now = Utc::now().timestamp();
+    let key = keys.presence(user_address);
+
+    redis::pipe()
+        .atomic()
+        .cmd("ZADD").arg(&key).arg(now).arg(connection_id).ignore()
+        // Drop connections that died without a disconnect (e.g. the API instance crashed)
+        .cmd("ZREMRANGEBYSCORE").arg(&key).arg("-inf").arg(now - timeout).ignore()
+        .cmd("EXPIRE").arg(&key).arg(timeout).ignore()
+        .cmd("SET").arg(keys.last_seen(user_address)).arg(now).ignore()
+        .query_async::<()>(conn)
+        .await?;
+
+    Ok(())
+}
+
+async fn remove_connection(
+    conn: &mut RedisConnection,
+    keys: RedisKeys<'_>,
+    user_address: &str,
+    connection_id: &str,
+) -> anyhow::Result<()> {
+    redis::pipe()
+        .atomic()
+        .cmd("ZREM").arg(keys.presence(user_address)).arg(connection_id).ignore()
+        .cmd("SET").arg(keys.last_seen(user_address)).arg(Utc::now().timestamp()).ignore()
+        .query_async::<()>(conn)
+        .await?;
+
+    Ok(())
+}
+
+async fn lookup_with(
+    conn: &mut RedisConnection,
+    keys: RedisKeys<'_>,
+    addresses: &[String],
+    timeout: i64,
+) -> anyhow::Result<Vec<Presence>> {
+    let now = Utc::now().timestamp();
+
+    let mut pipe = redis::pipe();
+    for address in addresses {
+        pipe.cmd("ZRANGE").arg(keys.presence(address)).arg(-1).arg(-1).arg("WITHSCORES");
+        pipe.cmd("GET").arg(keys.last_seen(address));
+    }
+
+    let replies: Vec<redis::Value> = pipe.query_async(conn).await?;
+
+    let mut presence = Vec::with_capacity(addresses.len());
+    for (address, pair) in addresses.iter().zip(replies.chunks(2)) {
+        let latest: Vec<(String, f64)> = redis::from_redis_value(&pair[0])?;
+        let latest_heartbeat = latest.first().map(|(_, score)| *score as i64);
+        let last_seen: Option<i64> = redis::from_redis_value(&pair[1])?;
+
+        let online = is_online(latest_heartbeat, now, timeout);
+        let last_seen = latest_heartbeat.max(last_seen).and_then(timestamp);
+
+        presence.push(Presence {
+            address: address.clone(),
+            online,
+            last_seen,
+        });
+    }
+
+    Ok(presence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_online_until_last_connection_drops() {
+        let now = 1_000;
+        let timeout = 90;
+
+        // Two tabs open for the same user
+        let mut connections: HashMap<&str, i64> = HashMap::new();
+        connections.insert("conn-a", now - 10);
+        connections.insert("conn-b", now - 5);
+        assert!(is_online(connections.values().copied().max(), now, timeout));
+
+        connections.remove("conn-a");
+        assert!(is_online(connections.values().copied().max(), now, timeout));
+
+        connections.remove("conn-b");
+        assert!(!is_online(connections.values().copied().max(), now, timeout));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis at REDIS_URL"]
+    async fn test_second_connection_keeps_user_online() {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_string());
+        let mut conn = redis::Client::open(url).unwrap().get_multiplexed_async_connection().await.unwrap();
+        let user = format!("0xpresence-{}", uuid::Uuid::new_v4());
+        let users = vec![user.clone()];
+        let config = relay_core::config::RedisConfig {
+            url: String::new(),
+            max_connections: 1,
+            stream_max_len: 1000,
+            stream_ttl_seconds: 60,
+            key_prefix: String::new(),
+            platform_namespaces: false,
+        };
+        let keys = config.keys();
+
+        record_heartbeat(&mut conn, keys, &user, "conn-a", 90).await.unwrap();
+        record_heartbeat(&mut conn, keys, &user, "conn-b", 90).await.unwrap();
+
+        remove_connection(&mut conn, keys, &user, "conn-a").await.unwrap();
+        let presence = lookup_with(&mut conn, keys, &users, 90).await.unwrap();
+        assert!(presence[0].online);
+
+        remove_connection(&mut conn, keys, &user, "conn-b").await.unwrap();
+        let presence = lookup_with(&mut conn, keys, &users, 90).await.unwrap();
+        assert!(!presence[0].online);
+        assert!(presence[0].last_seen.is_some());
+
+        redis::cmd("DEL").arg(keys.presence(&user)).arg(keys.last_seen(&user)).query_async::<()>(&mut conn).await.unwrap();
+    }
+
+    #[test]
+    fn test_stale_heartbeat_is_offline() {
+        assert!(!is_online(Some(1_000 - 90), 1_000, 90));
+        assert!(is_online(Some(1_000 - 89), 1_000, 90));
+    }
+}
@@ -0,0 +1,582 @@
+use axum::{
+    body::{to_bytes, Body},
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Request},
+    http::{header::RETRY_AFTER, request::Parts, Extensions, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::future::BoxFuture;
+use relay_core::{RelayContext, config::{RateLimitConfig, RouteRateLimit}, redis::get_connection};
+use std::net::{IpAddr, SocketAddr};
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tower::{Layer, Service};
+use tracing;
+use crate::auth::AuthenticatedUser;
+use crate::error::ApiError;
+
+/// Maximum body size buffered when a rate limit is keyed on a JSON body field
+const MAX_BUFFERED_BODY_BYTES: usize = 64 * 1024;
+
+/// Result of checking a request against a fixed-window rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitDecision {
+    Allowed,
+    Limited { retry_after: u64 },
+}
+
+/// Decide whether a request is allowed given the number of requests seen in the
+/// current window and the seconds remaining before the window resets
+pub fn evaluate(count: u64, max_requests: u32, ttl_seconds: i64, window_seconds: u64) -> RateLimitDecision {
+    if count <= max_requests as u64 {
+        return RateLimitDecision::Allowed;
+    }
+
+    // A missing or expired TTL means the window is about to reset, so ask the client to
+    // wait a full window rather than retrying immediately
+    let retry_after = if ttl_seconds > 0 {
+        ttl_seconds as u64
+    } else {
+        window_seconds
+    };
+
+    RateLimitDecision::Limited { retry_after }
+}
+
+/// Count a request against `key` using Redis INCR + EXPIRE
+/// The window starts on the first request and the counter expires after `window_seconds`
+pub async fn check_rate_limit(
+    ctx: &RelayContext,
+    key: &str,
+    max_requests: u32,
+    window_seconds: u64,
+) -> anyhow::Result<RateLimitDecision> {
+    let mut conn = get_connection(&ctx.redis_pool).await?;
+
+    let count: u64 = redis::cmd("INCR")
+        .arg(key)
+        .query_async(&mut conn)
+        .await?;
+
+    let mut ttl: i64 = redis::cmd("TTL")
+        .arg(key)
+        .query_async(&mut conn)
+        .await?;
+
+    // Set the expiry on the first hit, or repair a counter that lost its expiry
+    if count == 1 || ttl < 0 {
+        redis::cmd("EXPIRE")
+            .arg(key)
+            .arg(window_seconds)
+            .query_async::<()>(&mut conn)
+            .await?;
+        ttl = window_seconds as i64;
+    }
+
+    Ok(evaluate(count, max_requests, ttl, window_seconds))
+}
+
+/// Resolve the client IP. Behind `TRUSTED_PROXY_COUNT` proxies it's the `X-Forwarded-For` entry the outermost
+/// one appended, counted from the right since anything left of it came from the client; otherwise the socket address
+pub fn client_ip(req: &Request) -> String {
+    ip_from_parts(req.headers(), req.extensions())
+}
+
+fn ip_from_parts(headers: &HeaderMap, extensions: &Extensions) -> String {
+    let trusted_proxies = extensions
+        .get::<RelayContext>()
+        .map_or(0, |ctx| ctx.config.server.trusted_proxy_count);
+    resolve_ip(headers, extensions, trusted_proxies)
+}
+
+fn resolve_ip(headers: &HeaderMap, extensions: &Extensions, trusted_proxies: usize) -> String {
+    if let Some(ip) = forwarded_ip(headers, trusted_proxies) {
+        return ip;
+    }
+
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Each trusted proxy appends the address it was connected from, so the client is `trusted_proxies` entries
+/// from the right. None with no trusted proxies, or when the chain is shorter than that or the entry isn't an IP
+fn forwarded_ip(headers: &HeaderMap, trusted_proxies: usize) -> Option<String> {
+    if trusted_proxies == 0 {
+        return None;
+    }
+
+    // Repeated headers are one list in order; empty entries still count as hops so the client can't shift them
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(str::trim)
+        .collect();
+
+    let hop = hops.get(hops.len().checked_sub(trusted_proxies)?)?;
+    hop.parse::<IpAddr>().ok().map(|ip| ip.to_string())
+}
+
+/// Extractor for the caller's IP as resolved by `client_ip`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIp(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientIp(ip_from_parts(&parts.headers, &parts.extensions)))
+    }
+}
+
+/// Longest `User-Agent` / `X-App-Version` kept, in characters
+const MAX_CLIENT_HEADER_CHARS: usize = 256;
+
+/// Extractor for what the caller says about itself: IP as resolved by `client_ip`, `User-Agent` and
+/// `X-App-Version`, the latter two trimmed and capped; missing or empty headers are None
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub ip: String,
+    pub user_agent: Option<String>,
+    pub app_version: Option<String>,
+}
+
+fn header_text(headers: &HeaderMap, name: &str) -> Option<String> {
+    let value = headers.get(name)?.to_str().ok()?.trim();
+    (!value.is_empty()).then(|| value.chars().take(MAX_CLIENT_HEADER_CHARS).collect())
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientInfo {
+            ip: ip_from_parts(&parts.headers, &parts.extensions),
+            user_agent: header_text(&parts.headers, "user-agent"),
+            app_version: header_text(&parts.headers, "x-app-version"),
+        })
+    }
+}
+
+/// Build a `429 Too Many Requests` response with a `Retry-After` header and JSON body
+pub fn too_many_requests(retry_after: u64) -> Response {
+    let error = ApiError::new(
+        StatusCode::TOO_MANY_REQUESTS,
+        "rate_limited",
+        "Too many requests, please retry later",
+    )
+    .with_details(serde_json::json!({ "retry_after": retry_after }));
+
+    ([(RETRY_AFTER, retry_after.to_string())], error).into_response()
+}
+
+/// Atomically refill and take `cost` tokens from a bucket stored as a Redis hash; none are taken if fewer are left
+/// KEYS[1] = bucket key, ARGV = capacity, refill per second, now (ms), cost
+/// Returns {allowed (0/1), retry_after_seconds}
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local refill_per_sec = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local cost = tonumber(ARGV[4])
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(bucket[1]) or capacity
+local ts = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) / 1000 * refill_per_sec)
+local allowed = 0
+local retry_after = 0
+if tokens >= cost then
+    tokens = tokens - cost
+    allowed = 1
+else
+    retry_after = math.ceil((cost - tokens) / refill_per_sec)
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', tostring(now))
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity / refill_per_sec * 1000))
+return {allowed, retry_after}
+"#;
+
+/// Take a token from the bucket at `key`, creating it full if it doesn't exist
+pub async fn take_token(
+    ctx: &RelayContext,
+    key: &str,
+    limit: RouteRateLimit,
+) -> anyhow::Result<RateLimitDecision> {
+    take_tokens(ctx, key, limit, 1).await
+}
+
+/// Take `cost` tokens from the bucket at `key` at once, or none if it holds fewer
+pub async fn take_tokens(
+    ctx: &RelayContext,
+    key: &str,
+    limit: RouteRateLimit,
+    cost: u32,
+) -> anyhow::Result<RateLimitDecision> {
+    let mut conn = get_connection(&ctx.redis_pool).await?;
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)?
+        .as_millis() as u64;
+    let refill_per_sec = limit.capacity as f64 / limit.period_seconds as f64;
+
+    let (allowed, retry_after): (i64, u64) = redis::Script::new(TOKEN_BUCKET_SCRIPT)
+        .key(key)
+        .arg(limit.capacity)
+        .arg(refill_per_sec)
+        .arg(now_ms)
+        .arg(cost)
+        .invoke_async(&mut conn)
+        .await?;
+
+    if allowed == 1 {
+        Ok(RateLimitDecision::Allowed)
+    } else {
+        Ok(RateLimitDecision::Limited { retry_after: retry_after.max(1) })
+    }
+}
+
+/// Take one token per message from `user_address`'s `send_message` bucket, the one `POST /api/v1/messages` draws
+/// from, for sends the middleware doesn't charge: those over the WebSocket, and batches. Fails open like the middleware
+pub async fn take_send_message_tokens(ctx: &RelayContext, user_address: &str, messages: u32) -> RateLimitDecision {
+    let limit = ctx.config.rate_limit.send_message;
+    if limit.capacity == 0 {
+        return RateLimitDecision::Allowed;
+    }
+
+    let key = ctx.config.redis.keys().rate_limit("send_message", &format!("user:{}", user_address));
+    match take_tokens(ctx, &key, limit, messages).await {
+        Ok(RateLimitDecision::Limited { retry_after }) => {
+            tracing::warn!("Rate limit exceeded for {}", key);
+            RateLimitDecision::Limited { retry_after }
+        }
+        Ok(RateLimitDecision::Allowed) => RateLimitDecision::Allowed,
+        Err(e) => {
+            tracing::warn!("Rate limit check failed for {}: {}", key, e);
+            RateLimitDecision::Allowed
+        }
+    }
+}
+
+/// Resolve the named bucket and limit for a write request, or `None` if the route isn't limited
+/// Token generation is excluded because it has its own `RateLimitLayer`
+pub fn route_limit(config: &RateLimitConfig, method: &Method, path: &str) -> Option<(&'static str, RouteRateLimit)> {
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return None;
+    }
+
+    let (name, limit) = match path {
+        "/api/v1/auth/token" => return None,
+        // A batch is charged per message by its handler, which knows how many it carries
+        "/api/v1/messages/batch" => return None,
+        "/api/v1/messages" => ("send_message", config.send_message),
+        "/api/v1/preferences" => ("update_preferences", config.update_preferences),
+        // Test pushes hit the push providers, so they're held to the same low limit as registration
+        "/api/v1/device-tokens" | "/api/v1/device-tokens/test" => ("register_device_token", config.register_device_token),
+        _ => ("write", config.default_write),
+    };
+
+    if limit.capacity == 0 {
+        return None;
+    }
+
+    Some((name, limit))
+}
+
+/// Axum middleware applying per-route token bucket limits to write endpoints
+/// Buckets are keyed on the authenticated user, falling back to client IP for unauthenticated routes
+pub async fn rate_limit_middleware(req: Request, next: Next) -> Response {
+    let ctx = match req.extensions().get::<RelayContext>() {
+        Some(ctx) => ctx.clone(),
+        None => return next.run(req).await,
+    };
+
+    let (name, limit) = match route_limit(&ctx.config.rate_limit, req.method(), req.uri().path()) {
+        Some(l) => l,
+        None => return next.run(req).await,
+    };
+
+    let identity = match req.extensions().get::<AuthenticatedUser>() {
+        Some(user) => format!("user:{}", user.user_address),
+        None => format!("ip:{}", client_ip(&req)),
+    };
+    let key = ctx.config.redis.keys().rate_limit(name, &identity);
+
+    match take_token(&ctx, &key, limit).await {
+        Ok(RateLimitDecision::Allowed) => {}
+        Ok(RateLimitDecision::Limited { retry_after }) => {
+            tracing::warn!("Rate limit exceeded for {}", key);
+            return too_many_requests(retry_after);
+        }
+        Err(e) => {
+            // Fail open so a Redis outage doesn't take the endpoint down with it
+            tracing::warn!("Rate limit check failed for {}: {}", key, e);
+        }
+    }
+
+    next.run(req).await
+}
+
+/// Tower layer enforcing a per-IP fixed-window rate limit backed by Redis
+/// Keys are `RL:{route}:{ip}`, plus `RL:{route}:{field}:{value}` when a body field is configured
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    route: &'static str,
+    max_requests: u32,
+    window_seconds: u64,
+    body_field: Option<&'static str>,
+}
+
+impl RateLimitLayer {
+    pub fn new(route: &'static str, max_requests: u32, window_seconds: u64) -> Self {
+        Self {
+            route,
+            max_requests,
+            window_seconds,
+            body_field: None,
+        }
+    }
+
+    /// Additionally limit on a top-level string field of the JSON request body
+    /// (e.g. `wallet_address`), so a single identity can't be hammered from many IPs
+    pub fn with_body_field(mut self, field: &'static str) -> Self {
+        self.body_field = Some(field);
+        self
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    config: RateLimitLayer,
+}
+
+impl<S> Service<Request> for RateLimit<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        // Take the service that was driven to readiness and leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let ctx = match req.extensions().get::<RelayContext>() {
+                Some(ctx) => ctx.clone(),
+                None => {
+                    tracing::warn!("RelayContext missing, skipping rate limit");
+                    return inner.call(req).await;
+                }
+            };
+
+            let redis_keys = ctx.config.redis.keys();
+            let mut keys = vec![redis_keys.rate_limit(config.route, &client_ip(&req))];
+
+            let req = match config.body_field {
+                Some(field) => {
+                    let (parts, body) = req.into_parts();
+                    let bytes = match to_bytes(body, MAX_BUFFERED_BODY_BYTES).await {
+                        Ok(b) => b,
+                        Err(_) => {
+                            return Ok(ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", "Request body is too large")
+                                .into_response());
+                        }
+                    };
+
+                    if let Some(value) = serde_json::from_slice::<serde_json::Value>(&bytes)
+                        .ok()
+                        .as_ref()
+                        .and_then(|v| v.get(field))
+                        .and_then(|v| v.as_str())
+                    {
+                        keys.push(redis_keys.rate_limit(config.route, &format!("{}:{}", field, value.trim())));
+                    }
+
+                    Request::from_parts(parts, Body::from(bytes))
+                }
+                None => req,
+            };
+
+            for key in &keys {
+                match check_rate_limit(&ctx, key, config.max_requests, config.window_seconds).await {
+                    Ok(RateLimitDecision::Allowed) => {}
+                    Ok(RateLimitDecision::Limited { retry_after }) => {
+                        tracing::warn!("Rate limit exceeded for {}", key);
+                        return Ok(too_many_requests(retry_after));
+                    }
+                    Err(e) => {
+                        // Fail open so a Redis outage doesn't take the endpoint down with it
+                        tracing::warn!("Rate limit check failed for {}: {}", key, e);
+                    }
+                }
+            }
+
+            inner.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_trips_after_max_requests() {
+        let max_requests = 3;
+        let window = 60;
+
+        for count in 1..=3 {
+            assert_eq!(evaluate(count, max_requests, 60, window), RateLimitDecision::Allowed);
+        }
+
+        assert_eq!(
+            evaluate(4, max_requests, 42, window),
+            RateLimitDecision::Limited { retry_after: 42 }
+        );
+        assert_eq!(
+            evaluate(5, max_requests, -1, window),
+            RateLimitDecision::Limited { retry_after: 60 }
+        );
+    }
+
+    #[test]
+    fn test_route_limit_only_applies_to_writes() {
+        let limit = RouteRateLimit { capacity: 5, period_seconds: 60 };
+        let config = RateLimitConfig {
+            auth_max_requests: 10,
+            auth_window_seconds: 60,
+            send_message: limit,
+            update_preferences: limit,
+            register_device_token: RouteRateLimit { capacity: 0, period_seconds: 60 },
+            default_write: limit,
+        };
+
+        assert!(route_limit(&config, &Method::GET, "/api/v1/messages").is_none());
+        assert_eq!(route_limit(&config, &Method::POST, "/api/v1/messages").map(|(n, _)| n), Some("send_message"));
+        assert!(route_limit(&config, &Method::POST, "/api/v1/messages/batch").is_none());
+        assert!(route_limit(&config, &Method::POST, "/api/v1/auth/token").is_none());
+        assert!(route_limit(&config, &Method::POST, "/api/v1/device-tokens").is_none());
+        assert!(route_limit(&config, &Method::POST, "/api/v1/device-tokens/test").is_none());
+        assert_eq!(route_limit(&config, &Method::POST, "/api/v1/notifications/1/read").map(|(n, _)| n), Some("write"));
+    }
+
+    #[test]
+    fn test_client_ip_takes_the_hop_the_trusted_proxy_added() {
+        let proxy: SocketAddr = "10.0.0.1:443".parse().unwrap();
+        let request = |forwarded: &[&str]| {
+            let mut builder = Request::builder();
+            for value in forwarded {
+                builder = builder.header("x-forwarded-for", *value);
+            }
+            let mut req = builder.body(Body::empty()).unwrap();
+            req.extensions_mut().insert(ConnectInfo(proxy));
+            req
+        };
+        let ip = |req: &Request, trusted| resolve_ip(req.headers(), req.extensions(), trusted);
+
+        // The client prepended a spoofed hop; the proxy appended the address it really saw
+        let spoofed = request(&["203.0.113.7, 198.51.100.23"]);
+        assert_eq!(ip(&spoofed, 1), "198.51.100.23");
+        assert_eq!(ip(&request(&["203.0.113.7, 198.51.100.23, 10.0.0.2"]), 2), "198.51.100.23");
+        assert_eq!(ip(&request(&["203.0.113.7", "198.51.100.23"]), 1), "198.51.100.23");
+
+        // With no trusted proxy the header is ignored, as it is when it's shorter than the proxy chain or not an IP
+        assert_eq!(ip(&spoofed, 0), "10.0.0.1");
+        assert_eq!(ip(&request(&["198.51.100.23"]), 2), "10.0.0.1");
+        assert_eq!(ip(&request(&["not-an-ip"]), 1), "10.0.0.1");
+        assert_eq!(ip(&request(&["198.51.100.23, "]), 1), "10.0.0.1");
+        // No context means no configured proxies
+        assert_eq!(client_ip(&spoofed), "10.0.0.1");
+
+        let req = Request::builder().body(Body::empty()).unwrap();
+        assert_eq!(client_ip(&req), "unknown");
+    }
+
+    #[tokio::test]
+    async fn test_client_info_reads_client_headers() {
+        let proxy: SocketAddr = "10.0.0.1:443".parse().unwrap();
+        let mut req = Request::builder()
+            .header("user-agent", "MySocial/2.4 (iPhone; iOS 18.1)")
+            .header("x-app-version", " 2.4.0 ")
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut().insert(ConnectInfo(proxy));
+        let (mut parts, _) = req.into_parts();
+
+        let info = ClientInfo::from_request_parts(&mut parts, &()).await.unwrap();
+        assert_eq!(info.ip, "10.0.0.1");
+        assert_eq!(info.user_agent.as_deref(), Some("MySocial/2.4 (iPhone; iOS 18.1)"));
+        assert_eq!(info.app_version.as_deref(), Some("2.4.0"));
+
+        let mut req = Request::builder().header("user-agent", "  ").body(Body::empty()).unwrap();
+        req.extensions_mut().insert(ConnectInfo(proxy));
+        let (mut parts, _) = req.into_parts();
+        let info = ClientInfo::from_request_parts(&mut parts, &()).await.unwrap();
+        assert_eq!((info.user_agent, info.app_version), (None, None));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables, and Redis at REDIS_URL"]
+    async fn test_spoofed_forwarded_for_does_not_escape_the_limit() {
+        use axum::{routing::get, Extension, Router};
+        use tower::ServiceExt;
+
+        let mut config = relay_core::Config::from_env();
+        config.server.trusted_proxy_count = 1;
+        let ctx = RelayContext::new(config).await.unwrap();
+        // A fresh route per run, so earlier runs' counters don't count
+        let route: &'static str = Box::leak(format!("test_xff_{}", uuid::Uuid::new_v4().simple()).into_boxed_str());
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(RateLimitLayer::new(route, 2, 60))
+            .layer(Extension(ctx.clone()));
+
+        let proxy: SocketAddr = "10.0.0.1:443".parse().unwrap();
+        let send = |forwarded: String| {
+            let mut req = Request::get("/").header("x-forwarded-for", forwarded).body(Body::empty()).unwrap();
+            req.extensions_mut().insert(ConnectInfo(proxy));
+            app.clone().oneshot(req)
+        };
+
+        // A new made-up first hop on every request doesn't give the same client a new bucket
+        let mut statuses = Vec::new();
+        for i in 0..3 {
+            statuses.push(send(format!("203.0.113.{}, 198.51.100.23", i)).await.unwrap().status());
+        }
+        // Nor can another client spend that one's bucket by naming it
+        let other = send("198.51.100.23, 198.51.100.24".to_string()).await.unwrap().status();
+
+        let mut conn = get_connection(&ctx.redis_pool).await.unwrap();
+        for ip in ["198.51.100.23", "198.51.100.24"] {
+            let _: () = redis::cmd("DEL").arg(ctx.config.redis.keys().rate_limit(route, ip)).query_async(&mut conn).await.unwrap();
+        }
+
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::OK, StatusCode::TOO_MANY_REQUESTS]);
+        assert_eq!(other, StatusCode::OK);
+    }
+}
@@ -1,69 +1,334 @@
 use anyhow::Result;
 use axum::{
-    extract::Extension,
+    extract::{DefaultBodyLimit, Extension},
+    http::{HeaderName, HeaderValue, Method},
     middleware,
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Router,
 };
 use relay_core::RelayContext;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use tower::layer::util::{Identity, Stack};
 use tower::ServiceBuilder;
-use tower_http::cors::{CorsLayer, Any};
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::CorsLayer;
+use tower_http::limit::RequestBodyLimitLayer;
 use tracing;
 use std::env;
 
 use crate::handlers;
 use crate::websocket;
+use crate::audit;
 use crate::auth;
+use crate::rate_limit::{self, RateLimitLayer};
+use crate::tls;
+
+/// gzip or brotli for HTTP responses, whichever the client's `Accept-Encoding` prefers; tiny bodies
+/// are sent as-is. `/ws` frames are compressed by `ws_deflate` instead
+fn compression_layer() -> CompressionLayer {
+    CompressionLayer::new().gzip(true).br(true)
+}
+
+/// Reject request bodies over `max_bytes` with 413. Axum's own 2 MB extractor default is lifted
+/// so this is the only cap and can be configured in either direction
+fn body_limit_layer(max_bytes: usize) -> ServiceBuilder<Stack<RequestBodyLimitLayer, Stack<DefaultBodyLimit, Identity>>> {
+    ServiceBuilder::new()
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(max_bytes))
+}
+
+/// CORS for the comma-separated `origins` with credentials. Methods and headers are listed
+/// explicitly since browsers reject a wildcard alongside credentials; invalid entries are skipped
+fn cors_layer(origins: &str, methods: &[String], headers: &[String]) -> CorsLayer {
+    let origins: Vec<HeaderValue> = origins
+        .split(',')
+        .map(str::trim)
+        .filter_map(|origin| origin.parse().ok())
+        .collect();
+    let methods: Vec<Method> = methods
+        .iter()
+        .filter_map(|m| {
+            Method::from_bytes(m.to_ascii_uppercase().as_bytes())
+                .map_err(|_| tracing::warn!("Ignoring invalid CORS method '{}'", m))
+                .ok()
+        })
+        .collect();
+    let headers: Vec<HeaderName> = headers
+        .iter()
+        .filter_map(|h| {
+            HeaderName::from_bytes(h.as_bytes())
+                .map_err(|_| tracing::warn!("Ignoring invalid CORS header '{}'", h))
+                .ok()
+        })
+        .collect();
+
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .allow_credentials(true)
+}
+
+/// Every API route behind the shared middleware: CORS, compression, auth and per-route rate limits
+pub fn create_router(ctx: RelayContext, jwt_keys: Arc<auth::JwtKeys>, cors: CorsLayer) -> Router {
+    // Throttle token generation per client IP and per wallet address
+    let auth_rate_limit = RateLimitLayer::new(
+        "auth",
+        ctx.config.rate_limit.auth_max_requests,
+        ctx.config.rate_limit.auth_window_seconds,
+    )
+    .with_body_field("wallet_address");
+    
+    // Routes only addresses with the admin role may call; changes are audited, including refused ones
+    let admin_routes = Router::new()
+        .route("/api/v1/notifications/:id/deliveries", get(handlers::get_notification_deliveries))
+        .route("/api/v1/admin/audit", get(handlers::get_audit_log))
+        .route("/api/v1/admin/diagnostics", get(handlers::get_diagnostics))
+        .route("/api/v1/admin/notifications", post(handlers::send_admin_notification))
+        .route("/api/v1/admin/notifications/preview", post(handlers::preview_notification))
+        .route("/api/v1/admin/notification-templates", put(handlers::upsert_notification_template))
+        .route("/api/v1/admin/messages/retention/run", post(handlers::run_message_retention))
+        .route("/api/v1/admin/conversations/:conversation_id/reencrypt", post(handlers::reencrypt_conversation))
+        .route(
+            "/api/v1/admin/platforms/:platform_id/delivery-config",
+            post(handlers::create_platform_delivery_config)
+                .get(handlers::get_platform_delivery_config)
+                .put(handlers::update_platform_delivery_config)
+                .delete(handlers::delete_platform_delivery_config),
+        )
+        .route_layer(middleware::from_fn_with_state(ctx.config.clone(), auth::require_admin))
+        .route_layer(middleware::from_fn(audit::audit_admin));
+
+    Router::new()
+        .route("/health", get(handlers::health))
+        .route("/health/ready", get(handlers::ready))
+        .route("/metrics", get(handlers::metrics))
+        .route("/ws", get(websocket::websocket_handler))
+        .route("/api/v1/auth/token", post(handlers::generate_token).layer(auth_rate_limit))
+        .route("/api/v1/notifications", get(handlers::get_notifications).delete(handlers::clear_notifications))
+        .route("/api/v1/notifications/counts", get(handlers::get_notification_counts))
+        .route("/api/v1/notifications/counts/recompute", post(handlers::recompute_notification_counts))
+        .route("/api/v1/notifications/:id/read", post(handlers::mark_notification_read))
+        .route("/api/v1/notifications/:id/action", post(handlers::notification_action))
+        .route("/api/v1/messages", get(handlers::get_messages))
+        .route("/api/v1/messages", post(handlers::send_message))
+        .route("/api/v1/messages/batch", post(handlers::send_message_batch))
+        .route("/api/v1/messages/:id", delete(handlers::delete_message))
+        .route("/api/v1/messages/:id/read", post(handlers::mark_message_read))
+        .route(
+            "/api/v1/messages/:id/reactions",
+            post(handlers::add_message_reaction).delete(handlers::remove_message_reaction),
+        )
+        .route("/api/v1/conversations", get(handlers::get_conversations).post(handlers::create_conversation))
+        .route("/api/v1/conversations/unread", get(handlers::get_conversation_unread_counts))
+        .route("/api/v1/conversations/find", get(handlers::find_conversation))
+        .route("/api/v1/conversations/:id", get(handlers::get_conversation))
+        .route("/api/v1/conversations/:id/read", post(handlers::mark_conversation_read))
+        .route("/api/v1/conversations/:id/mute", post(handlers::mute_conversation).delete(handlers::unmute_conversation))
+        .route("/api/v1/conversations/:id/archive", post(handlers::archive_conversation).delete(handlers::unarchive_conversation))
+        .route("/api/v1/conversations/:id/pin", post(handlers::pin_conversation).delete(handlers::unpin_conversation))
+        .route("/api/v1/sessions", get(handlers::get_sessions))
+        .route("/api/v1/sessions/:id", delete(handlers::revoke_session))
+        .route("/api/v1/presence", get(handlers::get_presence))
+        .route("/api/v1/blocks", get(handlers::get_blocks))
+        .route("/api/v1/blocks/:address", post(handlers::block_user).delete(handlers::unblock_user))
+        .route("/api/v1/preferences", get(handlers::get_preferences))
+        .route("/api/v1/preferences", post(handlers::update_preferences))
+        .route("/api/v1/device-tokens", get(handlers::get_device_tokens).post(handlers::register_device_token))
+        .route("/api/v1/device-tokens/test", post(handlers::send_test_push))
+        .route("/api/v1/me", delete(handlers::delete_account))
+        .route("/api/v1/me/export", get(handlers::export_user_data))
+        .route("/api/v1/keys", post(handlers::publish_key))
+        .route("/api/v1/users/:address/keys", get(handlers::get_user_keys))
+        .route("/api/v1/media/upload-url", post(handlers::create_media_upload_url))
+        .merge(admin_routes)
+        .layer(body_limit_layer(ctx.config.server.max_request_body_bytes))
+        .layer(
+            // CORS outermost, so preflights are answered before auth and errors carry the CORS headers too
+            ServiceBuilder::new()
+                .layer(cors)
+                .layer(compression_layer())
+                .layer(Extension(ctx))
+                .layer(Extension(jwt_keys))
+                .layer(middleware::from_fn(auth::auth_middleware))
+                .layer(middleware::from_fn(rate_limit::rate_limit_middleware)),
+        )
+}
 
 pub async fn run(ctx: RelayContext) -> Result<()> {
     let api_port = ctx.config.server.api_port;
-    let ctx_clone = ctx.clone();
-    
+
+    // Fail at startup rather than on the first sign-in if the key material is bad
+    let jwt_keys = Arc::new(auth::JwtKeys::from_config(&ctx.config.server)?);
+
+    tokio::spawn(websocket::reap_stale_connections(ctx.clone()));
+
     // Configure CORS - allow specific origins or all if CORS_ORIGINS not set
     let cors_layer = if let Ok(origins) = env::var("CORS_ORIGINS") {
-        // Parse comma-separated origins
-        let origin_list: Vec<&str> = origins.split(',').map(|s| s.trim()).collect();
-        let mut cors = CorsLayer::new();
-        for origin in origin_list {
-            if let Ok(parsed) = origin.parse::<axum::http::HeaderValue>() {
-                cors = cors.allow_origin(parsed);
-            }
-        }
-        cors.allow_methods(Any)
-            .allow_headers(Any)
-            .allow_credentials(true)
+        cors_layer(&origins, &ctx.config.server.cors_allowed_methods, &ctx.config.server.cors_allowed_headers)
     } else {
         // Default to permissive for development, but log warning
         tracing::warn!("CORS_ORIGINS not set, using permissive CORS. Set CORS_ORIGINS for production!");
         CorsLayer::permissive()
     };
     
-    let app = Router::new()
-            .route("/health", get(handlers::health))
-            .route("/ws", get(websocket::websocket_handler))
-            .route("/api/v1/auth/token", post(handlers::generate_token))
-            .route("/api/v1/notifications", get(handlers::get_notifications))
-            .route("/api/v1/notifications/counts", get(handlers::get_notification_counts))
-            .route("/api/v1/notifications/:id/read", post(handlers::mark_notification_read))
-            .route("/api/v1/messages", get(handlers::get_messages))
-            .route("/api/v1/messages", post(handlers::send_message))
-            .route("/api/v1/conversations", get(handlers::get_conversations))
-            .route("/api/v1/preferences", get(handlers::get_preferences))
-            .route("/api/v1/preferences", post(handlers::update_preferences))
-            .route("/api/v1/device-tokens", post(handlers::register_device_token))
-            .layer(
-                ServiceBuilder::new()
-                    .layer(Extension(ctx_clone))
-                    .layer(middleware::from_fn(auth::auth_middleware))
-                    .layer(cors_layer),
-            );
+    let app = create_router(ctx.clone(), jwt_keys, cors_layer);
 
+    let tls = tls::from_config(&ctx.config.server)?;
     let addr = SocketAddr::from(([0, 0, 0, 0], api_port));
-    tracing::info!("Starting API server on {}", addr);
-
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+
+    match tls {
+        Some(tls) => {
+            tracing::info!("Starting API server on {} (HTTPS)", addr);
+            tls::serve(listener, app, tls).await?;
+        }
+        None => {
+            tracing::info!("Starting API server on {}", addr);
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::{header, Request}, response::Json};
+    use tower::ServiceExt;
+
+    /// A page of messages about the size a busy conversation returns
+    fn message_page() -> serde_json::Value {
+        let items: Vec<_> = (0..100)
+            .map(|id| serde_json::json!({"id": id, "conversation_id": "0xa:0xb", "content": "see you at the venue tonight", "deleted": false}))
+            .collect();
+        crate::pagination::Page::new(None, None).envelope(items, 100)
+    }
+
+    async fn get_messages(accept_encoding: Option<&str>) -> (Option<String>, usize) {
+        let app = Router::new()
+            .route("/api/v1/messages", get(|| async { Json(message_page()) }))
+            .layer(compression_layer());
+
+        let mut request = Request::get("/api/v1/messages");
+        if let Some(encoding) = accept_encoding {
+            request = request.header(header::ACCEPT_ENCODING, encoding);
+        }
+        let response = app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+
+        let encoding = response
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|v| v.to_str().unwrap().to_string());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (encoding, body.len())
+    }
+
+    #[tokio::test]
+    async fn test_large_responses_compressed_when_requested() {
+        let uncompressed_len = serde_json::to_vec(&message_page()).unwrap().len();
+
+        let (encoding, len) = get_messages(Some("gzip")).await;
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+        assert!(len < uncompressed_len / 4);
+
+        let (encoding, _) = get_messages(Some("br;q=1.0, gzip;q=0.5")).await;
+        assert_eq!(encoding.as_deref(), Some("br"));
+
+        // Clients that don't ask get plain JSON
+        assert_eq!(get_messages(None).await, (None, uncompressed_len));
+    }
+
+    async fn post_message(content_len: usize, max_bytes: usize) -> axum::http::StatusCode {
+        let app = Router::new()
+            .route("/api/v1/messages", post(|Json(body): Json<serde_json::Value>| async move { Json(body) }))
+            .layer(body_limit_layer(max_bytes));
+
+        let body = serde_json::json!({"recipient_address": "0xb", "content": "x".repeat(content_len)}).to_string();
+        let request = Request::post("/api/v1/messages")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_oversized_bodies_rejected() {
+        let max_bytes = 4 * 1024 * 1024;
+
+        assert_eq!(post_message(max_bytes + 1, max_bytes).await, axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+        // Above axum's 2 MB default but within the configured limit
+        assert_eq!(post_message(3 * 1024 * 1024, max_bytes).await, axum::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_preflight_lists_allowed_methods_and_headers() {
+        let list = |v: &str| v.split(',').map(String::from).collect::<Vec<_>>();
+        let app = Router::new()
+            .route("/api/v1/messages", post(|| async { "ok" }))
+            .layer(cors_layer(
+                "https://app.example.com, https://admin.example.com",
+                &list("GET,POST,PUT,PATCH,DELETE,OPTIONS"),
+                &list("Authorization,Content-Type,Idempotency-Key,X-Request-Id"),
+            ));
+
+        let preflight = |origin: &str| {
+            Request::builder()
+                .method(Method::OPTIONS)
+                .uri("/api/v1/messages")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization,idempotency-key")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(preflight("https://admin.example.com")).await.unwrap();
+        let allowed = |name| response.headers().get(name).map(|v| v.to_str().unwrap().to_string());
+        assert_eq!(allowed(header::ACCESS_CONTROL_ALLOW_ORIGIN).as_deref(), Some("https://admin.example.com"));
+        assert_eq!(allowed(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).as_deref(), Some("true"));
+        assert_eq!(allowed(header::ACCESS_CONTROL_ALLOW_METHODS).as_deref(), Some("GET,POST,PUT,PATCH,DELETE,OPTIONS"));
+        assert_eq!(
+            allowed(header::ACCESS_CONTROL_ALLOW_HEADERS).as_deref(),
+            Some("authorization,content-type,idempotency-key,x-request-id")
+        );
+
+        // Other origins get no grant
+        let response = app.oneshot(preflight("https://evil.example.com")).await.unwrap();
+        assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables, and Redis at REDIS_URL"]
+    async fn test_cors_wraps_auth_in_the_full_router() {
+        let ctx = RelayContext::new(relay_core::Config::from_env()).await.unwrap();
+        let keys = Arc::new(auth::JwtKeys::from_config(&ctx.config.server).unwrap());
+        let list = |v: &str| v.split(',').map(String::from).collect::<Vec<_>>();
+        let cors = cors_layer("https://app.example.com", &list("GET,POST,DELETE,OPTIONS"), &list("Authorization,Content-Type"));
+        let app = create_router(ctx, keys, cors);
+
+        // A preflight carries no Authorization header and is answered before auth runs
+        let preflight = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/v1/messages")
+            .header(header::ORIGIN, "https://app.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(preflight).await.unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://app.example.com");
+
+        // Auth's 401 still carries the grant, so the browser lets the client read it
+        let unauthenticated = Request::get("/api/v1/messages")
+            .header(header::ORIGIN, "https://app.example.com")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(unauthenticated).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://app.example.com");
+    }
+}
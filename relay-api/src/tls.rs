@@ -0,0 +1,154 @@
+use anyhow::{anyhow, bail, Context, Result};
+use axum::extract::ConnectInfo;
+use axum::Router;
+use hyper::body::Incoming;
+use hyper::Request;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use relay_core::config::ServerConfig;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+
+/// Connections that don't finish the TLS handshake within this long are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The TLS settings from `TLS_CERT_PATH` and `TLS_KEY_PATH`; None when neither is set, so the server
+/// speaks plain HTTP behind a proxy that terminates TLS
+pub fn from_config(config: &ServerConfig) -> Result<Option<Arc<rustls::ServerConfig>>> {
+    match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => load(cert_path, key_path).map(Some),
+        (None, None) => Ok(None),
+        _ => bail!("TLS_CERT_PATH and TLS_KEY_PATH must be set together"),
+    }
+}
+
+/// A PEM certificate chain and private key, offering HTTP/2 and HTTP/1.1 over ALPN
+pub fn load(cert_path: &str, key_path: &str) -> Result<Arc<rustls::ServerConfig>> {
+    let certs = rustls_pemfile::certs(&mut open(cert_path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid certificate in {}", cert_path))?;
+    if certs.is_empty() {
+        bail!("No certificate found in {}", cert_path);
+    }
+    let key = rustls_pemfile::private_key(&mut open(key_path)?)
+        .with_context(|| format!("Invalid private key in {}", key_path))?
+        .ok_or_else(|| anyhow!("No private key found in {}", key_path))?;
+
+    let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("Certificate and private key don't match")?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(Arc::new(config))
+}
+
+fn open(path: &str) -> Result<BufReader<File>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    Ok(BufReader::new(file))
+}
+
+/// Serve `app` over HTTPS. Upgrades work as over plain HTTP, so `/ws` is served as `wss://`, and handlers
+/// see the client address as `ConnectInfo` just like with `into_make_service_with_connect_info`
+pub async fn serve(listener: TcpListener, app: Router, tls: Arc<rustls::ServerConfig>) -> Result<()> {
+    let acceptor = TlsAcceptor::from(tls);
+
+    loop {
+        let (tcp, remote) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // e.g. out of file descriptors; back off rather than spin
+                tracing::warn!("Failed to accept connection: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    tracing::debug!("TLS handshake with {} failed: {}", remote, e);
+                    return;
+                }
+                Err(_) => {
+                    tracing::debug!("TLS handshake with {} timed out", remote);
+                    return;
+                }
+            };
+
+            let service = app.map_request(move |mut request: Request<Incoming>| {
+                request.extensions_mut().insert(ConnectInfo(remote));
+                request
+            });
+            let served = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), TowerToHyperService::new(service))
+                .await;
+            if let Err(e) = served {
+                tracing::debug!("Connection from {} closed with error: {}", remote, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{response::Json, routing::get};
+    use std::net::SocketAddr;
+
+    const CERT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/localhost.crt");
+    const KEY_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/localhost.key");
+
+    #[tokio::test]
+    async fn test_serves_health_over_tls() {
+        let app = Router::new().route(
+            "/health",
+            get(|ConnectInfo(remote): ConnectInfo<SocketAddr>| async move {
+                Json(serde_json::json!({"status": "ok", "loopback": remote.ip().is_loopback()}))
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(serve(listener, app, load(CERT_PATH, KEY_PATH).unwrap()));
+
+        // The self-signed certificate is trusted explicitly, so the handshake is fully verified
+        let cert = reqwest::Certificate::from_pem(&std::fs::read(CERT_PATH).unwrap()).unwrap();
+        let client = reqwest::Client::builder().add_root_certificate(cert).build().unwrap();
+        let health: serde_json::Value = client
+            .get(format!("https://127.0.0.1:{}/health", port))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(health, serde_json::json!({"status": "ok", "loopback": true}));
+
+        // Plain HTTP isn't answered on the TLS port
+        assert!(client.get(format!("http://127.0.0.1:{}/health", port)).send().await.is_err());
+    }
+
+    #[test]
+    fn test_cert_and_key_set_together() {
+        let config = relay_core::Config::from_env().server;
+        let plain = ServerConfig { tls_cert_path: None, tls_key_path: None, ..config.clone() };
+        assert!(from_config(&plain).unwrap().is_none());
+
+        let half = ServerConfig { tls_cert_path: Some(CERT_PATH.to_string()), tls_key_path: None, ..config.clone() };
+        assert!(from_config(&half).is_err());
+
+        let swapped = ServerConfig { tls_cert_path: Some(KEY_PATH.to_string()), tls_key_path: Some(CERT_PATH.to_string()), ..config };
+        assert!(from_config(&swapped).is_err());
+    }
+}
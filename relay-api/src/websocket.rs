@@ -1,9 +1,9 @@
 use axum::{
-    extract::{ws::WebSocketUpgrade, Extension, Query},
+    extract::{Extension, Query},
+    http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderMap, StatusCode},
     response::{Response, IntoResponse},
-    http::StatusCode,
 };
-use relay_core::{RelayContext, redis::get_connection};
+use relay_core::{RelayContext, lock, redis::get_connection};
 use serde::Deserialize;
 use tracing;
 use uuid::Uuid;
@@ -11,34 +11,106 @@ use futures_util::{SinkExt, StreamExt};
 use chrono::Utc;
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
-use relay_core::schema::relay_ws_connections;
-use crate::auth::verify_token;
+use relay_core::schema::{relay_messages, relay_ws_connections};
+use crate::auth::{ensure_not_revoked, verify_token, JwtKeys};
+use crate::error::ApiError;
+use crate::handlers::{send_message_from, unread_counts, SendMessageRequest};
+use crate::presence;
+use crate::rate_limit::{take_send_message_tokens, ClientInfo, RateLimitDecision};
+use crate::ws_cursor::{self, PendingAcks, CHAT_CHANNEL, NOTIFY_CHANNEL};
+use crate::ws_deflate::{self, DeflateUpgrade};
+use crate::ws_outbox::{self, Enqueued, OutboundFrame, SendQueue, REPLY_CHANNEL};
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Browsers can't set an Authorization header on a WebSocket, so they offer the JWT as a subprotocol:
+/// `Sec-WebSocket-Protocol: bearer, {token}`. Only `bearer` is echoed back, never the token
+pub const AUTH_PROTOCOL: &str = "bearer";
+
+/// Wait before the stream reader's first reconnect to Redis, doubled per failure up to `RECONNECT_MAX_DELAY`
+const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(250);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(8);
+/// Once Redis has failed for this long the socket is closed with 1013 (try again later), so the client
+/// reconnects, possibly to a healthier instance, instead of sitting on a socket that gets nothing
+const REDIS_GIVE_UP_AFTER: Duration = Duration::from_secs(30);
 
 #[derive(Deserialize)]
 pub struct WsQuery {
-    token: String,
+    /// For clients that can't send `Sec-WebSocket-Protocol`; the query string ends up in access logs
+    #[serde(default)]
+    token: Option<String>,
+    /// Replay both streams from this stream id instead of the stored cursor
+    since: Option<String>,
+    /// Only move the stored cursor when the client acks a frame, so unacked frames replay on reconnect
+    #[serde(default)]
+    ack: bool,
+    /// Read this platform's notification stream and keep a separate cursor, under `REDIS_PLATFORM_NAMESPACES`
+    platform_id: Option<String>,
 }
 
 pub async fn websocket_handler(
-    ws: WebSocketUpgrade,
+    ws: DeflateUpgrade,
     Extension(ctx): Extension<RelayContext>,
+    Extension(jwt_keys): Extension<Arc<JwtKeys>>,
+    client: ClientInfo,
+    headers: HeaderMap,
     Query(params): Query<WsQuery>,
 ) -> Response {
+    let Some(token) = protocol_token(&headers).or(params.token) else {
+        tracing::debug!("WebSocket upgrade without a token");
+        return ApiError::unauthorized("missing_token", "Offer the token as `Sec-WebSocket-Protocol: bearer, {token}`").into_response();
+    };
+
     // Verify JWT token and extract user_address
-    let user_address = match verify_token(&params.token, &ctx.config.server.jwt_secret) {
-        Ok(addr) => addr,
-        Err(_) => {
+    let user = match verify_token(&token, &jwt_keys) {
+        Ok(user) => user,
+        Err(e) => {
             tracing::warn!("Invalid JWT token for WebSocket connection");
-            return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+            return e.into_response();
         }
     };
+    if let Err(e) = ensure_not_revoked(&ctx, &user).await {
+        tracing::warn!("Rejected JWT token for WebSocket connection from {}: {}", user.user_address, e.code);
+        return e.into_response();
+    }
+    let user_address = user.user_address;
     
-    ws.on_upgrade(move |socket| handle_socket(socket, user_address, ctx))
+    if let Some(since) = params.since.as_deref() {
+        if !ws_cursor::is_stream_id(since) {
+            return ApiError::bad_request("invalid_since", "since must be a stream id such as 1700000000000-0").into_response();
+        }
+    }
+
+    let platform_id = params.platform_id.filter(|p| !p.trim().is_empty());
+    ws.protocol(AUTH_PROTOCOL)
+        .on_upgrade(move |socket| handle_socket(socket, user_address, client, params.since, params.ack, platform_id, ctx))
+}
+
+/// The entry following `bearer` in the offered subprotocols
+fn protocol_token(headers: &HeaderMap) -> Option<String> {
+    let offered: Vec<&str> = headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    let marker = offered.iter().position(|protocol| *protocol == AUTH_PROTOCOL)?;
+
+    offered.get(marker + 1).filter(|token| !token.is_empty()).map(|token| token.to_string())
 }
 
 async fn handle_socket(
-    socket: axum::extract::ws::WebSocket,
+    socket: ws_deflate::WebSocket,
     user_address: String,
+    client: ClientInfo,
+    since: Option<String>,
+    ack_mode: bool,
+    platform_id: Option<String>,
     ctx: RelayContext,
 ) {
     tracing::info!("WebSocket connection established for user: {}", user_address);
@@ -61,125 +133,810 @@ async fn handle_socket(
             relay_ws_connections::connection_id.eq(&connection_id),
             relay_ws_connections::connected_at.eq(Utc::now()),
             relay_ws_connections::last_heartbeat_at.eq(Utc::now()),
+            relay_ws_connections::ip.eq(&client.ip),
+            relay_ws_connections::user_agent.eq(&client.user_agent),
+            relay_ws_connections::app_version.eq(&client.app_version),
         ))
         .execute(&mut conn)
         .await
     {
         tracing::error!("Failed to register WebSocket connection: {}", e);
     }
+
+    if let Err(e) = presence::heartbeat(&ctx, &user_address, &connection_id).await {
+        tracing::warn!("Failed to record presence for {}: {}", user_address, e);
+    }
+
+    // Tell the client the socket is live and where its counts start, before any stream event
+    let unread = match get_connection(&ctx.redis_pool).await {
+        Ok(mut c) => Some(unread_counts(&mut c, ctx.config.redis.keys(), &user_address).await),
+        Err(e) => {
+            tracing::warn!("Failed to load unread counts for {}: {}", user_address, e);
+            None
+        }
+    };
+    if let Err(e) = sender.send(Message::Text(connected_frame(&connection_id, unread))).await {
+        tracing::debug!("Failed to send connected frame to {}: {}", user_address, e);
+        mark_disconnected(&ctx, &user_address, &connection_id).await;
+        return;
+    }
     
     // Clone for tasks
-    let ctx_send = ctx.clone();
+    let ctx_read = ctx.clone();
+    let ctx_write = ctx.clone();
     let ctx_recv = ctx.clone();
-    let user_address_send = user_address.clone();
+    let user_address_read = user_address.clone();
+    let user_address_write = user_address.clone();
+    let user_address_recv = user_address.clone();
     let connection_id_recv = connection_id.clone();
-    
-    // Spawn task to read from Redis stream and forward to WebSocket
-    let mut send_task = tokio::spawn(async move {
-        let stream_key = format!("STREAM:CHAT:{}", user_address_send);
-        let mut last_id = "0".to_string();
-        
+
+    let keys = ctx.config.redis.keys();
+    let chat_key = keys.chat_stream(&user_address);
+    let notify_key = keys.notify_stream(&user_address, platform_id.as_deref());
+    let cursor_key = keys.ws_cursor(&user_address, platform_id.as_deref());
+    let cursor_key_write = cursor_key.clone();
+    let cursor_key_recv = cursor_key.clone();
+
+    // Last time any frame arrived from the client; the writer closes the socket when it goes stale
+    let last_seen = Arc::new(Mutex::new(Instant::now()));
+    let last_seen_recv = last_seen.clone();
+    let ping_interval = Duration::from_secs(ctx.config.server.ws_ping_interval_seconds.max(1));
+    let pong_timeout = Duration::from_secs(ctx.config.server.ws_pong_timeout_seconds);
+
+    // Frames awaiting a client ack, shared so the receive task can tell which channel an ack belongs to
+    let pending_acks = Arc::new(Mutex::new(PendingAcks::default()));
+    let pending_acks_recv = pending_acks.clone();
+
+    // Bounded, so a client that reads slowly can't make the reader buffer its streams without limit
+    let queue = Arc::new(SendQueue::new(ctx.config.server.ws_send_queue_capacity));
+    let queue_write = queue.clone();
+    let queue_closing = queue.clone();
+    let queue_reply = queue.clone();
+    let max_backlog = Duration::from_secs(ctx.config.server.ws_max_backlog_seconds);
+
+    // Spawn task to read the chat and notification streams into the send queue
+    let mut read_task = tokio::spawn(async move {
+        // Resume after the last entry a previous connection delivered, unless the client asked for a replay
+        let stored = match get_connection(&ctx_read.redis_pool).await {
+            Ok(mut c) => ws_cursor::load(&mut c, &cursor_key, &[CHAT_CHANNEL, NOTIFY_CHANNEL]).await,
+            Err(e) => Err(e),
+        }
+        .unwrap_or_else(|e| {
+            tracing::warn!("Failed to load WebSocket cursor for {}, replaying retained stream: {}", user_address_read, e);
+            vec![None, None]
+        });
+        let mut start = ws_cursor::start_ids(since.as_deref(), stored).into_iter();
+        let mut chat_last_id = start.next().unwrap_or_else(|| "0".to_string());
+        let mut notify_last_id = start.next().unwrap_or_else(|| "0".to_string());
+
+        // One connection for all reads, replaced only after it fails
+        let mut redis = ReaderConnection::default();
         loop {
-            let mut redis_conn = match get_connection(&ctx_send.redis_pool).await {
-                Ok(c) => c,
-                Err(e) => {
-                    tracing::error!("Failed to get Redis connection: {}", e);
-                    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-                    continue;
-                }
+            let backlog = queue.backlog_age();
+            if backlog >= max_backlog {
+                tracing::info!(
+                    "Closing WebSocket for slow client {}: {} frames unsent for {:?}",
+                    user_address_read,
+                    queue.len(),
+                    backlog
+                );
+                return;
+            }
+
+            // Leave entries in Redis until the writer makes room; the stream is the real buffer
+            let room = queue.room();
+            if room == 0 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+
+            let Some(redis_conn) = redis.get(|| get_connection(&ctx_read.redis_pool)).await else {
+                break;
             };
-            
-            // Read from Redis stream
-            let result: Result<Vec<(String, Vec<(String, Vec<(String, String)>)>)>, redis::RedisError> = redis::cmd("XREAD")
+
+
+            // Read from both Redis streams
+            let result: Result<ws_cursor::StreamReadReply, redis::RedisError> = redis::cmd("XREAD")
+                .arg("COUNT")
+                .arg(room)
                 .arg("BLOCK")
                 .arg(1000) // Block for 1 second
                 .arg("STREAMS")
-                .arg(&stream_key)
-                .arg(&last_id)
-                .query_async(&mut redis_conn)
+                .arg(&chat_key)
+                .arg(&notify_key)
+                // Streams are trimmed and expire, so a stored id may be older than the first entry;
+                // XREAD then simply resumes from the oldest entry still retained
+                .arg(&chat_last_id)
+                .arg(&notify_last_id)
+                .query_async(redis_conn)
                 .await;
-            
+
             match result {
                 Ok(streams) => {
-                    for (_, messages) in streams {
+                    redis.succeeded();
+                    for (stream_key, messages) in streams {
+                        let (channel, last_id) = if stream_key == chat_key {
+                            (CHAT_CHANNEL, &mut chat_last_id)
+                        } else {
+                            (NOTIFY_CHANNEL, &mut notify_last_id)
+                        };
+
                         for (msg_id, fields) in messages {
-                            last_id = msg_id;
-                            
-                            // Find the "data" field - fields is Vec<(String, String)>
-                            let mut data_value = None;
-                            for (i, (key, value)) in fields.iter().enumerate() {
-                                if key == "data" && i + 1 < fields.len() {
-                                    data_value = Some(&fields[i + 1].1);
-                                    break;
-                                }
-                            }
-                            
-                            if let Some(data) = data_value {
-                                // Send to WebSocket
-                                if let Err(e) = sender.send(axum::extract::ws::Message::Text(data.clone())).await {
-                                    tracing::error!("Failed to send WebSocket message: {}", e);
-                                    return;
+                            if let Some(text) = envelope(channel, &msg_id, &fields) {
+                                let frame = OutboundFrame {
+                                    channel,
+                                    ephemeral: ws_outbox::is_ephemeral(&text),
+                                    message_id: (channel == CHAT_CHANNEL).then(|| delivered_message_id(&fields)).flatten(),
+                                    stream_id: msg_id.clone(),
+                                    text,
+                                };
+                                match queue.push(frame) {
+                                    Enqueued::Queued => {}
+                                    Enqueued::Shed => tracing::debug!("Shed an ephemeral frame for slow client {}", user_address_read),
+                                    // Read it again once there's room
+                                    Enqueued::Full => break,
                                 }
                             }
+                            *last_id = msg_id;
                         }
                     }
                 }
                 Err(e) if e.kind() == redis::ErrorKind::TypeError => {
                     // No messages, continue
+                    redis.succeeded();
                     continue;
                 }
                 Err(e) => {
-                    tracing::error!("Redis stream read error: {}", e);
-                    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                    tracing::warn!("Redis stream read for {} failed, reconnecting: {}", user_address_read, e);
+                    if !redis.failed().await {
+                        break;
+                    }
+                }
+            }
+        }
+
+        tracing::warn!("Closing WebSocket for {}: Redis unavailable for {:?}", user_address_read, REDIS_GIVE_UP_AFTER);
+        queue.close_with(CloseCode::Again.into(), "redis unavailable");
+    });
+
+    // Spawn task to send queued frames and pings to the WebSocket
+    let mut write_task = tokio::spawn(async move {
+        let cursor_ttl = ctx_write.config.redis.stream_ttl_seconds;
+        // Last entry per channel that actually reached the client, saved as the cursor once caught up
+        let mut unsaved: Vec<(&'static str, String)> = Vec::new();
+        let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + ping_interval, ping_interval);
+
+        loop {
+            tokio::select! {
+                frame = queue_write.pop() => {
+                    let Some(frame) = frame else {
+                        if let Some((code, reason)) = queue_write.close_reason() {
+                            let close = CloseFrame { code: code.into(), reason: reason.into() };
+                            let _ = sender.send(Message::Close(Some(close))).await;
+                        }
+                        break;
+                    };
+
+                    if let Err(e) = sender.send(Message::Text(frame.text)).await {
+                        tracing::error!("Failed to send WebSocket message: {}", e);
+                        break;
+                    }
+
+                    // In ack mode the stored cursor waits for the client instead; replies come from no stream
+                    match (frame.channel, ack_mode) {
+                        (REPLY_CHANNEL, _) => {}
+                        (_, true) => {
+                            if let Ok(mut pending) = pending_acks.lock() {
+                                pending.record(frame.channel, &frame.stream_id);
+                            }
+                        }
+                        (_, false) => {
+                            unsaved.retain(|(channel, _)| *channel != frame.channel);
+                            unsaved.push((frame.channel, frame.stream_id));
+                        }
+                    }
+
+                    if let Some(message_id) = frame.message_id {
+                        mark_message_delivered(&ctx_write, message_id).await;
+                    }
+
+                    if queue_write.is_empty() {
+                        save_cursors(&ctx_write, &cursor_key_write, &mut unsaved, cursor_ttl).await;
+                    }
+                }
+                _ = ping.tick() => {
+                    let idle = last_seen.lock().map(|t| t.elapsed()).unwrap_or_default();
+                    if idle >= pong_timeout {
+                        tracing::info!("Closing unresponsive WebSocket for user {} (idle {:?})", user_address_write, idle);
+                        let _ = sender.send(Message::Close(None)).await;
+                        break;
+                    }
+
+                    if let Err(e) = sender.send(Message::Ping(Vec::new())).await {
+                        tracing::debug!("Failed to send WebSocket ping: {}", e);
+                        break;
+                    }
                 }
             }
         }
+
+        save_cursors(&ctx_write, &cursor_key_write, &mut unsaved, cursor_ttl).await;
     });
     
-    // Handle incoming WebSocket messages (heartbeats, etc.)
+    // Handle incoming WebSocket messages (heartbeats, acks and sends)
     let mut recv_task = tokio::spawn(async move {
         while let Some(msg) = receiver.next().await {
+            if let Ok(mut seen) = last_seen_recv.lock() {
+                *seen = Instant::now();
+            }
+
             match msg {
-                Ok(axum::extract::ws::Message::Ping(_)) => {
-                    // Update heartbeat
-                    let mut conn = match ctx_recv.db_pool.get().await {
-                        Ok(c) => c,
-                        Err(_) => continue,
-                    };
-                    
-                    diesel::update(relay_ws_connections::table)
-                        .filter(relay_ws_connections::connection_id.eq(&connection_id_recv))
-                        .set(relay_ws_connections::last_heartbeat_at.eq(Utc::now()))
-                        .execute(&mut conn)
-                        .await
-                        .ok();
+                Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => {
+                    touch_heartbeat(&ctx_recv, &user_address_recv, &connection_id_recv).await;
                 }
-                Ok(axum::extract::ws::Message::Close(_)) => {
+                Ok(Message::Text(text)) => {
+                    if let Some(ack) = ws_cursor::parse_ack(&text) {
+                        let channel = pending_acks_recv.lock().ok().and_then(|mut pending| pending.acknowledge(&ack));
+                        match channel {
+                            Some(channel) => save_ack(&ctx_recv, &cursor_key_recv, channel, &ack.id).await,
+                            None => tracing::debug!("Ignoring ack for unknown frame {} from {}", ack.id, user_address_recv),
+                        }
+                    } else if let Some((client_id, req)) = parse_send(&text) {
+                        let reply = send_from_socket(&ctx_recv, &user_address_recv, client_id, req).await;
+                        if queue_reply.push(OutboundFrame::reply(reply)) == Enqueued::Full {
+                            tracing::debug!("Dropped a send reply for slow client {}", user_address_recv);
+                        }
+                    }
+                }
+                Ok(Message::Close(_)) | Err(_) => {
                     break;
                 }
                 _ => {}
             }
         }
-        
-        // Mark connection as disconnected
-        let mut conn = match ctx_recv.db_pool.get().await {
-            Ok(c) => c,
-            Err(_) => return,
-        };
-        
-        diesel::update(relay_ws_connections::table)
-            .filter(relay_ws_connections::connection_id.eq(&connection_id_recv))
-            .set(relay_ws_connections::disconnected_at.eq(Utc::now()))
-            .execute(&mut conn)
-            .await
-            .ok();
     });
     
-    // Wait for either task to complete
+    // Wait for any task to complete, then stop the others so a dead or slow client can't hold the socket open
     tokio::select! {
-        _ = &mut send_task => {}
+        _ = &mut read_task => {
+            // A reader that gave up on Redis left a Close frame for the writer to send
+            if queue_closing.close_reason().is_some() {
+                let _ = tokio::time::timeout(Duration::from_secs(1), &mut write_task).await;
+            }
+        }
+        _ = &mut write_task => {}
         _ = &mut recv_task => {}
     }
+    read_task.abort();
+    write_task.abort();
+    recv_task.abort();
+
+    mark_disconnected(&ctx, &user_address, &connection_id).await;
     
     tracing::info!("WebSocket connection closed for user: {}", user_address);
 }
+
+/// Retry delays while the stream reader can't reach Redis
+#[derive(Debug, Default)]
+struct ReconnectBackoff {
+    failures: u32,
+    failing_since: Option<Instant>,
+}
+
+impl ReconnectBackoff {
+    /// How long to wait after a failure at `now`; None once Redis has been failing for `REDIS_GIVE_UP_AFTER`
+    fn failed(&mut self, now: Instant) -> Option<Duration> {
+        let since = *self.failing_since.get_or_insert(now);
+        if now.duration_since(since) >= REDIS_GIVE_UP_AFTER {
+            return None;
+        }
+        let delay = RECONNECT_BASE_DELAY.saturating_mul(1 << self.failures.min(5)).min(RECONNECT_MAX_DELAY);
+        self.failures += 1;
+        Some(delay)
+    }
+}
+
+/// The stream reader's Redis connection, held across reads and re-established with backoff after errors
+struct ReaderConnection<C> {
+    conn: Option<C>,
+    backoff: ReconnectBackoff,
+}
+
+impl<C> Default for ReaderConnection<C> {
+    fn default() -> Self {
+        Self { conn: None, backoff: ReconnectBackoff::default() }
+    }
+}
+
+impl<C> ReaderConnection<C> {
+    /// The held connection, connecting with `connect` first if there is none; None once Redis has been
+    /// unreachable for too long
+    async fn get<F, Fut, E>(&mut self, mut connect: F) -> Option<&mut C>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<C, E>>,
+        E: Display,
+    {
+        while self.conn.is_none() {
+            match connect().await {
+                Ok(conn) => self.conn = Some(conn),
+                Err(e) => {
+                    tracing::warn!("Failed to get Redis connection: {}", e);
+                    if !self.wait().await {
+                        return None;
+                    }
+                }
+            }
+        }
+        self.conn.as_mut()
+    }
+
+    /// A read failed: drop the connection and wait before reconnecting; false once it's time to give up
+    async fn failed(&mut self) -> bool {
+        self.conn = None;
+        self.wait().await
+    }
+
+    fn succeeded(&mut self) {
+        self.backoff = ReconnectBackoff::default();
+    }
+
+    async fn wait(&mut self) -> bool {
+        match self.backoff.failed(Instant::now()) {
+            Some(delay) => {
+                tokio::time::sleep(delay).await;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Save the last sent entry of each channel as its cursor
+async fn save_cursors(ctx: &RelayContext, cursor_key: &str, unsaved: &mut Vec<(&'static str, String)>, ttl: u64) {
+    if unsaved.is_empty() {
+        return;
+    }
+
+    let mut conn = match get_connection(&ctx.redis_pool).await {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::warn!("Failed to save WebSocket cursor {}: {}", cursor_key, e);
+            return;
+        }
+    };
+    for (channel, id) in unsaved.drain(..) {
+        if let Err(e) = ws_cursor::advance(&mut conn, cursor_key, channel, &id, ttl).await {
+            tracing::warn!("Failed to save WebSocket cursor {}: {}", cursor_key, e);
+        }
+    }
+}
+
+/// Persist an acked stream id as the channel's cursor; the cursor never moves backwards
+async fn save_ack(ctx: &RelayContext, cursor_key: &str, channel: &str, id: &str) {
+    let result = match get_connection(&ctx.redis_pool).await {
+        Ok(mut conn) => ws_cursor::advance(&mut conn, cursor_key, channel, id, ctx.config.redis.stream_ttl_seconds).await,
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        tracing::warn!("Failed to save acked WebSocket cursor {}: {}", cursor_key, e);
+    }
+}
+
+async fn touch_heartbeat(ctx: &RelayContext, user_address: &str, connection_id: &str) {
+    if let Err(e) = presence::heartbeat(ctx, user_address, connection_id).await {
+        tracing::debug!("Failed to refresh presence for {}: {}", user_address, e);
+    }
+
+    let mut conn = match ctx.db_pool.get().await {
+        Ok(c) => c,
+        Err(_) => return,
+    };
+
+    diesel::update(relay_ws_connections::table)
+        .filter(relay_ws_connections::connection_id.eq(connection_id))
+        .set(relay_ws_connections::last_heartbeat_at.eq(Utc::now()))
+        .execute(&mut conn)
+        .await
+        .ok();
+}
+
+async fn mark_disconnected(ctx: &RelayContext, user_address: &str, connection_id: &str) {
+    if let Err(e) = presence::disconnect(ctx, user_address, connection_id).await {
+        tracing::warn!("Failed to clear presence for {}: {}", user_address, e);
+    }
+
+    let mut conn = match ctx.db_pool.get().await {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::error!("Failed to get DB connection: {}", e);
+            return;
+        }
+    };
+
+    diesel::update(relay_ws_connections::table)
+        .filter(relay_ws_connections::connection_id.eq(connection_id))
+        .set(relay_ws_connections::disconnected_at.eq(Utc::now()))
+        .execute(&mut conn)
+        .await
+        .ok();
+}
+
+/// Id of the chat message carried by a stream entry, if it is a new-message event
+fn delivered_message_id(fields: &[(String, String)]) -> Option<i64> {
+    let data = fields.iter().find(|(key, _)| key == "data").map(|(_, value)| value)?;
+    let payload: serde_json::Value = serde_json::from_str(data).ok()?;
+
+    if payload.get("type").and_then(|v| v.as_str()) != Some("message") {
+        return None;
+    }
+    payload.get("message_id").and_then(|v| v.as_i64())
+}
+
+async fn mark_message_delivered(ctx: &RelayContext, message_id: i64) {
+    let mut conn = match ctx.db_pool.get().await {
+        Ok(c) => c,
+        Err(_) => return,
+    };
+
+    diesel::update(
+        relay_messages::table
+            .filter(relay_messages::id.eq(message_id))
+            .filter(relay_messages::delivered_at.is_null()),
+    )
+    .set(relay_messages::delivered_at.eq(Utc::now()))
+    .execute(&mut conn)
+    .await
+    .ok();
+}
+
+/// Periodically mark connections whose heartbeat went stale as disconnected
+/// Covers sockets dropped without a Close frame and rows left behind by a crashed API instance.
+/// Only the instance holding the `ws-reaper` lock reaps
+pub async fn reap_stale_connections(ctx: RelayContext) {
+    lock::with_leader_lock(&ctx, "ws-reaper", || reap_forever(&ctx)).await
+}
+
+async fn reap_forever(ctx: &RelayContext) {
+    let stale_after = chrono::Duration::seconds(ctx.config.server.ws_stale_connection_seconds as i64);
+    let mut interval = tokio::time::interval(Duration::from_secs(ctx.config.server.ws_ping_interval_seconds.max(1)));
+
+    loop {
+        interval.tick().await;
+
+        let mut conn = match ctx.db_pool.get().await {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::warn!("Connection reaper failed to get DB connection: {}", e);
+                continue;
+            }
+        };
+
+        let now = Utc::now();
+        match diesel::update(relay_ws_connections::table)
+            .filter(relay_ws_connections::disconnected_at.is_null())
+            .filter(relay_ws_connections::last_heartbeat_at.lt(now - stale_after))
+            .set(relay_ws_connections::disconnected_at.eq(now))
+            .execute(&mut conn)
+            .await
+        {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("Marked {} stale WebSocket connections as disconnected", n),
+            Err(e) => tracing::warn!("Failed to reap stale WebSocket connections: {}", e),
+        }
+    }
+}
+
+/// A `{"type":"message", ...}` frame from the client, carrying the body of `POST /api/v1/messages` and an
+/// optional `client_id` to match its reply; the request is Err when the frame doesn't have that shape
+fn parse_send(text: &str) -> Option<(Option<String>, Result<SendMessageRequest, ApiError>)> {
+    let frame: serde_json::Value = serde_json::from_str(text).ok()?;
+    if frame.get("type").and_then(|v| v.as_str()) != Some("message") {
+        return None;
+    }
+
+    let client_id = frame.get("client_id").and_then(|v| v.as_str()).map(str::to_string);
+    let req = serde_json::from_value(frame).map_err(|e| ApiError::bad_request("invalid_message", e.to_string()));
+    Some((client_id, req))
+}
+
+/// Send a message from the socket's user through the same pipeline and rate limit as `POST /api/v1/messages`.
+/// The reply is `{"type":"message.sent","conversation_id","message_id"}`, or `{"type":"message.failed","error",
+/// "message"}` with the error the HTTP send would have returned; either echoes `client_id`
+async fn send_from_socket(
+    ctx: &RelayContext,
+    sender: &str,
+    client_id: Option<String>,
+    req: Result<SendMessageRequest, ApiError>,
+) -> String {
+    let sent = match req {
+        Ok(req) => match take_send_message_tokens(ctx, sender, 1).await {
+            RateLimitDecision::Allowed => send_message_from(ctx, sender, &req).await,
+            RateLimitDecision::Limited { retry_after } => Err(ApiError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+                "Too many messages, please retry later",
+            )
+            .with_details(serde_json::json!({"retry_after": retry_after}))),
+        },
+        Err(e) => Err(e),
+    };
+
+    send_reply(client_id, sent).to_string()
+}
+
+fn send_reply(client_id: Option<String>, sent: Result<serde_json::Value, ApiError>) -> serde_json::Value {
+    let mut reply = match sent {
+        Ok(sent) => serde_json::json!({
+            "type": "message.sent",
+            "conversation_id": sent["conversation_id"],
+            "message_id": sent["message_id"],
+        }),
+        Err(e) => serde_json::json!({"type": "message.failed", "error": e.code, "message": e.message}),
+    };
+    if let Some(client_id) = client_id {
+        reply["client_id"] = serde_json::Value::from(client_id);
+    }
+    reply
+}
+
+/// First frame on every socket; `unread` has the shape of `GET /api/v1/notifications/counts` and is
+/// null when Redis couldn't be read
+fn connected_frame(connection_id: &str, unread: Option<serde_json::Value>) -> String {
+    serde_json::json!({
+        "type": "connected",
+        "connection_id": connection_id,
+        "unread": unread,
+    })
+    .to_string()
+}
+
+/// Wrap a stream entry's `data` payload as `{"channel": ..., "stream_id": ..., ...payload}` so clients can tell
+/// chat and notify events apart and ack them
+fn envelope(channel: &str, stream_id: &str, fields: &[(String, String)]) -> Option<String> {
+    let data = fields.iter().find(|(key, _)| key == "data").map(|(_, value)| value)?;
+
+    let body = match serde_json::from_str::<serde_json::Value>(data) {
+        Ok(serde_json::Value::Object(mut payload)) => {
+            payload.insert("channel".to_string(), serde_json::Value::from(channel));
+            payload.insert("stream_id".to_string(), serde_json::Value::from(stream_id));
+            serde_json::Value::Object(payload)
+        }
+        Ok(other) => serde_json::json!({"channel": channel, "stream_id": stream_id, "data": other}),
+        Err(_) => serde_json::json!({"channel": channel, "stream_id": stream_id, "data": data}),
+    };
+
+    Some(body.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_tags_channel() {
+        let fields = vec![("data".to_string(), r#"{"type":"notification","notification":{"id":"n1"}}"#.to_string())];
+        let wrapped: serde_json::Value = serde_json::from_str(&envelope(NOTIFY_CHANNEL, "5-0", &fields).unwrap()).unwrap();
+
+        assert_eq!(wrapped["channel"], "notify");
+        assert_eq!(wrapped["stream_id"], "5-0");
+        assert_eq!(wrapped["type"], "notification");
+        assert_eq!(wrapped["notification"]["id"], "n1");
+    }
+
+    #[test]
+    fn test_delivered_message_id_only_for_new_messages() {
+        let message = vec![("data".to_string(), r#"{"type":"message","message_id":42}"#.to_string())];
+        assert_eq!(delivered_message_id(&message), Some(42));
+
+        let deleted = vec![("data".to_string(), r#"{"type":"message.deleted","message_id":42}"#.to_string())];
+        assert_eq!(delivered_message_id(&deleted), None);
+    }
+
+    #[test]
+    fn test_connected_frame() {
+        let unread = serde_json::json!({"total_unread": 3, "platform_counts": {"p1": 2}});
+        let frame: serde_json::Value = serde_json::from_str(&connected_frame("c1", Some(unread.clone()))).unwrap();
+        assert_eq!(frame, serde_json::json!({"type": "connected", "connection_id": "c1", "unread": unread}));
+
+        let frame: serde_json::Value = serde_json::from_str(&connected_frame("c1", None)).unwrap();
+        assert!(frame["unread"].is_null());
+    }
+
+    #[test]
+    fn test_envelope_requires_data_field() {
+        let fields = vec![("other".to_string(), "x".to_string())];
+        assert!(envelope(CHAT_CHANNEL, "5-0", &fields).is_none());
+    }
+
+    #[test]
+    fn test_token_read_from_subprotocol() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(protocol_token(&headers), None);
+
+        headers.insert(SEC_WEBSOCKET_PROTOCOL, "bearer, eyJhbGciOi.e30.c2ln".parse().unwrap());
+        assert_eq!(protocol_token(&headers).as_deref(), Some("eyJhbGciOi.e30.c2ln"));
+
+        // The marker alone, or another app's subprotocol, carries no token
+        headers.insert(SEC_WEBSOCKET_PROTOCOL, "bearer".parse().unwrap());
+        assert_eq!(protocol_token(&headers), None);
+        headers.insert(SEC_WEBSOCKET_PROTOCOL, "graphql-ws".parse().unwrap());
+        assert_eq!(protocol_token(&headers), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables, and Redis at REDIS_URL"]
+    async fn test_upgrade_with_token_in_subprotocol() {
+        use crate::auth::generate_token;
+        use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Error};
+
+        let ctx = RelayContext::new(relay_core::Config::from_env()).await.unwrap();
+        let keys = Arc::new(JwtKeys::from_config(&ctx.config.server).unwrap());
+        let token = generate_token(&format!("0xws-{}", Uuid::new_v4()), vec![], &keys, 1).unwrap().token;
+
+        let app = axum::Router::new()
+            .route("/ws", axum::routing::get(websocket_handler))
+            .layer(Extension(keys))
+            .layer(Extension(ctx));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/ws", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>()).await
+        });
+
+        let mut request = url.as_str().into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert(SEC_WEBSOCKET_PROTOCOL, format!("{}, {}", AUTH_PROTOCOL, token).parse().unwrap());
+        let (mut socket, response) = tokio_tungstenite::connect_async(request).await.unwrap();
+        assert_eq!(response.headers()[SEC_WEBSOCKET_PROTOCOL], AUTH_PROTOCOL);
+
+        let first = socket.next().await.unwrap().unwrap();
+        let frame: serde_json::Value = serde_json::from_str(first.to_text().unwrap()).unwrap();
+        assert_eq!(frame["type"], "connected");
+        socket.close(None).await.unwrap();
+
+        // Neither the header nor the query param: the upgrade is refused
+        match tokio_tungstenite::connect_async(url).await {
+            Err(Error::Http(response)) => assert_eq!(response.status(), 401),
+            other => panic!("expected 401, got {:?}", other.map(|(_, response)| response.status())),
+        }
+    }
+
+    #[test]
+    fn test_send_frames_parsed_and_answered() {
+        let (client_id, req) = parse_send(r#"{"type":"message","client_id":"c-1","recipient_address":"0xbob","content":"hi"}"#).unwrap();
+        let req = req.unwrap();
+        assert_eq!(client_id.as_deref(), Some("c-1"));
+        assert_eq!((req.recipient_address.as_str(), req.content.as_str()), ("0xbob", "hi"));
+
+        // A message frame without a recipient is answered with an error rather than ignored
+        let (_, invalid) = parse_send(r#"{"type":"message","content":"hi"}"#).unwrap();
+        let reply = send_reply(None, invalid.map(|_| serde_json::Value::Null));
+        assert_eq!((reply["type"].as_str(), reply["error"].as_str()), (Some("message.failed"), Some("invalid_message")));
+        assert!(reply.get("client_id").is_none());
+
+        // Acks and other frames aren't sends
+        assert!(parse_send(r#"{"type":"ack","id":"5-0"}"#).is_none());
+        assert!(parse_send("not json").is_none());
+
+        let sent = serde_json::json!({"status": "ok", "conversation_id": "0xalice:0xbob", "message_id": 7});
+        let reply = send_reply(Some("c-1".to_string()), Ok(sent));
+        assert_eq!(
+            reply,
+            serde_json::json!({"type": "message.sent", "conversation_id": "0xalice:0xbob", "message_id": 7, "client_id": "c-1"})
+        );
+    }
+
+    async fn next_json<S, E>(socket: &mut S) -> serde_json::Value
+    where
+        S: futures_util::Stream<Item = Result<Message, E>> + Unpin,
+        E: std::fmt::Debug,
+    {
+        let frame = tokio::time::timeout(Duration::from_secs(10), socket.next()).await.unwrap().unwrap().unwrap();
+        serde_json::from_str(frame.to_text().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables, Redis at REDIS_URL and Redpanda, with the messaging consumer running"]
+    async fn test_message_sent_over_socket_reaches_recipient() {
+        use crate::auth::generate_token;
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let ctx = RelayContext::new(relay_core::Config::from_env()).await.unwrap();
+        let keys = Arc::new(JwtKeys::from_config(&ctx.config.server).unwrap());
+        let run = Uuid::new_v4();
+        let (alice, bob) = (format!("0xalice-{}", run), format!("0xbob-{}", run));
+
+        let app = axum::Router::new()
+            .route("/ws", axum::routing::get(websocket_handler))
+            .layer(Extension(keys.clone()))
+            .layer(Extension(ctx.clone()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/ws", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>()).await
+        });
+        let connect = |user: &str| {
+            let token = generate_token(user, vec![], &keys, 1).unwrap().token;
+            let mut request = url.as_str().into_client_request().unwrap();
+            request
+                .headers_mut()
+                .insert(SEC_WEBSOCKET_PROTOCOL, format!("{}, {}", AUTH_PROTOCOL, token).parse().unwrap());
+            tokio_tungstenite::connect_async(request)
+        };
+        let (mut alice_socket, _) = connect(&alice).await.unwrap();
+        let (mut bob_socket, _) = connect(&bob).await.unwrap();
+        assert_eq!(next_json(&mut alice_socket).await["type"], "connected");
+        assert_eq!(next_json(&mut bob_socket).await["type"], "connected");
+
+        let send = serde_json::json!({"type": "message", "client_id": "c-1", "recipient_address": bob, "content": "over the socket"});
+        alice_socket.send(Message::Text(send.to_string())).await.unwrap();
+        let reply = next_json(&mut alice_socket).await;
+        let delivered = next_json(&mut bob_socket).await;
+        alice_socket.close(None).await.unwrap();
+        bob_socket.close(None).await.unwrap();
+
+        let mut conn = ctx.db_pool.get().await.unwrap();
+        let stored: Vec<(String, String)> = relay_messages::table
+            .filter(relay_messages::sender_address.eq(&alice))
+            .select((relay_messages::recipient_address, relay_messages::conversation_id))
+            .load(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(relay_messages::table.filter(relay_messages::sender_address.eq(&alice)))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        assert_eq!((reply["type"].as_str(), reply["client_id"].as_str()), (Some("message.sent"), Some("c-1")));
+        assert_eq!(stored.len(), 1);
+        assert_eq!((stored[0].0.as_str(), stored[0].1.as_str()), (bob.as_str(), reply["conversation_id"].as_str().unwrap()));
+        assert_eq!(delivered["channel"], CHAT_CHANNEL);
+        assert_eq!(delivered["type"], "message");
+        // The recipient gets the row the sender was acked with, not a copy stored by the consumer
+        assert!(reply["message_id"].is_i64());
+        assert_eq!(delivered["message_id"], reply["message_id"]);
+        assert_eq!(delivered["content"], "over the socket");
+    }
+
+    #[tokio::test]
+    async fn test_reader_reconnects_after_transient_redis_failure() {
+        // Redis refuses the first two connections, then comes back
+        let attempts = std::cell::Cell::new(0);
+        let connect = || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move { if attempt <= 2 { Err("connection refused") } else { Ok(attempt) } }
+        };
+
+        let mut redis = ReaderConnection::default();
+        assert_eq!(redis.get(connect).await.copied(), Some(3));
+        assert_eq!(redis.backoff.failures, 2);
+
+        // Later reads reuse the connection, and a successful read resets the backoff
+        assert_eq!(redis.get(connect).await.copied(), Some(3));
+        assert_eq!(attempts.get(), 3);
+        redis.succeeded();
+
+        // A failed read drops the connection; the next read reconnects after the shortest delay
+        assert!(redis.failed().await);
+        assert_eq!(redis.get(connect).await.copied(), Some(4));
+        assert_eq!(redis.backoff.failures, 1);
+    }
+
+    #[test]
+    fn test_backoff_gives_up_on_persistent_failure() {
+        let mut backoff = ReconnectBackoff::default();
+        let start = Instant::now();
+
+        let delays: Vec<_> = (0..7).map(|_| backoff.failed(start).unwrap()).collect();
+        assert_eq!(delays[0], RECONNECT_BASE_DELAY);
+        assert_eq!(delays[1], RECONNECT_BASE_DELAY * 2);
+        assert_eq!(delays[6], RECONNECT_MAX_DELAY);
+
+        assert!(backoff.failed(start + REDIS_GIVE_UP_AFTER - Duration::from_secs(1)).is_some());
+        assert_eq!(backoff.failed(start + REDIS_GIVE_UP_AFTER), None);
+    }
+}
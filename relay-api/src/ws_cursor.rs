@@ -0,0 +1,300 @@
+use relay_core::redis::RedisConnection;
+use std::collections::VecDeque;
+
+pub const CHAT_CHANNEL: &str = "chat";
+pub const NOTIFY_CHANNEL: &str = "notify";
+
+/// Unacknowledged frames remembered per connection; older ones can still be acked with an explicit channel
+const MAX_PENDING_ACKS: usize = 1000;
+
+/// XREAD reply: (stream key, [(entry id, [(field, value)])])
+pub type StreamReadReply = Vec<(String, Vec<(String, Vec<(String, String)>)>)>;
+
+/// Stored ids only move forward, so an older connection for the same user can't rewind the cursor
+const ADVANCE_SCRIPT: &str = r#"
+local current = redis.call('HGET', KEYS[1], ARGV[1])
+if current then
+    local cur_ms, cur_seq = string.match(current, '^(%d+)-(%d+)$')
+    local new_ms, new_seq = string.match(ARGV[2], '^(%d+)-(%d+)$')
+    if cur_ms and new_ms then
+        cur_ms, cur_seq, new_ms, new_seq = tonumber(cur_ms), tonumber(cur_seq), tonumber(new_ms), tonumber(new_seq)
+        if cur_ms > new_ms or (cur_ms == new_ms and cur_seq >= new_seq) then
+            return 0
+        end
+    end
+end
+redis.call('HSET', KEYS[1], ARGV[1], ARGV[2])
+redis.call('EXPIRE', KEYS[1], ARGV[3])
+return 1
+"#;
+
+/// A Redis stream id, either `<ms>` or `<ms>-<seq>`
+pub fn is_stream_id(id: &str) -> bool {
+    let mut parts = id.splitn(2, '-');
+    let valid = |p: Option<&str>| p.is_some_and(|p| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()));
+
+    match (parts.next(), parts.next()) {
+        (ms, None) => valid(ms),
+        (ms, seq) => valid(ms) && valid(seq),
+    }
+}
+
+/// Where to start reading `channels`: an explicit `since` replays from that point on every channel,
+/// otherwise each channel resumes after its stored cursor, or from the start of the stream if it has none
+pub fn start_ids(since: Option<&str>, stored: Vec<Option<String>>) -> Vec<String> {
+    stored
+        .into_iter()
+        .map(|cursor| match since {
+            Some(since) => since.to_string(),
+            None => cursor.unwrap_or_else(|| "0".to_string()),
+        })
+        .collect()
+}
+
+/// Stored cursors for `channels`, in the same order. `cursor_key` is the `WS_CURSOR` hash from
+/// `RedisKeys::ws_cursor`, holding the last entry delivered on each channel so a reconnecting client
+/// resumes where it left off instead of replaying the whole stream
+pub async fn load(conn: &mut RedisConnection, cursor_key: &str, channels: &[&str]) -> anyhow::Result<Vec<Option<String>>> {
+    let cursors = redis::cmd("HMGET")
+        .arg(cursor_key)
+        .arg(channels)
+        .query_async(conn)
+        .await?;
+
+    Ok(cursors)
+}
+
+/// Record that `id` on `channel` reached the client; call only after the frame was sent
+pub async fn advance(
+    conn: &mut RedisConnection,
+    cursor_key: &str,
+    channel: &str,
+    id: &str,
+    ttl_seconds: u64,
+) -> anyhow::Result<()> {
+    redis::Script::new(ADVANCE_SCRIPT)
+        .key(cursor_key)
+        .arg(channel)
+        .arg(id)
+        .arg(ttl_seconds)
+        .invoke_async::<i64>(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Inbound `{"type":"ack","id":"<stream id>"}` frame; `channel` may be echoed from the acked frame
+#[derive(Debug, PartialEq)]
+pub struct Ack {
+    pub id: String,
+    pub channel: Option<&'static str>,
+}
+
+pub fn parse_ack(text: &str) -> Option<Ack> {
+    let frame: serde_json::Value = serde_json::from_str(text).ok()?;
+    if frame.get("type").and_then(|v| v.as_str()) != Some("ack") {
+        return None;
+    }
+
+    let id = frame.get("id").and_then(|v| v.as_str()).filter(|id| is_stream_id(id))?;
+    let channel = match frame.get("channel").and_then(|v| v.as_str()) {
+        None => None,
+        Some(CHAT_CHANNEL) => Some(CHAT_CHANNEL),
+        Some(NOTIFY_CHANNEL) => Some(NOTIFY_CHANNEL),
+        Some(_) => return None,
+    };
+
+    Some(Ack { id: id.to_string(), channel })
+}
+
+/// Frames sent on a connection but not yet acknowledged, in send order
+/// Lets a bare ack id be matched to the channel whose cursor it advances
+#[derive(Debug, Default)]
+pub struct PendingAcks {
+    sent: VecDeque<(String, &'static str)>,
+}
+
+impl PendingAcks {
+    pub fn record(&mut self, channel: &'static str, id: &str) {
+        if self.sent.len() >= MAX_PENDING_ACKS {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((id.to_string(), channel));
+    }
+
+    /// Channel of an acked frame; acks are cumulative, so earlier frames on that channel are dropped too
+    pub fn acknowledge(&mut self, ack: &Ack) -> Option<&'static str> {
+        let position = self.sent.iter().position(|(id, channel)| {
+            id == &ack.id && ack.channel.is_none_or(|c| c == *channel)
+        });
+
+        let (position, channel) = match position {
+            Some(pos) => (pos, self.sent[pos].1),
+            None => return ack.channel,
+        };
+
+        let mut index = 0;
+        self.sent.retain(|(_, c)| {
+            let keep = *c != channel || index > position;
+            index += 1;
+            keep
+        });
+
+        Some(channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_id_validation() {
+        assert!(is_stream_id("1700000000000-0"));
+        assert!(is_stream_id("1700000000000"));
+        assert!(is_stream_id("0"));
+        assert!(!is_stream_id(""));
+        assert!(!is_stream_id("1700000000000-"));
+        assert!(!is_stream_id("$"));
+        assert!(!is_stream_id("abc-1"));
+    }
+
+    #[test]
+    fn test_start_ids_prefer_since_then_cursor() {
+        let stored = vec![Some("5-0".to_string()), None];
+
+        assert_eq!(start_ids(None, stored.clone()), vec!["5-0", "0"]);
+        assert_eq!(start_ids(Some("3-1"), stored), vec!["3-1", "3-1"]);
+    }
+
+    #[test]
+    fn test_parse_ack() {
+        assert_eq!(
+            parse_ack(r#"{"type":"ack","id":"1700000000000-1"}"#),
+            Some(Ack { id: "1700000000000-1".to_string(), channel: None })
+        );
+        assert_eq!(
+            parse_ack(r#"{"type":"ack","id":"5-0","channel":"notify"}"#),
+            Some(Ack { id: "5-0".to_string(), channel: Some(NOTIFY_CHANNEL) })
+        );
+        assert_eq!(parse_ack(r#"{"type":"ack","id":"$"}"#), None);
+        assert_eq!(parse_ack(r#"{"type":"ack","id":"5-0","channel":"other"}"#), None);
+        assert_eq!(parse_ack(r#"{"type":"typing"}"#), None);
+        assert_eq!(parse_ack("not json"), None);
+    }
+
+    #[test]
+    fn test_pending_acks_resolve_channel_cumulatively() {
+        let mut pending = PendingAcks::default();
+        pending.record(CHAT_CHANNEL, "1-0");
+        pending.record(NOTIFY_CHANNEL, "1-0");
+        pending.record(CHAT_CHANNEL, "2-0");
+        pending.record(CHAT_CHANNEL, "3-0");
+
+        // Same id on both streams: the explicit channel disambiguates
+        let ack = parse_ack(r#"{"type":"ack","id":"1-0","channel":"notify"}"#).unwrap();
+        assert_eq!(pending.acknowledge(&ack), Some(NOTIFY_CHANNEL));
+
+        // Acking 2-0 also settles 1-0 on chat, leaving only 3-0
+        let ack = parse_ack(r#"{"type":"ack","id":"2-0"}"#).unwrap();
+        assert_eq!(pending.acknowledge(&ack), Some(CHAT_CHANNEL));
+        assert_eq!(pending.sent.len(), 1);
+
+        let unknown = parse_ack(r#"{"type":"ack","id":"9-0"}"#).unwrap();
+        assert_eq!(pending.acknowledge(&unknown), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis at REDIS_URL"]
+    async fn test_ack_then_reconnect_replays_unacked_frames() {
+        let config = relay_core::config::RedisConfig {
+            url: std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_string()),
+            max_connections: 2,
+            stream_max_len: 1000,
+            stream_ttl_seconds: 60,
+            key_prefix: String::new(),
+            platform_namespaces: false,
+        };
+        let pool = relay_core::redis::create_pool(&config).await.unwrap();
+        let mut conn = relay_core::redis::get_connection(&pool).await.unwrap();
+        let user = format!("0xack-{}", uuid::Uuid::new_v4());
+        let stream = config.keys().chat_stream(&user);
+        let cursor = config.keys().ws_cursor(&user, None);
+
+        let mut ids = Vec::new();
+        for i in 0..3 {
+            ids.push(relay_core::redis::streams::xadd_stream(&mut conn, &config, &stream, &i.to_string()).await.unwrap());
+        }
+
+        // All three frames were sent, but the client only acknowledged the first two
+        let mut pending = PendingAcks::default();
+        for id in &ids {
+            pending.record(CHAT_CHANNEL, id);
+        }
+        let ack = parse_ack(&serde_json::json!({"type": "ack", "id": ids[1]}).to_string()).unwrap();
+        let channel = pending.acknowledge(&ack).unwrap();
+        advance(&mut conn, &cursor, channel, &ack.id, 60).await.unwrap();
+
+        // Reconnect: the unacknowledged third frame is replayed
+        let start = start_ids(None, load(&mut conn, &cursor, &[CHAT_CHANNEL]).await.unwrap());
+        let replay: StreamReadReply = redis::cmd("XREAD")
+            .arg("STREAMS")
+            .arg(&stream)
+            .arg(&start[0])
+            .query_async(&mut conn)
+            .await
+            .unwrap();
+        let replayed: Vec<&String> = replay[0].1.iter().map(|(id, _)| id).collect();
+
+        redis::cmd("DEL").arg(&stream).arg(&cursor).query_async::<()>(&mut conn).await.unwrap();
+
+        assert_eq!(replayed, vec![&ids[2]]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis at REDIS_URL"]
+    async fn test_reconnect_resumes_without_gap_or_replay() {
+        let config = relay_core::config::RedisConfig {
+            url: std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_string()),
+            max_connections: 2,
+            stream_max_len: 1000,
+            stream_ttl_seconds: 60,
+            key_prefix: String::new(),
+            platform_namespaces: false,
+        };
+        let pool = relay_core::redis::create_pool(&config).await.unwrap();
+        let mut conn = relay_core::redis::get_connection(&pool).await.unwrap();
+        let user = format!("0xcursor-{}", uuid::Uuid::new_v4());
+        let stream = config.keys().chat_stream(&user);
+        let cursor = config.keys().ws_cursor(&user, None);
+
+        let mut ids = Vec::new();
+        for i in 0..3 {
+            ids.push(relay_core::redis::streams::xadd_stream(&mut conn, &config, &stream, &i.to_string()).await.unwrap());
+        }
+
+        // First connection delivers the first two entries, then drops before the third is sent
+        let start = start_ids(None, load(&mut conn, &cursor, &["chat"]).await.unwrap());
+        assert_eq!(start, vec!["0"]);
+        advance(&mut conn, &cursor, "chat", &ids[0], 60).await.unwrap();
+        advance(&mut conn, &cursor, "chat", &ids[1], 60).await.unwrap();
+
+        // A stale write from another connection can't move the cursor back
+        advance(&mut conn, &cursor, "chat", &ids[0], 60).await.unwrap();
+
+        // Reconnect: XREAD from the stored cursor returns exactly the undelivered entry
+        let start = start_ids(None, load(&mut conn, &cursor, &["chat"]).await.unwrap());
+        let replay: StreamReadReply = redis::cmd("XREAD")
+            .arg("STREAMS")
+            .arg(&stream)
+            .arg(&start[0])
+            .query_async(&mut conn)
+            .await
+            .unwrap();
+        let replayed: Vec<&String> = replay[0].1.iter().map(|(id, _)| id).collect();
+
+        redis::cmd("DEL").arg(&stream).arg(&cursor).query_async::<()>(&mut conn).await.unwrap();
+
+        assert_eq!(replayed, vec![&ids[2]]);
+    }
+}
@@ -0,0 +1,611 @@
+use axum::{
+    async_trait,
+    body::Body,
+    extract::FromRequestParts,
+    http::{
+        header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE},
+        request::Parts,
+        HeaderMap, HeaderValue, Method, StatusCode,
+    },
+    response::Response,
+};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use hyper_util::rt::TokioIo;
+use std::future::Future;
+use std::io::{self, Cursor};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::{Data, OpCode};
+use tokio_tungstenite::tungstenite::protocol::frame::FrameHeader;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::WebSocketStream;
+use tracing;
+use crate::error::ApiError;
+
+pub const EXTENSION: &str = "permessage-deflate";
+
+/// Largest message inflated or buffered for compression, tungstenite's default `max_message_size`
+const MAX_MESSAGE_BYTES: usize = 64 << 20;
+/// Compressed bytes waiting for the client before writes wait for them to drain
+const WRITE_HIGH_WATER: usize = 64 * 1024;
+/// Every message compressed with a sync flush ends with these bytes, which RFC 7692 leaves off the wire
+const DEFLATE_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// A server socket, compressing frames when the client's `permessage-deflate` offer was accepted
+pub type WebSocket = WebSocketStream<DeflateStream<TokioIo<Upgraded>>>;
+
+/// What was agreed for `permessage-deflate`. Our window is always 15 bits, and the client may use any window
+/// or context takeover since one inflater handles them all
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DeflateConfig {
+    /// Reset the compressor after every message, as the client asked
+    server_no_context_takeover: bool,
+    /// The client asked for `server_max_window_bits=15`, which has to be echoed
+    server_max_window_bits: bool,
+}
+
+impl DeflateConfig {
+    /// The `Sec-WebSocket-Extensions` value accepting the offer
+    pub fn response(&self) -> String {
+        let mut response = EXTENSION.to_string();
+        if self.server_no_context_takeover {
+            response.push_str("; server_no_context_takeover");
+        }
+        if self.server_max_window_bits {
+            response.push_str("; server_max_window_bits=15");
+        }
+        response
+    }
+}
+
+/// The first `permessage-deflate` offer in `Sec-WebSocket-Extensions` that can be accepted. Offers asking
+/// for a server window under 15 bits, or with unknown or repeated parameters, are declined
+pub fn negotiate(headers: &HeaderMap) -> Option<DeflateConfig> {
+    headers
+        .get_all(SEC_WEBSOCKET_EXTENSIONS)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(accept_offer)
+}
+
+fn accept_offer(offer: &str) -> Option<DeflateConfig> {
+    let mut parts = offer.split(';').map(str::trim);
+    if !parts.next()?.eq_ignore_ascii_case(EXTENSION) {
+        return None;
+    }
+
+    let mut config = DeflateConfig::default();
+    let mut seen: Vec<String> = Vec::new();
+    for param in parts {
+        let (name, value) = match param.split_once('=') {
+            Some((name, value)) => (name.trim().to_ascii_lowercase(), Some(value.trim().trim_matches('"'))),
+            None => (param.to_ascii_lowercase(), None),
+        };
+        if seen.contains(&name) {
+            return None;
+        }
+        match (name.as_str(), value) {
+            ("server_no_context_takeover", None) => config.server_no_context_takeover = true,
+            ("client_no_context_takeover", None) => {}
+            ("server_max_window_bits", Some("15")) => config.server_max_window_bits = true,
+            ("client_max_window_bits", None) => {}
+            ("client_max_window_bits", Some(bits)) if matches!(bits.parse::<u8>(), Ok(8..=15)) => {}
+            _ => return None,
+        }
+        seen.push(name);
+    }
+
+    Some(config)
+}
+
+/// Like axum's `WebSocketUpgrade`, which can't answer extension offers, but also accepts `permessage-deflate`
+pub struct DeflateUpgrade {
+    key: HeaderValue,
+    on_upgrade: OnUpgrade,
+    offered_protocols: Option<HeaderValue>,
+    protocol: Option<&'static str>,
+    deflate: Option<DeflateConfig>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for DeflateUpgrade {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header_is = |name, value: &str| {
+            parts.headers.get(name).and_then(|v| v.to_str().ok()).is_some_and(|v| {
+                v.split(',').any(|token| token.trim().eq_ignore_ascii_case(value))
+            })
+        };
+        if parts.method != Method::GET {
+            return Err(ApiError::new(StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed", "WebSocket upgrades must be GET"));
+        }
+        if !header_is(CONNECTION, "upgrade") || !header_is(UPGRADE, "websocket") || !header_is(SEC_WEBSOCKET_VERSION, "13") {
+            return Err(ApiError::bad_request("invalid_upgrade", "Expected a version 13 WebSocket upgrade"));
+        }
+        let key = parts
+            .headers
+            .get(SEC_WEBSOCKET_KEY)
+            .cloned()
+            .ok_or_else(|| ApiError::bad_request("invalid_upgrade", "Sec-WebSocket-Key is missing"))?;
+        let on_upgrade = parts
+            .extensions
+            .remove::<OnUpgrade>()
+            .ok_or_else(|| ApiError::new(StatusCode::UPGRADE_REQUIRED, "upgrade_required", "This connection can't be upgraded"))?;
+
+        Ok(Self {
+            key,
+            on_upgrade,
+            offered_protocols: parts.headers.get(SEC_WEBSOCKET_PROTOCOL).cloned(),
+            protocol: None,
+            deflate: negotiate(&parts.headers),
+        })
+    }
+}
+
+impl DeflateUpgrade {
+    /// Echo this subprotocol when the client offered it
+    pub fn protocol(mut self, protocol: &'static str) -> Self {
+        let offered = self.offered_protocols.as_ref().and_then(|v| v.to_str().ok()).unwrap_or_default();
+        if offered.split(',').any(|p| p.trim() == protocol) {
+            self.protocol = Some(protocol);
+        }
+        self
+    }
+
+    /// Switch protocols and hand the socket to `callback` once hyper has upgraded the connection
+    pub fn on_upgrade<C, Fut>(self, callback: C) -> Response
+    where
+        C: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (on_upgrade, deflate) = (self.on_upgrade, self.deflate);
+        tokio::spawn(async move {
+            let upgraded = match on_upgrade.await {
+                Ok(upgraded) => upgraded,
+                Err(e) => {
+                    tracing::debug!("WebSocket upgrade failed: {}", e);
+                    return;
+                }
+            };
+            let stream = DeflateStream::new(TokioIo::new(upgraded), deflate);
+            callback(WebSocketStream::from_raw_socket(stream, Role::Server, None).await).await;
+        });
+
+        let mut response = Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(CONNECTION, "upgrade")
+            .header(UPGRADE, "websocket")
+            .header(SEC_WEBSOCKET_ACCEPT, derive_accept_key(self.key.as_bytes()));
+        if let Some(protocol) = self.protocol {
+            response = response.header(SEC_WEBSOCKET_PROTOCOL, protocol);
+        }
+        if let Some(deflate) = deflate {
+            response = response.header(SEC_WEBSOCKET_EXTENSIONS, deflate.response());
+        }
+        response.body(Body::empty()).unwrap()
+    }
+}
+
+/// Sits between the upgraded connection and tungstenite, which rejects RSV1 frames: the client's compressed
+/// messages are inflated into plain frames before tungstenite reads them, and the data frames tungstenite
+/// writes are compressed on the way out. Without `permessage-deflate` bytes pass through untouched
+pub struct DeflateStream<S> {
+    inner: S,
+    deflate: Option<Deflate>,
+    /// Bytes from the client that don't make a whole frame yet
+    read_raw: Vec<u8>,
+    /// Frames for tungstenite, and how much of them it has read
+    read_ready: Vec<u8>,
+    read_pos: usize,
+    read_eof: bool,
+    /// Bytes from tungstenite that don't make a whole frame yet
+    write_raw: Vec<u8>,
+    /// Frames for the client, and how much of them is written
+    write_ready: Vec<u8>,
+    write_pos: usize,
+}
+
+struct Deflate {
+    config: DeflateConfig,
+    compress: Compress,
+    decompress: Decompress,
+    /// A compressed message from the client, collected until its last fragment
+    inbound: Option<(OpCode, Vec<u8>)>,
+    /// A fragmented message from tungstenite, collected until its last fragment
+    outbound: Option<(OpCode, Vec<u8>)>,
+}
+
+impl<S> DeflateStream<S> {
+    pub fn new(inner: S, config: Option<DeflateConfig>) -> Self {
+        Self {
+            inner,
+            deflate: config.map(|config| Deflate {
+                config,
+                compress: Compress::new(Compression::default(), false),
+                decompress: Decompress::new(false),
+                inbound: None,
+                outbound: None,
+            }),
+            read_raw: Vec::new(),
+            read_ready: Vec::new(),
+            read_pos: 0,
+            read_eof: false,
+            write_raw: Vec::new(),
+            write_ready: Vec::new(),
+            write_pos: 0,
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> DeflateStream<S> {
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.write_pos < self.write_ready.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write_ready[self.write_pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_pos += n;
+        }
+        self.write_ready.clear();
+        self.write_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for DeflateStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let Some(deflate) = this.deflate.as_mut() else {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        };
+
+        loop {
+            if this.read_pos < this.read_ready.len() {
+                let n = buf.remaining().min(this.read_ready.len() - this.read_pos);
+                buf.put_slice(&this.read_ready[this.read_pos..this.read_pos + n]);
+                this.read_pos += n;
+                if this.read_pos == this.read_ready.len() {
+                    this.read_ready.clear();
+                    this.read_pos = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            if this.read_eof {
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut chunk = [0u8; 8192];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+            if chunk_buf.filled().is_empty() {
+                // A partial frame goes through as-is, for tungstenite to report
+                this.read_eof = true;
+                this.read_ready.append(&mut this.read_raw);
+                continue;
+            }
+            this.read_raw.extend_from_slice(chunk_buf.filled());
+            while let Some((header, start, end)) = next_frame(&this.read_raw)? {
+                deflate.inbound_frame(header, &this.read_raw[..end], start, &mut this.read_ready)?;
+                this.read_raw.drain(..end);
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for DeflateStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.deflate.is_none() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+        if this.write_ready.len() - this.write_pos >= WRITE_HIGH_WATER {
+            ready!(this.poll_drain(cx))?;
+        }
+
+        this.write_raw.extend_from_slice(buf);
+        let deflate = this.deflate.as_mut().expect("checked above");
+        while let Some((header, start, end)) = next_frame(&this.write_raw)? {
+            deflate.outbound_frame(header, &this.write_raw[..end], start, &mut this.write_ready)?;
+            this.write_raw.drain(..end);
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// The header of the first whole frame in `buf`, where its payload starts and where the frame ends
+fn next_frame(buf: &[u8]) -> io::Result<Option<(FrameHeader, usize, usize)>> {
+    let mut cursor = Cursor::new(buf);
+    let Some((header, len)) = FrameHeader::parse(&mut cursor).map_err(invalid_data)? else {
+        return Ok(None);
+    };
+    if len > MAX_MESSAGE_BYTES as u64 {
+        return Err(invalid_data("frame too large"));
+    }
+
+    let start = cursor.position() as usize;
+    let end = start + len as usize;
+    Ok((buf.len() >= end).then_some((header, start, end)))
+}
+
+impl Deflate {
+    /// A client frame: compressed messages are unmasked, collected and inflated, the rest go through as sent
+    fn inbound_frame(&mut self, header: FrameHeader, frame: &[u8], payload: usize, out: &mut Vec<u8>) -> io::Result<()> {
+        let starts_compressed = header.rsv1 && matches!(header.opcode, OpCode::Data(Data::Text | Data::Binary));
+        let continues = header.opcode == OpCode::Data(Data::Continue) && self.inbound.is_some();
+        if !starts_compressed && !continues {
+            out.extend_from_slice(frame);
+            return Ok(());
+        }
+        if starts_compressed && self.inbound.is_some() {
+            return Err(invalid_data("new message before the last one finished"));
+        }
+
+        let mut data = frame[payload..].to_vec();
+        if let Some(mask) = header.mask {
+            data.iter_mut().enumerate().for_each(|(i, byte)| *byte ^= mask[i % 4]);
+        }
+        let (opcode, message) = self.inbound.get_or_insert_with(|| (header.opcode, Vec::new()));
+        if message.len() + data.len() > MAX_MESSAGE_BYTES {
+            return Err(invalid_data("compressed message too large"));
+        }
+        message.extend_from_slice(&data);
+        if !header.is_final {
+            return Ok(());
+        }
+
+        let opcode = *opcode;
+        let (_, mut message) = self.inbound.take().expect("collected above");
+        message.extend_from_slice(&DEFLATE_TAIL);
+        let inflated = self.inflate(&message)?;
+        // tungstenite insists client frames are masked; an all-zero mask leaves the payload as it is
+        let header = FrameHeader { is_final: true, opcode, mask: Some([0; 4]), ..FrameHeader::default() };
+        header.format(inflated.len() as u64, out).map_err(invalid_data)?;
+        out.extend_from_slice(&inflated);
+        Ok(())
+    }
+
+    /// A frame from tungstenite: data messages are compressed, control frames go through as written
+    fn outbound_frame(&mut self, header: FrameHeader, frame: &[u8], payload: usize, out: &mut Vec<u8>) -> io::Result<()> {
+        let data = matches!(header.opcode, OpCode::Data(Data::Text | Data::Binary))
+            || (header.opcode == OpCode::Data(Data::Continue) && self.outbound.is_some());
+        if !data {
+            out.extend_from_slice(frame);
+            return Ok(());
+        }
+
+        let (opcode, message) = self.outbound.get_or_insert_with(|| (header.opcode, Vec::new()));
+        if message.len() + frame.len() > MAX_MESSAGE_BYTES {
+            return Err(invalid_data("message too large"));
+        }
+        message.extend_from_slice(&frame[payload..]);
+        if !header.is_final {
+            return Ok(());
+        }
+
+        let opcode = *opcode;
+        let (_, message) = self.outbound.take().expect("collected above");
+        let compressed = self.deflate(&message)?;
+        let header = FrameHeader { is_final: true, rsv1: true, opcode, mask: None, ..FrameHeader::default() };
+        header.format(compressed.len() as u64, out).map_err(invalid_data)?;
+        out.extend_from_slice(&compressed);
+        Ok(())
+    }
+
+    fn inflate(&mut self, mut input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(input.len() * 4);
+        loop {
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity().max(1024));
+            }
+            let (before_in, before_out) = (self.decompress.total_in(), self.decompress.total_out());
+            self.decompress.decompress_vec(input, &mut out, FlushDecompress::Sync).map_err(invalid_data)?;
+            let consumed = (self.decompress.total_in() - before_in) as usize;
+            input = &input[consumed..];
+            if out.len() > MAX_MESSAGE_BYTES {
+                return Err(invalid_data("inflated message too large"));
+            }
+            if input.is_empty() && out.len() < out.capacity() {
+                return Ok(out);
+            }
+            if consumed == 0 && self.decompress.total_out() == before_out {
+                return Err(invalid_data("truncated compressed message"));
+            }
+        }
+    }
+
+    fn deflate(&mut self, mut input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(input.len() / 2 + 64);
+        loop {
+            if out.capacity() - out.len() < 64 {
+                out.reserve(out.capacity().max(64));
+            }
+            let before = self.compress.total_in();
+            self.compress.compress_vec(input, &mut out, FlushCompress::Sync).map_err(invalid_data)?;
+            input = &input[(self.compress.total_in() - before) as usize..];
+            if input.is_empty() && out.len() < out.capacity() {
+                break;
+            }
+        }
+        if out.ends_with(&DEFLATE_TAIL) {
+            out.truncate(out.len() - DEFLATE_TAIL.len());
+        }
+        if self.config.server_no_context_takeover {
+            self.compress.reset();
+        }
+        Ok(out)
+    }
+}
+
+fn invalid_data(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::Control;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn offered(value: &str) -> Option<String> {
+        let mut headers = HeaderMap::new();
+        headers.insert(SEC_WEBSOCKET_EXTENSIONS, value.parse().unwrap());
+        negotiate(&headers).map(|config| config.response())
+    }
+
+    #[test]
+    fn test_deflate_offer_negotiated() {
+        assert_eq!(negotiate(&HeaderMap::new()), None);
+        assert_eq!(offered("permessage-deflate").as_deref(), Some("permessage-deflate"));
+        assert_eq!(offered("permessage-deflate; client_max_window_bits").as_deref(), Some("permessage-deflate"));
+        assert_eq!(
+            offered("permessage-deflate; server_no_context_takeover; server_max_window_bits=15").as_deref(),
+            Some("permessage-deflate; server_no_context_takeover; server_max_window_bits=15")
+        );
+
+        // A smaller server window can't be honoured, so that offer is declined in favour of the fallback
+        assert_eq!(offered("permessage-deflate; server_max_window_bits=10").as_deref(), None);
+        assert_eq!(
+            offered("permessage-deflate; server_max_window_bits=10, permessage-deflate").as_deref(),
+            Some("permessage-deflate")
+        );
+        assert_eq!(offered("x-webkit-deflate-frame").as_deref(), None);
+        assert_eq!(offered("permessage-deflate; unknown").as_deref(), None);
+    }
+
+    fn raw_deflate(text: &str) -> Vec<u8> {
+        let mut compress = Compress::new(Compression::default(), false);
+        let mut out = Vec::with_capacity(text.len() + 64);
+        compress.compress_vec(text.as_bytes(), &mut out, FlushCompress::Sync).unwrap();
+        out.truncate(out.len() - DEFLATE_TAIL.len());
+        out
+    }
+
+    fn raw_inflate(data: &[u8]) -> String {
+        let mut input = data.to_vec();
+        input.extend_from_slice(&DEFLATE_TAIL);
+        let mut out = Vec::with_capacity(1 << 20);
+        Decompress::new(false).decompress_vec(&input, &mut out, FlushDecompress::Sync).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// A masked client frame, optionally split across two fragments
+    fn client_frame(opcode: OpCode, rsv1: bool, is_final: bool, data: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let header = FrameHeader { is_final, rsv1, opcode, mask: Some(mask), ..FrameHeader::default() };
+        let mut frame = Vec::new();
+        header.format(data.len() as u64, &mut frame).unwrap();
+        frame.extend(data.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        frame
+    }
+
+    /// The next server frame, keeping whatever follows it in `buf`
+    async fn read_frame<R: AsyncRead + Unpin>(client: &mut R, buf: &mut Vec<u8>) -> (FrameHeader, Vec<u8>) {
+        loop {
+            if let Some((header, start, end)) = next_frame(buf).unwrap() {
+                let payload = buf[start..end].to_vec();
+                buf.drain(..end);
+                return (header, payload);
+            }
+            let mut chunk = [0u8; 4096];
+            let n = client.read(&mut chunk).await.unwrap();
+            assert!(n > 0, "socket closed mid-frame");
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_frames_compressed_both_ways() {
+        let (mut client, server) = tokio::io::duplex(1 << 20);
+        let stream = DeflateStream::new(server, Some(DeflateConfig::default()));
+        let mut socket = WebSocketStream::from_raw_socket(stream, Role::Server, None).await;
+
+        // A compressed message from the client, fragmented, with a ping between its fragments
+        // Large enough to inflate to many times the compressed size
+        let text = serde_json::json!({"type": "message", "recipient_address": "0xbob", "content": "hello ".repeat(20_000)}).to_string();
+        let compressed = raw_deflate(&text);
+        let (first, rest) = compressed.split_at(compressed.len() / 2);
+        client.write_all(&client_frame(OpCode::Data(Data::Text), true, false, first)).await.unwrap();
+        client.write_all(&client_frame(OpCode::Control(Control::Ping), false, true, b"p")).await.unwrap();
+        client.write_all(&client_frame(OpCode::Data(Data::Continue), false, true, rest)).await.unwrap();
+        // And an uncompressed one, which is still allowed
+        client.write_all(&client_frame(OpCode::Data(Data::Text), false, true, b"plain")).await.unwrap();
+
+        assert_eq!(socket.next().await.unwrap().unwrap(), Message::Ping(b"p".to_vec()));
+        assert_eq!(socket.next().await.unwrap().unwrap(), Message::Text(text));
+        assert_eq!(socket.next().await.unwrap().unwrap(), Message::Text("plain".to_string()));
+
+        // The pong for the ping goes out as-is; the large frame is compressed with RSV1 set
+        let large = serde_json::json!({"items": vec![serde_json::json!({"content": "a long message body"}); 200]}).to_string();
+        socket.send(Message::Text(large.clone())).await.unwrap();
+        let mut buf = Vec::new();
+        let (pong, data) = read_frame(&mut client, &mut buf).await;
+        assert_eq!((pong.opcode, pong.rsv1, data), (OpCode::Control(Control::Pong), false, b"p".to_vec()));
+        let (header, data) = read_frame(&mut client, &mut buf).await;
+        assert_eq!((header.opcode, header.rsv1, header.mask), (OpCode::Data(Data::Text), true, None));
+        assert!(data.len() < large.len() / 10);
+        assert_eq!(raw_inflate(&data), large);
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_negotiates_permessage_deflate() {
+        let app = axum::Router::new().route(
+            "/ws",
+            axum::routing::get(|ws: DeflateUpgrade| async move {
+                ws.protocol("bearer").on_upgrade(|mut socket| async move {
+                    let _ = socket.send(Message::Text("compressed compressed compressed".to_string())).await;
+                })
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET /ws HTTP/1.1\r\nHost: {}\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Protocol: bearer, token\r\n\
+             Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n\r\n",
+            addr
+        );
+        client.write_all(request.as_bytes()).await.unwrap();
+
+        let mut received = Vec::new();
+        let head_end = loop {
+            let mut chunk = [0u8; 4096];
+            let n = client.read(&mut chunk).await.unwrap();
+            assert!(n > 0, "connection closed before the handshake finished");
+            received.extend_from_slice(&chunk[..n]);
+            if let Some(i) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+                break i + 4;
+            }
+        };
+        let head = String::from_utf8_lossy(&received[..head_end]).to_ascii_lowercase();
+        assert!(head.starts_with("http/1.1 101"));
+        assert!(head.contains("sec-websocket-accept: s3pplmbitxaq9kygzzhzrbk+xoo="));
+        assert!(head.contains("sec-websocket-protocol: bearer\r\n"));
+        assert!(head.contains("sec-websocket-extensions: permessage-deflate\r\n"));
+
+        let (header, data) = read_frame(&mut client, &mut received.split_off(head_end)).await;
+        assert!(header.rsv1);
+        assert_eq!(raw_inflate(&data), "compressed compressed compressed");
+    }
+}
@@ -0,0 +1,213 @@
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Frame types that are stale once superseded, so they may be shed for a slow client
+const EPHEMERAL_TYPES: &[&str] = &["typing", "presence"];
+
+/// A stream entry on its way to the socket, with what the writer records once it's sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboundFrame {
+    pub channel: &'static str,
+    pub stream_id: String,
+    pub text: String,
+    /// The chat message to mark delivered once sent
+    pub message_id: Option<i64>,
+    pub ephemeral: bool,
+}
+
+/// Channel of replies to frames the client sent; they come from no stream, so no cursor or ack tracks them
+pub const REPLY_CHANNEL: &str = "reply";
+
+impl OutboundFrame {
+    pub fn reply(text: String) -> Self {
+        Self { channel: REPLY_CHANNEL, stream_id: String::new(), text, message_id: None, ephemeral: false }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enqueued {
+    Queued,
+    /// An ephemeral frame was dropped to make room: the oldest queued one, or this one
+    Shed,
+    /// The queue is full of frames that can't be dropped, so this one wasn't queued; the reader
+    /// leaves it in the stream and retries once the writer catches up
+    Full,
+}
+
+#[derive(Default)]
+struct Inner {
+    frames: VecDeque<OutboundFrame>,
+    /// When the queue last went from empty to non-empty
+    backlog_since: Option<Instant>,
+    closed: bool,
+    /// Close code and reason the writer should send the client
+    close_reason: Option<(u16, &'static str)>,
+}
+
+/// Bounded buffer between a socket's stream reader and its writer. A full queue sheds ephemeral
+/// frames (typing, presence) oldest first and never drops chat or notifications; those wait in Redis
+/// instead, and the socket is closed once the backlog outlasts `WS_MAX_BACKLOG_SECONDS`
+pub struct SendQueue {
+    inner: Mutex<Inner>,
+    notify: Notify,
+    capacity: usize,
+}
+
+impl SendQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            notify: Notify::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn push(&self, frame: OutboundFrame) -> Enqueued {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let mut outcome = Enqueued::Queued;
+
+        if inner.frames.len() >= self.capacity {
+            match inner.frames.iter().position(|f| f.ephemeral) {
+                Some(oldest) => {
+                    inner.frames.remove(oldest);
+                    outcome = Enqueued::Shed;
+                }
+                None if frame.ephemeral => return Enqueued::Shed,
+                None => return Enqueued::Full,
+            }
+        }
+
+        if inner.frames.is_empty() {
+            inner.backlog_since = Some(Instant::now());
+        }
+        inner.frames.push_back(frame);
+        drop(inner);
+        self.notify.notify_one();
+        outcome
+    }
+
+    /// The oldest frame, waiting for one; None once closed
+    pub async fn pop(&self) -> Option<OutboundFrame> {
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+                if inner.closed {
+                    return None;
+                }
+                if let Some(frame) = inner.frames.pop_front() {
+                    if inner.frames.is_empty() {
+                        inner.backlog_since = None;
+                    }
+                    return Some(frame);
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    pub fn close(&self) {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).closed = true;
+        self.notify.notify_one();
+    }
+
+    /// Close, asking the writer to send the client a Close frame with `code` and `reason`
+    pub fn close_with(&self, code: u16, reason: &'static str) {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).close_reason = Some((code, reason));
+        self.close();
+    }
+
+    pub fn close_reason(&self) -> Option<(u16, &'static str)> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).close_reason
+    }
+
+    /// How long the queue has held frames without draining; zero when empty
+    pub fn backlog_age(&self) -> Duration {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.backlog_since.map(|since| since.elapsed()).unwrap_or_default()
+    }
+
+    /// Frames that fit before the queue starts shedding
+    pub fn room(&self) -> usize {
+        self.capacity.saturating_sub(self.len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Deserialize)]
+struct FrameType<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Option<&'a str>,
+}
+
+/// Whether an enveloped frame may be shed under backpressure
+pub fn is_ephemeral(text: &str) -> bool {
+    serde_json::from_str::<FrameType>(text)
+        .ok()
+        .and_then(|f| f.kind)
+        .is_some_and(|kind| EPHEMERAL_TYPES.contains(&kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws_cursor::CHAT_CHANNEL;
+
+    fn frame(id: u64, kind: &str) -> OutboundFrame {
+        let text = serde_json::json!({"type": kind, "channel": CHAT_CHANNEL, "stream_id": format!("{}-0", id)}).to_string();
+        OutboundFrame {
+            channel: CHAT_CHANNEL,
+            stream_id: format!("{}-0", id),
+            ephemeral: is_ephemeral(&text),
+            text,
+            message_id: (kind == "message").then_some(id as i64),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stalled_writer_sheds_ephemeral_frames_only() {
+        // Nobody pops, as with a client that stopped reading
+        let queue = SendQueue::new(4);
+
+        assert_eq!(queue.push(frame(1, "typing")), Enqueued::Queued);
+        assert_eq!(queue.push(frame(2, "message")), Enqueued::Queued);
+        assert_eq!(queue.push(frame(3, "presence")), Enqueued::Queued);
+        assert_eq!(queue.push(frame(4, "message")), Enqueued::Queued);
+
+        // Full: each chat frame evicts the oldest ephemeral one
+        assert_eq!(queue.push(frame(5, "message")), Enqueued::Shed);
+        assert_eq!(queue.push(frame(6, "message")), Enqueued::Shed);
+        // Nothing left to shed, so a new ephemeral frame is dropped and a chat frame waits its turn
+        assert_eq!(queue.push(frame(7, "typing")), Enqueued::Shed);
+        assert_eq!(queue.push(frame(8, "message")), Enqueued::Full);
+        assert_eq!(queue.room(), 0);
+        assert!(queue.inner.lock().unwrap().backlog_since.is_some());
+
+        let mut sent = Vec::new();
+        while !queue.is_empty() {
+            sent.push(queue.pop().await.unwrap().message_id.unwrap());
+        }
+        assert_eq!(sent, [2, 4, 5, 6]);
+        assert_eq!(queue.backlog_age(), Duration::ZERO);
+
+        queue.close();
+        assert_eq!(queue.pop().await, None);
+    }
+
+    #[test]
+    fn test_only_typing_and_presence_are_ephemeral() {
+        assert!(is_ephemeral(r#"{"type":"typing","conversation_id":"c1"}"#));
+        assert!(!is_ephemeral(r#"{"type":"message","message_id":1}"#));
+        assert!(!is_ephemeral(r#"{"type":"notification"}"#));
+        assert!(!is_ephemeral("not json"));
+    }
+}
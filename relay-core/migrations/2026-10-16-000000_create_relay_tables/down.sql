@@ -0,0 +1,11 @@
+DROP TABLE IF EXISTS platform_delivery_config;
+DROP TABLE IF EXISTS relay_ws_connections;
+DROP TABLE IF EXISTS relay_blocks;
+DROP TABLE IF EXISTS relay_device_tokens;
+DROP TABLE IF EXISTS relay_user_preferences;
+DROP TABLE IF EXISTS relay_messages;
+DROP TABLE IF EXISTS relay_conversations;
+DROP TABLE IF EXISTS relay_notification_templates;
+DROP TABLE IF EXISTS relay_notification_deliveries;
+DROP TABLE IF EXISTS relay_notifications;
+DROP TABLE IF EXISTS relay_outbox;
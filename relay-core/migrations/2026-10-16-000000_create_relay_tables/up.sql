@@ -0,0 +1,159 @@
+-- Relay tables. IF NOT EXISTS lets deployments that created the schema by hand adopt migrations.
+-- `profiles` belongs to the indexer database and is not created here.
+
+CREATE TABLE IF NOT EXISTS relay_outbox (
+    id BIGSERIAL PRIMARY KEY,
+    event_type TEXT NOT NULL,
+    event_data JSONB NOT NULL,
+    event_id TEXT,
+    transaction_id TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    processed_at TIMESTAMPTZ,
+    published_at TIMESTAMPTZ,
+    retry_count INTEGER NOT NULL DEFAULT 0,
+    error_message TEXT,
+    next_retry_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_relay_outbox_unprocessed
+    ON relay_outbox (created_at)
+    WHERE processed_at IS NULL;
+
+CREATE TABLE IF NOT EXISTS relay_notifications (
+    id BIGSERIAL PRIMARY KEY,
+    user_address TEXT NOT NULL,
+    notification_type TEXT NOT NULL,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    data JSONB,
+    platform_id TEXT,
+    read_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_relay_notifications_user_created
+    ON relay_notifications (user_address, created_at DESC);
+
+CREATE TABLE IF NOT EXISTS relay_notification_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    notification_id BIGINT,
+    user_address TEXT NOT NULL,
+    channel TEXT NOT NULL,
+    status TEXT NOT NULL,
+    provider_id TEXT,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_relay_notification_deliveries_notification
+    ON relay_notification_deliveries (notification_id);
+
+CREATE TABLE IF NOT EXISTS relay_notification_templates (
+    id BIGSERIAL PRIMARY KEY,
+    platform_id TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    locale TEXT NOT NULL DEFAULT 'en',
+    title_template TEXT NOT NULL,
+    body_template TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (platform_id, event_type, locale)
+);
+
+CREATE TABLE IF NOT EXISTS relay_conversations (
+    id BIGSERIAL PRIMARY KEY,
+    conversation_id TEXT NOT NULL UNIQUE,
+    participant1_address TEXT NOT NULL,
+    participant2_address TEXT NOT NULL,
+    last_message_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_relay_conversations_participant1 ON relay_conversations (participant1_address);
+CREATE INDEX IF NOT EXISTS idx_relay_conversations_participant2 ON relay_conversations (participant2_address);
+
+CREATE TABLE IF NOT EXISTS relay_messages (
+    id BIGSERIAL PRIMARY KEY,
+    conversation_id TEXT NOT NULL,
+    sender_address TEXT NOT NULL,
+    recipient_address TEXT NOT NULL,
+    content BYTEA NOT NULL,
+    content_type TEXT NOT NULL DEFAULT 'text',
+    media_urls JSONB,
+    metadata JSONB,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    delivered_at TIMESTAMPTZ,
+    read_at TIMESTAMPTZ,
+    deleted_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_relay_messages_conversation_created
+    ON relay_messages (conversation_id, created_at DESC);
+
+CREATE TABLE IF NOT EXISTS relay_user_preferences (
+    user_address TEXT PRIMARY KEY,
+    push_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    email_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    sms_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    notification_types JSONB NOT NULL DEFAULT '{}'::jsonb,
+    dnd_start TIME,
+    dnd_end TIME,
+    timezone TEXT,
+    dnd_digest_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    email_digest TEXT NOT NULL DEFAULT 'off',
+    last_digest_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS relay_device_tokens (
+    id BIGSERIAL PRIMARY KEY,
+    user_address TEXT NOT NULL,
+    device_token TEXT NOT NULL,
+    platform TEXT NOT NULL,
+    device_id TEXT,
+    app_version TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (user_address, device_token)
+);
+
+CREATE TABLE IF NOT EXISTS relay_blocks (
+    id BIGSERIAL PRIMARY KEY,
+    blocker_address TEXT NOT NULL,
+    blocked_address TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (blocker_address, blocked_address)
+);
+
+CREATE INDEX IF NOT EXISTS idx_relay_blocks_blocked ON relay_blocks (blocked_address);
+
+CREATE TABLE IF NOT EXISTS relay_ws_connections (
+    id BIGSERIAL PRIMARY KEY,
+    user_address TEXT NOT NULL,
+    connection_id TEXT NOT NULL UNIQUE,
+    connected_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_heartbeat_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    disconnected_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_relay_ws_connections_open
+    ON relay_ws_connections (last_heartbeat_at)
+    WHERE disconnected_at IS NULL;
+
+CREATE TABLE IF NOT EXISTS platform_delivery_config (
+    id BIGSERIAL PRIMARY KEY,
+    platform_id TEXT NOT NULL UNIQUE,
+    apns_bundle_id TEXT,
+    apns_key_id TEXT,
+    apns_team_id TEXT,
+    apns_key_path TEXT,
+    apns_key_content TEXT,
+    fcm_server_key TEXT,
+    resend_api_key TEXT,
+    resend_from_email TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
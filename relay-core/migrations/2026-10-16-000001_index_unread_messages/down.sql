@@ -0,0 +1 @@
+DROP INDEX IF EXISTS idx_relay_messages_unread;
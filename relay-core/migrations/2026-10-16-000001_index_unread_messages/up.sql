@@ -0,0 +1,4 @@
+-- Backs GET /api/v1/conversations/unread
+CREATE INDEX IF NOT EXISTS idx_relay_messages_unread
+    ON relay_messages (recipient_address, conversation_id)
+    WHERE read_at IS NULL AND deleted_at IS NULL;
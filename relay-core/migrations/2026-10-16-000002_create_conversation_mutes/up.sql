@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS relay_conversation_mutes (
+    id BIGSERIAL PRIMARY KEY,
+    user_address TEXT NOT NULL,
+    conversation_id TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (user_address, conversation_id)
+);
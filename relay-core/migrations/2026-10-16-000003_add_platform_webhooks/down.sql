@@ -0,0 +1,2 @@
+ALTER TABLE platform_delivery_config DROP COLUMN IF EXISTS webhook_secret;
+ALTER TABLE platform_delivery_config DROP COLUMN IF EXISTS webhook_url;
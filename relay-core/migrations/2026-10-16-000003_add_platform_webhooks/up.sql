@@ -0,0 +1,3 @@
+-- Platforms that receive notifications at their own endpoint, signed with webhook_secret
+ALTER TABLE platform_delivery_config ADD COLUMN IF NOT EXISTS webhook_url TEXT;
+ALTER TABLE platform_delivery_config ADD COLUMN IF NOT EXISTS webhook_secret TEXT;
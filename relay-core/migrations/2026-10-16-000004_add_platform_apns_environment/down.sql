@@ -0,0 +1 @@
+ALTER TABLE platform_delivery_config DROP COLUMN IF EXISTS apns_environment;
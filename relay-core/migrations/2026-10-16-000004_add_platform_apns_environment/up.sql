@@ -0,0 +1,3 @@
+-- Explicit APNs gateway; NULL keeps guessing it from the bundle id
+ALTER TABLE platform_delivery_config ADD COLUMN IF NOT EXISTS apns_environment TEXT
+    CHECK (apns_environment IN ('sandbox', 'production'));
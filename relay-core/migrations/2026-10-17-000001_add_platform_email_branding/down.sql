@@ -0,0 +1,4 @@
+ALTER TABLE platform_delivery_config DROP COLUMN IF EXISTS email_template;
+ALTER TABLE platform_delivery_config DROP COLUMN IF EXISTS email_footer;
+ALTER TABLE platform_delivery_config DROP COLUMN IF EXISTS email_brand_color;
+ALTER TABLE platform_delivery_config DROP COLUMN IF EXISTS email_logo_url;
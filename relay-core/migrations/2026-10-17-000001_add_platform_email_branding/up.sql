@@ -0,0 +1,6 @@
+-- Email branding; NULL keeps the relay's defaults
+ALTER TABLE platform_delivery_config ADD COLUMN IF NOT EXISTS email_logo_url TEXT;
+ALTER TABLE platform_delivery_config ADD COLUMN IF NOT EXISTS email_brand_color TEXT;
+ALTER TABLE platform_delivery_config ADD COLUMN IF NOT EXISTS email_footer TEXT;
+-- Handlebars HTML template for notification emails; NULL uses the built-in one
+ALTER TABLE platform_delivery_config ADD COLUMN IF NOT EXISTS email_template TEXT;
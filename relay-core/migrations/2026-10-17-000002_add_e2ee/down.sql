@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS relay_user_keys;
+ALTER TABLE relay_messages DROP COLUMN IF EXISTS key_exchange;
+ALTER TABLE relay_messages DROP COLUMN IF EXISTS e2ee;
@@ -0,0 +1,16 @@
+-- Messages encrypted by the client under E2EE_MODE; `content` is their ciphertext, stored as submitted
+ALTER TABLE relay_messages ADD COLUMN IF NOT EXISTS e2ee BOOLEAN NOT NULL DEFAULT FALSE;
+-- Opaque key-exchange blob the sender attached for the recipient
+ALTER TABLE relay_messages ADD COLUMN IF NOT EXISTS key_exchange TEXT;
+
+-- Public keys users publish so others can encrypt to them
+CREATE TABLE IF NOT EXISTS relay_user_keys (
+    id BIGSERIAL PRIMARY KEY,
+    user_address TEXT NOT NULL,
+    key_id TEXT NOT NULL,
+    algorithm TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (user_address, key_id)
+);
@@ -0,0 +1,17 @@
+-- Security-relevant events: sign-ins, blocks and admin changes
+CREATE TABLE IF NOT EXISTS relay_audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    -- Wallet address of who acted; NULL when unknown
+    actor TEXT,
+    action TEXT NOT NULL,
+    target TEXT,
+    -- `success` or `failure`
+    result TEXT NOT NULL,
+    -- Error code of a failure
+    reason TEXT,
+    ip TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_relay_audit_log_created_at ON relay_audit_log (created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_relay_audit_log_actor ON relay_audit_log (actor, created_at DESC);
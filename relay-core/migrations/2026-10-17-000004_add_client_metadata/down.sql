@@ -0,0 +1,6 @@
+ALTER TABLE relay_device_tokens DROP COLUMN IF EXISTS user_agent;
+ALTER TABLE relay_device_tokens DROP COLUMN IF EXISTS ip;
+
+ALTER TABLE relay_ws_connections DROP COLUMN IF EXISTS app_version;
+ALTER TABLE relay_ws_connections DROP COLUMN IF EXISTS user_agent;
+ALTER TABLE relay_ws_connections DROP COLUMN IF EXISTS ip;
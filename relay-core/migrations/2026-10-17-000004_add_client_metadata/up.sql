@@ -0,0 +1,7 @@
+-- Where a socket or device registration came from, for abuse investigation and device management
+ALTER TABLE relay_ws_connections ADD COLUMN IF NOT EXISTS ip TEXT;
+ALTER TABLE relay_ws_connections ADD COLUMN IF NOT EXISTS user_agent TEXT;
+ALTER TABLE relay_ws_connections ADD COLUMN IF NOT EXISTS app_version TEXT;
+
+ALTER TABLE relay_device_tokens ADD COLUMN IF NOT EXISTS ip TEXT;
+ALTER TABLE relay_device_tokens ADD COLUMN IF NOT EXISTS user_agent TEXT;
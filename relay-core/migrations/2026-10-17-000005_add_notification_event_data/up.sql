@@ -0,0 +1,2 @@
+-- The raw event a notification was created from, kept for debugging; clients only see the projected `data`
+ALTER TABLE relay_notifications ADD COLUMN IF NOT EXISTS event_data JSONB;
@@ -0,0 +1 @@
+ALTER TABLE relay_notifications DROP COLUMN IF EXISTS deleted_at;
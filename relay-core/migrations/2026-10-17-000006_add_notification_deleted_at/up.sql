@@ -0,0 +1,2 @@
+-- Set when a user clears their inbox with soft deletes; cleared notifications are hidden everywhere
+ALTER TABLE relay_notifications ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
@@ -0,0 +1,10 @@
+-- Actions users took from a notification's buttons, e.g. reply or follow back
+CREATE TABLE IF NOT EXISTS relay_notification_actions (
+    id BIGSERIAL PRIMARY KEY,
+    notification_id BIGINT NOT NULL REFERENCES relay_notifications(id) ON DELETE CASCADE,
+    user_address TEXT NOT NULL,
+    action TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_relay_notification_actions_notification ON relay_notification_actions(notification_id);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_relay_messages_created_at;
+
+ALTER TABLE relay_conversations DROP COLUMN IF EXISTS retention_exempt;
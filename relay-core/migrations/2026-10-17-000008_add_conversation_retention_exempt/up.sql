@@ -0,0 +1,4 @@
+-- Conversations whose messages the retention job keeps regardless of MESSAGE_RETENTION_DAYS
+ALTER TABLE relay_conversations ADD COLUMN IF NOT EXISTS retention_exempt BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX IF NOT EXISTS idx_relay_messages_created_at ON relay_messages (created_at);
@@ -0,0 +1,14 @@
+-- Tokens issued by /api/v1/auth/token, keyed by their `jti` claim, so users can see and revoke them
+CREATE TABLE IF NOT EXISTS relay_sessions (
+    id BIGSERIAL PRIMARY KEY,
+    jti TEXT NOT NULL UNIQUE,
+    user_address TEXT NOT NULL,
+    ip TEXT,
+    user_agent TEXT,
+    issued_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_relay_sessions_user ON relay_sessions(user_address, expires_at);
@@ -0,0 +1 @@
+ALTER TABLE relay_messages DROP COLUMN IF EXISTS flagged;
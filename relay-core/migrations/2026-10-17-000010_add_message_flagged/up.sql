@@ -0,0 +1,2 @@
+-- Messages the moderation classifier flagged for review; blocked ones are never stored
+ALTER TABLE relay_messages ADD COLUMN IF NOT EXISTS flagged BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,9 @@
+-- Emoji reactions on direct messages; a user can add each emoji once per message
+CREATE TABLE IF NOT EXISTS relay_message_reactions (
+    id BIGSERIAL PRIMARY KEY,
+    message_id BIGINT NOT NULL REFERENCES relay_messages(id) ON DELETE CASCADE,
+    user_address TEXT NOT NULL,
+    emoji TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (message_id, user_address, emoji)
+);
@@ -0,0 +1,8 @@
+-- Conversations a participant archived; archiving is per user, the other participant is unaffected
+CREATE TABLE IF NOT EXISTS relay_conversation_archives (
+    id BIGSERIAL PRIMARY KEY,
+    user_address TEXT NOT NULL,
+    conversation_id TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (user_address, conversation_id)
+);
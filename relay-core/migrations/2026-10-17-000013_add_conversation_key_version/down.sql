@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_relay_conversations_key_version;
+ALTER TABLE relay_conversations DROP COLUMN IF EXISTS conversation_key_version;
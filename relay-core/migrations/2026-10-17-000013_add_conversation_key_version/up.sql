@@ -0,0 +1,4 @@
+-- ENCRYPTION_KEY_VERSION every message in the conversation is encrypted under; conversations behind the
+-- current version are re-encrypted in the background
+ALTER TABLE relay_conversations ADD COLUMN IF NOT EXISTS conversation_key_version INTEGER NOT NULL DEFAULT 1;
+CREATE INDEX IF NOT EXISTS idx_relay_conversations_key_version ON relay_conversations (conversation_key_version);
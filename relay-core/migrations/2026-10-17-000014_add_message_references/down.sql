@@ -0,0 +1,2 @@
+ALTER TABLE relay_messages DROP COLUMN IF EXISTS forwarded_from_message_id;
+ALTER TABLE relay_messages DROP COLUMN IF EXISTS reply_to_message_id;
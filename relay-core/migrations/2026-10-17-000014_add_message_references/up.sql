@@ -0,0 +1,3 @@
+-- The message a message replies to, and the one it was forwarded from; cleared if that message is purged
+ALTER TABLE relay_messages ADD COLUMN IF NOT EXISTS reply_to_message_id BIGINT REFERENCES relay_messages(id) ON DELETE SET NULL;
+ALTER TABLE relay_messages ADD COLUMN IF NOT EXISTS forwarded_from_message_id BIGINT REFERENCES relay_messages(id) ON DELETE SET NULL;
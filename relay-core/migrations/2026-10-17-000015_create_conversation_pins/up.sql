@@ -0,0 +1,8 @@
+-- Conversations a participant pinned to the top of their list; pinning is per user and capped in the API
+CREATE TABLE IF NOT EXISTS relay_conversation_pins (
+    id BIGSERIAL PRIMARY KEY,
+    user_address TEXT NOT NULL,
+    conversation_id TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (user_address, conversation_id)
+);
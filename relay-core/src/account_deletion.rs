@@ -0,0 +1,347 @@
+use anyhow::Result;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+
+use crate::db::DbConnection;
+use crate::redis::{RedisConnection, RedisKeys};
+use crate::schema::{
+    relay_blocks, relay_conversation_archives, relay_conversations, relay_conversation_mutes, relay_conversation_pins, relay_device_tokens,
+    relay_message_reactions, relay_messages, relay_notification_actions, relay_notification_deliveries, relay_notifications,
+    relay_sessions, relay_user_keys, relay_user_preferences, relay_ws_connections,
+};
+
+/// What deleting an account removed from Postgres
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DeletedRows {
+    pub notifications: usize,
+    /// Messages the user sent, blanked so their counterparts keep a tombstone in place of each
+    pub messages_tombstoned: usize,
+    pub device_tokens: usize,
+    pub sessions: usize,
+    pub ws_connections: usize,
+    pub blocks: usize,
+}
+
+/// Remove everything stored about `user_address` in one transaction: notifications with their deliveries
+/// and actions, preferences, devices, sessions, WebSocket connections, blocks either way, published keys,
+/// reactions, mutes, archives and pins. Sent messages become tombstones as if deleted for everyone; messages the
+/// user received stay with their senders. Sessions are deleted without denylisting their tokens, so do that
+/// first. Running it again removes nothing more
+pub async fn delete_rows(conn: &mut DbConnection, user_address: &str) -> QueryResult<DeletedRows> {
+    conn.transaction(|conn| async move { delete_rows_in(conn, user_address).await }.scope_boxed()).await
+}
+
+async fn delete_rows_in(conn: &mut AsyncPgConnection, user_address: &str) -> QueryResult<DeletedRows> {
+    let sessions = diesel::delete(relay_sessions::table.filter(relay_sessions::user_address.eq(user_address)))
+        .execute(conn)
+        .await?;
+
+    let messages_tombstoned = diesel::update(
+        relay_messages::table
+            .filter(relay_messages::sender_address.eq(user_address))
+            .filter(relay_messages::deleted_at.is_null()),
+    )
+    .set((
+        relay_messages::deleted_at.eq(Utc::now()),
+        relay_messages::content.eq(Vec::<u8>::new()),
+        relay_messages::media_urls.eq(None::<serde_json::Value>),
+        relay_messages::metadata.eq(None::<serde_json::Value>),
+        relay_messages::key_exchange.eq(None::<String>),
+    ))
+    .execute(conn)
+    .await?;
+    diesel::delete(relay_message_reactions::table.filter(relay_message_reactions::user_address.eq(user_address)))
+        .execute(conn)
+        .await?;
+
+    diesel::delete(relay_notification_actions::table.filter(relay_notification_actions::user_address.eq(user_address)))
+        .execute(conn)
+        .await?;
+    diesel::delete(relay_notification_deliveries::table.filter(relay_notification_deliveries::user_address.eq(user_address)))
+        .execute(conn)
+        .await?;
+    let notifications = diesel::delete(relay_notifications::table.filter(relay_notifications::user_address.eq(user_address)))
+        .execute(conn)
+        .await?;
+
+    let device_tokens = diesel::delete(relay_device_tokens::table.filter(relay_device_tokens::user_address.eq(user_address)))
+        .execute(conn)
+        .await?;
+    let ws_connections = diesel::delete(relay_ws_connections::table.filter(relay_ws_connections::user_address.eq(user_address)))
+        .execute(conn)
+        .await?;
+    let blocks = diesel::delete(
+        relay_blocks::table.filter(relay_blocks::blocker_address.eq(user_address).or(relay_blocks::blocked_address.eq(user_address))),
+    )
+    .execute(conn)
+    .await?;
+
+    diesel::delete(relay_user_preferences::table.filter(relay_user_preferences::user_address.eq(user_address)))
+        .execute(conn)
+        .await?;
+    diesel::delete(relay_user_keys::table.filter(relay_user_keys::user_address.eq(user_address)))
+        .execute(conn)
+        .await?;
+    diesel::delete(relay_conversation_mutes::table.filter(relay_conversation_mutes::user_address.eq(user_address)))
+        .execute(conn)
+        .await?;
+    diesel::delete(relay_conversation_archives::table.filter(relay_conversation_archives::user_address.eq(user_address)))
+        .execute(conn)
+        .await?;
+    diesel::delete(relay_conversation_pins::table.filter(relay_conversation_pins::user_address.eq(user_address)))
+        .execute(conn)
+        .await?;
+
+    Ok(DeletedRows { notifications, messages_tombstoned, device_tokens, sessions, ws_connections, blocks })
+}
+
+/// The conversations `user_address` takes part in, whose Redis caches hold copies of their messages
+pub async fn conversation_ids(conn: &mut DbConnection, user_address: &str) -> QueryResult<Vec<String>> {
+    relay_conversations::table
+        .filter(
+            relay_conversations::participant1_address
+                .eq(user_address)
+                .or(relay_conversations::participant2_address.eq(user_address)),
+        )
+        .select(relay_conversations::conversation_id)
+        .load(conn)
+        .await
+}
+
+/// Delete the user's Redis keys: inboxes, streams and WebSocket cursors (per platform too), unread
+/// counters, hidden messages, idempotency keys, presence, the cached profile and any pending quiet-hours
+/// digest, plus the message cache and preview of each of `conversation_ids`, which hold their messages
+/// in plaintext. Returns how many keys were deleted
+pub async fn clear_redis(
+    conn: &mut RedisConnection,
+    keys: RedisKeys<'_>,
+    user_address: &str,
+    conversation_ids: &[String],
+) -> Result<usize> {
+    let mut user_keys = vec![
+        keys.inbox(user_address, None),
+        keys.notify_stream(user_address, None),
+        keys.chat_stream(user_address),
+        keys.ws_cursor(user_address, None),
+        keys.unread_total(user_address),
+        keys.unread_platforms(user_address),
+        keys.unread_indexed(user_address),
+        keys.presence(user_address),
+        keys.last_seen(user_address),
+        keys.profile_exists(&user_address.to_lowercase()),
+        keys.dnd_digest(user_address),
+    ];
+    for conversation_id in conversation_ids {
+        user_keys.push(keys.chat_cache(conversation_id));
+        user_keys.push(keys.conversation_preview(conversation_id));
+    }
+
+    // Keys with a further part after the user: platforms, conversations, idempotency keys
+    let patterns = [
+        format!("{}:*", keys.inbox(user_address, None)),
+        format!("{}:*", keys.notify_stream(user_address, None)),
+        format!("{}:*", keys.ws_cursor(user_address, None)),
+        format!("{}:*", keys.unread_total(user_address)),
+        keys.hidden_messages(user_address, "*"),
+        keys.idempotency(user_address, "*"),
+    ];
+    for pattern in patterns {
+        let mut cursor = 0u64;
+        loop {
+            let (next, found): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(1000)
+                .query_async(conn)
+                .await?;
+            user_keys.extend(found);
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+    }
+    user_keys.sort();
+    user_keys.dedup();
+
+    let (deleted,): (usize,) = redis::pipe()
+        .del(&user_keys)
+        .zrem(keys.dnd_digest_due(), user_address)
+        .ignore()
+        .query_async(conn)
+        .await?;
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::relay_conversations;
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables, and Redis at REDIS_URL"]
+    async fn test_account_rows_and_keys_gone() {
+        let config = crate::Config::from_env();
+        let keys = config.redis.keys();
+        let pool = crate::db::create_pool(&config.database).await.unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let redis_pool = crate::redis::create_pool(&config.redis).await.unwrap();
+        let mut redis_conn = crate::redis::get_connection(&redis_pool).await.unwrap();
+
+        let run = uuid::Uuid::new_v4();
+        let (me, friend) = (format!("0xme-{}", run), format!("0xfriend-{}", run));
+        let conversation = format!("{}:{}", friend, me);
+        diesel::insert_into(relay_conversations::table)
+            .values((
+                relay_conversations::conversation_id.eq(&conversation),
+                relay_conversations::participant1_address.eq(&friend),
+                relay_conversations::participant2_address.eq(&me),
+            ))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let message = |sender: &String, recipient: &String| {
+            (
+                relay_messages::conversation_id.eq(conversation.clone()),
+                relay_messages::sender_address.eq(sender.clone()),
+                relay_messages::recipient_address.eq(recipient.clone()),
+                relay_messages::content.eq(b"ciphertext".to_vec()),
+            )
+        };
+        diesel::insert_into(relay_messages::table)
+            .values(vec![message(&me, &friend), message(&friend, &me)])
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::insert_into(relay_notifications::table)
+            .values((
+                relay_notifications::user_address.eq(&me),
+                relay_notifications::notification_type.eq("follow.created"),
+                relay_notifications::title.eq("New follower"),
+                relay_notifications::body.eq("Someone followed you"),
+            ))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::insert_into(relay_device_tokens::table)
+            .values((
+                relay_device_tokens::user_address.eq(&me),
+                relay_device_tokens::device_token.eq(format!("token-{}", run)),
+                relay_device_tokens::platform.eq("ios"),
+            ))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::insert_into(relay_sessions::table)
+            .values((
+                relay_sessions::jti.eq(run.to_string()),
+                relay_sessions::user_address.eq(&me),
+                relay_sessions::expires_at.eq(Utc::now() + chrono::Duration::days(1)),
+            ))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::insert_into(relay_ws_connections::table)
+            .values((relay_ws_connections::user_address.eq(&me), relay_ws_connections::connection_id.eq(run.to_string())))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::insert_into(relay_blocks::table)
+            .values((relay_blocks::blocker_address.eq(&friend), relay_blocks::blocked_address.eq(&me)))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::insert_into(relay_user_preferences::table)
+            .values((relay_user_preferences::user_address.eq(&me), relay_user_preferences::notification_types.eq(serde_json::json!({}))))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        redis::pipe()
+            .lpush(keys.inbox(&me, None), "{}").ignore()
+            .lpush(keys.inbox(&me, Some("app")), "{}").ignore()
+            .xadd(keys.chat_stream(&me), "*", &[("data", "{}")]).ignore()
+            .set(keys.unread_total(&me), 3).ignore()
+            .set(keys.unread_platform(&me, "app"), 3).ignore()
+            .sadd(keys.unread_platforms(&me), "app").ignore()
+            .sadd(keys.hidden_messages(&me, &conversation), 1).ignore()
+            .set(keys.idempotency(&me, "retry-1"), 1).ignore()
+            .set(keys.unread_total(&friend), 1).ignore()
+            .lpush(keys.chat_cache(&conversation), r#"{"sender":"me","content":"hello"}"#).ignore()
+            .set(keys.conversation_preview(&conversation), "{}").ignore()
+            .query_async::<()>(&mut redis_conn)
+            .await
+            .unwrap();
+
+        let conversations = conversation_ids(&mut conn, &me).await.unwrap();
+        let deleted = delete_rows(&mut conn, &me).await.unwrap();
+        let again = delete_rows(&mut conn, &me).await.unwrap();
+        let cleared = clear_redis(&mut redis_conn, keys, &me, &conversations).await.unwrap();
+        let chat_cached: bool = redis::cmd("EXISTS").arg(keys.chat_cache(&conversation)).query_async(&mut redis_conn).await.unwrap();
+
+        let mut remaining = Vec::new();
+        let mut cursor = 0u64;
+        loop {
+            let (next, found): (u64, Vec<String>) =
+                redis::cmd("SCAN").arg(cursor).arg("MATCH").arg(format!("*{}*", me)).query_async(&mut redis_conn).await.unwrap();
+            remaining.extend(found);
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+        let friends_count: Option<i64> = redis::cmd("GET").arg(keys.unread_total(&friend)).query_async(&mut redis_conn).await.unwrap();
+        let notifications: i64 = relay_notifications::table
+            .filter(relay_notifications::user_address.eq(&me))
+            .count()
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+        let per_user: i64 = [
+            relay_device_tokens::table.filter(relay_device_tokens::user_address.eq(&me)).count().get_result::<i64>(&mut conn).await.unwrap(),
+            relay_sessions::table.filter(relay_sessions::user_address.eq(&me)).count().get_result::<i64>(&mut conn).await.unwrap(),
+            relay_ws_connections::table.filter(relay_ws_connections::user_address.eq(&me)).count().get_result::<i64>(&mut conn).await.unwrap(),
+            relay_blocks::table.filter(relay_blocks::blocked_address.eq(&me)).count().get_result::<i64>(&mut conn).await.unwrap(),
+            relay_user_preferences::table.filter(relay_user_preferences::user_address.eq(&me)).count().get_result::<i64>(&mut conn).await.unwrap(),
+        ]
+        .iter()
+        .sum();
+        let messages: Vec<(String, Vec<u8>, bool)> = relay_messages::table
+            .filter(relay_messages::conversation_id.eq(&conversation))
+            .order(relay_messages::id)
+            .select((relay_messages::sender_address, relay_messages::content, relay_messages::deleted_at.is_not_null()))
+            .load(&mut conn)
+            .await
+            .unwrap();
+
+        diesel::delete(relay_messages::table.filter(relay_messages::conversation_id.eq(&conversation)))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(relay_conversations::table.filter(relay_conversations::conversation_id.eq(&conversation)))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        redis::cmd("DEL").arg(keys.unread_total(&friend)).query_async::<()>(&mut redis_conn).await.unwrap();
+
+        assert_eq!((deleted.notifications, deleted.messages_tombstoned, deleted.device_tokens, deleted.blocks), (1, 1, 1, 1));
+        assert_eq!(deleted.sessions, 1);
+        assert_eq!(again, DeletedRows::default());
+        assert_eq!((notifications, per_user), (0, 0));
+        // The "app" inbox is its own key only under REDIS_PLATFORM_NAMESPACES
+        assert_eq!(conversations, vec![conversation.clone()]);
+        assert_eq!(cleared, if keys.platform_inbox_pattern(&me).is_some() { 10 } else { 9 });
+        assert!(!chat_cached);
+        assert!(remaining.is_empty(), "left behind: {:?}", remaining);
+        // The friend keeps their own message and a tombstone in place of mine
+        assert_eq!(messages[0], (me.clone(), Vec::new(), true));
+        assert_eq!(messages[1], (friend.clone(), b"ciphertext".to_vec(), false));
+        assert_eq!(friends_count, Some(1));
+    }
+}
@@ -0,0 +1,150 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
+use crate::db::DbConnection;
+use crate::schema::relay_audit_log;
+
+/// A wallet asked for a JWT via `POST /api/v1/auth/token`
+pub const AUTH_TOKEN: &str = "auth.token";
+/// A user deleted their account via `DELETE /api/v1/me`
+pub const ACCOUNT_DELETE: &str = "account.delete";
+pub const BLOCK_CREATE: &str = "block.create";
+pub const BLOCK_DELETE: &str = "block.delete";
+pub const SESSION_REVOKE: &str = "session.revoke";
+
+pub const SUCCESS: &str = "success";
+pub const FAILURE: &str = "failure";
+
+/// A stored audit record
+#[derive(Debug, Clone, Queryable, Selectable, Serialize)]
+#[diesel(table_name = relay_audit_log)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    /// `success` or `failure`
+    pub result: String,
+    /// Error code of a failure
+    pub reason: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An audit record to write, built with `success`/`failure` and the setters
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = relay_audit_log)]
+pub struct NewAuditEntry {
+    pub actor: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub result: &'static str,
+    pub reason: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl NewAuditEntry {
+    pub fn success(action: impl Into<String>) -> Self {
+        Self {
+            actor: None,
+            action: action.into(),
+            target: None,
+            result: SUCCESS,
+            reason: None,
+            ip: None,
+        }
+    }
+
+    pub fn failure(action: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self { result: FAILURE, reason: Some(reason.into()), ..Self::success(action) }
+    }
+
+    /// `success` for `Ok`, otherwise `failure` with the error's code
+    pub fn from_result<T, E>(action: impl Into<String>, result: &Result<T, E>, reason: impl Fn(&E) -> String) -> Self {
+        match result {
+            Ok(_) => Self::success(action),
+            Err(e) => Self::failure(action, reason(e)),
+        }
+    }
+
+    pub fn actor(mut self, actor: impl Into<String>) -> Self {
+        self.actor = Some(actor.into());
+        self
+    }
+
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    pub fn ip(mut self, ip: impl Into<String>) -> Self {
+        self.ip = Some(ip.into());
+        self
+    }
+}
+
+pub async fn record(conn: &mut DbConnection, entry: &NewAuditEntry) -> anyhow::Result<()> {
+    diesel::insert_into(relay_audit_log::table)
+        .values(entry)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Filters for `GET /api/v1/admin/audit`; unset fields match everything
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub result: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl AuditFilter {
+    fn query(&self) -> relay_audit_log::BoxedQuery<'_, diesel::pg::Pg> {
+        let mut query = relay_audit_log::table.into_boxed();
+        if let Some(actor) = &self.actor {
+            query = query.filter(relay_audit_log::actor.eq(actor));
+        }
+        if let Some(action) = &self.action {
+            query = query.filter(relay_audit_log::action.eq(action));
+        }
+        if let Some(target) = &self.target {
+            query = query.filter(relay_audit_log::target.eq(target));
+        }
+        if let Some(result) = &self.result {
+            query = query.filter(relay_audit_log::result.eq(result));
+        }
+        if let Some(since) = self.since {
+            query = query.filter(relay_audit_log::created_at.ge(since));
+        }
+        if let Some(until) = self.until {
+            query = query.filter(relay_audit_log::created_at.lt(until));
+        }
+        query
+    }
+}
+
+/// A page of matching records, newest first, and the total number matching
+pub async fn query(
+    conn: &mut DbConnection,
+    filter: &AuditFilter,
+    limit: i64,
+    offset: i64,
+) -> anyhow::Result<(Vec<AuditEntry>, i64)> {
+    let entries = filter
+        .query()
+        .order((relay_audit_log::created_at.desc(), relay_audit_log::id.desc()))
+        .limit(limit)
+        .offset(offset)
+        .select(AuditEntry::as_select())
+        .load(conn)
+        .await?;
+
+    let total = filter.query().count().get_result(conn).await?;
+
+    Ok((entries, total))
+}
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+/// Exponential backoff between retries of something that keeps failing, starting over after a success
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    /// Consecutive failures; saturates rather than wrapping during a long outage
+    failures: u32,
+}
+
+impl Backoff {
+    /// Waits of up to `base` after the first failure, doubling with each further failure up to `max`
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self { base, max, failures: 0 }
+    }
+
+    /// Redpanda receive errors: up to 1s, doubling to 30s
+    pub fn for_consumer() -> Self {
+        Self::new(Duration::from_secs(1), Duration::from_secs(30))
+    }
+
+    /// Failures since the last success
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Record a failure; how long to wait before trying again
+    pub fn fail(&mut self) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+        self.delay(rand::random::<f64>())
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+
+    /// The wait after the current failure: half the exponential delay plus `jitter` (0.0-1.0) of the other
+    /// half, so callers that failed together don't retry together
+    fn delay(&self, jitter: f64) -> Duration {
+        let exponent = self.failures.saturating_sub(1).min(20);
+        let delay = self.base.saturating_mul(1 << exponent).min(self.max);
+        let half = delay / 2;
+
+        half + half.mul_f64(jitter.clamp(0.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_sequence_and_reset() {
+        let mut backoff = Backoff::for_consumer();
+        let mut ceilings = Vec::new();
+        for _ in 0..8 {
+            let waited = backoff.fail();
+            let ceiling = backoff.delay(1.0);
+            assert!(waited >= backoff.delay(0.0) && waited <= ceiling);
+            ceilings.push(ceiling.as_secs());
+        }
+        assert_eq!(ceilings, vec![1, 2, 4, 8, 16, 30, 30, 30]);
+        assert_eq!(backoff.delay(0.0), Duration::from_secs(15));
+        assert_eq!(backoff.failures(), 8);
+
+        backoff.reset();
+        assert_eq!(backoff.failures(), 0);
+        backoff.fail();
+        assert_eq!(backoff.delay(1.0), Duration::from_secs(1));
+
+        // A long outage neither overflows the count nor the delay
+        let mut outage = Backoff { failures: u32::MAX - 1, ..Backoff::for_consumer() };
+        outage.fail();
+        outage.fail();
+        assert_eq!(outage.failures(), u32::MAX);
+        assert_eq!(outage.delay(1.0), Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,67 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use std::collections::HashSet;
+use crate::db::DbConnection;
+use crate::schema::relay_blocks;
+
+/// Block `blocked` for `blocker`; returns false if the block already existed
+pub async fn block_user(conn: &mut DbConnection, blocker: &str, blocked: &str) -> anyhow::Result<bool> {
+    let inserted = diesel::insert_into(relay_blocks::table)
+        .values((
+            relay_blocks::blocker_address.eq(blocker),
+            relay_blocks::blocked_address.eq(blocked),
+            relay_blocks::created_at.eq(Utc::now()),
+        ))
+        .on_conflict((relay_blocks::blocker_address, relay_blocks::blocked_address))
+        .do_nothing()
+        .execute(conn)
+        .await?;
+
+    Ok(inserted > 0)
+}
+
+/// Remove a block; returns false if there was none
+pub async fn unblock_user(conn: &mut DbConnection, blocker: &str, blocked: &str) -> anyhow::Result<bool> {
+    let deleted = diesel::delete(
+        relay_blocks::table
+            .filter(relay_blocks::blocker_address.eq(blocker))
+            .filter(relay_blocks::blocked_address.eq(blocked)),
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(deleted > 0)
+}
+
+/// Addresses `blocker` has blocked, most recent first
+pub async fn list_blocked(conn: &mut DbConnection, blocker: &str) -> anyhow::Result<Vec<(String, DateTime<Utc>)>> {
+    let blocked = relay_blocks::table
+        .filter(relay_blocks::blocker_address.eq(blocker))
+        .order(relay_blocks::created_at.desc())
+        .select((relay_blocks::blocked_address, relay_blocks::created_at))
+        .load(conn)
+        .await?;
+
+    Ok(blocked)
+}
+
+pub async fn is_blocked(conn: &mut DbConnection, blocker: &str, blocked: &str) -> anyhow::Result<bool> {
+    Ok(blockers_among(conn, &[blocker.to_string()], blocked).await?.contains(blocker))
+}
+
+/// Which of `candidates` have blocked `actor`
+pub async fn blockers_among(conn: &mut DbConnection, candidates: &[String], actor: &str) -> anyhow::Result<HashSet<String>> {
+    if candidates.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let blockers: Vec<String> = relay_blocks::table
+        .filter(relay_blocks::blocked_address.eq(actor))
+        .filter(relay_blocks::blocker_address.eq_any(candidates))
+        .select(relay_blocks::blocker_address)
+        .load(conn)
+        .await?;
+
+    Ok(blockers.into_iter().collect())
+}
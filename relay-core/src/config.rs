@@ -1,4 +1,7 @@
+use crate::encryption::EncryptionAlgorithm;
+use crate::types::{ApnsEnvironment, JwtAlgorithm};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -8,24 +11,58 @@ pub struct Config {
     pub redpanda: RedpandaConfig,
     pub server: ServerConfig,
     pub delivery: DeliveryConfig,
+    pub rate_limit: RateLimitConfig,
+    pub notify: NotifyConfig,
+    pub outbox: OutboxConfig,
+    pub media: MediaConfig,
+    pub retention: RetentionConfig,
+    pub moderation: ModerationConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DatabaseConfig {
     pub url: String,
     pub max_connections: u32,
+    /// Read replica for read-heavy API queries; reads use the primary when unset
+    pub replica_url: Option<String>,
+    /// Apply pending embedded migrations at startup
+    pub run_migrations: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RedisConfig {
     pub url: String,
     pub max_connections: u32,
+    /// Approximate cap on entries kept in each per-user stream (`STREAM:CHAT`, `STREAM:NOTIFY`)
+    pub stream_max_len: usize,
+    /// Streams with no writes for this long are expired
+    pub stream_ttl_seconds: u64,
+    /// Prepended to every key, e.g. `staging:`, so several deployments can share one Redis
+    pub key_prefix: String,
+    /// Keep a separate inbox, notification stream and WebSocket cursor per platform
+    pub platform_namespaces: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RedpandaConfig {
     pub brokers: String,
     pub consumer_group: String,
+    /// Tries a consumer gives each message before dead-lettering it
+    pub handler_max_attempts: u32,
+    pub handler_retry_delay_ms: u64,
+    /// Produce messages that exhausted their attempts to `{topic}.dlq`; when off they are logged and dropped
+    pub dead_letter_enabled: bool,
+    /// How often each consumer's lag is measured
+    pub lag_interval_seconds: u64,
+    /// Partitions further behind than this many messages are logged as a warning
+    pub lag_warn_threshold: i64,
+    /// Messages each consumer handles at once; messages with the same key are still handled in order
+    pub consumer_concurrency: usize,
+    /// Round-trip a message through `relay.healthcheck` at startup and refuse to start if it fails
+    pub startup_probe: bool,
+    pub startup_probe_timeout_ms: u64,
+    /// Prepended to every topic name, e.g. `staging.`, so several deployments can share one cluster
+    pub topic_prefix: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,8 +70,69 @@ pub struct ServerConfig {
     pub api_port: u16,
     pub ws_port: u16,
     pub host: String,
+    /// HS256 signing key; unused for signing with RS256/ES256
     pub jwt_secret: String,
+    pub jwt_algorithm: JwtAlgorithm,
+    /// `kid` header of newly issued tokens, matched against `jwt_verification_keys` during rotation
+    pub jwt_key_id: Option<String>,
+    /// PEM (or base64 PEM) key pair for RS256/ES256
+    pub jwt_private_key: Option<String>,
+    pub jwt_public_key: Option<String>,
+    /// Extra keys tokens are accepted from, e.g. the previous key while its tokens expire
+    pub jwt_verification_keys: Vec<JwtVerificationKey>,
     pub encryption_key: String,
+    /// Version recorded in each new ciphertext and conversation, bumped when `encryption_key` is rotated
+    pub encryption_key_version: i32,
+    /// Earlier master keys by version, kept until every conversation has been re-encrypted off them
+    pub encryption_previous_keys: BTreeMap<i32, String>,
+    /// How often conversations still on a previous key are re-encrypted
+    pub encryption_reencrypt_interval_seconds: u64,
+    /// Cipher for newly stored messages; existing ones are decrypted with whatever their header names
+    pub encryption_algorithm: EncryptionAlgorithm,
+    /// How often the server pings each WebSocket client
+    pub ws_ping_interval_seconds: u64,
+    /// Close a WebSocket if the client has sent nothing (including Pongs) for this long
+    pub ws_pong_timeout_seconds: u64,
+    /// Connections whose heartbeat is older than this are marked disconnected by the reaper
+    pub ws_stale_connection_seconds: u64,
+    /// Frames buffered per WebSocket for a client that reads slower than its streams fill
+    pub ws_send_queue_capacity: usize,
+    /// Close a WebSocket whose send buffer hasn't drained for this long
+    pub ws_max_backlog_seconds: u64,
+    /// Wallet addresses allowed to use admin endpoints
+    pub admin_addresses: Vec<String>,
+    /// Methods and request headers allowed cross-origin when `CORS_ORIGINS` is set; browsers don't
+    /// accept a wildcard for either once credentials are allowed
+    pub cors_allowed_methods: Vec<String>,
+    pub cors_allowed_headers: Vec<String>,
+    /// Requests with a larger body are rejected with 413 before reaching a handler
+    pub max_request_body_bytes: usize,
+    /// Longest message `content` accepted, in characters
+    pub max_message_length: usize,
+    /// Clients encrypt message text themselves and the server stores and returns their ciphertext untouched
+    pub e2ee_mode: bool,
+    /// How long a wallet found in `profiles` is remembered in Redis, skipping the lookup on later sign-ins; 0 disables
+    pub profile_cache_ttl_seconds: u64,
+    /// Signature schemes accepted at sign-in, lowercase (e.g. `ed25519`, `zklogin`); empty accepts every scheme
+    pub auth_allowed_signature_schemes: Vec<String>,
+    /// Oldest sign-in message accepted, by its `Timestamp:`
+    pub auth_message_max_age_seconds: u64,
+    /// Reject sign-in messages without a `Nonce:` line
+    pub auth_require_nonce: bool,
+    /// Reverse proxies in front of the API that each append to `X-Forwarded-For`; 0 ignores the header
+    pub trusted_proxy_count: usize,
+    /// PEM certificate chain and private key; with both set the API serves HTTPS itself instead of plain HTTP
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+}
+
+/// A key API tokens are verified with besides the signing key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JwtVerificationKey {
+    pub kid: String,
+    pub algorithm: JwtAlgorithm,
+    /// The secret for HS256, otherwise the PEM (or base64 PEM) public key
+    pub key: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,9 +142,178 @@ pub struct DeliveryConfig {
     pub apns_team_id: Option<String>,
     pub apns_key_path: Option<String>,
     pub apns_key_content: Option<String>, // Base64 encoded key content (alternative to path)
+    /// APNs gateway; guessed from the bundle id when unset
+    pub apns_environment: Option<ApnsEnvironment>,
     pub fcm_server_key: Option<String>,
     pub resend_api_key: Option<String>,
     pub resend_from_email: Option<String>,
+    /// Endpoint that receives every notification as a signed POST, alongside push and email
+    pub webhook_url: Option<String>,
+    /// HMAC-SHA256 key for the webhook's `X-Relay-Signature` header
+    pub webhook_secret: Option<String>,
+    /// Branding for notification emails; the relay's defaults are used for anything unset
+    pub email_logo_url: Option<String>,
+    pub email_brand_color: Option<String>,
+    pub email_footer: Option<String>,
+    /// Handlebars HTML template for notification emails, from a file or inline (platform configs)
+    pub email_template_path: Option<String>,
+    pub email_template: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotifyConfig {
+    /// Repeat notifications of the same type on the same target within this window are merged into one; 0 disables
+    pub coalesce_window_seconds: u64,
+    /// Decimal places of each token's base unit by symbol, for showing event amounts in whole tokens
+    pub token_decimals: BTreeMap<String, u32>,
+    /// `DELETE /api/v1/notifications` removes rows instead of setting `deleted_at`
+    pub clear_hard_delete: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxConfig {
+    /// Pause between polls when the previous batch succeeded
+    pub poll_interval_ms: u64,
+    pub batch_size: u64,
+    /// Publish attempts per event before it is left for manual inspection
+    pub max_retries: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    /// Messages older than this many days are deleted; unset or 0 keeps them forever
+    pub message_retention_days: Option<u32>,
+    /// Messages deleted per statement, keeping each delete's locks short
+    pub batch_size: i64,
+    pub interval_seconds: u64,
+}
+
+/// External classifier that plaintext messages are checked against before they're stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModerationConfig {
+    /// Unset disables moderation
+    pub url: Option<String>,
+    /// Refuse messages when the classifier is unreachable or answers garbage, instead of letting them through
+    pub fail_closed: bool,
+    pub timeout_ms: u64,
+}
+
+/// S3-compatible bucket that clients upload message attachments to through presigned URLs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaConfig {
+    /// e.g. `https://s3.us-east-1.amazonaws.com`; objects are addressed path-style as `{endpoint}/{bucket}/{key}`
+    pub s3_endpoint: Option<String>,
+    pub s3_region: String,
+    pub s3_bucket: Option<String>,
+    pub s3_access_key_id: Option<String>,
+    pub s3_secret_access_key: Option<String>,
+    /// Base for the URLs embedded in messages (e.g. a CDN); defaults to the bucket's own URL
+    pub public_base_url: Option<String>,
+    pub upload_url_expiry_seconds: u64,
+    pub max_upload_bytes: u64,
+    pub allowed_content_types: Vec<String>,
+}
+
+/// Read a strictly positive integer, falling back to `default` (with a warning) for zero or unparsable values
+fn positive_from_env<T>(var: &str, default: T) -> T
+where
+    T: std::str::FromStr + PartialOrd + Default + std::fmt::Display + Copy,
+{
+    let value = match env::var(var) {
+        Ok(v) => v,
+        Err(_) => return default,
+    };
+
+    match value.trim().parse::<T>() {
+        Ok(parsed) if parsed > T::default() => parsed,
+        _ => {
+            tracing::warn!("Invalid {} value '{}', expected a positive integer; using {}", var, value, default);
+            default
+        }
+    }
+}
+
+/// Read a comma-separated list, falling back to `default` when unset
+fn list_from_env(var: &str, default: &str) -> Vec<String> {
+    env::var(var)
+        .unwrap_or_else(|_| default.to_string())
+        .split(',')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// The prefix in `var`, ending in exactly one `separator`; empty when unset
+fn prefix_from_env(var: &str, separator: char) -> String {
+    let prefix = env::var(var).unwrap_or_default();
+    let prefix = prefix.trim().trim_end_matches(separator);
+    if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("{}{}", prefix, separator)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    pub auth_max_requests: u32,
+    pub auth_window_seconds: u64,
+    pub send_message: RouteRateLimit,
+    pub update_preferences: RouteRateLimit,
+    pub register_device_token: RouteRateLimit,
+    pub default_write: RouteRateLimit,
+}
+
+/// Token bucket limit: `capacity` requests per `period_seconds`, refilled continuously
+/// Configured from env as `{capacity}/{period_seconds}` (e.g. `30/60`); a capacity of 0 disables the limit
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RouteRateLimit {
+    pub capacity: u32,
+    pub period_seconds: u64,
+}
+
+impl RouteRateLimit {
+    fn from_env(var: &str, capacity: u32, period_seconds: u64) -> Self {
+        let default = RouteRateLimit { capacity, period_seconds };
+        let value = match env::var(var) {
+            Ok(v) => v,
+            Err(_) => return default,
+        };
+
+        let parsed = value
+            .split_once('/')
+            .and_then(|(c, p)| Some((c.trim().parse().ok()?, p.trim().parse().ok()?)));
+
+        match parsed {
+            Some((capacity, period_seconds)) if period_seconds > 0 => RouteRateLimit { capacity, period_seconds },
+            _ => {
+                tracing::warn!("Invalid {} value '{}', expected <capacity>/<period_seconds>", var, value);
+                default
+            }
+        }
+    }
+}
+
+/// MYSO amounts arrive in MIST (10^-9 MYSO); social proof tokens are whole units
+const DEFAULT_TOKEN_DECIMALS: [(&str, u32); 2] = [("MYSO", 9), ("SPT", 0)];
+
+/// `DEFAULT_TOKEN_DECIMALS` overridden or extended by `{symbol}={decimals}` pairs (e.g. `MYSO=9,SPT=6`)
+fn token_decimals_from_env(var: &str) -> BTreeMap<String, u32> {
+    let mut decimals: BTreeMap<String, u32> = DEFAULT_TOKEN_DECIMALS
+        .iter()
+        .map(|(symbol, decimals)| (symbol.to_string(), *decimals))
+        .collect();
+
+    let value = env::var(var).unwrap_or_default();
+    for pair in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match pair.split_once('=').and_then(|(s, d)| Some((s.trim(), d.trim().parse().ok()?))) {
+            Some((symbol, places)) if !symbol.is_empty() => {
+                decimals.insert(symbol.to_ascii_uppercase(), places);
+            }
+            _ => tracing::warn!("Invalid {} entry '{}', expected <symbol>=<decimals>", var, pair),
+        }
+    }
+    decimals
 }
 
 impl Config {
@@ -61,6 +328,10 @@ impl Config {
                     .unwrap_or_else(|_| "10".to_string())
                     .parse()
                     .unwrap_or(10),
+                replica_url: env::var("DATABASE_REPLICA_URL").ok().filter(|s| !s.is_empty()),
+                run_migrations: env::var("RUN_MIGRATIONS")
+                    .map(|v| v == "true" || v == "1")
+                    .unwrap_or(false),
             },
             redis: RedisConfig {
                 url: env::var("REDIS_URL")
@@ -69,12 +340,40 @@ impl Config {
                     .unwrap_or_else(|_| "10".to_string())
                     .parse()
                     .unwrap_or(10),
+                stream_max_len: env::var("REDIS_STREAM_MAX_LEN")
+                    .unwrap_or_else(|_| "1000".to_string())
+                    .parse()
+                    .unwrap_or(1000),
+                stream_ttl_seconds: env::var("REDIS_STREAM_TTL_SECONDS")
+                    .unwrap_or_else(|_| "604800".to_string())
+                    .parse()
+                    .unwrap_or(604800),
+                key_prefix: prefix_from_env("RELAY_KEY_PREFIX", ':'),
+                platform_namespaces: env::var("REDIS_PLATFORM_NAMESPACES")
+                    .map(|v| v == "true" || v == "1")
+                    .unwrap_or(false),
             },
             redpanda: RedpandaConfig {
                 brokers: env::var("REDPANDA_BROKERS")
                     .unwrap_or_else(|_| "localhost:9092".to_string()),
                 consumer_group: env::var("REDPANDA_CONSUMER_GROUP")
                     .unwrap_or_else(|_| "relay-consumer-group".to_string()),
+                handler_max_attempts: positive_from_env("CONSUMER_MAX_ATTEMPTS", 3),
+                handler_retry_delay_ms: env::var("CONSUMER_RETRY_DELAY_MS")
+                    .unwrap_or_else(|_| "500".to_string())
+                    .parse()
+                    .unwrap_or(500),
+                dead_letter_enabled: env::var("CONSUMER_DEAD_LETTER_ENABLED")
+                    .map(|v| v != "false" && v != "0")
+                    .unwrap_or(true),
+                lag_interval_seconds: positive_from_env("CONSUMER_LAG_INTERVAL_SECONDS", 30),
+                lag_warn_threshold: positive_from_env("CONSUMER_LAG_WARN_THRESHOLD", 1000),
+                consumer_concurrency: positive_from_env("CONSUMER_CONCURRENCY", 8),
+                startup_probe: env::var("STARTUP_PROBE")
+                    .map(|v| v == "true" || v == "1")
+                    .unwrap_or(false),
+                startup_probe_timeout_ms: positive_from_env("STARTUP_PROBE_TIMEOUT_MS", 10000),
+                topic_prefix: prefix_from_env("TOPIC_PREFIX", '.'),
             },
             server: ServerConfig {
                 host: env::var("SERVER_HOST")
@@ -90,11 +389,105 @@ impl Config {
                     .unwrap_or(8081),
                 jwt_secret: env::var("JWT_SECRET")
                     .unwrap_or_else(|_| "your-secret-key-change-in-production".to_string()),
+                jwt_algorithm: env::var("JWT_ALGORITHM")
+                    .ok()
+                    .filter(|v| !v.is_empty())
+                    .and_then(|v| {
+                        v.parse()
+                            .map_err(|e| tracing::warn!("Invalid JWT_ALGORITHM: {}; using HS256", e))
+                            .ok()
+                    })
+                    .unwrap_or_default(),
+                jwt_key_id: env::var("JWT_KEY_ID").ok().filter(|s| !s.is_empty()),
+                jwt_private_key: env::var("JWT_PRIVATE_KEY").ok().filter(|s| !s.is_empty()),
+                jwt_public_key: env::var("JWT_PUBLIC_KEY").ok().filter(|s| !s.is_empty()),
+                jwt_verification_keys: env::var("JWT_VERIFICATION_KEYS")
+                    .ok()
+                    .filter(|s| !s.trim().is_empty())
+                    .and_then(|v| {
+                        serde_json::from_str(&v)
+                            .map_err(|e| tracing::warn!("Invalid JWT_VERIFICATION_KEYS, ignoring them: {}", e))
+                            .ok()
+                    })
+                    .unwrap_or_default(),
                 encryption_key: env::var("ENCRYPTION_KEY")
                     .unwrap_or_else(|_| {
                         // Generate a default key for development (32 bytes base64)
                         "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef".to_string()
                     }),
+                encryption_key_version: positive_from_env("ENCRYPTION_KEY_VERSION", 1),
+                encryption_previous_keys: env::var("ENCRYPTION_PREVIOUS_KEYS")
+                    .ok()
+                    .filter(|s| !s.trim().is_empty())
+                    .and_then(|v| {
+                        serde_json::from_str(&v)
+                            .map_err(|e| tracing::warn!("Invalid ENCRYPTION_PREVIOUS_KEYS, ignoring them: {}", e))
+                            .ok()
+                    })
+                    .unwrap_or_default(),
+                encryption_reencrypt_interval_seconds: positive_from_env("ENCRYPTION_REENCRYPT_INTERVAL_SECONDS", 3600),
+                encryption_algorithm: env::var("ENCRYPTION_ALGORITHM")
+                    .ok()
+                    .and_then(|v| {
+                        v.parse()
+                            .map_err(|e| tracing::warn!("Invalid ENCRYPTION_ALGORITHM: {}; using aes-256-gcm", e))
+                            .ok()
+                    })
+                    .unwrap_or_default(),
+                ws_ping_interval_seconds: env::var("WS_PING_INTERVAL_SECONDS")
+                    .unwrap_or_else(|_| "30".to_string())
+                    .parse()
+                    .unwrap_or(30),
+                ws_pong_timeout_seconds: env::var("WS_PONG_TIMEOUT_SECONDS")
+                    .unwrap_or_else(|_| "90".to_string())
+                    .parse()
+                    .unwrap_or(90),
+                ws_stale_connection_seconds: env::var("WS_STALE_CONNECTION_SECONDS")
+                    .unwrap_or_else(|_| "180".to_string())
+                    .parse()
+                    .unwrap_or(180),
+                ws_send_queue_capacity: env::var("WS_SEND_QUEUE_CAPACITY")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .filter(|n| *n > 0)
+                    .unwrap_or(256),
+                ws_max_backlog_seconds: env::var("WS_MAX_BACKLOG_SECONDS")
+                    .unwrap_or_else(|_| "30".to_string())
+                    .parse()
+                    .unwrap_or(30),
+                admin_addresses: env::var("ADMIN_ADDRESSES")
+                    .map(|v| {
+                        v.split(',')
+                            .map(|a| a.trim().to_string())
+                            .filter(|a| !a.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                cors_allowed_methods: list_from_env("CORS_ALLOWED_METHODS", "GET,POST,PUT,PATCH,DELETE,OPTIONS"),
+                cors_allowed_headers: list_from_env("CORS_ALLOWED_HEADERS", "Authorization,Content-Type,Idempotency-Key,X-Request-Id"),
+                max_request_body_bytes: positive_from_env("MAX_REQUEST_BODY_BYTES", 1024 * 1024),
+                max_message_length: positive_from_env("MAX_MESSAGE_LENGTH", 10_000),
+                e2ee_mode: env::var("E2EE_MODE")
+                    .map(|v| v == "true" || v == "1")
+                    .unwrap_or(false),
+                profile_cache_ttl_seconds: env::var("PROFILE_CACHE_TTL_SECONDS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0),
+                auth_allowed_signature_schemes: list_from_env("AUTH_ALLOWED_SIGNATURE_SCHEMES", "")
+                    .into_iter()
+                    .map(|s| s.to_lowercase())
+                    .collect(),
+                auth_message_max_age_seconds: positive_from_env("AUTH_MESSAGE_MAX_AGE_SECONDS", 300),
+                auth_require_nonce: env::var("AUTH_REQUIRE_NONCE")
+                    .map(|v| v == "true" || v == "1")
+                    .unwrap_or(false),
+                trusted_proxy_count: env::var("TRUSTED_PROXY_COUNT")
+                    .ok()
+                    .and_then(|v| v.trim().parse().ok())
+                    .unwrap_or(0),
+                tls_cert_path: env::var("TLS_CERT_PATH").ok().filter(|s| !s.is_empty()),
+                tls_key_path: env::var("TLS_KEY_PATH").ok().filter(|s| !s.is_empty()),
             },
             delivery: DeliveryConfig {
                 apns_bundle_id: env::var("APNS_BUNDLE_ID").ok(),
@@ -102,9 +495,84 @@ impl Config {
                 apns_team_id: env::var("APNS_TEAM_ID").ok(),
                 apns_key_path: env::var("APNS_KEY_PATH").ok(),
                 apns_key_content: env::var("APNS_KEY_CONTENT").ok(),
+                apns_environment: env::var("APNS_ENVIRONMENT")
+                    .ok()
+                    .filter(|v| !v.is_empty())
+                    .and_then(|v| {
+                        v.parse()
+                            .map_err(|e| tracing::warn!("Invalid APNS_ENVIRONMENT: {}; guessing from the bundle id", e))
+                            .ok()
+                    }),
                 fcm_server_key: env::var("FCM_SERVER_KEY").ok(),
                 resend_api_key: env::var("RESEND_API_KEY").ok(),
                 resend_from_email: env::var("RESEND_FROM_EMAIL").ok(),
+                webhook_url: env::var("DELIVERY_WEBHOOK_URL").ok().filter(|s| !s.is_empty()),
+                webhook_secret: env::var("DELIVERY_WEBHOOK_SECRET").ok().filter(|s| !s.is_empty()),
+                email_logo_url: env::var("EMAIL_LOGO_URL").ok().filter(|s| !s.is_empty()),
+                email_brand_color: env::var("EMAIL_BRAND_COLOR").ok().filter(|s| !s.is_empty()),
+                email_footer: env::var("EMAIL_FOOTER").ok().filter(|s| !s.is_empty()),
+                email_template_path: env::var("EMAIL_TEMPLATE_PATH").ok().filter(|s| !s.is_empty()),
+                email_template: None,
+            },
+            rate_limit: RateLimitConfig {
+                auth_max_requests: env::var("AUTH_RATE_LIMIT_MAX_REQUESTS")
+                    .unwrap_or_else(|_| "10".to_string())
+                    .parse()
+                    .unwrap_or(10),
+                auth_window_seconds: env::var("AUTH_RATE_LIMIT_WINDOW_SECONDS")
+                    .unwrap_or_else(|_| "60".to_string())
+                    .parse()
+                    .unwrap_or(60),
+                send_message: RouteRateLimit::from_env("RATE_LIMIT_SEND_MESSAGE", 30, 60),
+                update_preferences: RouteRateLimit::from_env("RATE_LIMIT_UPDATE_PREFERENCES", 10, 60),
+                register_device_token: RouteRateLimit::from_env("RATE_LIMIT_REGISTER_DEVICE_TOKEN", 10, 60),
+                default_write: RouteRateLimit::from_env("RATE_LIMIT_DEFAULT_WRITE", 60, 60),
+            },
+            notify: NotifyConfig {
+                coalesce_window_seconds: env::var("NOTIFICATION_COALESCE_WINDOW_SECONDS")
+                    .unwrap_or_else(|_| "300".to_string())
+                    .parse()
+                    .unwrap_or(300),
+                token_decimals: token_decimals_from_env("TOKEN_DECIMALS"),
+                clear_hard_delete: env::var("NOTIFICATION_CLEAR_MODE")
+                    .map(|v| v.eq_ignore_ascii_case("hard"))
+                    .unwrap_or(false),
+            },
+            outbox: OutboxConfig {
+                poll_interval_ms: positive_from_env("OUTBOX_POLL_INTERVAL_MS", 150),
+                batch_size: positive_from_env("OUTBOX_BATCH_SIZE", 100),
+                max_retries: positive_from_env("OUTBOX_MAX_RETRIES", 3),
+            },
+            media: MediaConfig {
+                s3_endpoint: env::var("MEDIA_S3_ENDPOINT").ok().filter(|s| !s.is_empty()),
+                s3_region: env::var("MEDIA_S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+                s3_bucket: env::var("MEDIA_S3_BUCKET").ok().filter(|s| !s.is_empty()),
+                s3_access_key_id: env::var("MEDIA_S3_ACCESS_KEY_ID").ok().filter(|s| !s.is_empty()),
+                s3_secret_access_key: env::var("MEDIA_S3_SECRET_ACCESS_KEY").ok().filter(|s| !s.is_empty()),
+                public_base_url: env::var("MEDIA_PUBLIC_BASE_URL").ok().filter(|s| !s.is_empty()),
+                upload_url_expiry_seconds: positive_from_env("MEDIA_UPLOAD_URL_EXPIRY_SECONDS", 300),
+                max_upload_bytes: positive_from_env("MEDIA_MAX_UPLOAD_BYTES", 25 * 1024 * 1024),
+                allowed_content_types: env::var("MEDIA_ALLOWED_CONTENT_TYPES")
+                    .unwrap_or_else(|_| "image/jpeg,image/png,image/gif,image/webp,video/mp4,video/quicktime,audio/mpeg,audio/mp4".to_string())
+                    .split(',')
+                    .map(|t| t.trim().to_ascii_lowercase())
+                    .filter(|t| !t.is_empty())
+                    .collect(),
+            },
+            retention: RetentionConfig {
+                message_retention_days: env::var("MESSAGE_RETENTION_DAYS")
+                    .ok()
+                    .and_then(|v| v.trim().parse().ok())
+                    .filter(|days| *days > 0),
+                batch_size: positive_from_env("MESSAGE_RETENTION_BATCH_SIZE", 1000),
+                interval_seconds: positive_from_env("MESSAGE_RETENTION_INTERVAL_SECONDS", 3600),
+            },
+            moderation: ModerationConfig {
+                url: env::var("MODERATION_URL").ok().filter(|s| !s.trim().is_empty()),
+                fail_closed: env::var("MODERATION_FAIL_CLOSED")
+                    .map(|v| v == "true" || v == "1")
+                    .unwrap_or(false),
+                timeout_ms: positive_from_env("MODERATION_TIMEOUT_MS", 2000),
             },
         }
     }
@@ -0,0 +1,48 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+
+/// Consecutive receive errors of every consumer running in this process, 0 once it receives again
+#[derive(Debug, Clone, Default)]
+pub struct ConsumerErrors {
+    counts: Arc<RwLock<BTreeMap<String, u32>>>,
+}
+
+impl ConsumerErrors {
+    pub fn record(&self, consumer: &str, consecutive: u32) {
+        self.counts.write().unwrap().insert(consumer.to_string(), consecutive);
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, u32> {
+        self.counts.read().unwrap().clone()
+    }
+
+    /// Prometheus text exposition of each consumer's current error streak
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::from(
+            "# HELP relay_consumer_consecutive_errors Receive errors since the consumer last received a message\n\
+             # TYPE relay_consumer_consecutive_errors gauge\n",
+        );
+        for (consumer, count) in self.snapshot() {
+            let _ = writeln!(out, "relay_consumer_consecutive_errors{{consumer=\"{}\"}} {}", consumer, count);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latest_streak_per_consumer() {
+        let errors = ConsumerErrors::default();
+        errors.record("relay-notify", 3);
+        errors.record("relay-delivery", 1);
+        errors.record("relay-delivery", 0);
+
+        let metrics = errors.to_prometheus();
+        assert!(metrics.contains("relay_consumer_consecutive_errors{consumer=\"relay-notify\"} 3\n"));
+        assert!(metrics.contains("relay_consumer_consecutive_errors{consumer=\"relay-delivery\"} 0\n"));
+    }
+}
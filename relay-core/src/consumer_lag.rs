@@ -0,0 +1,194 @@
+use chrono::{DateTime, Utc};
+use rdkafka::consumer::Consumer;
+use rdkafka::error::KafkaResult;
+use rdkafka::{Offset, TopicPartitionList};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::context::RelayContext;
+use crate::redpanda::RedpandaConsumer;
+
+/// Broker calls made for one measurement give up after this long
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How far one partition's committed offset trails its high watermark
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PartitionLag {
+    pub topic: String,
+    pub partition: i32,
+    /// None until the group has committed anything on the partition
+    pub committed: Option<i64>,
+    pub high_watermark: i64,
+    pub lag: i64,
+}
+
+/// Messages still to be consumed on a partition. Without a committed offset (or with one the broker has
+/// already deleted) the consumer starts from the earliest retained message, so everything retained counts
+pub fn compute_lag(low_watermark: i64, high_watermark: i64, committed: Option<i64>) -> i64 {
+    let from = match committed {
+        Some(offset) if offset >= low_watermark => offset,
+        _ => low_watermark,
+    };
+    (high_watermark - from).max(0)
+}
+
+/// The latest measurement for one consumer group
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsumerLagReport {
+    pub total: i64,
+    pub partitions: Vec<PartitionLag>,
+    pub measured_at: DateTime<Utc>,
+}
+
+impl ConsumerLagReport {
+    pub fn new(partitions: Vec<PartitionLag>) -> Self {
+        Self {
+            total: partitions.iter().map(|p| p.lag).sum(),
+            partitions,
+            measured_at: Utc::now(),
+        }
+    }
+}
+
+/// Latest lag of every consumer running in this process, shared between the reporters and the API
+#[derive(Debug, Clone, Default)]
+pub struct ConsumerLag {
+    reports: Arc<RwLock<BTreeMap<String, ConsumerLagReport>>>,
+}
+
+impl ConsumerLag {
+    pub fn record(&self, consumer: &str, report: ConsumerLagReport) {
+        self.reports.write().unwrap().insert(consumer.to_string(), report);
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, ConsumerLagReport> {
+        self.reports.read().unwrap().clone()
+    }
+
+    /// `{consumer: {total, partitions, measured_at}}`
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self.snapshot()).unwrap_or_default()
+    }
+
+    /// Prometheus text exposition of the per-partition lag
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::from(
+            "# HELP relay_consumer_lag Messages between the committed offset and the high watermark\n\
+             # TYPE relay_consumer_lag gauge\n",
+        );
+        for (consumer, report) in self.snapshot() {
+            for p in &report.partitions {
+                let _ = writeln!(
+                    out,
+                    "relay_consumer_lag{{consumer=\"{}\",topic=\"{}\",partition=\"{}\"}} {}",
+                    consumer, p.topic, p.partition, p.lag
+                );
+            }
+        }
+        out
+    }
+}
+
+/// Committed offsets against watermarks for every partition currently assigned to `consumer`
+fn measure(consumer: &RedpandaConsumer) -> KafkaResult<Vec<PartitionLag>> {
+    let committed: TopicPartitionList = consumer.committed_offsets(consumer.assignment()?, FETCH_TIMEOUT)?;
+    committed
+        .elements()
+        .iter()
+        .map(|elem| {
+            let (low, high) = consumer.fetch_watermarks(elem.topic(), elem.partition(), FETCH_TIMEOUT)?;
+            let committed = match elem.offset() {
+                Offset::Offset(offset) => Some(offset),
+                _ => None,
+            };
+            Ok(PartitionLag {
+                topic: elem.topic().to_string(),
+                partition: elem.partition(),
+                committed,
+                high_watermark: high,
+                lag: compute_lag(low, high, committed),
+            })
+        })
+        .collect()
+}
+
+/// Measure `consumer`'s lag every `CONSUMER_LAG_INTERVAL_SECONDS`, publish it on `ctx.consumer_lag` and
+/// warn about partitions over `CONSUMER_LAG_WARN_THRESHOLD`
+pub async fn report(ctx: RelayContext, consumer: RedpandaConsumer, name: &'static str) {
+    let config = &ctx.config.redpanda;
+    let mut interval = tokio::time::interval(Duration::from_secs(config.lag_interval_seconds));
+    loop {
+        interval.tick().await;
+
+        // The broker calls block, so keep them off the runtime's worker threads
+        let measuring = consumer.clone();
+        let partitions = match tokio::task::spawn_blocking(move || measure(&measuring)).await {
+            Ok(Ok(partitions)) => partitions,
+            Ok(Err(e)) => {
+                tracing::warn!("Failed to measure lag of consumer {}: {}", name, e);
+                continue;
+            }
+            Err(e) => {
+                tracing::warn!("Lag measurement of consumer {} panicked: {}", name, e);
+                continue;
+            }
+        };
+
+        for p in partitions.iter().filter(|p| p.lag > config.lag_warn_threshold) {
+            tracing::warn!(
+                "Consumer {} is {} messages behind on {}[{}] (committed {:?}, high watermark {})",
+                name,
+                p.lag,
+                p.topic,
+                p.partition,
+                p.committed,
+                p.high_watermark
+            );
+        }
+        ctx.consumer_lag.record(name, ConsumerLagReport::new(partitions));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_lag() {
+        // Caught up, behind, and a committed offset past a just-fetched watermark
+        assert_eq!(compute_lag(0, 100, Some(100)), 0);
+        assert_eq!(compute_lag(0, 100, Some(40)), 60);
+        assert_eq!(compute_lag(0, 100, Some(105)), 0);
+
+        // Nothing committed yet: every retained message is outstanding
+        assert_eq!(compute_lag(20, 100, None), 80);
+        // Committed offset already deleted by retention: resumes from the low watermark
+        assert_eq!(compute_lag(50, 100, Some(10)), 50);
+        // Empty partition
+        assert_eq!(compute_lag(0, 0, None), 0);
+    }
+
+    #[test]
+    fn test_reports_totals_and_metrics() {
+        let partition = |partition, committed, high_watermark| PartitionLag {
+            topic: "events.message.created".to_string(),
+            partition,
+            committed,
+            high_watermark,
+            lag: compute_lag(0, high_watermark, committed),
+        };
+        let lag = ConsumerLag::default();
+        lag.record("relay-messaging", ConsumerLagReport::new(vec![partition(0, Some(90), 100), partition(1, None, 5)]));
+
+        let json = lag.to_json();
+        assert_eq!(json["relay-messaging"]["total"], 15);
+        assert_eq!(json["relay-messaging"]["partitions"][1]["committed"], serde_json::Value::Null);
+
+        let metrics = lag.to_prometheus();
+        assert!(metrics.contains("relay_consumer_lag{consumer=\"relay-messaging\",topic=\"events.message.created\",partition=\"0\"} 10\n"));
+        assert!(metrics.contains("relay_consumer_lag{consumer=\"relay-messaging\",topic=\"events.message.created\",partition=\"1\"} 5\n"));
+    }
+}
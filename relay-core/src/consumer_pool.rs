@@ -0,0 +1,216 @@
+use rdkafka::message::OwnedMessage;
+use rdkafka::Message;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// Messages waiting per worker before `dispatch` stops reading from the broker
+const WORKER_QUEUE: usize = 32;
+
+#[derive(Debug, Default)]
+struct PartitionOffsets {
+    in_flight: BTreeSet<i64>,
+    first: Option<i64>,
+    highest: i64,
+    stored: Option<i64>,
+}
+
+/// Offsets handed to workers per partition, so only offsets below every unfinished one are stored.
+/// Workers finish out of order; storing a later offset first would skip the earlier message on restart
+#[derive(Debug, Default)]
+pub struct OffsetTracker {
+    partitions: HashMap<(String, i32), PartitionOffsets>,
+}
+
+impl OffsetTracker {
+    pub fn start(&mut self, topic: &str, partition: i32, offset: i64) {
+        let p = self.partitions.entry((topic.to_string(), partition)).or_default();
+        p.in_flight.insert(offset);
+        p.first = Some(p.first.map_or(offset, |first| first.min(offset)));
+        p.highest = p.highest.max(offset);
+    }
+
+    /// Mark `offset` handled; returns the offset to store when everything up to it is now handled
+    pub fn finish(&mut self, topic: &str, partition: i32, offset: i64) -> Option<i64> {
+        let p = self.partitions.get_mut(&(topic.to_string(), partition))?;
+        p.in_flight.remove(&offset);
+
+        let done = match p.in_flight.first() {
+            Some(lowest) => lowest - 1,
+            None => p.highest,
+        };
+        if done < p.first? || p.stored.is_some_and(|stored| stored >= done) {
+            return None;
+        }
+        p.stored = Some(done);
+        Some(done)
+    }
+}
+
+/// Fans consumed messages out to `CONSUMER_CONCURRENCY` workers. Messages with the same key always go
+/// to the same worker, so they're handled in order; keyless messages are spread round-robin
+pub struct WorkerPool {
+    workers: Vec<mpsc::Sender<OwnedMessage>>,
+    tracker: Arc<Mutex<OffsetTracker>>,
+    next: AtomicUsize,
+}
+
+impl WorkerPool {
+    /// `handler` runs each message; `store` is called with the offset below which every message of
+    /// a partition has been handled, and must be a cheap, non-blocking call like `store_offset`
+    pub fn new<H, Fut, S>(concurrency: usize, handler: H, store: S) -> Self
+    where
+        H: Fn(OwnedMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+        S: Fn(&str, i32, i64) + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        let store = Arc::new(store);
+        let tracker = Arc::new(Mutex::new(OffsetTracker::default()));
+
+        let workers = (0..concurrency.max(1))
+            .map(|_| {
+                let (tx, mut rx) = mpsc::channel::<OwnedMessage>(WORKER_QUEUE);
+                let handler = handler.clone();
+                let store = store.clone();
+                let tracker = tracker.clone();
+                tokio::spawn(async move {
+                    while let Some(message) = rx.recv().await {
+                        let (topic, partition, offset) = (message.topic().to_string(), message.partition(), message.offset());
+                        handler(message).await;
+                        let done = tracker
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .finish(&topic, partition, offset);
+                        if let Some(offset) = done {
+                            store(&topic, partition, offset);
+                        }
+                    }
+                });
+                tx
+            })
+            .collect();
+
+        Self {
+            workers,
+            tracker,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Queue a message on its worker, waiting while that worker's queue is full
+    pub async fn dispatch(&self, message: OwnedMessage) {
+        let (partition, offset) = (message.partition(), message.offset());
+        self.tracker
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .start(message.topic(), partition, offset);
+
+        let worker = self.worker_for(message.key());
+        if let Err(e) = self.workers[worker].send(message).await {
+            tracing::error!("Consumer worker stopped; message at {}/{}@{} was not handled", e.0.topic(), partition, offset);
+        }
+    }
+
+    fn worker_for(&self, key: Option<&[u8]>) -> usize {
+        match key {
+            Some(key) => {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                (hasher.finish() % self.workers.len() as u64) as usize
+            }
+            None => self.next.fetch_add(1, Ordering::Relaxed) % self.workers.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdkafka::message::Timestamp;
+    use std::time::Duration;
+    use tokio::sync::Notify;
+
+    fn message(key: &str, offset: i64) -> OwnedMessage {
+        OwnedMessage::new(None, Some(key.as_bytes().to_vec()), "events.test".to_string(), Timestamp::NotAvailable, 0, offset, None)
+    }
+
+    #[tokio::test]
+    async fn test_slow_message_does_not_block_other_keys() {
+        let release = Arc::new(Notify::new());
+        let (handled_tx, mut handled_rx) = mpsc::unbounded_channel::<String>();
+        let stored = Arc::new(Mutex::new(Vec::new()));
+
+        let pool = {
+            let release = release.clone();
+            let stored = stored.clone();
+            WorkerPool::new(
+                4,
+                move |message: OwnedMessage| {
+                    let release = release.clone();
+                    let handled_tx = handled_tx.clone();
+                    let key = String::from_utf8(message.key().unwrap().to_vec()).unwrap();
+                    async move {
+                        if key.starts_with("slow") {
+                            release.notified().await;
+                        }
+                        handled_tx.send(key).unwrap();
+                    }
+                },
+                move |_topic: &str, _partition: i32, offset: i64| stored.lock().unwrap().push(offset),
+            )
+        };
+
+        // Keys that land on other workers than the slow one
+        let slow_worker = pool.worker_for(Some(b"slow"));
+        let fast: Vec<String> = (0..)
+            .map(|i| format!("fast-{}", i))
+            .filter(|key| pool.worker_for(Some(key.as_bytes())) != slow_worker)
+            .take(5)
+            .collect();
+
+        pool.dispatch(message("slow", 0)).await;
+        // Same key as the slow message, so it has to wait its turn
+        pool.dispatch(message("slow", 1)).await;
+        for (i, key) in fast.iter().enumerate() {
+            pool.dispatch(message(key, i as i64 + 2)).await;
+        }
+
+        let mut handled = Vec::new();
+        for _ in 0..fast.len() {
+            let key = tokio::time::timeout(Duration::from_secs(1), handled_rx.recv()).await.unwrap().unwrap();
+            handled.push(key);
+        }
+        handled.sort();
+        let mut expected = fast.clone();
+        expected.sort();
+        assert_eq!(handled, expected);
+        // Nothing is stored past the unfinished slow message
+        assert!(stored.lock().unwrap().is_empty());
+
+        release.notify_one();
+        assert_eq!(handled_rx.recv().await.as_deref(), Some("slow"));
+        release.notify_one();
+        assert_eq!(handled_rx.recv().await.as_deref(), Some("slow"));
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(stored.lock().unwrap().last(), Some(&6));
+    }
+
+    #[test]
+    fn test_offsets_stored_up_to_lowest_unfinished() {
+        let mut tracker = OffsetTracker::default();
+        for offset in 10..14 {
+            tracker.start("t", 0, offset);
+        }
+
+        assert_eq!(tracker.finish("t", 0, 11), None);
+        assert_eq!(tracker.finish("t", 0, 10), Some(11));
+        assert_eq!(tracker.finish("t", 0, 13), None);
+        assert_eq!(tracker.finish("t", 0, 12), Some(13));
+    }
+}
@@ -1,5 +1,8 @@
 use std::sync::Arc;
-use crate::config::Config;
+use crate::config::{Config, DatabaseConfig};
+use crate::consumer_errors::ConsumerErrors;
+use crate::consumer_lag::ConsumerLag;
+use crate::moderation::Moderator;
 use crate::db::{DbPool, create_pool as create_db_pool};
 use crate::redis::{RedisPool, create_pool as create_redis_pool};
 use crate::redpanda::{RedpandaProducer, RedpandaConsumer, create_producer, create_consumer};
@@ -7,22 +10,43 @@ use crate::redpanda::{RedpandaProducer, RedpandaConsumer, create_producer, creat
 #[derive(Clone)]
 pub struct RelayContext {
     pub config: Arc<Config>,
+    /// Primary database; all writes and transactions go here
     pub db_pool: Arc<DbPool>,
+    /// Replica for read-only queries that tolerate replication lag; the primary when no replica is configured
+    pub db_read_pool: Arc<DbPool>,
     pub redis_pool: RedisPool,
     pub redpanda_producer: RedpandaProducer,
+    /// Latest lag of the consumers running in this process
+    pub consumer_lag: ConsumerLag,
+    /// Current receive-error streak of the consumers running in this process
+    pub consumer_errors: ConsumerErrors,
+    /// Classifier client for `MODERATION_URL`; lets everything through when unset
+    pub moderator: Arc<Moderator>,
 }
 
 impl RelayContext {
     pub async fn new(config: Config) -> anyhow::Result<Self> {
         let db_pool = create_db_pool(&config.database).await?;
+        let db_read_pool = match &config.database.replica_url {
+            Some(url) => {
+                tracing::info!("Using read replica for read-only queries");
+                create_db_pool(&DatabaseConfig { url: url.clone(), ..config.database.clone() }).await?
+            }
+            None => db_pool.clone(),
+        };
         let redis_pool = create_redis_pool(&config.redis).await?;
         let redpanda_producer = create_producer(&config.redpanda)?;
+        let moderator = Arc::new(Moderator::new(&config.moderation)?);
 
         Ok(RelayContext {
             config: Arc::new(config),
             db_pool,
+            db_read_pool,
             redis_pool,
             redpanda_producer,
+            consumer_lag: ConsumerLag::default(),
+            consumer_errors: ConsumerErrors::default(),
+            moderator,
         })
     }
 
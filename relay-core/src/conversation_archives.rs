@@ -0,0 +1,74 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use crate::db::DbConnection;
+use crate::schema::relay_conversation_archives;
+
+/// Archive `conversation_id` for `user_address`; returns false if it was already archived
+pub async fn archive_conversation(conn: &mut DbConnection, user_address: &str, conversation_id: &str) -> anyhow::Result<bool> {
+    let inserted = diesel::insert_into(relay_conversation_archives::table)
+        .values((
+            relay_conversation_archives::user_address.eq(user_address),
+            relay_conversation_archives::conversation_id.eq(conversation_id),
+        ))
+        .on_conflict((relay_conversation_archives::user_address, relay_conversation_archives::conversation_id))
+        .do_nothing()
+        .execute(conn)
+        .await?;
+
+    Ok(inserted > 0)
+}
+
+/// Move a conversation back to the inbox; returns false if it wasn't archived. Takes a plain connection so
+/// messages stored in a transaction can unarchive their conversation in it
+pub async fn unarchive_conversation(conn: &mut AsyncPgConnection, user_address: &str, conversation_id: &str) -> QueryResult<bool> {
+    let deleted = diesel::delete(
+        relay_conversation_archives::table
+            .filter(relay_conversation_archives::user_address.eq(user_address))
+            .filter(relay_conversation_archives::conversation_id.eq(conversation_id)),
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(deleted > 0)
+}
+
+pub async fn is_archived(conn: &mut DbConnection, user_address: &str, conversation_id: &str) -> anyhow::Result<bool> {
+    let archived: Option<i64> = relay_conversation_archives::table
+        .filter(relay_conversation_archives::user_address.eq(user_address))
+        .filter(relay_conversation_archives::conversation_id.eq(conversation_id))
+        .select(relay_conversation_archives::id)
+        .first(conn)
+        .await
+        .optional()?;
+
+    Ok(archived.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables"]
+    async fn test_archive_is_per_user() {
+        let config = crate::Config::from_env();
+        let pool = crate::db::create_pool(&config.database).await.unwrap();
+        let mut conn = pool.get().await.unwrap();
+
+        let run = uuid::Uuid::new_v4();
+        let (alice, bob) = (format!("0xalice-{}", run), format!("0xbob-{}", run));
+        let conversation = format!("{}:{}", alice, bob);
+
+        let archived = archive_conversation(&mut conn, &alice, &conversation).await.unwrap();
+        let archived_again = archive_conversation(&mut conn, &alice, &conversation).await.unwrap();
+        let for_alice = is_archived(&mut conn, &alice, &conversation).await.unwrap();
+        let for_bob = is_archived(&mut conn, &bob, &conversation).await.unwrap();
+        let unarchived = unarchive_conversation(&mut conn, &alice, &conversation).await.unwrap();
+        let unarchived_again = unarchive_conversation(&mut conn, &alice, &conversation).await.unwrap();
+
+        assert!(archived && !archived_again);
+        assert!(for_alice && !for_bob);
+        assert!(unarchived && !unarchived_again);
+        assert!(!is_archived(&mut conn, &alice, &conversation).await.unwrap());
+    }
+}
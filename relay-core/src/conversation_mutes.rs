@@ -0,0 +1,53 @@
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use std::collections::HashSet;
+use crate::db::DbConnection;
+use crate::schema::relay_conversation_mutes;
+
+/// Mute `conversation_id` for `user_address`; returns false if it was already muted
+pub async fn mute_conversation(conn: &mut DbConnection, user_address: &str, conversation_id: &str) -> anyhow::Result<bool> {
+    let inserted = diesel::insert_into(relay_conversation_mutes::table)
+        .values((
+            relay_conversation_mutes::user_address.eq(user_address),
+            relay_conversation_mutes::conversation_id.eq(conversation_id),
+        ))
+        .on_conflict((relay_conversation_mutes::user_address, relay_conversation_mutes::conversation_id))
+        .do_nothing()
+        .execute(conn)
+        .await?;
+
+    Ok(inserted > 0)
+}
+
+/// Remove a mute; returns false if the conversation wasn't muted
+pub async fn unmute_conversation(conn: &mut DbConnection, user_address: &str, conversation_id: &str) -> anyhow::Result<bool> {
+    let deleted = diesel::delete(
+        relay_conversation_mutes::table
+            .filter(relay_conversation_mutes::user_address.eq(user_address))
+            .filter(relay_conversation_mutes::conversation_id.eq(conversation_id)),
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(deleted > 0)
+}
+
+pub async fn is_muted(conn: &mut DbConnection, user_address: &str, conversation_id: &str) -> anyhow::Result<bool> {
+    Ok(muted_among(conn, user_address, &[conversation_id.to_string()]).await?.contains(conversation_id))
+}
+
+/// Which of `conversation_ids` `user_address` has muted
+pub async fn muted_among(conn: &mut DbConnection, user_address: &str, conversation_ids: &[String]) -> anyhow::Result<HashSet<String>> {
+    if conversation_ids.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let muted: Vec<String> = relay_conversation_mutes::table
+        .filter(relay_conversation_mutes::user_address.eq(user_address))
+        .filter(relay_conversation_mutes::conversation_id.eq_any(conversation_ids))
+        .select(relay_conversation_mutes::conversation_id)
+        .load(conn)
+        .await?;
+
+    Ok(muted.into_iter().collect())
+}
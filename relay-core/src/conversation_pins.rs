@@ -0,0 +1,134 @@
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use std::collections::HashSet;
+use crate::db::DbConnection;
+use crate::schema::relay_conversation_pins;
+
+/// Most conversations one user may have pinned at once
+pub const MAX_PINNED_CONVERSATIONS: i64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinOutcome {
+    Pinned,
+    AlreadyPinned,
+    /// The user already has `MAX_PINNED_CONVERSATIONS` pinned; nothing was changed
+    LimitReached,
+}
+
+/// Pin `conversation_id` for `user_address`, unless the cap is reached. The user's pins are counted and added
+/// to under a transaction-scoped advisory lock, so concurrent pins can't push them past the cap
+pub async fn pin_conversation(conn: &mut DbConnection, user_address: &str, conversation_id: &str) -> QueryResult<PinOutcome> {
+    conn.transaction(|conn| async move { pin_in(conn, user_address, conversation_id).await }.scope_boxed()).await
+}
+
+async fn pin_in(conn: &mut AsyncPgConnection, user_address: &str, conversation_id: &str) -> QueryResult<PinOutcome> {
+    diesel::sql_query("SELECT pg_advisory_xact_lock(hashtext('relay_conversation_pins:' || $1))")
+        .bind::<Text, _>(user_address)
+        .execute(conn)
+        .await?;
+
+    let pinned: Vec<String> = relay_conversation_pins::table
+        .filter(relay_conversation_pins::user_address.eq(user_address))
+        .select(relay_conversation_pins::conversation_id)
+        .load(conn)
+        .await?;
+    if pinned.iter().any(|id| id == conversation_id) {
+        return Ok(PinOutcome::AlreadyPinned);
+    }
+    if pinned.len() as i64 >= MAX_PINNED_CONVERSATIONS {
+        return Ok(PinOutcome::LimitReached);
+    }
+
+    diesel::insert_into(relay_conversation_pins::table)
+        .values((
+            relay_conversation_pins::user_address.eq(user_address),
+            relay_conversation_pins::conversation_id.eq(conversation_id),
+        ))
+        .on_conflict((relay_conversation_pins::user_address, relay_conversation_pins::conversation_id))
+        .do_nothing()
+        .execute(conn)
+        .await?;
+
+    Ok(PinOutcome::Pinned)
+}
+
+/// Remove a pin; returns false if the conversation wasn't pinned
+pub async fn unpin_conversation(conn: &mut DbConnection, user_address: &str, conversation_id: &str) -> QueryResult<bool> {
+    let deleted = diesel::delete(
+        relay_conversation_pins::table
+            .filter(relay_conversation_pins::user_address.eq(user_address))
+            .filter(relay_conversation_pins::conversation_id.eq(conversation_id)),
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(deleted > 0)
+}
+
+pub async fn is_pinned(conn: &mut DbConnection, user_address: &str, conversation_id: &str) -> QueryResult<bool> {
+    Ok(pinned_among(conn, user_address, &[conversation_id.to_string()]).await?.contains(conversation_id))
+}
+
+/// Which of `conversation_ids` `user_address` has pinned
+pub async fn pinned_among(conn: &mut DbConnection, user_address: &str, conversation_ids: &[String]) -> QueryResult<HashSet<String>> {
+    if conversation_ids.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let pinned: Vec<String> = relay_conversation_pins::table
+        .filter(relay_conversation_pins::user_address.eq(user_address))
+        .filter(relay_conversation_pins::conversation_id.eq_any(conversation_ids))
+        .select(relay_conversation_pins::conversation_id)
+        .load(conn)
+        .await?;
+
+    Ok(pinned.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables"]
+    async fn test_pins_capped_per_user() {
+        let config = crate::Config::from_env();
+        let pool = crate::db::create_pool(&config.database).await.unwrap();
+        let mut conn = pool.get().await.unwrap();
+
+        let run = uuid::Uuid::new_v4();
+        let (alice, bob) = (format!("0xalice-{}", run), format!("0xbob-{}", run));
+        let conversations: Vec<String> = (0..=MAX_PINNED_CONVERSATIONS).map(|i| format!("{}:0xfriend-{}-{}", alice, i, run)).collect();
+        let (allowed, over_cap) = conversations.split_at(MAX_PINNED_CONVERSATIONS as usize);
+
+        let mut outcomes = Vec::new();
+        for conversation in allowed {
+            outcomes.push(pin_conversation(&mut conn, &alice, conversation).await.unwrap());
+        }
+        let again = pin_conversation(&mut conn, &alice, &allowed[0]).await.unwrap();
+        let capped = pin_conversation(&mut conn, &alice, &over_cap[0]).await.unwrap();
+        // Pins are per user, so Bob's own cap is untouched
+        let for_bob = pin_conversation(&mut conn, &bob, &over_cap[0]).await.unwrap();
+        let pinned = pinned_among(&mut conn, &alice, &conversations).await.unwrap();
+
+        // Unpinning one makes room again
+        let unpinned = unpin_conversation(&mut conn, &alice, &allowed[0]).await.unwrap();
+        let after_unpin = pin_conversation(&mut conn, &alice, &over_cap[0]).await.unwrap();
+
+        diesel::delete(relay_conversation_pins::table.filter(relay_conversation_pins::user_address.eq_any([&alice, &bob])))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        assert!(outcomes.iter().all(|outcome| *outcome == PinOutcome::Pinned));
+        assert_eq!(again, PinOutcome::AlreadyPinned);
+        assert_eq!(capped, PinOutcome::LimitReached);
+        assert_eq!(for_bob, PinOutcome::Pinned);
+        assert_eq!(pinned.len(), MAX_PINNED_CONVERSATIONS as usize);
+        assert!(!pinned.contains(&over_cap[0]));
+        assert!(unpinned);
+        assert_eq!(after_unpin, PinOutcome::Pinned);
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::redis::{RedisConnection, RedisKeys};
+use crate::types::MessageContentType;
+
+/// Longest last-message preview in the conversation list, in characters
+pub const PREVIEW_CHARS: usize = 100;
+
+/// Previews of conversations nobody writes to expire; the conversation list falls back to Postgres
+const PREVIEW_TTL_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+/// The latest message of a conversation as shown in the conversation list
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConversationPreview {
+    /// The message's id
+    pub id: i64,
+    pub sender_address: String,
+    pub content_type: String,
+    /// Truncated plaintext; None for non-text, client-encrypted and undecryptable messages
+    pub preview: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ConversationPreview {
+    /// `text` is the message's plaintext; it's cut to `PREVIEW_CHARS` and dropped for `e2ee` messages
+    pub fn new(
+        id: i64,
+        sender_address: &str,
+        content_type: &str,
+        text: Option<&str>,
+        created_at: DateTime<Utc>,
+        e2ee: bool,
+    ) -> Self {
+        let preview = text
+            .filter(|_| content_type == MessageContentType::Text.as_str() && !e2ee)
+            .map(|text| text.chars().take(PREVIEW_CHARS).collect());
+
+        Self {
+            id,
+            sender_address: sender_address.to_string(),
+            content_type: content_type.to_string(),
+            preview,
+            created_at,
+        }
+    }
+}
+
+/// Record a conversation's newest message
+pub async fn store(
+    redis_conn: &mut RedisConnection,
+    keys: RedisKeys<'_>,
+    conversation_id: &str,
+    preview: &ConversationPreview,
+) -> Result<()> {
+    redis::cmd("SET")
+        .arg(keys.conversation_preview(conversation_id))
+        .arg(serde_json::to_string(preview)?)
+        .arg("EX")
+        .arg(PREVIEW_TTL_SECONDS)
+        .query_async::<()>(redis_conn)
+        .await?;
+    Ok(())
+}
+
+/// Cache a preview read from Postgres, without overwriting one a concurrent send just stored
+pub async fn store_if_missing(
+    redis_conn: &mut RedisConnection,
+    keys: RedisKeys<'_>,
+    conversation_id: &str,
+    preview: &ConversationPreview,
+) -> Result<()> {
+    redis::cmd("SET")
+        .arg(keys.conversation_preview(conversation_id))
+        .arg(serde_json::to_string(preview)?)
+        .arg("NX")
+        .arg("EX")
+        .arg(PREVIEW_TTL_SECONDS)
+        .query_async::<Option<String>>(redis_conn)
+        .await?;
+    Ok(())
+}
+
+/// Cached previews by conversation id; missing and unreadable entries are left out
+pub async fn load_many(
+    redis_conn: &mut RedisConnection,
+    keys: RedisKeys<'_>,
+    conversation_ids: &[String],
+) -> Result<HashMap<String, ConversationPreview>> {
+    if conversation_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let preview_keys: Vec<String> = conversation_ids.iter().map(|id| keys.conversation_preview(id)).collect();
+    let values: Vec<Option<String>> = redis::cmd("MGET").arg(&preview_keys).query_async(redis_conn).await?;
+
+    Ok(conversation_ids
+        .iter()
+        .zip(values)
+        .filter_map(|(id, value)| {
+            let preview = serde_json::from_str(value.as_deref()?)
+                .map_err(|e| tracing::warn!("Ignoring unreadable preview for conversation {}: {}", id, e))
+                .ok()?;
+            Some((id.clone(), preview))
+        })
+        .collect())
+}
+
+/// Drop a conversation's cached preview, e.g. after its latest message was deleted
+pub async fn invalidate(redis_conn: &mut RedisConnection, keys: RedisKeys<'_>, conversation_id: &str) -> Result<()> {
+    redis::cmd("DEL")
+        .arg(keys.conversation_preview(conversation_id))
+        .query_async::<()>(redis_conn)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_truncated_and_omitted_when_unreadable() {
+        let long = "é".repeat(PREVIEW_CHARS + 20);
+        let preview = ConversationPreview::new(1, "0xa", "text", Some(&long), Utc::now(), false);
+        assert_eq!(preview.preview.unwrap().chars().count(), PREVIEW_CHARS);
+
+        let e2ee = ConversationPreview::new(2, "0xa", "text", Some("c2VhbGVk"), Utc::now(), true);
+        assert_eq!(e2ee.preview, None);
+
+        let image = ConversationPreview::new(3, "0xa", "image", Some(""), Utc::now(), false);
+        assert_eq!(image.preview, None);
+    }
+}
@@ -59,12 +59,13 @@ pub async fn create_pool(config: &DatabaseConfig) -> Result<Arc<DbPool>> {
     Err(anyhow!("Failed to establish database connection after 5 attempts"))
 }
 
-fn mask_database_url(url: &str) -> String {
-    if let Some(at_pos) = url.find('@') {
+/// The URL with its password replaced by `****`, for logs and diagnostics
+pub fn mask_database_url(url: &str) -> String {
+    if let Some(at_pos) = url.rfind('@') {
         let (before_at, after_at) = url.split_at(at_pos);
         if let Some(colon_pos) = before_at.rfind(':') {
             let (protocol_user, _password) = before_at.split_at(colon_pos);
-            format!("{}:****@{}", protocol_user, after_at)
+            format!("{}:****{}", protocol_user, after_at)
         } else {
             "postgres://****@****".to_string()
         }
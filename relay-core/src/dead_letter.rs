@@ -0,0 +1,231 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Duration;
+
+use crate::config::RedpandaConfig;
+use crate::redpanda::{produce_message, RedpandaProducer};
+
+/// Topic that messages from `topic` are parked on when every attempt to handle them failed
+pub fn dead_letter_topic(topic: &str) -> String {
+    format!("{}.dlq", topic)
+}
+
+/// Where dead letters are produced to; Redpanda in production
+pub trait DeadLetterSink {
+    fn send(&self, topic: &str, key: Option<&str>, payload: &[u8]) -> impl Future<Output = Result<()>> + Send;
+}
+
+impl DeadLetterSink for RedpandaProducer {
+    fn send(&self, topic: &str, key: Option<&str>, payload: &[u8]) -> impl Future<Output = Result<()>> + Send {
+        produce_message(self, topic, key, payload)
+    }
+}
+
+/// A consumed message, as much of it as a dead letter needs
+#[derive(Debug, Clone, Copy)]
+pub struct SourceMessage<'a> {
+    pub topic: &'a str,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<&'a [u8]>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> SourceMessage<'a> {
+    pub fn from_message<M: rdkafka::Message>(message: &'a M) -> Self {
+        Self {
+            topic: message.topic(),
+            partition: message.partition(),
+            offset: message.offset(),
+            key: message.key(),
+            payload: message.payload().unwrap_or_default(),
+        }
+    }
+}
+
+/// What lands on `{topic}.dlq`: the original payload plus why it couldn't be handled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub source_topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<String>,
+    /// Lossily decoded as UTF-8; payloads are JSON, and a poison one may not even be that
+    pub payload: String,
+    pub error: String,
+    pub attempts: u32,
+    pub failed_at: DateTime<Utc>,
+}
+
+impl DeadLetter {
+    fn new(message: &SourceMessage<'_>, error: &anyhow::Error, attempts: u32) -> Self {
+        Self {
+            source_topic: message.topic.to_string(),
+            partition: message.partition,
+            offset: message.offset,
+            key: message.key.map(|k| String::from_utf8_lossy(k).into_owned()),
+            payload: String::from_utf8_lossy(message.payload).into_owned(),
+            error: format!("{:#}", error),
+            attempts,
+            failed_at: Utc::now(),
+        }
+    }
+}
+
+/// Run `handle` up to `handler_max_attempts` times; if it never succeeds the message is produced to its
+/// dead-letter topic and Ok is returned, so the consumer can move past it without losing it
+/// Errors only when dead-lettering is disabled or the dead letter itself couldn't be produced
+pub async fn handle_or_dead_letter<S, F, Fut>(
+    sink: &S,
+    config: &RedpandaConfig,
+    message: SourceMessage<'_>,
+    mut handle: F,
+) -> Result<()>
+where
+    S: DeadLetterSink,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let max_attempts = config.handler_max_attempts.max(1);
+    let mut attempts = 1;
+    let error = loop {
+        match handle().await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempts >= max_attempts => break e,
+            Err(e) => {
+                tracing::warn!(
+                    "Attempt {}/{} for {} offset {} failed, retrying: {}",
+                    attempts,
+                    max_attempts,
+                    message.topic,
+                    message.offset,
+                    e
+                );
+                tokio::time::sleep(Duration::from_millis(config.handler_retry_delay_ms)).await;
+                attempts += 1;
+            }
+        }
+    };
+
+    if !config.dead_letter_enabled {
+        return Err(error);
+    }
+
+    let topic = dead_letter_topic(message.topic);
+    let letter = serde_json::to_vec(&DeadLetter::new(&message, &error, attempts))?;
+    let key = message.key.and_then(|k| std::str::from_utf8(k).ok());
+    sink.send(&topic, key, &letter)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to dead-letter message after error ({}): {}", error, e))?;
+
+    tracing::error!(
+        "Moved {} offset {} to {} after {} attempts: {}",
+        message.topic,
+        message.offset,
+        topic,
+        attempts,
+        error
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingSink {
+        sent: Mutex<Vec<(String, Vec<u8>)>>,
+    }
+
+    impl DeadLetterSink for RecordingSink {
+        fn send(&self, topic: &str, _key: Option<&str>, payload: &[u8]) -> impl Future<Output = Result<()>> + Send {
+            self.sent.lock().unwrap().push((topic.to_string(), payload.to_vec()));
+            async { Ok(()) }
+        }
+    }
+
+    fn config(dead_letter_enabled: bool) -> RedpandaConfig {
+        RedpandaConfig {
+            brokers: "localhost:9092".to_string(),
+            consumer_group: "test".to_string(),
+            handler_max_attempts: 3,
+            handler_retry_delay_ms: 0,
+            dead_letter_enabled,
+            lag_interval_seconds: 30,
+            lag_warn_threshold: 1000,
+            consumer_concurrency: 1,
+            startup_probe: false,
+            startup_probe_timeout_ms: 10000,
+            topic_prefix: String::new(),
+        }
+    }
+
+    fn message(payload: &[u8]) -> SourceMessage<'_> {
+        SourceMessage {
+            topic: "events.post.tip",
+            partition: 0,
+            offset: 42,
+            key: Some(b"evt-1"),
+            payload,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_poison_message_dead_lettered_once() {
+        let sink = RecordingSink::default();
+        let calls = AtomicU32::new(0);
+
+        let result = handle_or_dead_letter(&sink, &config(true), message(b"{not json"), || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(anyhow::anyhow!("expected value at line 1 column 2"))
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let sent = sink.sent.into_inner().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "events.post.tip.dlq");
+
+        let letter: DeadLetter = serde_json::from_slice(&sent[0].1).unwrap();
+        assert_eq!(letter.source_topic, "events.post.tip");
+        assert_eq!(letter.offset, 42);
+        assert_eq!(letter.payload, "{not json");
+        assert_eq!(letter.attempts, 3);
+        assert!(letter.error.contains("expected value"));
+    }
+
+    #[tokio::test]
+    async fn test_retry_success_not_dead_lettered() {
+        let sink = RecordingSink::default();
+        let calls = AtomicU32::new(0);
+
+        let result = handle_or_dead_letter(&sink, &config(true), message(b"{}"), || async {
+            if calls.fetch_add(1, Ordering::SeqCst) == 0 { Err(anyhow::anyhow!("transient")) } else { Ok(()) }
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert!(sink.sent.into_inner().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter_disabled_returns_error() {
+        let sink = RecordingSink::default();
+
+        let result = handle_or_dead_letter(&sink, &config(false), message(b"{}"), || async {
+            Err(anyhow::anyhow!("always fails"))
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert!(sink.sent.into_inner().unwrap().is_empty());
+    }
+}
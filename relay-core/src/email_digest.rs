@@ -0,0 +1,75 @@
+use chrono::{DateTime, Duration, Utc};
+
+/// How often a user receives a summary email instead of one email per notification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmailDigest {
+    #[default]
+    Off,
+    Hourly,
+    Daily,
+}
+
+impl EmailDigest {
+    /// Parse the stored/API value (`off`, `hourly`, `daily`)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(Self::Off),
+            "hourly" => Some(Self::Hourly),
+            "daily" => Some(Self::Daily),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Hourly => "hourly",
+            Self::Daily => "daily",
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        *self != Self::Off
+    }
+
+    pub fn period(&self) -> Option<Duration> {
+        match self {
+            Self::Off => None,
+            Self::Hourly => Some(Duration::hours(1)),
+            Self::Daily => Some(Duration::days(1)),
+        }
+    }
+
+    /// Whether a digest should go out at `now`; a user who has never had one is due immediately
+    pub fn is_due(&self, last_digest_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+        match (self.period(), last_digest_at) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(period), Some(last)) => last + period <= now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_round_trips() {
+        for digest in [EmailDigest::Off, EmailDigest::Hourly, EmailDigest::Daily] {
+            assert_eq!(EmailDigest::parse(digest.as_str()), Some(digest));
+        }
+        assert_eq!(EmailDigest::parse("weekly"), None);
+    }
+
+    #[test]
+    fn test_is_due() {
+        let now = Utc::now();
+
+        assert!(!EmailDigest::Off.is_due(None, now));
+        assert!(EmailDigest::Daily.is_due(None, now));
+        assert!(EmailDigest::Hourly.is_due(Some(now - Duration::minutes(61)), now));
+        assert!(!EmailDigest::Hourly.is_due(Some(now - Duration::minutes(59)), now));
+        assert!(!EmailDigest::Daily.is_due(Some(now - Duration::hours(23)), now));
+    }
+}
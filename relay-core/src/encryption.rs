@@ -4,74 +4,316 @@ use aes_gcm::{
 };
 use anyhow::{Result, anyhow};
 use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::ChaCha20Poly1305;
 use hex;
 use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
 use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
 
-/// Encrypt message content using AES-256-GCM
+/// Both ciphers use 96-bit nonces
+const NONCE_LEN: usize = 12;
+
+/// Ciphertexts are `HEADER_MAGIC, HEADER_VERSION, algorithm id, key version (u32, big-endian), nonce, ciphertext`.
+/// Version 1 headers have no key version, and rows written before the header existed are bare `nonce, ciphertext`
+/// under AES-256-GCM; both are tried against each key
+const HEADER_MAGIC: u8 = 0xE7;
+const HEADER_VERSION: u8 = 2;
+const HEADER_LEN: usize = 7;
+const UNKEYED_HEADER_VERSION: u8 = 1;
+const UNKEYED_HEADER_LEN: usize = 3;
+
+/// Authenticated cipher used for message content; `key` is the per-conversation key from HKDF
+pub trait Cipher: Send + Sync {
+    fn algorithm(&self) -> EncryptionAlgorithm;
+
+    /// Returns `nonce || ciphertext`
+    fn encrypt(&self, key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>>;
+
+    /// Takes the output of [`Cipher::encrypt`]
+    fn decrypt(&self, key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// AES-256-GCM, the algorithm every message was encrypted with before `ENCRYPTION_ALGORITHM` existed
+pub struct AesGcmCipher;
+
+impl Cipher for AesGcmCipher {
+    fn algorithm(&self) -> EncryptionAlgorithm {
+        EncryptionAlgorithm::Aes256Gcm
+    }
+
+    fn encrypt(&self, key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|e| anyhow!("Encryption failed: {}", e))?;
+
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    fn decrypt(&self, key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>> {
+        let (nonce, ciphertext) = split_nonce(data)?;
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|e| anyhow!("Decryption failed: {}", e))
+    }
+}
+
+/// ChaCha20-Poly1305 (RFC 8439), for deployments that must avoid AES
+pub struct ChaCha20Cipher;
+
+impl Cipher for ChaCha20Cipher {
+    fn algorithm(&self) -> EncryptionAlgorithm {
+        EncryptionAlgorithm::ChaCha20Poly1305
+    }
+
+    fn encrypt(&self, key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let cipher = ChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(key));
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|e| anyhow!("Encryption failed: {}", e))?;
+
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    fn decrypt(&self, key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>> {
+        let (nonce, ciphertext) = split_nonce(data)?;
+        ChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(key))
+            .decrypt(chacha20poly1305::Nonce::from_slice(nonce), ciphertext)
+            .map_err(|e| anyhow!("Decryption failed: {}", e))
+    }
+}
+
+fn split_nonce(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    if data.len() < NONCE_LEN {
+        return Err(anyhow!("Invalid encrypted data: too short"));
+    }
+    Ok(data.split_at(NONCE_LEN))
+}
+
+/// Cipher for new messages, chosen with `ENCRYPTION_ALGORITHM`; the id is stored in each ciphertext's header,
+/// so existing messages stay readable after switching
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EncryptionAlgorithm {
+    #[default]
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl EncryptionAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Aes256Gcm => "aes-256-gcm",
+            Self::ChaCha20Poly1305 => "chacha20-poly1305",
+        }
+    }
+
+    /// Byte written to the ciphertext header; never reuse an id
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::Aes256Gcm => 1,
+            Self::ChaCha20Poly1305 => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Self::Aes256Gcm),
+            2 => Some(Self::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    pub fn cipher(&self) -> &'static dyn Cipher {
+        match self {
+            Self::Aes256Gcm => &AesGcmCipher,
+            Self::ChaCha20Poly1305 => &ChaCha20Cipher,
+        }
+    }
+}
+
+impl FromStr for EncryptionAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "aes-256-gcm" | "aes256gcm" => Ok(Self::Aes256Gcm),
+            "chacha20-poly1305" | "chacha20poly1305" => Ok(Self::ChaCha20Poly1305),
+            _ => Err(anyhow!("unknown encryption algorithm {:?}, expected aes-256-gcm or chacha20-poly1305", s)),
+        }
+    }
+}
+
+impl fmt::Display for EncryptionAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Master keys by version: `ENCRYPTION_KEY` under `ENCRYPTION_KEY_VERSION`, which new messages are encrypted
+/// with, and the `ENCRYPTION_PREVIOUS_KEYS` that messages not yet re-encrypted still need
+#[derive(Debug, Clone, Copy)]
+pub struct EncryptionKeys<'a> {
+    pub current_version: i32,
+    current: &'a str,
+    previous: &'a BTreeMap<i32, String>,
+}
+
+impl<'a> EncryptionKeys<'a> {
+    pub fn new(current_version: i32, current: &'a str, previous: &'a BTreeMap<i32, String>) -> Self {
+        Self { current_version, current, previous }
+    }
+
+    pub fn get(&self, version: i32) -> Option<&'a str> {
+        if version == self.current_version {
+            return Some(self.current);
+        }
+        self.previous.get(&version).map(String::as_str)
+    }
+
+    /// The current key, then previous ones newest first
+    fn all(self) -> impl Iterator<Item = &'a str> {
+        let current_version = self.current_version;
+        let previous = self.previous.iter().rev().filter(move |(v, _)| **v != current_version);
+        std::iter::once(self.current).chain(previous.map(|(_, key)| key.as_str()))
+    }
+}
+
+impl crate::config::ServerConfig {
+    pub fn encryption_keys(&self) -> EncryptionKeys<'_> {
+        EncryptionKeys::new(self.encryption_key_version, &self.encryption_key, &self.encryption_previous_keys)
+    }
+}
+
+/// Encrypt message content with `algorithm` under the current key
 /// Derives a key from the master encryption key and conversation ID for per-conversation encryption
 pub fn encrypt_message(
     content: &str,
     conversation_id: &str,
-    master_key: &str,
+    keys: EncryptionKeys<'_>,
+    algorithm: EncryptionAlgorithm,
 ) -> Result<String> {
     // Derive a conversation-specific key using HKDF
-    let key = derive_conversation_key(master_key, conversation_id)?;
-    
-    // Create cipher
-    let cipher = Aes256Gcm::new(&key);
+    let key = derive_conversation_key(keys.current, conversation_id)?;
     
-    // Generate a random nonce
-    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = algorithm.cipher().encrypt(&key, content.as_bytes())?;
     
-    // Encrypt the content
-    let ciphertext = cipher
-        .encrypt(&nonce, content.as_bytes())
-        .map_err(|e| anyhow!("Encryption failed: {}", e))?;
-    
-    // Combine nonce and ciphertext, then base64 encode
-    let mut encrypted_data = nonce.to_vec();
+    // Prefix the versioned header, then base64 encode
+    let mut encrypted_data = vec![HEADER_MAGIC, HEADER_VERSION, algorithm.id()];
+    encrypted_data.extend_from_slice(&(keys.current_version as u32).to_be_bytes());
     encrypted_data.extend_from_slice(&ciphertext);
     
     Ok(STANDARD.encode(&encrypted_data))
 }
 
-/// Decrypt message content using AES-256-GCM
+/// Decrypt message content with the algorithm and key version named in its header
 pub fn decrypt_message(
     encrypted_content: &str,
     conversation_id: &str,
-    master_key: &str,
+    keys: EncryptionKeys<'_>,
 ) -> Result<String> {
+    open(encrypted_content, conversation_id, keys).map(|(plaintext, _)| plaintext)
+}
+
+/// Re-encrypt message content under the current key with `algorithm`; None when it already is under the
+/// current key, so there's nothing to rewrite
+pub fn reencrypt_message(
+    encrypted_content: &str,
+    conversation_id: &str,
+    keys: EncryptionKeys<'_>,
+    algorithm: EncryptionAlgorithm,
+) -> Result<Option<String>> {
+    let (plaintext, key_version) = open(encrypted_content, conversation_id, keys)?;
+    if key_version == Some(keys.current_version) {
+        return Ok(None);
+    }
+    encrypt_message(&plaintext, conversation_id, keys, algorithm).map(Some)
+}
+
+/// Plaintext and the key version its header names, None for ciphertexts from before key versions
+fn open(encrypted_content: &str, conversation_id: &str, keys: EncryptionKeys<'_>) -> Result<(String, Option<i32>)> {
     // Decode base64
     let encrypted_data = STANDARD
         .decode(encrypted_content)
         .map_err(|e| anyhow!("Base64 decode failed: {}", e))?;
-    
-    if encrypted_data.len() < 12 {
-        return Err(anyhow!("Invalid encrypted data: too short"));
+
+    // A headerless AES-GCM nonce can start with the header bytes by chance, so a failed header falls back to it
+    let headerless = || try_keys(keys, conversation_id, |key| AesGcmCipher.decrypt(key, &encrypted_data));
+    let (plaintext, key_version) = match parse_header(&encrypted_data) {
+        Some(Header { algorithm, key_version: Some(version), ciphertext }) => {
+            let decrypted = keys
+                .get(version)
+                .ok_or_else(|| anyhow!("Unknown encryption key version {}", version))
+                .and_then(|master_key| algorithm.cipher().decrypt(&derive_conversation_key(master_key, conversation_id)?, ciphertext));
+            match decrypted {
+                Ok(plaintext) => (plaintext, Some(version)),
+                Err(e) => (headerless().map_err(|_| e)?, None),
+            }
+        }
+        Some(Header { algorithm, key_version: None, ciphertext }) => {
+            let plaintext = try_keys(keys, conversation_id, |key| algorithm.cipher().decrypt(key, ciphertext))
+                .or_else(|e| headerless().map_err(|_| e))?;
+            (plaintext, None)
+        }
+        None => (headerless()?, None),
+    };
+
+    let plaintext = String::from_utf8(plaintext)
+        .map_err(|e| anyhow!("Invalid UTF-8 after decryption: {}", e))?;
+    Ok((plaintext, key_version))
+}
+
+/// `decrypt` with each key in turn, for ciphertexts that don't say which key they're under
+fn try_keys(
+    keys: EncryptionKeys<'_>,
+    conversation_id: &str,
+    decrypt: impl Fn(&[u8; 32]) -> Result<Vec<u8>>,
+) -> Result<Vec<u8>> {
+    let mut last_error = anyhow!("No encryption keys configured");
+    for master_key in keys.all() {
+        match decrypt(&derive_conversation_key(master_key, conversation_id)?) {
+            Ok(plaintext) => return Ok(plaintext),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+struct Header<'a> {
+    algorithm: EncryptionAlgorithm,
+    /// None in version 1 headers
+    key_version: Option<i32>,
+    ciphertext: &'a [u8],
+}
+
+/// The header and remaining bytes, or None for a headerless (legacy AES-GCM) ciphertext
+fn parse_header(data: &[u8]) -> Option<Header<'_>> {
+    match data {
+        [HEADER_MAGIC, HEADER_VERSION, id, a, b, c, d, rest @ ..] if rest.len() >= NONCE_LEN => Some(Header {
+            algorithm: EncryptionAlgorithm::from_id(*id)?,
+            key_version: Some(i32::try_from(u32::from_be_bytes([*a, *b, *c, *d])).ok()?),
+            ciphertext: &data[HEADER_LEN..],
+        }),
+        [HEADER_MAGIC, UNKEYED_HEADER_VERSION, id, rest @ ..] if rest.len() >= NONCE_LEN => Some(Header {
+            algorithm: EncryptionAlgorithm::from_id(*id)?,
+            key_version: None,
+            ciphertext: &data[UNKEYED_HEADER_LEN..],
+        }),
+        _ => None,
     }
-    
-    // Extract nonce (first 12 bytes) and ciphertext
-    let nonce = Nonce::from_slice(&encrypted_data[..12]);
-    let ciphertext = &encrypted_data[12..];
-    
-    // Derive the same conversation-specific key
-    let key = derive_conversation_key(master_key, conversation_id)?;
-    
-    // Create cipher
-    let cipher = Aes256Gcm::new(&key);
-    
-    // Decrypt
-    let plaintext = cipher
-        .decrypt(nonce, ciphertext)
-        .map_err(|e| anyhow!("Decryption failed: {}", e))?;
-    
-    String::from_utf8(plaintext)
-        .map_err(|e| anyhow!("Invalid UTF-8 after decryption: {}", e))
 }
 
 /// Derive a conversation-specific encryption key using HKDF
-fn derive_conversation_key(master_key: &str, conversation_id: &str) -> Result<Key<Aes256Gcm>> {
+fn derive_conversation_key(master_key: &str, conversation_id: &str) -> Result<[u8; 32]> {
     // Decode master key from hex or use directly as bytes
     let master_key_bytes = if master_key.len() == 64 {
         // Assume hex encoding (32 bytes = 64 hex chars)
@@ -90,24 +332,100 @@ fn derive_conversation_key(master_key: &str, conversation_id: &str) -> Result<Ke
     hk.expand(conversation_id.as_bytes(), &mut okm)
         .map_err(|e| anyhow!("HKDF expansion failed: {}", e))?;
     
-    Ok(*Key::<Aes256Gcm>::from_slice(&okm))
+    Ok(okm)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const MASTER_KEY: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+    const NEXT_MASTER_KEY: &str = "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543210";
+
+    fn only(previous: &BTreeMap<i32, String>) -> EncryptionKeys<'_> {
+        EncryptionKeys::new(1, MASTER_KEY, previous)
+    }
+
     #[test]
     fn test_encrypt_decrypt() {
-        let master_key = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+        let none = BTreeMap::new();
         let conversation_id = "conv-123";
         let original = "Hello, this is a secret message!";
         
-        let encrypted = encrypt_message(original, conversation_id, master_key).unwrap();
-        assert_ne!(encrypted, original);
-        
-        let decrypted = decrypt_message(&encrypted, conversation_id, master_key).unwrap();
-        assert_eq!(decrypted, original);
+        for algorithm in [EncryptionAlgorithm::Aes256Gcm, EncryptionAlgorithm::ChaCha20Poly1305] {
+            let encrypted = encrypt_message(original, conversation_id, only(&none), algorithm).unwrap();
+            assert_ne!(encrypted, original);
+            
+            let decrypted = decrypt_message(&encrypted, conversation_id, only(&none)).unwrap();
+            assert_eq!(decrypted, original);
+        }
     }
-}
 
+    #[test]
+    fn test_decrypt_legacy_headerless_message() {
+        let none = BTreeMap::new();
+        let key = derive_conversation_key(MASTER_KEY, "conv-123").unwrap();
+        let legacy = STANDARD.encode(AesGcmCipher.encrypt(&key, b"written before headers").unwrap());
+
+        assert_eq!(decrypt_message(&legacy, "conv-123", only(&none)).unwrap(), "written before headers");
+    }
+
+    #[test]
+    fn test_cross_algorithm_decrypt_fails() {
+        let none = BTreeMap::new();
+        let key = derive_conversation_key(MASTER_KEY, "conv-123").unwrap();
+        let aes = AesGcmCipher.encrypt(&key, b"secret").unwrap();
+        let chacha = ChaCha20Cipher.encrypt(&key, b"secret").unwrap();
+
+        assert!(ChaCha20Cipher.decrypt(&key, &aes).is_err());
+        assert!(AesGcmCipher.decrypt(&key, &chacha).is_err());
+
+        // Relabelling the header doesn't let the other cipher open it
+        let mut relabelled = STANDARD
+            .decode(encrypt_message("secret", "conv-123", only(&none), EncryptionAlgorithm::ChaCha20Poly1305).unwrap())
+            .unwrap();
+        relabelled[2] = EncryptionAlgorithm::Aes256Gcm.id();
+        assert!(decrypt_message(&STANDARD.encode(&relabelled), "conv-123", only(&none)).is_err());
+    }
+
+    #[test]
+    fn test_rotated_key_reads_old_messages_until_reencrypted() {
+        let none = BTreeMap::new();
+        let old = only(&none);
+        let key = derive_conversation_key(MASTER_KEY, "conv-123").unwrap();
+        let mut unkeyed = vec![HEADER_MAGIC, UNKEYED_HEADER_VERSION, EncryptionAlgorithm::Aes256Gcm.id()];
+        unkeyed.extend(AesGcmCipher.encrypt(&key, b"before key versions").unwrap());
+        let unkeyed = STANDARD.encode(unkeyed);
+        let keyed = encrypt_message("under key 1", "conv-123", old, EncryptionAlgorithm::Aes256Gcm).unwrap();
+
+        // Version 2 is current; version 1 is kept as a previous key
+        let previous = BTreeMap::from([(1, MASTER_KEY.to_string())]);
+        let rotated = EncryptionKeys::new(2, NEXT_MASTER_KEY, &previous);
+        assert_eq!(decrypt_message(&unkeyed, "conv-123", rotated).unwrap(), "before key versions");
+        assert_eq!(decrypt_message(&keyed, "conv-123", rotated).unwrap(), "under key 1");
+
+        // Re-encrypted messages no longer need the old key, and aren't rewritten twice
+        let rotated_only = EncryptionKeys::new(2, NEXT_MASTER_KEY, &none);
+        for (encrypted, original) in [(&unkeyed, "before key versions"), (&keyed, "under key 1")] {
+            assert!(decrypt_message(encrypted, "conv-123", rotated_only).is_err());
+            let rewritten = reencrypt_message(encrypted, "conv-123", rotated, EncryptionAlgorithm::ChaCha20Poly1305).unwrap().unwrap();
+            assert_eq!(decrypt_message(&rewritten, "conv-123", rotated_only).unwrap(), original);
+            assert!(reencrypt_message(&rewritten, "conv-123", rotated, EncryptionAlgorithm::ChaCha20Poly1305).unwrap().is_none());
+        }
+
+        // A ciphertext naming a key that's been dropped is an error, not a guess
+        let err = decrypt_message(&keyed, "conv-123", rotated_only).unwrap_err();
+        assert!(err.to_string().contains("Unknown encryption key version 1"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_encryption_algorithm() {
+        assert_eq!("aes-256-gcm".parse::<EncryptionAlgorithm>().unwrap(), EncryptionAlgorithm::Aes256Gcm);
+        assert_eq!("ChaCha20-Poly1305".parse::<EncryptionAlgorithm>().unwrap(), EncryptionAlgorithm::ChaCha20Poly1305);
+        assert!("des".parse::<EncryptionAlgorithm>().is_err());
+
+        for algorithm in [EncryptionAlgorithm::Aes256Gcm, EncryptionAlgorithm::ChaCha20Poly1305] {
+            assert_eq!(EncryptionAlgorithm::from_id(algorithm.id()), Some(algorithm));
+        }
+    }
+}
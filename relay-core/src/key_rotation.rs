@@ -0,0 +1,270 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
+
+use crate::context::RelayContext;
+use crate::db::DbConnection;
+use crate::encryption::{reencrypt_message, EncryptionAlgorithm, EncryptionKeys};
+use crate::lock;
+use crate::schema::{relay_conversations, relay_messages};
+use crate::types::MessageContentType;
+
+/// Messages read per query while re-encrypting a conversation
+const BATCH_SIZE: i64 = 500;
+
+/// What re-encrypting one conversation did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConversationReencryption {
+    pub conversation_id: String,
+    /// The conversation's `conversation_key_version` afterwards; unchanged while any message failed
+    pub key_version: i32,
+    pub rewritten: usize,
+    /// Messages that couldn't be decrypted with any configured key, left as they are
+    pub failed: usize,
+}
+
+/// What one background pass re-encrypted
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReencryptReport {
+    pub conversations: usize,
+    pub rewritten: usize,
+    pub failed: usize,
+}
+
+/// Rewrite each of the conversation's server-encrypted messages under the current key, then move the
+/// conversation to the current key version. Client-encrypted (`e2ee`) and deleted messages have nothing to
+/// rewrite. Each row is only overwritten if its content hasn't changed since it was read. None when the
+/// conversation doesn't exist
+pub async fn reencrypt_conversation(
+    conn: &mut DbConnection,
+    keys: EncryptionKeys<'_>,
+    algorithm: EncryptionAlgorithm,
+    conversation_id: &str,
+) -> Result<Option<ConversationReencryption>> {
+    let key_version: Option<i32> = relay_conversations::table
+        .filter(relay_conversations::conversation_id.eq(conversation_id))
+        .select(relay_conversations::conversation_key_version)
+        .first(conn)
+        .await
+        .optional()?;
+    let Some(mut key_version) = key_version else {
+        return Ok(None);
+    };
+
+    let (mut rewritten, mut failed) = (0, 0);
+    let mut after = 0i64;
+    loop {
+        let rows: Vec<(i64, Vec<u8>)> = relay_messages::table
+            .filter(relay_messages::conversation_id.eq(conversation_id))
+            .filter(relay_messages::id.gt(after))
+            .filter(relay_messages::content_type.eq(MessageContentType::Text.as_str()))
+            .filter(relay_messages::e2ee.eq(false))
+            .filter(relay_messages::deleted_at.is_null())
+            .order(relay_messages::id)
+            .limit(BATCH_SIZE)
+            .select((relay_messages::id, relay_messages::content))
+            .load(conn)
+            .await?;
+        let Some((last_id, _)) = rows.last() else {
+            break;
+        };
+        after = *last_id;
+        let batch_len = rows.len();
+
+        for (id, content) in rows {
+            let reencrypted = match reencrypt_message(&STANDARD.encode(&content), conversation_id, keys, algorithm) {
+                Ok(Some(reencrypted)) => STANDARD.decode(reencrypted)?,
+                Ok(None) => continue,
+                Err(e) => {
+                    tracing::warn!("Failed to re-encrypt message {} in {}: {}", id, conversation_id, e);
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            rewritten += diesel::update(
+                relay_messages::table.filter(relay_messages::id.eq(id)).filter(relay_messages::content.eq(&content)),
+            )
+            .set(relay_messages::content.eq(reencrypted))
+            .execute(conn)
+            .await?;
+        }
+
+        if (batch_len as i64) < BATCH_SIZE {
+            break;
+        }
+    }
+
+    if failed == 0 && key_version != keys.current_version {
+        diesel::update(relay_conversations::table.filter(relay_conversations::conversation_id.eq(conversation_id)))
+            .set(relay_conversations::conversation_key_version.eq(keys.current_version))
+            .execute(conn)
+            .await?;
+        key_version = keys.current_version;
+    }
+
+    Ok(Some(ConversationReencryption { conversation_id: conversation_id.to_string(), key_version, rewritten, failed }))
+}
+
+/// Re-encrypt every conversation whose `conversation_key_version` isn't `ENCRYPTION_KEY_VERSION`
+pub async fn run_once(ctx: &RelayContext) -> Result<ReencryptReport> {
+    let server = &ctx.config.server;
+    let mut conn = ctx.db_pool.get().await?;
+
+    let mut report = ReencryptReport::default();
+    let mut after = 0i64;
+    loop {
+        let stale: Vec<(i64, String)> = relay_conversations::table
+            .filter(relay_conversations::conversation_key_version.ne(server.encryption_key_version))
+            .filter(relay_conversations::id.gt(after))
+            .order(relay_conversations::id)
+            .limit(BATCH_SIZE)
+            .select((relay_conversations::id, relay_conversations::conversation_id))
+            .load(&mut conn)
+            .await?;
+        let Some((last_id, _)) = stale.last() else {
+            break;
+        };
+        after = *last_id;
+        let batch_len = stale.len();
+
+        for (_, conversation_id) in stale {
+            let reencrypted = reencrypt_conversation(&mut conn, server.encryption_keys(), server.encryption_algorithm, &conversation_id).await?;
+            if let Some(reencrypted) = reencrypted {
+                report.conversations += 1;
+                report.rewritten += reencrypted.rewritten;
+                report.failed += reencrypted.failed;
+            }
+        }
+
+        if (batch_len as i64) < BATCH_SIZE {
+            break;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Every `ENCRYPTION_REENCRYPT_INTERVAL_SECONDS`, move conversations off previous keys; only on the instance
+/// holding the `reencrypt` lock, and only while `ENCRYPTION_PREVIOUS_KEYS` names any
+pub async fn run(ctx: RelayContext) {
+    if ctx.config.server.encryption_previous_keys.is_empty() {
+        tracing::info!("ENCRYPTION_PREVIOUS_KEYS not set, no conversations to re-encrypt");
+        return;
+    }
+
+    tracing::info!("Re-encrypting conversations under key version {}", ctx.config.server.encryption_key_version);
+    lock::with_leader_lock(&ctx, "reencrypt", || reencrypt_forever(&ctx)).await
+}
+
+async fn reencrypt_forever(ctx: &RelayContext) {
+    let interval_seconds = ctx.config.server.encryption_reencrypt_interval_seconds;
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_seconds));
+    loop {
+        interval.tick().await;
+
+        match run_once(ctx).await {
+            Ok(report) if report.conversations > 0 => tracing::info!(
+                "Re-encrypted {} messages in {} conversations ({} could not be decrypted)",
+                report.rewritten,
+                report.conversations,
+                report.failed
+            ),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Re-encryption pass failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::{decrypt_message, encrypt_message};
+    use std::collections::BTreeMap;
+
+    const OLD_KEY: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+    const NEW_KEY: &str = "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543210";
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables"]
+    async fn test_conversation_migrated_across_key_versions() {
+        let config = crate::Config::from_env();
+        let pool = crate::db::create_pool(&config.database).await.unwrap();
+        let mut conn = pool.get().await.unwrap();
+
+        let run = uuid::Uuid::new_v4();
+        let (alice, bob) = (format!("0xalice-{}", run), format!("0xbob-{}", run));
+        let conversation = format!("{}:{}", alice, bob);
+        diesel::insert_into(relay_conversations::table)
+            .values((
+                relay_conversations::conversation_id.eq(conversation.clone()),
+                relay_conversations::participant1_address.eq(alice.clone()),
+                relay_conversations::participant2_address.eq(bob.clone()),
+                relay_conversations::conversation_key_version.eq(1),
+            ))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        // Two messages under key 1, and one client-encrypted message that's never touched
+        let none = BTreeMap::new();
+        let old = EncryptionKeys::new(1, OLD_KEY, &none);
+        let message = |content: Vec<u8>, e2ee: bool| {
+            (
+                relay_messages::conversation_id.eq(conversation.clone()),
+                relay_messages::sender_address.eq(alice.clone()),
+                relay_messages::recipient_address.eq(bob.clone()),
+                relay_messages::content.eq(content),
+                relay_messages::e2ee.eq(e2ee),
+            )
+        };
+        let encrypted = |text: &str| STANDARD.decode(encrypt_message(text, &conversation, old, EncryptionAlgorithm::Aes256Gcm).unwrap()).unwrap();
+        diesel::insert_into(relay_messages::table)
+            .values(vec![message(encrypted("first"), false), message(encrypted("second"), false), message(b"client ciphertext".to_vec(), true)])
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let previous = BTreeMap::from([(1, OLD_KEY.to_string())]);
+        let rotated = EncryptionKeys::new(2, NEW_KEY, &previous);
+        let migrated = reencrypt_conversation(&mut conn, rotated, EncryptionAlgorithm::ChaCha20Poly1305, &conversation).await.unwrap().unwrap();
+        let again = reencrypt_conversation(&mut conn, rotated, EncryptionAlgorithm::ChaCha20Poly1305, &conversation).await.unwrap().unwrap();
+        let missing = reencrypt_conversation(&mut conn, rotated, EncryptionAlgorithm::ChaCha20Poly1305, "0xnobody:0xnowhere").await.unwrap();
+
+        let stored: Vec<(Vec<u8>, bool)> = relay_messages::table
+            .filter(relay_messages::conversation_id.eq(&conversation))
+            .order(relay_messages::id)
+            .select((relay_messages::content, relay_messages::e2ee))
+            .load(&mut conn)
+            .await
+            .unwrap();
+        let version: i32 = relay_conversations::table
+            .filter(relay_conversations::conversation_id.eq(&conversation))
+            .select(relay_conversations::conversation_key_version)
+            .first(&mut conn)
+            .await
+            .unwrap();
+
+        diesel::delete(relay_messages::table.filter(relay_messages::conversation_id.eq(&conversation)))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(relay_conversations::table.filter(relay_conversations::conversation_id.eq(&conversation)))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        assert_eq!((migrated.rewritten, migrated.failed, migrated.key_version), (2, 0, 2));
+        assert_eq!((again.rewritten, again.key_version), (0, 2));
+        assert!(missing.is_none());
+        assert_eq!(version, 2);
+
+        // The old key is no longer needed to read the conversation
+        let new_only = EncryptionKeys::new(2, NEW_KEY, &none);
+        let texts: Vec<String> = stored[..2].iter().map(|(content, _)| decrypt_message(&STANDARD.encode(content), &conversation, new_only).unwrap()).collect();
+        assert_eq!(texts, vec!["first", "second"]);
+        assert_eq!(stored[2], (b"client ciphertext".to_vec(), true));
+    }
+}
@@ -1,20 +1,51 @@
+pub mod account_deletion;
+pub mod audit;
+pub mod backoff;
+pub mod blocks;
 pub mod config;
+pub mod consumer_errors;
+pub mod consumer_lag;
+pub mod consumer_pool;
 pub mod context;
+pub mod conversation_archives;
+pub mod conversation_mutes;
+pub mod conversation_pins;
+pub mod conversation_previews;
 pub mod db;
+pub mod dead_letter;
+pub mod email_digest;
 pub mod encryption;
+pub mod key_rotation;
+pub mod lock;
+pub mod message_reactions;
+pub mod message_references;
+pub mod message_retention;
+pub mod migrations;
+pub mod moderation;
+pub mod notification_actions;
+pub mod notification_counts;
+pub mod notification_templates;
+pub mod outbox;
 pub mod platform_delivery_config;
+pub mod processed_events;
+pub mod profiles;
+pub mod quiet_hours;
 pub mod redis;
 pub mod redpanda;
 pub mod schema;
+pub mod sessions;
 pub mod signature;
+pub mod startup;
+pub mod topics;
 pub mod types;
+pub mod user_keys;
 
 pub use config::Config;
 pub use context::RelayContext;
 pub use db::DbPool;
-pub use encryption::{decrypt_message, encrypt_message};
+pub use encryption::{decrypt_message, encrypt_message, EncryptionAlgorithm, EncryptionKeys};
 pub use platform_delivery_config::{get_platform_delivery_config, PlatformDeliveryConfig};
 pub use redis::RedisPool;
 pub use redpanda::{RedpandaProducer, RedpandaConsumer};
-pub use signature::{validate_auth_message, verify_mysocial_signature};
+pub use signature::{validate_auth_message, verify_mysocial_signature, AuthMessagePolicy, SignatureError};
 
@@ -0,0 +1,199 @@
+use anyhow::Result;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::context::RelayContext;
+use crate::redis::{get_connection, RedisPool};
+
+/// How long a lease lasts unless renewed, so how long a crashed holder's task is paused everywhere
+pub const LEASE_TTL: Duration = Duration::from_secs(30);
+
+/// Extend the lease only while it still holds our token; a lease that expired and was taken over isn't ours
+const RENEW_SCRIPT: &str =
+    "if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('PEXPIRE', KEYS[1], ARGV[2]) else return 0 end";
+const RELEASE_SCRIPT: &str =
+    "if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) else return 0 end";
+
+/// A lease on one Redis key, held by whoever set it with `SET NX PX` until it expires or is released.
+/// Each lock has its own random token, so two locks on the same key are two contenders
+#[derive(Clone)]
+pub struct LeaseLock {
+    pool: RedisPool,
+    key: String,
+    token: String,
+    ttl: Duration,
+}
+
+impl LeaseLock {
+    pub fn new(pool: RedisPool, key: String, ttl: Duration) -> Self {
+        Self { pool, key, token: uuid::Uuid::new_v4().to_string(), ttl }
+    }
+
+    /// Take the lease if nobody holds it
+    pub async fn try_acquire(&self) -> Result<bool> {
+        let mut conn = get_connection(&self.pool).await?;
+        let acquired: Option<String> = redis::cmd("SET")
+            .arg(&self.key)
+            .arg(&self.token)
+            .arg("NX")
+            .arg("PX")
+            .arg(self.ttl.as_millis() as u64)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(acquired.is_some())
+    }
+
+    /// Push the expiry a full TTL out; false if the lease is no longer ours
+    pub async fn renew(&self) -> Result<bool> {
+        let mut conn = get_connection(&self.pool).await?;
+        let renewed: i64 = redis::cmd("EVAL")
+            .arg(RENEW_SCRIPT)
+            .arg(1)
+            .arg(&self.key)
+            .arg(&self.token)
+            .arg(self.ttl.as_millis() as u64)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(renewed == 1)
+    }
+
+    /// Give the lease up early so another contender doesn't wait for it to expire
+    pub async fn release(&self) -> Result<()> {
+        let mut conn = get_connection(&self.pool).await?;
+        redis::cmd("EVAL")
+            .arg(RELEASE_SCRIPT)
+            .arg(1)
+            .arg(&self.key)
+            .arg(&self.token)
+            .query_async::<()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Run `task` whenever this lock holds the lease, trying to acquire it every third of the TTL until
+    /// then. A lost lease cancels the running task, which is started afresh once the lease is back.
+    /// Returns the task's output, releasing the lease, if the task ever finishes
+    pub async fn run<F, Fut, T>(&self, mut task: F) -> T
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let retry_interval = self.ttl / 3;
+
+        loop {
+            match self.try_acquire().await {
+                Ok(true) => {}
+                Ok(false) => {
+                    tokio::time::sleep(retry_interval).await;
+                    continue;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to acquire {}: {}", self.key, e);
+                    tokio::time::sleep(retry_interval).await;
+                    continue;
+                }
+            }
+
+            tracing::info!("Acquired {}, running its task on this instance", self.key);
+            tokio::select! {
+                output = task() => {
+                    if let Err(e) = self.release().await {
+                        tracing::warn!("Failed to release {}: {}", self.key, e);
+                    }
+                    return output;
+                }
+                _ = self.keep_renewed() => {
+                    tracing::warn!("Lost {}, stopped its task on this instance", self.key);
+                }
+            }
+        }
+    }
+
+    /// Renew every third of the TTL; returns once the lease is lost, or could expire before the next
+    /// renewal because Redis has been unreachable
+    async fn keep_renewed(&self) {
+        let renew_interval = self.ttl / 3;
+        let mut renewed_at = Instant::now();
+
+        loop {
+            tokio::time::sleep(renew_interval).await;
+            match self.renew().await {
+                Ok(true) => renewed_at = Instant::now(),
+                Ok(false) => return,
+                Err(e) => {
+                    tracing::warn!("Failed to renew {}: {}", self.key, e);
+                    if renewed_at.elapsed() + renew_interval >= self.ttl {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Run the singleton task `name` only on the instance holding its `LOCK` key; see [`LeaseLock::run`]
+pub async fn with_leader_lock<F, Fut, T>(ctx: &RelayContext, name: &str, task: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = T>,
+{
+    let key = ctx.config.redis.keys().leader_lock(name);
+    LeaseLock::new(ctx.redis_pool.clone(), key, LEASE_TTL).run(task).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis at REDIS_URL"]
+    async fn test_two_contenders_one_holder() {
+        let config = crate::Config::from_env();
+        let pool = crate::redis::create_pool(&config.redis).await.unwrap();
+        let key = config.redis.keys().leader_lock(&format!("test-{}", uuid::Uuid::new_v4()));
+        let ttl = Duration::from_millis(600);
+        let (a, b) = (LeaseLock::new(pool.clone(), key.clone(), ttl), LeaseLock::new(pool, key, ttl));
+
+        // Racing for the lease, exactly one wins and only the winner can renew it
+        let (a_won, b_won) = tokio::join!(a.try_acquire(), b.try_acquire());
+        let (a_won, b_won) = (a_won.unwrap(), b_won.unwrap());
+        assert!(a_won != b_won);
+        let (holder, other) = if a_won { (&a, &b) } else { (&b, &a) };
+        assert!(holder.renew().await.unwrap());
+        assert!(!other.renew().await.unwrap());
+        other.release().await.unwrap();
+        assert!(!other.try_acquire().await.unwrap());
+        holder.release().await.unwrap();
+
+        // Running the task, only one contender starts it; the other takes over once the holder stops renewing
+        let started = [Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0))];
+        let contenders: Vec<_> = [a, b]
+            .into_iter()
+            .zip(started.clone())
+            .map(|(lock, started)| {
+                tokio::spawn(async move {
+                    lock.run(|| {
+                        started.fetch_add(1, Ordering::SeqCst);
+                        std::future::pending::<()>()
+                    })
+                    .await
+                })
+            })
+            .collect();
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let counts = || started.iter().map(|s| s.load(Ordering::SeqCst)).collect::<Vec<_>>();
+        assert_eq!(counts().iter().sum::<usize>(), 1);
+        let holder = counts().iter().position(|&c| c == 1).unwrap();
+
+        contenders[holder].abort();
+        tokio::time::sleep(ttl + ttl / 3 + Duration::from_millis(200)).await;
+        assert_eq!(counts(), vec![1, 1]);
+        contenders[1 - holder].abort();
+    }
+}
@@ -0,0 +1,200 @@
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
+use std::collections::HashMap;
+use crate::db::DbConnection;
+use crate::schema::relay_message_reactions;
+
+/// Longest emoji accepted, in bytes; enough for ZWJ sequences like family or flag emoji
+pub const MAX_EMOJI_BYTES: usize = 32;
+
+/// One emoji's tally on a message, as shown under it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReactionSummary {
+    pub emoji: String,
+    pub count: usize,
+    /// Whether the viewer is one of the reactors
+    pub reacted: bool,
+}
+
+/// A trimmed, non-empty emoji without whitespace, at most `MAX_EMOJI_BYTES` long
+pub fn parse_emoji(value: &str) -> Option<&str> {
+    let emoji = value.trim();
+    let valid = !emoji.is_empty()
+        && emoji.len() <= MAX_EMOJI_BYTES
+        && !emoji.chars().any(|c| c.is_whitespace() || c.is_control());
+    valid.then_some(emoji)
+}
+
+/// Add `emoji` from `user_address` to a message; returns false if they had already added it
+pub async fn add_reaction(conn: &mut DbConnection, message_id: i64, user_address: &str, emoji: &str) -> anyhow::Result<bool> {
+    let inserted = diesel::insert_into(relay_message_reactions::table)
+        .values((
+            relay_message_reactions::message_id.eq(message_id),
+            relay_message_reactions::user_address.eq(user_address),
+            relay_message_reactions::emoji.eq(emoji),
+        ))
+        .on_conflict((
+            relay_message_reactions::message_id,
+            relay_message_reactions::user_address,
+            relay_message_reactions::emoji,
+        ))
+        .do_nothing()
+        .execute(conn)
+        .await?;
+
+    Ok(inserted > 0)
+}
+
+/// Remove a reaction; returns false if there was none
+pub async fn remove_reaction(conn: &mut DbConnection, message_id: i64, user_address: &str, emoji: &str) -> anyhow::Result<bool> {
+    let deleted = diesel::delete(
+        relay_message_reactions::table
+            .filter(relay_message_reactions::message_id.eq(message_id))
+            .filter(relay_message_reactions::user_address.eq(user_address))
+            .filter(relay_message_reactions::emoji.eq(emoji)),
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(deleted > 0)
+}
+
+/// Reactions on each of `message_ids` as seen by `viewer`; messages without any are left out
+pub async fn summaries(
+    conn: &mut DbConnection,
+    message_ids: &[i64],
+    viewer: &str,
+) -> anyhow::Result<HashMap<i64, Vec<ReactionSummary>>> {
+    if message_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let rows: Vec<(i64, String, String)> = relay_message_reactions::table
+        .filter(relay_message_reactions::message_id.eq_any(message_ids))
+        .order((relay_message_reactions::created_at, relay_message_reactions::id))
+        .select((
+            relay_message_reactions::message_id,
+            relay_message_reactions::emoji,
+            relay_message_reactions::user_address,
+        ))
+        .load(conn)
+        .await?;
+
+    Ok(summarize(rows, viewer))
+}
+
+/// Group `(message_id, emoji, user_address)` rows per message and emoji, emoji in the order they were
+/// first used on the message
+pub fn summarize(rows: impl IntoIterator<Item = (i64, String, String)>, viewer: &str) -> HashMap<i64, Vec<ReactionSummary>> {
+    let mut by_message: HashMap<i64, Vec<ReactionSummary>> = HashMap::new();
+    for (message_id, emoji, user_address) in rows {
+        let reactions = by_message.entry(message_id).or_default();
+        let summary = match reactions.iter_mut().position(|r| r.emoji == emoji) {
+            Some(i) => &mut reactions[i],
+            None => {
+                reactions.push(ReactionSummary { emoji, count: 0, reacted: false });
+                reactions.last_mut().expect("just pushed")
+            }
+        };
+        summary.count += 1;
+        summary.reacted |= user_address == viewer;
+    }
+    by_message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{relay_conversations, relay_messages};
+
+    fn row(message_id: i64, emoji: &str, user: &str) -> (i64, String, String) {
+        (message_id, emoji.to_string(), user.to_string())
+    }
+
+    #[test]
+    fn test_reactions_aggregate_per_emoji() {
+        let rows = vec![
+            row(1, "👍", "0xa"),
+            row(1, "❤️", "0xb"),
+            row(2, "😂", "0xb"),
+            row(1, "👍", "0xb"),
+        ];
+        let summaries = summarize(rows, "0xa");
+
+        assert_eq!(
+            summaries[&1],
+            vec![
+                ReactionSummary { emoji: "👍".to_string(), count: 2, reacted: true },
+                ReactionSummary { emoji: "❤️".to_string(), count: 1, reacted: false },
+            ]
+        );
+        assert_eq!(summaries[&2], vec![ReactionSummary { emoji: "😂".to_string(), count: 1, reacted: false }]);
+        assert!(!summaries.contains_key(&3));
+    }
+
+    #[test]
+    fn test_parse_emoji() {
+        assert_eq!(parse_emoji(" 👍 "), Some("👍"));
+        assert_eq!(parse_emoji("👨‍👩‍👧‍👦"), Some("👨‍👩‍👧‍👦"));
+        assert_eq!(parse_emoji(""), None);
+        assert_eq!(parse_emoji("👍 👍"), None);
+        assert_eq!(parse_emoji(&"👍".repeat(10)), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables"]
+    async fn test_add_and_remove_reaction() {
+        let config = crate::Config::from_env();
+        let pool = crate::db::create_pool(&config.database).await.unwrap();
+        let mut conn = pool.get().await.unwrap();
+
+        let run = uuid::Uuid::new_v4();
+        let (alice, bob) = (format!("0xalice-{}", run), format!("0xbob-{}", run));
+        let conversation = format!("{}:{}", alice, bob);
+        diesel::insert_into(relay_conversations::table)
+            .values((
+                relay_conversations::conversation_id.eq(&conversation),
+                relay_conversations::participant1_address.eq(&alice),
+                relay_conversations::participant2_address.eq(&bob),
+            ))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let message_id: i64 = diesel::insert_into(relay_messages::table)
+            .values((
+                relay_messages::conversation_id.eq(&conversation),
+                relay_messages::sender_address.eq(&alice),
+                relay_messages::recipient_address.eq(&bob),
+                relay_messages::content.eq(b"ciphertext".to_vec()),
+            ))
+            .returning(relay_messages::id)
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let added = add_reaction(&mut conn, message_id, &bob, "👍").await.unwrap();
+        let added_again = add_reaction(&mut conn, message_id, &bob, "👍").await.unwrap();
+        add_reaction(&mut conn, message_id, &alice, "👍").await.unwrap();
+        let both = summaries(&mut conn, &[message_id], &alice).await.unwrap();
+
+        let removed = remove_reaction(&mut conn, message_id, &bob, "👍").await.unwrap();
+        let removed_again = remove_reaction(&mut conn, message_id, &bob, "👍").await.unwrap();
+        let after = summaries(&mut conn, &[message_id], &bob).await.unwrap();
+
+        // Reactions go with the message
+        diesel::delete(relay_messages::table.filter(relay_messages::id.eq(message_id)))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(relay_conversations::table.filter(relay_conversations::conversation_id.eq(&conversation)))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        assert!(added && !added_again);
+        assert_eq!(both[&message_id], vec![ReactionSummary { emoji: "👍".to_string(), count: 2, reacted: true }]);
+        assert!(removed && !removed_again);
+        assert_eq!(after[&message_id], vec![ReactionSummary { emoji: "👍".to_string(), count: 1, reacted: false }]);
+    }
+}
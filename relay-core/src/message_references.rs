@@ -0,0 +1,203 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use std::collections::HashMap;
+
+use crate::conversation_previews::ConversationPreview;
+use crate::db::DbConnection;
+use crate::encryption::{decrypt_message, EncryptionKeys};
+use crate::schema::relay_messages;
+
+/// (id, conversation_id, sender, recipient, content, content_type, created_at, deleted, e2ee)
+type ReferencedRow = (i64, String, String, String, Vec<u8>, String, chrono::DateTime<chrono::Utc>, bool, bool);
+
+/// Whether `message_id` can be replied to from `conversation_id`: only messages of the same conversation can
+pub async fn can_reply_to(conn: &mut DbConnection, conversation_id: &str, message_id: i64) -> QueryResult<bool> {
+    let found: Option<i64> = relay_messages::table
+        .filter(relay_messages::id.eq(message_id))
+        .filter(relay_messages::conversation_id.eq(conversation_id))
+        .select(relay_messages::id)
+        .first(conn)
+        .await
+        .optional()?;
+
+    Ok(found.is_some())
+}
+
+/// Whether `sender` can forward `message_id`: a message they sent or received that hasn't been deleted
+pub async fn can_forward(conn: &mut DbConnection, sender: &str, message_id: i64) -> QueryResult<bool> {
+    let found: Option<i64> = relay_messages::table
+        .filter(relay_messages::id.eq(message_id))
+        .filter(relay_messages::sender_address.eq(sender).or(relay_messages::recipient_address.eq(sender)))
+        .filter(relay_messages::deleted_at.is_null())
+        .select(relay_messages::id)
+        .first(conn)
+        .await
+        .optional()?;
+
+    Ok(found.is_some())
+}
+
+/// Previews of the messages `ids` as `reader` may see them. None for messages that were deleted or that belong
+/// to a conversation `reader` isn't in, e.g. the original of a message forwarded to them; ids of messages that
+/// no longer exist are left out
+pub async fn previews(
+    conn: &mut DbConnection,
+    keys: EncryptionKeys<'_>,
+    reader: &str,
+    ids: &[i64],
+) -> QueryResult<HashMap<i64, Option<ConversationPreview>>> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let rows: Vec<ReferencedRow> = relay_messages::table
+        .filter(relay_messages::id.eq_any(ids))
+        .select((
+            relay_messages::id,
+            relay_messages::conversation_id,
+            relay_messages::sender_address,
+            relay_messages::recipient_address,
+            relay_messages::content,
+            relay_messages::content_type,
+            relay_messages::created_at,
+            relay_messages::deleted_at.is_not_null(),
+            relay_messages::e2ee,
+        ))
+        .load(conn)
+        .await?;
+
+    let previews = rows
+        .into_iter()
+        .map(|(id, conversation_id, sender, recipient, content, content_type, created_at, deleted, e2ee)| {
+            if deleted || (reader != sender && reader != recipient) {
+                return (id, None);
+            }
+            let text = (!e2ee)
+                .then(|| {
+                    decrypt_message(&STANDARD.encode(&content), &conversation_id, keys)
+                        .map_err(|e| tracing::warn!("Failed to decrypt referenced message {}: {}", id, e))
+                        .ok()
+                })
+                .flatten();
+            (id, Some(ConversationPreview::new(id, &sender, &content_type, text.as_deref(), created_at, e2ee)))
+        })
+        .collect();
+
+    Ok(previews)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::encrypt_message;
+    use crate::schema::relay_conversations;
+    use std::collections::BTreeMap;
+
+    const KEY: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    /// Alice and Bob's conversation plus one between Alice and Carol, each with one text message
+    async fn two_conversations(conn: &mut DbConnection, run: uuid::Uuid) -> [(String, i64); 2] {
+        let (alice, bob, carol) = (format!("0xalice-{}", run), format!("0xbob-{}", run), format!("0xcarol-{}", run));
+        let none = BTreeMap::new();
+        let mut created = Vec::new();
+        for (other, text) in [(&bob, "lunch at noon?"), (&carol, "the report is ready")] {
+            let conversation = format!("{}:{}", alice, other);
+            diesel::insert_into(relay_conversations::table)
+                .values((
+                    relay_conversations::conversation_id.eq(&conversation),
+                    relay_conversations::participant1_address.eq(&alice),
+                    relay_conversations::participant2_address.eq(other),
+                ))
+                .execute(conn)
+                .await
+                .unwrap();
+            let content = encrypt_message(text, &conversation, EncryptionKeys::new(1, KEY, &none), Default::default()).unwrap();
+            let id: i64 = diesel::insert_into(relay_messages::table)
+                .values((
+                    relay_messages::conversation_id.eq(&conversation),
+                    relay_messages::sender_address.eq(other),
+                    relay_messages::recipient_address.eq(&alice),
+                    relay_messages::content.eq(STANDARD.decode(content).unwrap()),
+                ))
+                .returning(relay_messages::id)
+                .get_result(conn)
+                .await
+                .unwrap();
+            created.push((conversation, id));
+        }
+        [created[0].clone(), created[1].clone()]
+    }
+
+    async fn clean_up(conn: &mut DbConnection, conversations: &[(String, i64)]) {
+        let ids: Vec<&String> = conversations.iter().map(|(c, _)| c).collect();
+        diesel::delete(relay_messages::table.filter(relay_messages::conversation_id.eq_any(&ids)))
+            .execute(conn)
+            .await
+            .unwrap();
+        diesel::delete(relay_conversations::table.filter(relay_conversations::conversation_id.eq_any(&ids)))
+            .execute(conn)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables"]
+    async fn test_reply_within_conversation() {
+        let config = crate::Config::from_env();
+        let pool = crate::db::create_pool(&config.database).await.unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let run = uuid::Uuid::new_v4();
+        let [(with_bob, bobs_message), (with_carol, carols_message)] = two_conversations(&mut conn, run).await;
+
+        let in_conversation = can_reply_to(&mut conn, &with_bob, bobs_message).await.unwrap();
+        let across_conversations = can_reply_to(&mut conn, &with_bob, carols_message).await.unwrap();
+        let none = BTreeMap::new();
+        let seen_by_bob = previews(&mut conn, EncryptionKeys::new(1, KEY, &none), &format!("0xbob-{}", run), &[bobs_message]).await.unwrap();
+
+        clean_up(&mut conn, &[(with_bob, bobs_message), (with_carol, carols_message)]).await;
+
+        assert!(in_conversation);
+        assert!(!across_conversations);
+        let preview = seen_by_bob[&bobs_message].as_ref().unwrap();
+        assert_eq!(preview.preview.as_deref(), Some("lunch at noon?"));
+        assert_eq!(preview.sender_address, format!("0xbob-{}", run));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables"]
+    async fn test_forward_across_conversations() {
+        let config = crate::Config::from_env();
+        let pool = crate::db::create_pool(&config.database).await.unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let run = uuid::Uuid::new_v4();
+        let [(with_bob, bobs_message), (with_carol, carols_message)] = two_conversations(&mut conn, run).await;
+        let (alice, bob) = (format!("0xalice-{}", run), format!("0xbob-{}", run));
+
+        // Alice received Carol's message so she may forward it to Bob; Bob was never part of it
+        let alice_forwards = can_forward(&mut conn, &alice, carols_message).await.unwrap();
+        let bob_forwards = can_forward(&mut conn, &bob, carols_message).await.unwrap();
+        let none = BTreeMap::new();
+        let keys = EncryptionKeys::new(1, KEY, &none);
+        let seen_by_bob = previews(&mut conn, keys, &bob, &[carols_message, bobs_message]).await.unwrap();
+        let seen_by_alice = previews(&mut conn, keys, &alice, &[carols_message]).await.unwrap();
+
+        // Deleted messages can't be forwarded
+        diesel::update(relay_messages::table.filter(relay_messages::id.eq(carols_message)))
+            .set(relay_messages::deleted_at.eq(chrono::Utc::now()))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let forwards_deleted = can_forward(&mut conn, &alice, carols_message).await.unwrap();
+
+        clean_up(&mut conn, &[(with_bob, bobs_message), (with_carol, carols_message)]).await;
+
+        assert!(alice_forwards);
+        assert!(!bob_forwards);
+        assert!(!forwards_deleted);
+        // The original stays private to its own conversation
+        assert_eq!(seen_by_bob[&carols_message], None);
+        assert!(seen_by_bob[&bobs_message].is_some());
+        assert_eq!(seen_by_alice[&carols_message].as_ref().unwrap().preview.as_deref(), Some("the report is ready"));
+    }
+}
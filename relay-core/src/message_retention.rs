@@ -0,0 +1,220 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+use crate::context::RelayContext;
+use crate::db::DbConnection;
+use crate::lock;
+use crate::redis::{get_connection, RedisConnection, RedisKeys};
+use crate::schema::{relay_conversations, relay_messages};
+
+/// What one retention pass removed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PurgeReport {
+    pub cutoff: DateTime<Utc>,
+    pub deleted: usize,
+    /// Conversations that lost messages, whose caches were dropped
+    pub conversations: usize,
+}
+
+/// Messages created before this are past retention
+pub fn cutoff(now: DateTime<Utc>, retention_days: u32) -> DateTime<Utc> {
+    now - Duration::days(retention_days.into())
+}
+
+/// Delete messages created before `cutoff`, `batch_size` rows per statement, skipping conversations
+/// marked `retention_exempt`. Returns the number deleted and the conversations they belonged to
+pub async fn delete_expired(
+    conn: &mut DbConnection,
+    cutoff: DateTime<Utc>,
+    batch_size: i64,
+) -> Result<(usize, BTreeSet<String>)> {
+    let exempt = relay_conversations::table
+        .filter(relay_conversations::retention_exempt.eq(true))
+        .select(relay_conversations::conversation_id);
+
+    let mut deleted = 0;
+    let mut conversations = BTreeSet::new();
+    loop {
+        let ids: Vec<i64> = relay_messages::table
+            .filter(relay_messages::created_at.lt(cutoff))
+            .filter(relay_messages::conversation_id.ne_all(exempt))
+            .order(relay_messages::id)
+            .limit(batch_size)
+            .select(relay_messages::id)
+            .load(conn)
+            .await?;
+        if ids.is_empty() {
+            break;
+        }
+
+        let removed: Vec<String> = diesel::delete(relay_messages::table.filter(relay_messages::id.eq_any(&ids)))
+            .returning(relay_messages::conversation_id)
+            .get_results(conn)
+            .await?;
+        deleted += removed.len();
+        conversations.extend(removed);
+
+        if (ids.len() as i64) < batch_size {
+            break;
+        }
+    }
+
+    Ok((deleted, conversations))
+}
+
+/// Drop the `CHAT:{conversation_id}` message cache and cached preview of each conversation
+pub async fn purge_caches(redis_conn: &mut RedisConnection, keys: RedisKeys<'_>, conversations: &BTreeSet<String>) -> Result<()> {
+    if conversations.is_empty() {
+        return Ok(());
+    }
+
+    let mut pipe = redis::pipe();
+    for conversation_id in conversations {
+        pipe.del(keys.chat_cache(conversation_id)).ignore();
+        pipe.del(keys.conversation_preview(conversation_id)).ignore();
+    }
+    pipe.query_async::<()>(redis_conn).await?;
+
+    Ok(())
+}
+
+/// One pass with `MESSAGE_RETENTION_DAYS`; errors when retention isn't configured
+pub async fn run_once(ctx: &RelayContext) -> Result<PurgeReport> {
+    let config = &ctx.config.retention;
+    let days = config.message_retention_days.ok_or_else(|| anyhow!("MESSAGE_RETENTION_DAYS is not set"))?;
+    let cutoff = cutoff(Utc::now(), days);
+
+    let mut conn = ctx.db_pool.get().await?;
+    let (deleted, conversations) = delete_expired(&mut conn, cutoff, config.batch_size).await?;
+
+    if let Err(e) = async { purge_caches(&mut get_connection(&ctx.redis_pool).await?, ctx.config.redis.keys(), &conversations).await }.await {
+        tracing::warn!("Deleted {} expired messages but failed to purge their caches: {}", deleted, e);
+    }
+
+    Ok(PurgeReport { cutoff, deleted, conversations: conversations.len() })
+}
+
+/// Every `MESSAGE_RETENTION_INTERVAL_SECONDS`, delete messages older than `MESSAGE_RETENTION_DAYS`; only on the
+/// instance holding the `message-retention` lock
+pub async fn run(ctx: RelayContext) {
+    let Some(days) = ctx.config.retention.message_retention_days else {
+        tracing::info!("MESSAGE_RETENTION_DAYS not set, messages are kept forever");
+        return;
+    };
+
+    tracing::info!("Deleting messages older than {} days", days);
+    lock::with_leader_lock(&ctx, "message-retention", || purge_forever(&ctx)).await
+}
+
+async fn purge_forever(ctx: &RelayContext) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(ctx.config.retention.interval_seconds));
+    loop {
+        interval.tick().await;
+
+        match run_once(ctx).await {
+            Ok(report) if report.deleted > 0 => tracing::info!(
+                "Deleted {} messages from {} conversations created before {}",
+                report.deleted,
+                report.conversations,
+                report.cutoff
+            ),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Message retention pass failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::create_pool as create_redis_pool;
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables and Redis at REDIS_URL"]
+    async fn test_expired_messages_removed_and_fresh_kept() {
+        let config = crate::Config::from_env();
+        let pool = crate::db::create_pool(&config.database).await.unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let mut redis_conn = get_connection(&create_redis_pool(&config.redis).await.unwrap()).await.unwrap();
+        let keys = config.redis.keys();
+
+        let run = uuid::Uuid::new_v4();
+        let (alice, bob) = (format!("0xalice-{}", run), format!("0xbob-{}", run));
+        let conversation = format!("{}:{}", alice, bob);
+        let exempt = format!("{}:{}-hold", alice, bob);
+        diesel::insert_into(relay_conversations::table)
+            .values(vec![
+                (
+                    relay_conversations::conversation_id.eq(conversation.clone()),
+                    relay_conversations::participant1_address.eq(alice.clone()),
+                    relay_conversations::participant2_address.eq(bob.clone()),
+                    relay_conversations::retention_exempt.eq(false),
+                ),
+                (
+                    relay_conversations::conversation_id.eq(exempt.clone()),
+                    relay_conversations::participant1_address.eq(alice.clone()),
+                    relay_conversations::participant2_address.eq(bob.clone()),
+                    relay_conversations::retention_exempt.eq(true),
+                ),
+            ])
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let now = Utc::now();
+        let message = |conversation_id: &str, age_days: i64| {
+            (
+                relay_messages::conversation_id.eq(conversation_id.to_string()),
+                relay_messages::sender_address.eq(alice.clone()),
+                relay_messages::recipient_address.eq(bob.clone()),
+                relay_messages::content.eq(b"ciphertext".to_vec()),
+                relay_messages::created_at.eq(now - Duration::days(age_days)),
+            )
+        };
+        let fresh: i64 = diesel::insert_into(relay_messages::table)
+            .values(message(&conversation, 1))
+            .returning(relay_messages::id)
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+        diesel::insert_into(relay_messages::table)
+            .values(vec![message(&conversation, 40), message(&conversation, 45), message(&exempt, 40)])
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let _: () = redis::cmd("LPUSH").arg(keys.chat_cache(&conversation)).arg("{}").query_async(&mut redis_conn).await.unwrap();
+
+        // A batch of one exercises the loop
+        let (deleted, conversations) = delete_expired(&mut conn, cutoff(now, 30), 1).await.unwrap();
+        purge_caches(&mut redis_conn, keys, &conversations).await.unwrap();
+
+        let remaining: Vec<(i64, String)> = relay_messages::table
+            .filter(relay_messages::conversation_id.eq_any([&conversation, &exempt]))
+            .select((relay_messages::id, relay_messages::conversation_id))
+            .load(&mut conn)
+            .await
+            .unwrap();
+        let cached: bool = redis::cmd("EXISTS").arg(keys.chat_cache(&conversation)).query_async(&mut redis_conn).await.unwrap();
+
+        diesel::delete(relay_messages::table.filter(relay_messages::conversation_id.eq_any([&conversation, &exempt])))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(relay_conversations::table.filter(relay_conversations::conversation_id.eq_any([&conversation, &exempt])))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        assert!(deleted >= 2);
+        assert!(conversations.contains(&conversation));
+        assert!(!conversations.contains(&exempt));
+        assert_eq!(remaining.len(), 2);
+        assert!(remaining.contains(&(fresh, conversation.clone())));
+        assert!(remaining.iter().any(|(_, c)| *c == exempt));
+        assert!(!cached);
+    }
+}
@@ -0,0 +1,47 @@
+use anyhow::{anyhow, Result};
+use diesel::Connection;
+use diesel_async::async_connection_wrapper::AsyncConnectionWrapper;
+use diesel_async::AsyncPgConnection;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+use crate::config::DatabaseConfig;
+
+/// SQL migrations under `relay-core/migrations`, compiled into the binary
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+/// Apply any pending migrations against the primary database, returning the versions applied
+pub async fn run_pending_migrations(config: &DatabaseConfig) -> Result<Vec<String>> {
+    let url = config.url.clone();
+
+    // The migration harness is synchronous, so run it off the async workers
+    tokio::task::spawn_blocking(move || {
+        let mut conn = AsyncConnectionWrapper::<AsyncPgConnection>::establish(&url)
+            .map_err(|e| anyhow!("Failed to connect for migrations: {}", e))?;
+
+        let applied = conn
+            .run_pending_migrations(MIGRATIONS)
+            .map_err(|e| anyhow!("Failed to run migrations: {}", e))?;
+
+        Ok(applied.iter().map(|version| version.to_string()).collect())
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires an empty Postgres database at DATABASE_URL"]
+    async fn test_migrations_apply_once() {
+        let config = DatabaseConfig {
+            url: std::env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://postgres@localhost:5432/relay_test".to_string()),
+            max_connections: 1,
+            replica_url: None,
+            run_migrations: true,
+        };
+
+        run_pending_migrations(&config).await.unwrap();
+        assert!(run_pending_migrations(&config).await.unwrap().is_empty());
+    }
+}
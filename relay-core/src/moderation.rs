@@ -0,0 +1,173 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::config::ModerationConfig;
+
+/// What the classifier decided about a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Allow,
+    /// Stored and delivered, but marked `flagged` for review
+    Flag,
+    /// Neither stored nor streamed
+    Block,
+}
+
+#[derive(Deserialize)]
+struct ClassifierResponse {
+    action: Verdict,
+}
+
+/// POSTs message plaintext to `MODERATION_URL` as `{"content", "sender_address", "recipient_address"}`
+/// and expects `{"action": "allow" | "flag" | "block"}` back
+pub struct Moderator {
+    client: Option<reqwest::Client>,
+    url: String,
+    fail_closed: bool,
+}
+
+impl Moderator {
+    pub fn new(config: &ModerationConfig) -> Result<Self> {
+        let client = match &config.url {
+            Some(url) => {
+                let client = reqwest::Client::builder()
+                    .timeout(Duration::from_millis(config.timeout_ms))
+                    .build()
+                    .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?;
+                tracing::info!("Message moderation enabled via {}", url);
+                Some(client)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            client,
+            url: config.url.clone().unwrap_or_default(),
+            fail_closed: config.fail_closed,
+        })
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// The classifier's verdict on `content`; Allow when moderation is off. When the classifier
+    /// fails, `MODERATION_FAIL_CLOSED` decides between Block and Allow
+    pub async fn check(&self, sender: &str, recipient: &str, content: &str) -> Verdict {
+        let Some(client) = &self.client else {
+            return Verdict::Allow;
+        };
+
+        match self.classify(client, sender, recipient, content).await {
+            Ok(verdict) => verdict,
+            Err(e) if self.fail_closed => {
+                tracing::warn!("Moderation failed for message from {}, rejecting it: {}", sender, e);
+                Verdict::Block
+            }
+            Err(e) => {
+                tracing::warn!("Moderation failed for message from {}, letting it through: {}", sender, e);
+                Verdict::Allow
+            }
+        }
+    }
+
+    async fn classify(&self, client: &reqwest::Client, sender: &str, recipient: &str, content: &str) -> Result<Verdict> {
+        let response = client
+            .post(&self.url)
+            .json(&serde_json::json!({
+                "content": content,
+                "sender_address": sender,
+                "recipient_address": recipient,
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response.json::<ClassifierResponse>().await?.action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// A classifier that blocks anything mentioning "scam" and flags anything mentioning "spam"
+    async fn mock_classifier(requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/classify", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            for _ in 0..requests {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                // Read until the full body named by Content-Length has arrived
+                loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().to_string()))
+                            .and_then(|v| v.parse::<usize>().ok())
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            break;
+                        }
+                    }
+                    if n == 0 {
+                        break;
+                    }
+                }
+
+                let body = String::from_utf8_lossy(&request);
+                let action = if body.contains("scam") {
+                    "block"
+                } else if body.contains("spam") {
+                    "flag"
+                } else {
+                    "allow"
+                };
+                let reply = format!(r#"{{"action":"{}"}}"#, action);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    reply.len(),
+                    reply
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        url
+    }
+
+    fn moderator(url: Option<String>, fail_closed: bool) -> Moderator {
+        Moderator::new(&ModerationConfig { url, fail_closed, timeout_ms: 2000 }).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_classifier_verdicts_are_applied() {
+        let moderator = moderator(Some(mock_classifier(3).await), false);
+
+        assert_eq!(moderator.check("0xa", "0xb", "see you tonight").await, Verdict::Allow);
+        assert_eq!(moderator.check("0xa", "0xb", "buy my spam").await, Verdict::Flag);
+        assert_eq!(moderator.check("0xa", "0xb", "send me your seed phrase, not a scam").await, Verdict::Block);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_classifier_follows_policy() {
+        // Bound and dropped, so nothing listens there
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/classify", listener.local_addr().unwrap());
+        drop(listener);
+
+        assert_eq!(moderator(Some(url.clone()), false).check("0xa", "0xb", "hi").await, Verdict::Allow);
+        assert_eq!(moderator(Some(url), true).check("0xa", "0xb", "hi").await, Verdict::Block);
+        assert_eq!(moderator(None, true).check("0xa", "0xb", "hi").await, Verdict::Allow);
+    }
+}
@@ -0,0 +1,56 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+
+use crate::db::DbConnection;
+use crate::schema::relay_notification_actions;
+
+/// Answer a message or comment from the notification
+pub const REPLY: &str = "reply";
+pub const MARK_READ: &str = "mark_read";
+pub const FOLLOW_BACK: &str = "follow_back";
+
+/// Buttons offered on a notification of `notification_type`, in display order. Clients register
+/// handlers for them under the push category (e.g. `MESSAGE_CREATED`)
+pub fn for_type(notification_type: &str) -> &'static [&'static str] {
+    match notification_type {
+        "message.created" => &[REPLY, MARK_READ],
+        "comment.created" => &[REPLY, MARK_READ],
+        "follow.created" => &[FOLLOW_BACK, MARK_READ],
+        _ => &[],
+    }
+}
+
+/// Whether `action` is offered on notifications of `notification_type`
+pub fn is_allowed(notification_type: &str, action: &str) -> bool {
+    for_type(notification_type).contains(&action)
+}
+
+/// Record that `user_address` took `action` on a notification
+pub async fn record(conn: &mut DbConnection, notification_id: i64, user_address: &str, action: &str) -> Result<DateTime<Utc>> {
+    let created_at = diesel::insert_into(relay_notification_actions::table)
+        .values((
+            relay_notification_actions::notification_id.eq(notification_id),
+            relay_notification_actions::user_address.eq(user_address),
+            relay_notification_actions::action.eq(action),
+        ))
+        .returning(relay_notification_actions::created_at)
+        .get_result(conn)
+        .await?;
+
+    Ok(created_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_notifications_offer_reply() {
+        assert_eq!(for_type("message.created").first(), Some(&REPLY));
+        assert!(is_allowed("comment.created", REPLY));
+        assert!(!is_allowed("follow.created", REPLY));
+        assert!(for_type("tip.created").is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::Result;
+use diesel::dsl::count_star;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use std::collections::BTreeMap;
+
+use crate::db::DbConnection;
+use crate::redis::{counts, RedisConnection, RedisKeys};
+use crate::schema::relay_notifications;
+
+/// Unread notifications for a user, overall and per platform
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnreadCounts {
+    pub total: i64,
+    pub platforms: BTreeMap<String, i64>,
+}
+
+impl UnreadCounts {
+    /// Counters only go negative when Redis missed increments or applied a decrement twice
+    pub fn is_negative(&self) -> bool {
+        self.total < 0 || self.platforms.values().any(|c| *c < 0)
+    }
+
+    /// Negative counters read as 0
+    pub fn clamped(mut self) -> Self {
+        self.total = self.total.max(0);
+        self.platforms.values_mut().for_each(|c| *c = (*c).max(0));
+        self
+    }
+
+    /// Shape served by `GET /api/v1/notifications/counts`
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "total_unread": self.total,
+            "platform_counts": self.platforms,
+        })
+    }
+}
+
+/// The counts `relay_notifications` says a user should have, i.e. rows with `read_at IS NULL`
+pub async fn count_unread(conn: &mut DbConnection, user_address: &str) -> Result<UnreadCounts> {
+    let rows: Vec<(Option<String>, i64)> = relay_notifications::table
+        .filter(relay_notifications::user_address.eq(user_address))
+        .filter(relay_notifications::read_at.is_null())
+        .filter(relay_notifications::deleted_at.is_null())
+        .group_by(relay_notifications::platform_id)
+        .select((relay_notifications::platform_id, count_star()))
+        .load(conn)
+        .await?;
+
+    let mut counts = UnreadCounts::default();
+    for (platform_id, count) in rows {
+        counts.total += count;
+        if let Some(platform_id) = platform_id {
+            counts.platforms.insert(platform_id, count);
+        }
+    }
+
+    Ok(counts)
+}
+
+/// Recompute a user's unread counters from the database, for when Redis has drifted (e.g. a mark-read that
+/// couldn't reach Redis). A notification arriving mid-recompute may be counted twice or not at all until the
+/// next recompute
+pub async fn recompute(
+    conn: &mut DbConnection,
+    redis_conn: &mut RedisConnection,
+    keys: RedisKeys<'_>,
+    user_address: &str,
+) -> Result<UnreadCounts> {
+    let counts = count_unread(conn, user_address).await?;
+    counts::set_counts(redis_conn, keys, user_address, &counts).await?;
+
+    tracing::info!("Recomputed unread notification counts for {}: {}", user_address, counts.total);
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::{create_pool as create_redis_pool, get_connection};
+
+    #[test]
+    fn test_negative_counts_detected_and_clamped() {
+        let counts = UnreadCounts { total: 3, platforms: BTreeMap::from([("app-a".to_string(), -1)]) };
+        assert!(counts.is_negative());
+
+        let clamped = counts.clamped();
+        assert!(!clamped.is_negative());
+        assert_eq!(clamped.to_json(), serde_json::json!({"total_unread": 3, "platform_counts": {"app-a": 0}}));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables and Redis at REDIS_URL"]
+    async fn test_recompute_corrects_desynced_counts() {
+        let config = crate::Config::from_env();
+        let pool = crate::db::create_pool(&config.database).await.unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let mut redis_conn = get_connection(&create_redis_pool(&config.redis).await.unwrap()).await.unwrap();
+        let user = format!("0xuser-{}", uuid::Uuid::new_v4());
+        let keys = config.redis.keys();
+
+        let notification = |platform_id: Option<&'static str>, read: bool| {
+            (
+                relay_notifications::user_address.eq(user.clone()),
+                relay_notifications::notification_type.eq("follow.created"),
+                relay_notifications::title.eq("New follower"),
+                relay_notifications::body.eq("0xfan followed you"),
+                relay_notifications::platform_id.eq(platform_id),
+                relay_notifications::read_at.eq(read.then(chrono::Utc::now)),
+            )
+        };
+        diesel::insert_into(relay_notifications::table)
+            .values(vec![
+                notification(Some("app-a"), false),
+                notification(Some("app-a"), true),
+                notification(None, false),
+            ])
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        // A decrement that ran twice, and a platform whose notifications are all read
+        let _: () = redis::pipe()
+            .set(keys.unread_total(&user), -1)
+            .set(keys.unread_platform(&user, "app-a"), -1)
+            .set(keys.unread_platform(&user, "app-b"), 4)
+            .query_async(&mut redis_conn)
+            .await
+            .unwrap();
+
+        let counts = recompute(&mut conn, &mut redis_conn, keys, &user).await.unwrap();
+        let stored: (Option<i64>, Option<i64>, Option<i64>) = redis::pipe()
+            .get(keys.unread_total(&user))
+            .get(keys.unread_platform(&user, "app-a"))
+            .get(keys.unread_platform(&user, "app-b"))
+            .query_async(&mut redis_conn)
+            .await
+            .unwrap();
+
+        diesel::delete(relay_notifications::table.filter(relay_notifications::user_address.eq(&user)))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let _: () = redis::pipe()
+            .del(keys.unread_total(&user))
+            .del(keys.unread_platform(&user, "app-a"))
+            .del(keys.unread_platforms(&user))
+            .query_async(&mut redis_conn)
+            .await
+            .unwrap();
+
+        assert_eq!(counts.total, 2);
+        assert_eq!(counts.platforms, BTreeMap::from([("app-a".to_string(), 1)]));
+        assert_eq!(stored, (Some(2), Some(1), None));
+    }
+}
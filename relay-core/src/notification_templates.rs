@@ -0,0 +1,67 @@
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::schema::relay_notification_templates;
+use crate::db::DbConnection;
+
+/// Locale used when an event doesn't specify one
+pub const DEFAULT_LOCALE: &str = "en";
+
+#[derive(Debug, Clone, Queryable, Selectable, Serialize, Deserialize)]
+#[diesel(table_name = relay_notification_templates)]
+pub struct NotificationTemplate {
+    pub id: i64,
+    pub platform_id: String,
+    pub event_type: String,
+    pub locale: String,
+    pub title_template: String,
+    pub body_template: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = relay_notification_templates)]
+pub struct NewNotificationTemplate {
+    pub platform_id: String,
+    pub event_type: String,
+    pub locale: String,
+    pub title_template: String,
+    pub body_template: String,
+}
+
+pub async fn load_notification_templates(conn: &mut DbConnection) -> anyhow::Result<Vec<NotificationTemplate>> {
+    let templates = diesel_async::RunQueryDsl::load(
+        relay_notification_templates::table.select(NotificationTemplate::as_select()),
+        &mut *conn,
+    )
+    .await?;
+
+    Ok(templates)
+}
+
+/// Insert a template or replace the one with the same (platform_id, event_type, locale)
+pub async fn upsert_notification_template(
+    conn: &mut DbConnection,
+    template: &NewNotificationTemplate,
+) -> anyhow::Result<NotificationTemplate> {
+    let saved = diesel_async::RunQueryDsl::get_result(
+        diesel::insert_into(relay_notification_templates::table)
+            .values(template)
+            .on_conflict((
+                relay_notification_templates::platform_id,
+                relay_notification_templates::event_type,
+                relay_notification_templates::locale,
+            ))
+            .do_update()
+            .set((
+                relay_notification_templates::title_template.eq(&template.title_template),
+                relay_notification_templates::body_template.eq(&template.body_template),
+                relay_notification_templates::updated_at.eq(chrono::Utc::now()),
+            ))
+            .returning(NotificationTemplate::as_returning()),
+        &mut *conn,
+    )
+    .await?;
+
+    Ok(saved)
+}
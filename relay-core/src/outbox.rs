@@ -0,0 +1,111 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use crate::db::DbConnection;
+use crate::schema::relay_outbox;
+
+/// Why an event was refused before it reached `relay_outbox`
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum InvalidOutboxEvent {
+    #[error("event_type must look like `category.action`, got {0:?}")]
+    EventType(String),
+    #[error("event_data must be a JSON object")]
+    EventData,
+    #[error("event_id and transaction_id must not be empty when given")]
+    EmptyId,
+}
+
+/// A row for `relay_outbox`; the poller publishes it to the topic for its `event_type` category
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = relay_outbox)]
+pub struct NewOutboxEvent<'a> {
+    pub event_type: &'a str,
+    pub event_data: &'a serde_json::Value,
+    /// Consumers skip an event_id they have already processed
+    pub event_id: Option<&'a str>,
+    pub transaction_id: Option<&'a str>,
+}
+
+impl<'a> NewOutboxEvent<'a> {
+    pub fn new(event_type: &'a str, event_data: &'a serde_json::Value) -> Self {
+        Self {
+            event_type,
+            event_data,
+            event_id: None,
+            transaction_id: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), InvalidOutboxEvent> {
+        let valid_part = |part: &str| {
+            !part.is_empty() && part.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+        };
+        if !self.event_type.contains('.') || !self.event_type.split('.').all(valid_part) {
+            return Err(InvalidOutboxEvent::EventType(self.event_type.to_string()));
+        }
+        if !self.event_data.is_object() {
+            return Err(InvalidOutboxEvent::EventData);
+        }
+        if self.event_id.is_some_and(|id| id.trim().is_empty())
+            || self.transaction_id.is_some_and(|id| id.trim().is_empty())
+        {
+            return Err(InvalidOutboxEvent::EmptyId);
+        }
+        Ok(())
+    }
+}
+
+/// Validate and insert one event; returns the outbox row id
+pub async fn enqueue_event(
+    conn: &mut DbConnection,
+    event_type: &str,
+    event_data: &serde_json::Value,
+    event_id: Option<&str>,
+    transaction_id: Option<&str>,
+) -> anyhow::Result<i64> {
+    let event = NewOutboxEvent {
+        event_type,
+        event_data,
+        event_id,
+        transaction_id,
+    };
+    enqueue_event_in_transaction(conn, &event).await
+}
+
+/// Like [`enqueue_event`], on a connection the caller may already have a transaction open on (e.g. inside
+/// `AsyncConnection::transaction`), so the event is only published if the caller's own writes commit
+pub async fn enqueue_event_in_transaction(conn: &mut AsyncPgConnection, event: &NewOutboxEvent<'_>) -> anyhow::Result<i64> {
+    event.validate()?;
+
+    let id = diesel::insert_into(relay_outbox::table)
+        .values(event)
+        .returning(relay_outbox::id)
+        .get_result(conn)
+        .await?;
+
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_outbox_event() {
+        let data = serde_json::json!({"post_id": "0x1"});
+        assert_eq!(NewOutboxEvent::new("reaction.created", &data).validate(), Ok(()));
+        assert_eq!(NewOutboxEvent::new("spt_v2.token_created", &data).validate(), Ok(()));
+
+        for event_type in ["", "reaction", "reaction.", "Reaction.created", "reaction created"] {
+            assert_eq!(
+                NewOutboxEvent::new(event_type, &data).validate(),
+                Err(InvalidOutboxEvent::EventType(event_type.to_string()))
+            );
+        }
+
+        let not_object = serde_json::json!(["x"]);
+        assert_eq!(NewOutboxEvent::new("tip.created", &not_object).validate(), Err(InvalidOutboxEvent::EventData));
+
+        let empty_id = NewOutboxEvent { event_id: Some(" "), ..NewOutboxEvent::new("tip.created", &data) };
+        assert_eq!(empty_id.validate(), Err(InvalidOutboxEvent::EmptyId));
+    }
+}
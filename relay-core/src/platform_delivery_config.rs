@@ -2,6 +2,7 @@ use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use crate::schema::platform_delivery_config;
 use crate::db::DbConnection;
+use crate::types::ApnsEnvironment;
 
 #[derive(Debug, Clone, Queryable, Selectable, Serialize, Deserialize)]
 #[diesel(table_name = platform_delivery_config)]
@@ -18,10 +19,18 @@ pub struct PlatformDeliveryConfig {
     pub resend_from_email: Option<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
+    pub apns_environment: Option<String>,
+    pub email_logo_url: Option<String>,
+    pub email_brand_color: Option<String>,
+    pub email_footer: Option<String>,
+    pub email_template: Option<String>,
 }
 
-#[derive(Debug, Insertable, Serialize, Deserialize)]
-#[diesel(table_name = platform_delivery_config)]
+/// Also used as the changeset for updates; `None` clears the column
+#[derive(Debug, Insertable, AsChangeset, Serialize, Deserialize)]
+#[diesel(table_name = platform_delivery_config, treat_none_as_null = true)]
 pub struct NewPlatformDeliveryConfig {
     pub platform_id: String,
     pub apns_bundle_id: Option<String>,
@@ -32,6 +41,108 @@ pub struct NewPlatformDeliveryConfig {
     pub fcm_server_key: Option<String>,
     pub resend_api_key: Option<String>,
     pub resend_from_email: Option<String>,
+    /// HTTPS endpoint notifications are POSTed to, signed with `webhook_secret`
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
+    /// `sandbox` or `production`; guessed from `apns_bundle_id` when unset
+    pub apns_environment: Option<String>,
+    /// https URL of the logo shown at the top of notification emails
+    pub email_logo_url: Option<String>,
+    /// `#rgb` or `#rrggbb` accent color for notification emails
+    pub email_brand_color: Option<String>,
+    /// Plain text shown under each notification email
+    pub email_footer: Option<String>,
+    /// Handlebars HTML template for notification emails; the built-in one is used when unset
+    pub email_template: Option<String>,
+}
+
+/// `#rgb` or `#rrggbb`; anything else could break out of the `style` attribute it's rendered into
+pub fn is_hex_color(color: &str) -> bool {
+    color
+        .strip_prefix('#')
+        .is_some_and(|hex| matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+impl NewPlatformDeliveryConfig {
+    /// APNs needs a key id, team id and key together; a partial set would silently disable push
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let has_key = self.apns_key_content.is_some() || self.apns_key_path.is_some();
+        let any_apns = self.apns_key_id.is_some() || self.apns_team_id.is_some() || has_key;
+
+        if any_apns && (self.apns_key_id.is_none() || self.apns_team_id.is_none() || !has_key) {
+            return Err("APNs config requires apns_key_id, apns_team_id and apns_key_content together");
+        }
+
+        if let Some(content) = &self.apns_key_content {
+            use base64::Engine;
+            if base64::engine::general_purpose::STANDARD.decode(content).is_err() {
+                return Err("apns_key_content must be base64 encoded");
+            }
+        }
+
+        if self.apns_environment.as_deref().is_some_and(|env| env.parse::<ApnsEnvironment>().is_err()) {
+            return Err("apns_environment must be sandbox or production");
+        }
+
+        if self.webhook_url.is_some() != self.webhook_secret.is_some() {
+            return Err("Webhook config requires webhook_url and webhook_secret together");
+        }
+
+        if self.webhook_url.as_deref().is_some_and(|url| !url.starts_with("https://")) {
+            return Err("webhook_url must be an https:// URL");
+        }
+
+        if self.email_logo_url.as_deref().is_some_and(|url| !url.starts_with("https://")) {
+            return Err("email_logo_url must be an https:// URL");
+        }
+
+        if self.email_brand_color.as_deref().is_some_and(|color| !is_hex_color(color)) {
+            return Err("email_brand_color must be a hex color like #1d4ed8");
+        }
+
+        Ok(())
+    }
+}
+
+/// Hide all but the last few characters of a secret so admins can tell keys apart
+fn mask_secret(secret: &Option<String>) -> Option<String> {
+    const VISIBLE: usize = 4;
+    const MIN_LEN_TO_REVEAL: usize = 16;
+
+    secret.as_ref().map(|s| {
+        let chars: Vec<char> = s.chars().collect();
+        if chars.len() < MIN_LEN_TO_REVEAL {
+            "****".to_string()
+        } else {
+            format!("****{}", chars[chars.len() - VISIBLE..].iter().collect::<String>())
+        }
+    })
+}
+
+impl PlatformDeliveryConfig {
+    /// API representation with secrets masked; secrets are write-only
+    pub fn masked(&self) -> serde_json::Value {
+        serde_json::json!({
+            "platform_id": self.platform_id,
+            "apns_bundle_id": self.apns_bundle_id,
+            "apns_key_id": self.apns_key_id,
+            "apns_team_id": self.apns_team_id,
+            "apns_key_path": self.apns_key_path,
+            "apns_key_content": mask_secret(&self.apns_key_content),
+            "apns_environment": self.apns_environment,
+            "fcm_server_key": mask_secret(&self.fcm_server_key),
+            "resend_api_key": mask_secret(&self.resend_api_key),
+            "resend_from_email": self.resend_from_email,
+            "webhook_url": self.webhook_url,
+            "webhook_secret": mask_secret(&self.webhook_secret),
+            "email_logo_url": self.email_logo_url,
+            "email_brand_color": self.email_brand_color,
+            "email_footer": self.email_footer,
+            "email_template": self.email_template,
+            "created_at": self.created_at,
+            "updated_at": self.updated_at,
+        })
+    }
 }
 
 /// Get platform delivery configuration, falling back to None if not found
@@ -52,6 +163,53 @@ pub async fn get_platform_delivery_config(
     Ok(configs.into_iter().next())
 }
 
+/// Insert a platform's config; None if the platform already has one
+pub async fn insert_platform_delivery_config(
+    conn: &mut DbConnection,
+    config: &NewPlatformDeliveryConfig,
+) -> anyhow::Result<Option<PlatformDeliveryConfig>> {
+    let saved = diesel_async::RunQueryDsl::get_result(
+        diesel::insert_into(platform_delivery_config::table)
+            .values(config)
+            .on_conflict(platform_delivery_config::platform_id)
+            .do_nothing()
+            .returning(PlatformDeliveryConfig::as_returning()),
+        &mut *conn,
+    )
+    .await
+    .optional()?;
+
+    Ok(saved)
+}
+
+/// Replace a platform's config; bumping `updated_at` makes delivery rebuild its cached clients
+pub async fn update_platform_delivery_config(
+    conn: &mut DbConnection,
+    config: &NewPlatformDeliveryConfig,
+) -> anyhow::Result<Option<PlatformDeliveryConfig>> {
+    let saved = diesel_async::RunQueryDsl::get_result(
+        diesel::update(platform_delivery_config::table.filter(platform_delivery_config::platform_id.eq(&config.platform_id)))
+            .set((config, platform_delivery_config::updated_at.eq(chrono::Utc::now())))
+            .returning(PlatformDeliveryConfig::as_returning()),
+        &mut *conn,
+    )
+    .await
+    .optional()?;
+
+    Ok(saved)
+}
+
+/// Delete a platform's config; false if it had none
+pub async fn delete_platform_delivery_config(conn: &mut DbConnection, platform_id: &str) -> anyhow::Result<bool> {
+    let deleted = diesel_async::RunQueryDsl::execute(
+        diesel::delete(platform_delivery_config::table.filter(platform_delivery_config::platform_id.eq(platform_id))),
+        &mut *conn,
+    )
+    .await?;
+
+    Ok(deleted > 0)
+}
+
 /// Convert platform delivery config to DeliveryConfig format for compatibility
 impl From<&PlatformDeliveryConfig> for crate::config::DeliveryConfig {
     fn from(config: &PlatformDeliveryConfig) -> Self {
@@ -61,10 +219,117 @@ impl From<&PlatformDeliveryConfig> for crate::config::DeliveryConfig {
             apns_team_id: config.apns_team_id.clone(),
             apns_key_path: config.apns_key_path.clone(),
             apns_key_content: config.apns_key_content.clone(),
+            apns_environment: config.apns_environment.as_deref().and_then(|env| env.parse().ok()),
             fcm_server_key: config.fcm_server_key.clone(),
             resend_api_key: config.resend_api_key.clone(),
             resend_from_email: config.resend_from_email.clone(),
+            webhook_url: config.webhook_url.clone(),
+            webhook_secret: config.webhook_secret.clone(),
+            email_logo_url: config.email_logo_url.clone(),
+            email_brand_color: config.email_brand_color.clone(),
+            email_footer: config.email_footer.clone(),
+            email_template_path: None,
+            email_template: config.email_template.clone(),
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> NewPlatformDeliveryConfig {
+        NewPlatformDeliveryConfig {
+            platform_id: "platform-a".to_string(),
+            apns_bundle_id: None,
+            apns_key_id: None,
+            apns_team_id: None,
+            apns_key_path: None,
+            apns_key_content: None,
+            fcm_server_key: None,
+            resend_api_key: None,
+            resend_from_email: None,
+            webhook_url: None,
+            webhook_secret: None,
+            apns_environment: None,
+            email_logo_url: None,
+            email_brand_color: None,
+            email_footer: None,
+            email_template: None,
+        }
+    }
+
+    #[test]
+    fn test_apns_fields_must_be_complete() {
+        assert!(config().validate().is_ok());
+
+        let partial = NewPlatformDeliveryConfig { apns_key_id: Some("KEY".into()), ..config() };
+        assert!(partial.validate().is_err());
+
+        let complete = NewPlatformDeliveryConfig {
+            apns_key_id: Some("KEY".into()),
+            apns_team_id: Some("TEAM".into()),
+            apns_key_content: Some("LS0tLS1CRUdJTg==".into()),
+            ..config()
+        };
+        assert!(complete.validate().is_ok());
+
+        let not_base64 = NewPlatformDeliveryConfig { apns_key_content: Some("not base64!".into()), ..complete };
+        assert!(not_base64.validate().is_err());
+    }
+
+    #[test]
+    fn test_apns_environment_must_be_known() {
+        let sandbox = NewPlatformDeliveryConfig { apns_environment: Some("sandbox".into()), ..config() };
+        assert!(sandbox.validate().is_ok());
+
+        let staging = NewPlatformDeliveryConfig { apns_environment: Some("staging".into()), ..config() };
+        assert!(staging.validate().is_err());
+    }
+
+    #[test]
+    fn test_webhook_needs_https_url_and_secret() {
+        let webhook = NewPlatformDeliveryConfig {
+            webhook_url: Some("https://platform.example.com/relay".into()),
+            webhook_secret: Some("whsec_0123456789".into()),
+            ..config()
+        };
+        assert!(webhook.validate().is_ok());
+
+        let no_secret = NewPlatformDeliveryConfig { webhook_secret: None, ..webhook };
+        assert!(no_secret.validate().is_err());
+
+        let plain_http = NewPlatformDeliveryConfig {
+            webhook_url: Some("http://platform.example.com/relay".into()),
+            webhook_secret: Some("whsec_0123456789".into()),
+            ..config()
+        };
+        assert!(plain_http.validate().is_err());
+    }
+
+    #[test]
+    fn test_email_branding_validated() {
+        let branded = NewPlatformDeliveryConfig {
+            email_logo_url: Some("https://cdn.example.com/logo.png".into()),
+            email_brand_color: Some("#1D4ED8".into()),
+            ..config()
+        };
+        assert!(branded.validate().is_ok());
+
+        let plain_http = NewPlatformDeliveryConfig { email_logo_url: Some("http://cdn.example.com/logo.png".into()), ..config() };
+        assert!(plain_http.validate().is_err());
+
+        for color in ["1d4ed8", "#1d4e", "#zzz", "red; background: url(x)"] {
+            let bad = NewPlatformDeliveryConfig { email_brand_color: Some(color.into()), ..config() };
+            assert!(bad.validate().is_err(), "{}", color);
+        }
+    }
+
+    #[test]
+    fn test_secrets_masked() {
+        assert_eq!(mask_secret(&None), None);
+        assert_eq!(mask_secret(&Some("short".into())).as_deref(), Some("****"));
+        assert_eq!(mask_secret(&Some("re_0123456789abcdef".into())).as_deref(), Some("****cdef"));
+    }
+}
@@ -0,0 +1,108 @@
+use crate::redis::{get_connection, RedisConnection, RedisKeys, RedisPool};
+use std::future::Future;
+
+/// How long a processed event id is remembered; comfortably longer than topic retention plus an outbox
+/// re-publish after a crash
+pub const PROCESSED_EVENT_TTL_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+/// The outbox `event_id` of a published event, if it has one
+pub fn event_id(event: &serde_json::Value) -> Option<&str> {
+    event.get("event_id").and_then(|v| v.as_str()).filter(|id| !id.is_empty())
+}
+
+/// Mark `event_id` as processed by `consumer`; false if it already was and should be skipped.
+/// Each consumer tracks its own ids, since the same event may be handled by several of them
+pub async fn claim(conn: &mut RedisConnection, keys: RedisKeys<'_>, consumer: &str, event_id: &str) -> anyhow::Result<bool> {
+    let claimed: Option<String> = redis::cmd("SET")
+        .arg(keys.processed_event(consumer, event_id))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(PROCESSED_EVENT_TTL_SECONDS)
+        .query_async(conn)
+        .await?;
+
+    Ok(claimed.is_some())
+}
+
+/// Forget a claim after processing failed, so a redelivery of the event is handled
+pub async fn release(conn: &mut RedisConnection, keys: RedisKeys<'_>, consumer: &str, event_id: &str) -> anyhow::Result<()> {
+    redis::cmd("DEL")
+        .arg(keys.processed_event(consumer, event_id))
+        .query_async::<()>(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Run `process` unless `consumer` already handled `event_id`
+/// Events without an id, or arriving while Redis is unreachable, are processed rather than dropped
+pub async fn process_once<F>(pool: &RedisPool, keys: RedisKeys<'_>, consumer: &str, event_id: Option<&str>, process: F) -> anyhow::Result<()>
+where
+    F: Future<Output = anyhow::Result<()>>,
+{
+    let event_id = match event_id {
+        Some(id) => id,
+        None => return process.await,
+    };
+
+    let mut conn = match get_connection(pool).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            tracing::warn!("Processing event {} without duplicate check: {}", event_id, e);
+            return process.await;
+        }
+    };
+
+    match claim(&mut conn, keys, consumer, event_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            tracing::debug!("Skipping event {} already processed by {}", event_id, consumer);
+            return Ok(());
+        }
+        Err(e) => {
+            tracing::warn!("Processing event {} without duplicate check: {}", event_id, e);
+            return process.await;
+        }
+    }
+
+    let result = process.await;
+    if result.is_err() {
+        if let Err(e) = release(&mut conn, keys, consumer, event_id).await {
+            tracing::warn!("Failed to release processed marker for event {}: {}", event_id, e);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_id_extraction() {
+        assert_eq!(event_id(&serde_json::json!({"event_id": "evt-1", "transaction_id": "tx"})), Some("evt-1"));
+        assert_eq!(event_id(&serde_json::json!({"event_id": null, "transaction_id": "tx"})), None);
+        assert_eq!(event_id(&serde_json::json!({"event_id": ""})), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis at REDIS_URL"]
+    async fn test_event_claimed_once_per_consumer() {
+        let config = crate::Config::from_env();
+        let keys = config.redis.keys();
+        let mut conn = redis::Client::open(config.redis.url.as_str()).unwrap().get_multiplexed_async_connection().await.unwrap();
+        let event_id = format!("evt-{}", uuid::Uuid::new_v4());
+
+        assert!(claim(&mut conn, keys, "relay-notify", &event_id).await.unwrap());
+        assert!(!claim(&mut conn, keys, "relay-notify", &event_id).await.unwrap());
+        assert!(claim(&mut conn, keys, "relay-messaging", &event_id).await.unwrap());
+
+        release(&mut conn, keys, "relay-notify", &event_id).await.unwrap();
+        assert!(claim(&mut conn, keys, "relay-notify", &event_id).await.unwrap());
+
+        release(&mut conn, keys, "relay-notify", &event_id).await.unwrap();
+        release(&mut conn, keys, "relay-messaging", &event_id).await.unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::Result;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use diesel_async::RunQueryDsl;
+use std::future::Future;
+
+use crate::db::DbConnection;
+use crate::redis::{RedisConnection, RedisKeys};
+use crate::schema::profiles;
+
+define_sql_function!(fn lower(x: Text) -> Text);
+
+/// Whether a profile is owned by `address`, ignoring case. Compares `lower(owner_address)` so the indexer
+/// can answer it from an index on that expression, which an `ILIKE` can't use
+pub async fn exists(conn: &mut DbConnection, address: &str) -> Result<bool> {
+    let id: Option<i32> = profiles::table
+        .filter(lower(profiles::owner_address).eq(address.to_lowercase()))
+        .select(profiles::id)
+        .first(conn)
+        .await
+        .optional()?;
+
+    Ok(id.is_some())
+}
+
+/// Whether `address` has a profile, asking `lookup` only when `PROFILE_EXISTS` doesn't already say so.
+/// A found profile is cached for `ttl_seconds`, refreshed on each miss; missing profiles aren't cached so a
+/// newly indexed profile can sign in straight away. Without Redis, or with a zero TTL, every call asks `lookup`
+pub async fn exists_cached<F, Fut>(
+    redis_conn: Option<&mut RedisConnection>,
+    keys: RedisKeys<'_>,
+    ttl_seconds: u64,
+    address: &str,
+    lookup: F,
+) -> Result<bool>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<bool>>,
+{
+    let Some(redis_conn) = redis_conn.filter(|_| ttl_seconds > 0) else {
+        return lookup().await;
+    };
+    let key = keys.profile_exists(&address.to_lowercase());
+
+    match redis::cmd("EXISTS").arg(&key).query_async::<bool>(redis_conn).await {
+        Ok(true) => return Ok(true),
+        Ok(false) => {}
+        Err(e) => tracing::warn!("Failed to read cached profile for {}: {}", address, e),
+    }
+
+    let exists = lookup().await?;
+    if exists {
+        let cached = redis::cmd("SET").arg(&key).arg(1).arg("EX").arg(ttl_seconds).query_async::<()>(redis_conn).await;
+        if let Err(e) = cached {
+            tracing::warn!("Failed to cache profile for {}: {}", address, e);
+        }
+    }
+
+    Ok(exists)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis at REDIS_URL"]
+    async fn test_second_auth_skips_profile_lookup() {
+        let config = crate::Config::from_env();
+        let keys = config.redis.keys();
+        let pool = crate::redis::create_pool(&config.redis).await.unwrap();
+        let mut redis_conn = crate::redis::get_connection(&pool).await.unwrap();
+        let wallet = format!("0xWallet-{}", uuid::Uuid::new_v4());
+
+        let lookups = Cell::new(0);
+        let lookup = |found: bool| {
+            lookups.set(lookups.get() + 1);
+            async move { Ok(found) }
+        };
+
+        // Missing profiles are looked up every time
+        assert!(!exists_cached(Some(&mut redis_conn), keys, 60, &wallet, || lookup(false)).await.unwrap());
+        assert!(!exists_cached(Some(&mut redis_conn), keys, 60, &wallet, || lookup(false)).await.unwrap());
+        assert_eq!(lookups.get(), 2);
+
+        // Once found, later sign-ins with the address in any case are answered from Redis
+        assert!(exists_cached(Some(&mut redis_conn), keys, 60, &wallet, || lookup(true)).await.unwrap());
+        assert!(exists_cached(Some(&mut redis_conn), keys, 60, &wallet.to_lowercase(), || lookup(true)).await.unwrap());
+        assert_eq!(lookups.get(), 3);
+
+        // A zero TTL turns the cache off
+        assert!(!exists_cached(Some(&mut redis_conn), keys, 0, &wallet, || lookup(false)).await.unwrap());
+        assert_eq!(lookups.get(), 4);
+    }
+}
@@ -0,0 +1,123 @@
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// A user's do-not-disturb window in their local time
+/// `start > end` means the window wraps midnight (e.g. 22:00 to 07:00)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub timezone: Tz,
+}
+
+impl QuietHours {
+    /// Build from stored preferences; unset or unknown values disable the window
+    pub fn from_preferences(start: Option<NaiveTime>, end: Option<NaiveTime>, timezone: Option<&str>) -> Option<Self> {
+        let (start, end) = (start?, end?);
+        if start == end {
+            return None;
+        }
+
+        let timezone = match timezone {
+            Some(tz) => parse_timezone(tz)?,
+            None => Tz::UTC,
+        };
+
+        Some(Self { start, end, timezone })
+    }
+
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.timezone).time();
+
+        if self.start < self.end {
+            local >= self.start && local < self.end
+        } else {
+            local >= self.start || local < self.end
+        }
+    }
+
+    /// When the window containing `now` ends, or None if `now` is outside quiet hours
+    pub fn ends_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if !self.contains(now) {
+            return None;
+        }
+
+        let local = now.with_timezone(&self.timezone);
+        let mut end_date = local.date_naive();
+        if local.time() >= self.end {
+            // Still in the part of a wrapped window before midnight; it ends tomorrow
+            end_date += Duration::days(1);
+        }
+
+        // `earliest` picks the first instant when the end time falls in a DST overlap
+        let end = self.timezone
+            .from_local_datetime(&end_date.and_time(self.end))
+            .earliest()
+            .map(|t| t.with_timezone(&Utc))
+            // The end time doesn't exist that day (DST gap); end an hour later instead
+            .unwrap_or_else(|| now + Duration::hours(1));
+
+        Some(end)
+    }
+}
+
+/// Parse an IANA timezone name such as `America/New_York`
+pub fn parse_timezone(name: &str) -> Option<Tz> {
+    name.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_window_wrapping_midnight() {
+        let quiet = QuietHours::from_preferences(Some(time(22, 0)), Some(time(7, 0)), Some("UTC")).unwrap();
+
+        assert!(quiet.contains(utc("2025-01-15T23:30:00Z")));
+        assert!(quiet.contains(utc("2025-01-16T03:00:00Z")));
+        assert!(!quiet.contains(utc("2025-01-16T07:00:00Z")));
+        assert!(!quiet.contains(utc("2025-01-16T12:00:00Z")));
+
+        // Before midnight the window ends the next morning; after midnight, the same morning
+        assert_eq!(quiet.ends_at(utc("2025-01-15T23:30:00Z")), Some(utc("2025-01-16T07:00:00Z")));
+        assert_eq!(quiet.ends_at(utc("2025-01-16T03:00:00Z")), Some(utc("2025-01-16T07:00:00Z")));
+        assert_eq!(quiet.ends_at(utc("2025-01-16T12:00:00Z")), None);
+    }
+
+    #[test]
+    fn test_window_uses_user_timezone() {
+        // 22:00-07:00 in New York (UTC-5 in January)
+        let quiet = QuietHours::from_preferences(Some(time(22, 0)), Some(time(7, 0)), Some("America/New_York")).unwrap();
+
+        // 08:00 UTC is 03:00 in New York
+        assert!(quiet.contains(utc("2025-01-16T08:00:00Z")));
+        assert_eq!(quiet.ends_at(utc("2025-01-16T08:00:00Z")), Some(utc("2025-01-16T12:00:00Z")));
+
+        // 20:00 UTC is 15:00 in New York
+        assert!(!quiet.contains(utc("2025-01-16T20:00:00Z")));
+    }
+
+    #[test]
+    fn test_same_day_window() {
+        let quiet = QuietHours::from_preferences(Some(time(13, 0)), Some(time(14, 0)), None).unwrap();
+
+        assert!(quiet.contains(utc("2025-01-16T13:30:00Z")));
+        assert!(!quiet.contains(utc("2025-01-16T23:30:00Z")));
+    }
+
+    #[test]
+    fn test_incomplete_or_invalid_settings_disable_window() {
+        assert!(QuietHours::from_preferences(Some(time(22, 0)), None, None).is_none());
+        assert!(QuietHours::from_preferences(Some(time(22, 0)), Some(time(22, 0)), None).is_none());
+        assert!(QuietHours::from_preferences(Some(time(22, 0)), Some(time(7, 0)), Some("Mars/Olympus")).is_none());
+    }
+}
@@ -6,9 +6,149 @@ use tracing;
 
 use crate::config::RedisConfig;
 
+pub mod chat_cache;
+pub mod counts;
+pub mod inbox;
+pub mod streams;
+
 pub type RedisPool = Arc<Client>;
 pub type RedisConnection = MultiplexedConnection;
 
+/// Every Redis key the relay uses. Building them here keeps `RELAY_KEY_PREFIX` and
+/// `REDIS_PLATFORM_NAMESPACES` applied the same way at each call site
+#[derive(Debug, Clone, Copy)]
+pub struct RedisKeys<'a> {
+    prefix: &'a str,
+    platform_namespaces: bool,
+}
+
+impl RedisConfig {
+    pub fn keys(&self) -> RedisKeys<'_> {
+        RedisKeys { prefix: &self.key_prefix, platform_namespaces: self.platform_namespaces }
+    }
+}
+
+impl RedisKeys<'_> {
+    fn key(&self, name: &str, parts: &[&str]) -> String {
+        let mut key = format!("{}{}", self.prefix, name);
+        for part in parts {
+            key.push(':');
+            key.push_str(part);
+        }
+        key
+    }
+
+    /// `parts`, plus the platform when keys are namespaced per platform
+    fn platform_key(&self, name: &str, parts: &[&str], platform_id: Option<&str>) -> String {
+        match platform_id.filter(|_| self.platform_namespaces) {
+            Some(platform_id) => self.key(name, &[parts, &[platform_id]].concat()),
+            None => self.key(name, parts),
+        }
+    }
+
+    /// Recent notifications, newest first
+    pub fn inbox(&self, user_address: &str, platform_id: Option<&str>) -> String {
+        self.platform_key("INBOX", &[user_address], platform_id)
+    }
+
+    /// Pattern matching a user's per-platform inboxes; None when inboxes aren't namespaced
+    pub fn platform_inbox_pattern(&self, user_address: &str) -> Option<String> {
+        self.platform_namespaces.then(|| self.key("INBOX", &[user_address, "*"]))
+    }
+
+    pub fn notify_stream(&self, user_address: &str, platform_id: Option<&str>) -> String {
+        self.platform_key("STREAM:NOTIFY", &[user_address], platform_id)
+    }
+
+    /// Messages are platform-agnostic, so a user has one chat stream
+    pub fn chat_stream(&self, user_address: &str) -> String {
+        self.key("STREAM:CHAT", &[user_address])
+    }
+
+    pub fn ws_cursor(&self, user_address: &str, platform_id: Option<&str>) -> String {
+        self.platform_key("WS_CURSOR", &[user_address], platform_id)
+    }
+
+    pub fn unread_total(&self, user_address: &str) -> String {
+        self.key("UNREAD", &[user_address])
+    }
+
+    pub fn unread_platform(&self, user_address: &str, platform_id: &str) -> String {
+        self.key("UNREAD", &[user_address, platform_id])
+    }
+
+    /// Platforms `user_address` has an `unread_platform` counter for
+    pub fn unread_platforms(&self, user_address: &str) -> String {
+        self.key("UNREAD_PLATFORMS", &[user_address])
+    }
+
+    /// Set once `user_address`'s platform counters from before `unread_platforms` have been indexed
+    pub fn unread_indexed(&self, user_address: &str) -> String {
+        self.key("UNREAD_INDEXED", &[user_address])
+    }
+
+    pub fn chat_cache(&self, conversation_id: &str) -> String {
+        self.key("CHAT", &[conversation_id])
+    }
+
+    pub fn conversation_preview(&self, conversation_id: &str) -> String {
+        self.key("CONV_PREVIEW", &[conversation_id])
+    }
+
+    pub fn hidden_messages(&self, user_address: &str, conversation_id: &str) -> String {
+        self.key("HIDDEN_MESSAGES", &[user_address, conversation_id])
+    }
+
+    pub fn idempotency(&self, user_address: &str, key: &str) -> String {
+        self.key("IDEMPOTENCY", &[user_address, key])
+    }
+
+    pub fn presence(&self, user_address: &str) -> String {
+        self.key("PRESENCE", &[user_address])
+    }
+
+    pub fn last_seen(&self, user_address: &str) -> String {
+        self.key("LAST_SEEN", &[user_address])
+    }
+
+    pub fn rate_limit(&self, route: &str, identity: &str) -> String {
+        self.key("RL", &[route, identity])
+    }
+
+    /// Denylist entry for a revoked token's `jti`
+    pub fn revoked_token(&self, jti: &str) -> String {
+        self.key("REVOKED_TOKEN", &[jti])
+    }
+
+    /// Set while a request has recently updated the session's `last_seen_at`
+    pub fn session_touched(&self, jti: &str) -> String {
+        self.key("SESSION_TOUCHED", &[jti])
+    }
+
+    /// Set while `user_address` (lowercased) is known to have a profile
+    pub fn profile_exists(&self, user_address: &str) -> String {
+        self.key("PROFILE_EXISTS", &[user_address])
+    }
+
+    pub fn processed_event(&self, consumer: &str, event_id: &str) -> String {
+        self.key("PROCESSED", &[consumer, event_id])
+    }
+
+    pub fn dnd_digest(&self, user_address: &str) -> String {
+        self.key("DND_DIGEST", &[user_address])
+    }
+
+    /// Users with a pending quiet-hours digest
+    pub fn dnd_digest_due(&self) -> String {
+        self.key("DND_DIGEST_DUE", &[])
+    }
+
+    /// Lease held by the one instance running singleton task `name`
+    pub fn leader_lock(&self, name: &str) -> String {
+        self.key("LOCK", &[name])
+    }
+}
+
 pub async fn create_pool(config: &RedisConfig) -> Result<RedisPool> {
     tracing::info!("Setting up Redis connection pool");
     tracing::info!("Redis URL: {}", mask_redis_url(&config.url));
@@ -38,17 +178,69 @@ pub async fn get_connection(pool: &RedisPool) -> Result<RedisConnection> {
         .map_err(|e| anyhow!("Failed to get Redis connection: {}", e))
 }
 
-fn mask_redis_url(url: &str) -> String {
-    if let Some(at_pos) = url.find('@') {
+/// The URL with its password replaced by `****`, for logs and diagnostics
+pub fn mask_redis_url(url: &str) -> String {
+    if let Some(at_pos) = url.rfind('@') {
         let (before_at, after_at) = url.split_at(at_pos);
         if let Some(colon_pos) = before_at.rfind(':') {
             let (protocol_user, _password) = before_at.split_at(colon_pos);
-            format!("{}:****@{}", protocol_user, after_at)
+            format!("{}:****{}", protocol_user, after_at)
         } else {
-            format!("redis://****@{}", after_at)
+            format!("redis://****{}", after_at)
         }
     } else {
         url.to_string()
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(stream_max_len: usize) -> RedisConfig {
+        RedisConfig {
+            url: std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_string()),
+            max_connections: 1,
+            stream_max_len,
+            stream_ttl_seconds: 60,
+            key_prefix: String::new(),
+            platform_namespaces: false,
+        }
+    }
+
+    #[test]
+    fn test_platform_keys_do_not_collide() {
+        let config = RedisConfig { key_prefix: "staging:".to_string(), platform_namespaces: true, ..test_config(50) };
+        let keys = config.keys();
+
+        assert_ne!(keys.inbox("0xabc", Some("app-a")), keys.inbox("0xabc", Some("app-b")));
+        assert_ne!(keys.notify_stream("0xabc", Some("app-a")), keys.notify_stream("0xabc", Some("app-b")));
+        assert_ne!(keys.ws_cursor("0xabc", Some("app-a")), keys.ws_cursor("0xabc", Some("app-b")));
+        assert_eq!(keys.inbox("0xabc", Some("app-a")), "staging:INBOX:0xabc:app-a");
+        assert_eq!(keys.inbox("0xabc", None), "staging:INBOX:0xabc");
+        assert_eq!(keys.chat_stream("0xabc"), "staging:STREAM:CHAT:0xabc");
+        assert_eq!(keys.dnd_digest_due(), "staging:DND_DIGEST_DUE");
+        // Idempotency keys are scoped to the sender so users can't read each other's results
+        assert_ne!(keys.idempotency("0xalice", "k1"), keys.idempotency("0xbob", "k1"));
+
+        // Without namespacing the platform is ignored and keys keep their unprefixed names
+        let shared = test_config(50);
+        assert_eq!(shared.keys().inbox("0xabc", Some("app-a")), "INBOX:0xabc");
+        assert_eq!(shared.keys().notify_stream("0xabc", Some("app-b")), "STREAM:NOTIFY:0xabc");
+        assert_eq!(shared.keys().unread_platform("0xabc", "app-a"), "UNREAD:0xabc:app-a");
+    }
+
+    #[test]
+    fn test_count_keys() {
+        let config = RedisConfig { key_prefix: "staging:".to_string(), ..test_config(50) };
+        let keys = config.keys();
+
+        assert_eq!(keys.unread_total("0xabc"), "staging:UNREAD:0xabc");
+        assert_eq!(keys.unread_platform("0xabc", "app-a"), "staging:UNREAD:0xabc:app-a");
+        assert_eq!(keys.unread_platforms("0xabc"), "staging:UNREAD_PLATFORMS:0xabc");
+        assert_eq!(keys.unread_indexed("0xabc"), "staging:UNREAD_INDEXED:0xabc");
+        // Neither is caught by the scan for platform counters written before the index
+        assert!(!keys.unread_platforms("0xabc").starts_with(&keys.unread_platform("0xabc", "")));
+        assert!(!keys.unread_indexed("0xabc").starts_with(&keys.unread_platform("0xabc", "")));
+    }
+}
@@ -0,0 +1,66 @@
+use anyhow::Result;
+use chrono::Utc;
+use serde_json::Value;
+
+use super::{RedisConnection, RedisKeys};
+
+/// Messages kept in each conversation's `CHAT:{conversation_id}` cache
+pub const CHAT_CACHE_LEN: isize = 50;
+
+/// The `message_id` of a cached entry; None for entries written before it was recorded
+fn entry_message_id(entry: &str) -> Option<i64> {
+    serde_json::from_str::<Value>(entry).ok()?.get("message_id")?.as_i64()
+}
+
+/// Push a just-sent message onto its conversation's cache, keeping the newest `CHAT_CACHE_LEN`
+pub async fn push_message(
+    conn: &mut RedisConnection,
+    keys: RedisKeys<'_>,
+    conversation_id: &str,
+    message_id: i64,
+    sender: &str,
+    recipient: &str,
+    content: &str,
+) -> Result<()> {
+    let key = keys.chat_cache(conversation_id);
+    let entry = serde_json::json!({
+        "message_id": message_id,
+        "sender": sender,
+        "recipient": recipient,
+        "content": content,
+        "created_at": Utc::now(),
+    });
+
+    redis::pipe()
+        .atomic()
+        .cmd("LPUSH")
+        .arg(&key)
+        .arg(serde_json::to_string(&entry)?)
+        .ignore()
+        .cmd("LTRIM")
+        .arg(&key)
+        .arg(0)
+        .arg(CHAT_CACHE_LEN - 1)
+        .ignore()
+        .query_async::<()>(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Drop a deleted message from its conversation's cache. Entries from before `message_id` was recorded
+/// can't be told apart, so if the cache holds any the whole cache is dropped instead
+pub async fn remove_message(conn: &mut RedisConnection, keys: RedisKeys<'_>, conversation_id: &str, message_id: i64) -> Result<()> {
+    let key = keys.chat_cache(conversation_id);
+    let entries: Vec<String> = redis::cmd("LRANGE").arg(&key).arg(0).arg(-1).query_async(conn).await?;
+
+    if entries.iter().any(|entry| entry_message_id(entry).is_none()) {
+        redis::cmd("DEL").arg(&key).query_async::<()>(conn).await?;
+        return Ok(());
+    }
+    for entry in entries.iter().filter(|entry| entry_message_id(entry) == Some(message_id)) {
+        redis::cmd("LREM").arg(&key).arg(0).arg(entry).query_async::<()>(conn).await?;
+    }
+
+    Ok(())
+}
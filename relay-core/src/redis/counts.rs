@@ -0,0 +1,205 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+use super::{RedisConnection, RedisKeys};
+use crate::notification_counts::UnreadCounts;
+
+/// DECR a platform counter, dropping it and its `UNREAD_PLATFORMS` entry once it reaches zero. A counter
+/// that goes negative stays, so the drift is still seen and recomputed
+const DECR_PLATFORM_SCRIPT: &str = "local n = redis.call('DECR', KEYS[1]) \
+    if n == 0 then redis.call('DEL', KEYS[1]) redis.call('SREM', KEYS[2], ARGV[1]) end \
+    return n";
+
+/// Queue the increments for a new unread notification: the user's total, and their count for `platform_id`
+/// (added to `UNREAD_PLATFORMS` so reads don't have to scan for it)
+pub fn incr_unread(pipe: &mut redis::Pipeline, keys: RedisKeys<'_>, user_address: &str, platform_id: Option<&str>) {
+    pipe.cmd("INCR").arg(keys.unread_total(user_address)).ignore();
+    if let Some(platform_id) = platform_id {
+        pipe.cmd("INCR")
+            .arg(keys.unread_platform(user_address, platform_id))
+            .ignore()
+            .cmd("SADD")
+            .arg(keys.unread_platforms(user_address))
+            .arg(platform_id)
+            .ignore();
+    }
+}
+
+/// Take a notification that was read off the user's total and its platform's count, unindexing the
+/// platform once nothing on it is unread
+pub async fn decr_unread(conn: &mut RedisConnection, keys: RedisKeys<'_>, user_address: &str, platform_id: Option<&str>) -> Result<()> {
+    let mut pipe = redis::pipe();
+    pipe.cmd("DECR").arg(keys.unread_total(user_address)).ignore();
+    if let Some(platform_id) = platform_id {
+        pipe.cmd("EVAL")
+            .arg(DECR_PLATFORM_SCRIPT)
+            .arg(2)
+            .arg(keys.unread_platform(user_address, platform_id))
+            .arg(keys.unread_platforms(user_address))
+            .arg(platform_id)
+            .ignore();
+    }
+    pipe.query_async::<()>(conn).await?;
+
+    Ok(())
+}
+
+/// A user's `UNREAD` total, e.g. for the app badge; a missing key counts as 0 and a negative one reads as 0.
+/// A failed read is an error, so "nothing unread" can be told from "unknown"
+pub async fn get_total(conn: &mut RedisConnection, keys: RedisKeys<'_>, user_address: &str) -> Result<i64> {
+    let total: Option<i64> = redis::cmd("GET").arg(keys.unread_total(user_address)).query_async(conn).await?;
+    Ok(total.unwrap_or(0).max(0))
+}
+
+/// A user's counters as stored, missing ones counting as 0. Negative counters are returned as they are so
+/// callers can spot drift
+pub async fn get_counts(conn: &mut RedisConnection, keys: RedisKeys<'_>, user_address: &str) -> Result<UnreadCounts> {
+    let total: Option<i64> = redis::cmd("GET").arg(keys.unread_total(user_address)).query_async(conn).await?;
+    let total = total.unwrap_or(0);
+
+    // With nothing unread there are no platform counts worth looking for
+    let platform_ids = indexed_platforms(conn, keys, user_address, total != 0).await?;
+    let mut platforms = BTreeMap::new();
+    if !platform_ids.is_empty() {
+        let counters: Vec<String> = platform_ids.iter().map(|id| keys.unread_platform(user_address, id)).collect();
+        let counts: Vec<Option<i64>> = redis::cmd("MGET").arg(&counters).query_async(conn).await?;
+        platforms.extend(platform_ids.into_iter().zip(counts.into_iter().map(|c| c.unwrap_or(0))));
+    }
+
+    Ok(UnreadCounts { total, platforms })
+}
+
+/// Overwrite a user's counters with `counts`, dropping platform counters that are no longer unread
+pub async fn set_counts(conn: &mut RedisConnection, keys: RedisKeys<'_>, user_address: &str, counts: &UnreadCounts) -> Result<()> {
+    let existing = indexed_platforms(conn, keys, user_address, true).await?;
+    let index = keys.unread_platforms(user_address);
+
+    let mut pipe = redis::pipe();
+    pipe.atomic().set(keys.unread_total(user_address), counts.total).ignore();
+    for platform_id in existing.iter().filter(|id| !counts.platforms.contains_key(*id)) {
+        pipe.del(keys.unread_platform(user_address, platform_id)).ignore();
+    }
+    pipe.del(&index).ignore();
+    for (platform_id, count) in &counts.platforms {
+        pipe.set(keys.unread_platform(user_address, platform_id), *count).ignore();
+        pipe.sadd(&index, platform_id).ignore();
+    }
+    pipe.set(keys.unread_indexed(user_address), 1).ignore();
+    pipe.query_async::<()>(conn).await?;
+
+    Ok(())
+}
+
+/// Platforms the user has counters for, from `UNREAD_PLATFORMS`. With `find_unindexed`, a user whose counters
+/// haven't been indexed yet (`UNREAD_INDEXED` unset) has counters written before the index found with `SCAN`
+/// and indexed, once
+async fn indexed_platforms(
+    conn: &mut RedisConnection,
+    keys: RedisKeys<'_>,
+    user_address: &str,
+    find_unindexed: bool,
+) -> Result<Vec<String>> {
+    let index = keys.unread_platforms(user_address);
+    let marker = keys.unread_indexed(user_address);
+    let (mut platforms, migrated): (Vec<String>, bool) =
+        redis::pipe().cmd("SMEMBERS").arg(&index).cmd("EXISTS").arg(&marker).query_async(conn).await?;
+    if migrated || !find_unindexed {
+        return Ok(platforms);
+    }
+
+    let prefix = keys.unread_platform(user_address, "");
+    let mut unindexed = Vec::new();
+    let mut cursor = 0u64;
+    loop {
+        let (next, counters): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(format!("{}*", prefix))
+            .arg("COUNT")
+            .arg(1000)
+            .query_async(conn)
+            .await?;
+        unindexed.extend(counters.iter().filter_map(|key| key.strip_prefix(&prefix)).map(str::to_string));
+        cursor = next;
+        if cursor == 0 {
+            break;
+        }
+    }
+
+    let mut pipe = redis::pipe();
+    if !unindexed.is_empty() {
+        pipe.sadd(&index, &unindexed).ignore();
+    }
+    pipe.set(&marker, 1).ignore().query_async::<()>(conn).await?;
+
+    unindexed.retain(|id| !platforms.contains(id));
+    platforms.extend(unindexed);
+    Ok(platforms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_platform_increment_is_indexed() {
+        let config = crate::Config::from_env();
+        let keys = config.redis.keys();
+        let mut pipe = redis::pipe();
+        incr_unread(&mut pipe, keys, "0xa", Some("app"));
+        incr_unread(&mut pipe, keys, "0xb", None);
+        let packed = String::from_utf8_lossy(&pipe.get_packed_pipeline()).to_string();
+
+        assert_eq!(packed.matches("INCR").count(), 3);
+        assert!(packed.contains(&format!("SADD\r\n${}\r\n{}\r\n$3\r\napp", keys.unread_platforms("0xa").len(), keys.unread_platforms("0xa"))));
+        // Notifications without a platform leave the index alone
+        assert_eq!(packed.matches("SADD").count(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis at REDIS_URL"]
+    async fn test_counts_read_through_the_index() {
+        let config = crate::Config::from_env();
+        let keys = config.redis.keys();
+        let pool = crate::redis::create_pool(&config.redis).await.unwrap();
+        let mut conn = crate::redis::get_connection(&pool).await.unwrap();
+        let (user, legacy) = (format!("0xcounts-{}", uuid::Uuid::new_v4()), format!("0xlegacy-{}", uuid::Uuid::new_v4()));
+
+        let mut pipe = redis::pipe();
+        for platform_id in [Some("app-a"), Some("app-a"), Some("app-b"), None] {
+            incr_unread(&mut pipe, keys, &user, platform_id);
+        }
+        pipe.query_async::<()>(&mut conn).await.unwrap();
+        decr_unread(&mut conn, keys, &user, Some("app-b")).await.unwrap();
+        let counts = get_counts(&mut conn, keys, &user).await.unwrap();
+        let total = get_total(&mut conn, keys, &user).await.unwrap();
+        let index: Vec<String> = redis::cmd("SMEMBERS").arg(keys.unread_platforms(&user)).query_async(&mut conn).await.unwrap();
+
+        // Counters from before the index are found once and indexed
+        let _: () = redis::pipe()
+            .set(keys.unread_total(&legacy), 2)
+            .set(keys.unread_platform(&legacy, "app-c"), 2)
+            .query_async(&mut conn)
+            .await
+            .unwrap();
+        let legacy_counts = get_counts(&mut conn, keys, &legacy).await.unwrap();
+        let legacy_index: Vec<String> = redis::cmd("SMEMBERS").arg(keys.unread_platforms(&legacy)).query_async(&mut conn).await.unwrap();
+
+        for (address, platforms) in [(&user, &["app-a", "app-b"][..]), (&legacy, &["app-c"][..])] {
+            let mut pipe = redis::pipe();
+            pipe.del(keys.unread_total(address)).del(keys.unread_platforms(address)).del(keys.unread_indexed(address));
+            for platform_id in platforms {
+                pipe.del(keys.unread_platform(address, platform_id));
+            }
+            pipe.query_async::<()>(&mut conn).await.unwrap();
+        }
+
+        assert_eq!(counts.total, 3);
+        // app-b is unindexed once its last unread notification is read
+        assert_eq!(counts.platforms, BTreeMap::from([("app-a".to_string(), 2)]));
+        assert_eq!(index, vec!["app-a"]);
+        assert_eq!(total, 3);
+        assert_eq!(legacy_counts.platforms, BTreeMap::from([("app-c".to_string(), 2)]));
+        assert_eq!(legacy_index, vec!["app-c"]);
+    }
+}
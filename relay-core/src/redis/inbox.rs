@@ -0,0 +1,112 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::HashSet;
+
+use super::{RedisConnection, RedisKeys};
+
+/// Notifications kept in each inbox
+pub const INBOX_LEN: isize = 100;
+
+/// The `id` of a stored inbox entry
+fn entry_id(entry: &str) -> Option<Value> {
+    serde_json::from_str::<Value>(entry).ok().and_then(|v| v.get("id").cloned())
+}
+
+/// Queue an LPUSH of `notification` onto the user's inbox for `platform_id`, trimmed to `INBOX_LEN`, so it
+/// can share a MULTI block with the unread counters
+pub fn push_inbox(
+    pipe: &mut redis::Pipeline,
+    keys: RedisKeys<'_>,
+    user_address: &str,
+    platform_id: Option<&str>,
+    notification: &Value,
+) -> Result<()> {
+    let key = keys.inbox(user_address, platform_id);
+    pipe.cmd("LPUSH")
+        .arg(&key)
+        .arg(serde_json::to_string(notification)?)
+        .ignore()
+        .cmd("LTRIM")
+        .arg(&key)
+        .arg(0)
+        .arg(INBOX_LEN - 1)
+        .ignore();
+    Ok(())
+}
+
+/// Swap the entry with `notification`'s id for its updated version, moved to the front
+pub async fn replace_in_inbox(
+    conn: &mut RedisConnection,
+    keys: RedisKeys<'_>,
+    user_address: &str,
+    platform_id: Option<&str>,
+    notification: &Value,
+) -> Result<()> {
+    let key = keys.inbox(user_address, platform_id);
+    let entries: Vec<String> = redis::cmd("LRANGE").arg(&key).arg(0).arg(INBOX_LEN - 1).query_async(conn).await?;
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    if let Some(stale) = entries.into_iter().find(|entry| entry_id(entry).as_ref() == notification.get("id")) {
+        pipe.cmd("LREM").arg(&key).arg(1).arg(stale).ignore();
+    }
+    push_inbox(&mut pipe, keys, user_address, platform_id, notification)?;
+    pipe.query_async::<()>(conn).await?;
+
+    Ok(())
+}
+
+/// Drop cleared notifications from the user's inboxes (`INBOX:{user}`, and `INBOX:{user}:{platform}`
+/// under `REDIS_PLATFORM_NAMESPACES`): every list when everything was cleared, otherwise just the
+/// entries with a removed id
+pub async fn remove_from_inbox(
+    conn: &mut RedisConnection,
+    keys: RedisKeys<'_>,
+    user_address: &str,
+    removed: &[i64],
+    everything: bool,
+) -> Result<()> {
+    let mut inboxes = vec![keys.inbox(user_address, None)];
+    if let Some(pattern) = keys.platform_inbox_pattern(user_address) {
+        let platform_inboxes: Vec<String> = redis::cmd("KEYS").arg(pattern).query_async(conn).await?;
+        inboxes.extend(platform_inboxes);
+    }
+
+    if everything {
+        redis::cmd("DEL").arg(&inboxes).query_async::<()>(conn).await?;
+        return Ok(());
+    }
+    if removed.is_empty() {
+        return Ok(());
+    }
+
+    let removed: HashSet<i64> = removed.iter().copied().collect();
+    for key in inboxes {
+        let entries: Vec<String> = redis::cmd("LRANGE").arg(&key).arg(0).arg(-1).query_async(conn).await?;
+        for entry in entries {
+            if entry_id(&entry).and_then(|id| id.as_i64()).is_some_and(|id| removed.contains(&id)) {
+                redis::cmd("LREM").arg(&key).arg(0).arg(&entry).query_async::<()>(conn).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_trims_the_platform_inbox() {
+        let config = crate::config::RedisConfig { platform_namespaces: true, ..crate::Config::from_env().redis };
+        let keys = config.keys();
+        let mut pipe = redis::pipe();
+        push_inbox(&mut pipe, keys, "0xa", Some("app"), &serde_json::json!({"id": 1})).unwrap();
+        let packed = String::from_utf8_lossy(&pipe.get_packed_pipeline()).to_string();
+
+        let key = keys.inbox("0xa", Some("app"));
+        assert!(packed.contains(&format!("LPUSH\r\n${}\r\n{}\r\n", key.len(), key)));
+        assert!(packed.contains(&format!("LTRIM\r\n${}\r\n{}\r\n$1\r\n0\r\n$2\r\n99", key.len(), key)));
+    }
+}
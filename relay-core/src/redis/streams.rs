@@ -0,0 +1,123 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+use super::RedisConnection;
+use crate::config::RedisConfig;
+
+/// Append an entry to a per-user stream, trimming it to about `stream_max_len` entries and
+/// refreshing its TTL so streams of users who never reconnect are eventually reclaimed
+pub async fn xadd_stream(conn: &mut RedisConnection, config: &RedisConfig, key: &str, data: &str) -> Result<String> {
+    let (id,): (String,) = xadd_pipeline(config, key, data)
+        .query_async(conn)
+        .await
+        .map_err(|e| anyhow!("Failed to append to stream {}: {}", key, e))?;
+
+    Ok(id)
+}
+
+fn xadd_pipeline(config: &RedisConfig, key: &str, data: &str) -> redis::Pipeline {
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .cmd("XADD")
+        .arg(key)
+        .arg("MAXLEN")
+        .arg("~")
+        .arg(config.stream_max_len)
+        .arg("*")
+        .arg("data")
+        .arg(data)
+        .cmd("EXPIRE")
+        .arg(key)
+        .arg(config.stream_ttl_seconds)
+        .ignore();
+    pipe
+}
+
+/// Push an event onto a user's chat stream, which their WebSocket connections forward to the client
+pub async fn push_chat_event(conn: &mut RedisConnection, config: &RedisConfig, user_address: &str, event: &Value) -> Result<String> {
+    xadd_stream(conn, config, &config.keys().chat_stream(user_address), &serde_json::to_string(event)?).await
+}
+
+/// Push an event onto a user's notification stream, the platform's own under `REDIS_PLATFORM_NAMESPACES`
+pub async fn push_notify_event(
+    conn: &mut RedisConnection,
+    config: &RedisConfig,
+    user_address: &str,
+    platform_id: Option<&str>,
+    event: &Value,
+) -> Result<String> {
+    let key = config.keys().notify_stream(user_address, platform_id);
+    xadd_stream(conn, config, &key, &serde_json::to_string(event)?).await
+}
+
+/// Delete the `message` entry for `message_id` from a user's chat stream, so a deleted message isn't replayed
+/// to sockets that resume from before it. Returns how many entries were removed
+pub async fn remove_chat_message(conn: &mut RedisConnection, config: &RedisConfig, user_address: &str, message_id: i64) -> Result<usize> {
+    let key = config.keys().chat_stream(user_address);
+    let entries: Vec<(String, Vec<(String, String)>)> = redis::cmd("XRANGE").arg(&key).arg("-").arg("+").query_async(conn).await?;
+
+    let ids: Vec<String> = entries
+        .into_iter()
+        .filter(|(_, fields)| {
+            fields.iter().any(|(field, data)| {
+                field == "data"
+                    && serde_json::from_str::<Value>(data).is_ok_and(|event| {
+                        event["type"] == "message" && event["message_id"].as_i64() == Some(message_id)
+                    })
+            })
+        })
+        .map(|(id, _)| id)
+        .collect();
+    if ids.is_empty() {
+        return Ok(0);
+    }
+
+    Ok(redis::cmd("XDEL").arg(&key).arg(&ids).query_async(conn).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::{create_pool, get_connection};
+
+    fn test_config(stream_max_len: usize) -> RedisConfig {
+        RedisConfig {
+            url: std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_string()),
+            max_connections: 1,
+            stream_max_len,
+            stream_ttl_seconds: 60,
+            key_prefix: String::new(),
+            platform_namespaces: false,
+        }
+    }
+
+    #[test]
+    fn test_stream_append_is_capped_and_expires() {
+        let packed = xadd_pipeline(&test_config(50), "STREAM:CHAT:0xabc", "{}").get_packed_pipeline();
+        let packed = String::from_utf8_lossy(&packed);
+
+        assert!(packed.contains("MAXLEN\r\n$1\r\n~\r\n$2\r\n50"));
+        assert!(packed.contains("EXPIRE\r\n$17\r\nSTREAM:CHAT:0xabc\r\n$2\r\n60"));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis at REDIS_URL"]
+    async fn test_stream_length_stays_bounded() {
+        let config = test_config(50);
+        let pool = create_pool(&config).await.unwrap();
+        let mut conn = get_connection(&pool).await.unwrap();
+        let key = format!("STREAM:TEST:{}", uuid::Uuid::new_v4());
+
+        for i in 0..1000 {
+            xadd_stream(&mut conn, &config, &key, &i.to_string()).await.unwrap();
+        }
+
+        // `MAXLEN ~` trims whole radix-tree nodes (100 entries by default), so allow that much slack
+        let len: usize = redis::cmd("XLEN").arg(&key).query_async(&mut conn).await.unwrap();
+        let ttl: i64 = redis::cmd("TTL").arg(&key).query_async(&mut conn).await.unwrap();
+        redis::cmd("DEL").arg(&key).query_async::<()>(&mut conn).await.unwrap();
+
+        assert!((50..50 + 100).contains(&len), "stream length {} not bounded", len);
+        assert!((1..=60).contains(&ttl));
+    }
+}
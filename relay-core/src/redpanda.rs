@@ -123,6 +123,9 @@ pub fn create_consumer(config: &RedpandaConfig, group_id: Option<&str>) -> Resul
         .set("enable.partition.eof", "false")
         .set("session.timeout.ms", "30000")
         .set("enable.auto.commit", "true")
+        // Consumers store each offset once the message is handled or dead-lettered, so a crash mid-retry
+        // redelivers it
+        .set("enable.auto.offset.store", "false")
         .set("auto.offset.reset", "earliest")
         .create()
         .map_err(|e| {
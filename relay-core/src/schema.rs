@@ -12,6 +12,7 @@ table! {
         published_at -> Nullable<Timestamptz>,
         retry_count -> Integer,
         error_message -> Nullable<Text>,
+        next_retry_at -> Nullable<Timestamptz>, // Failed rows aren't polled again before this
     }
 }
 
@@ -26,6 +27,62 @@ table! {
         platform_id -> Nullable<Text>,
         read_at -> Nullable<Timestamptz>,
         created_at -> Timestamptz,
+        event_data -> Nullable<Jsonb>,
+        deleted_at -> Nullable<Timestamptz>,
+    }
+}
+
+// One row per delivery attempt on a channel (apns, fcm, email, webhook) for a notification
+table! {
+    relay_notification_deliveries (id) {
+        id -> BigInt,
+        notification_id -> Nullable<BigInt>,
+        user_address -> Text,
+        channel -> Text,
+        status -> Text, // sent, failed, skipped
+        provider_id -> Nullable<Text>,
+        error -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+// Actions taken from a notification's buttons
+table! {
+    relay_notification_actions (id) {
+        id -> BigInt,
+        notification_id -> BigInt,
+        user_address -> Text,
+        action -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+// Issued auth tokens by `jti`, listed and revoked through /api/v1/sessions
+table! {
+    relay_sessions (id) {
+        id -> BigInt,
+        jti -> Text,
+        user_address -> Text,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        issued_at -> Timestamptz,
+        last_seen_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+// Per-platform overrides for notification copy, unique on (platform_id, event_type, locale)
+table! {
+    relay_notification_templates (id) {
+        id -> BigInt,
+        platform_id -> Text,
+        event_type -> Text,
+        locale -> Text,
+        title_template -> Text,
+        body_template -> Text,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
@@ -42,6 +99,12 @@ table! {
         created_at -> Timestamptz,
         delivered_at -> Nullable<Timestamptz>,
         read_at -> Nullable<Timestamptz>,
+        deleted_at -> Nullable<Timestamptz>, // Set when the sender deletes the message; content is blanked
+        e2ee -> Bool, // Client-encrypted: content is the client's ciphertext, never decrypted here
+        key_exchange -> Nullable<Text>,
+        flagged -> Bool, // Flagged by the moderation classifier
+        reply_to_message_id -> Nullable<BigInt>, // Message in the same conversation this one replies to
+        forwarded_from_message_id -> Nullable<BigInt>, // Message, possibly in another conversation, this one forwards
     }
 }
 
@@ -54,6 +117,8 @@ table! {
         last_message_at -> Nullable<Timestamptz>,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        retention_exempt -> Bool, // Kept past MESSAGE_RETENTION_DAYS
+        conversation_key_version -> Integer, // ENCRYPTION_KEY_VERSION all its messages are encrypted under
     }
 }
 
@@ -64,6 +129,12 @@ table! {
         email_enabled -> Bool,
         sms_enabled -> Bool,
         notification_types -> Jsonb,
+        dnd_start -> Nullable<Time>, // Local time quiet hours begin
+        dnd_end -> Nullable<Time>, // Local time quiet hours end; earlier than dnd_start when wrapping midnight
+        timezone -> Nullable<Text>, // IANA name, e.g. America/New_York
+        dnd_digest_enabled -> Bool, // Send one summary push when quiet hours end
+        email_digest -> Text, // off, hourly, daily
+        last_digest_at -> Nullable<Timestamptz>,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
     }
@@ -80,6 +151,85 @@ table! {
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
         last_used_at -> Timestamptz,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+    }
+}
+
+// Directional: blocker_address no longer receives messages or notifications from blocked_address
+// Unique on (blocker_address, blocked_address)
+table! {
+    relay_blocks (id) {
+        id -> BigInt,
+        blocker_address -> Text,
+        blocked_address -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+// Unique on (user_address, key_id)
+table! {
+    relay_user_keys (id) {
+        id -> BigInt,
+        user_address -> Text,
+        key_id -> Text,
+        algorithm -> Text,
+        public_key -> Text,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+table! {
+    relay_audit_log (id) {
+        id -> BigInt,
+        actor -> Nullable<Text>,
+        action -> Text,
+        target -> Nullable<Text>,
+        result -> Text,
+        reason -> Nullable<Text>,
+        ip -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+// Unique on (message_id, user_address, emoji)
+table! {
+    relay_message_reactions (id) {
+        id -> BigInt,
+        message_id -> BigInt,
+        user_address -> Text,
+        emoji -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+// Unique on (user_address, conversation_id)
+table! {
+    relay_conversation_mutes (id) {
+        id -> BigInt,
+        user_address -> Text,
+        conversation_id -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    relay_conversation_archives (id) {
+        id -> BigInt,
+        user_address -> Text,
+        conversation_id -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+// Unique on (user_address, conversation_id)
+table! {
+    relay_conversation_pins (id) {
+        id -> BigInt,
+        user_address -> Text,
+        conversation_id -> Text,
+        created_at -> Timestamptz,
     }
 }
 
@@ -91,6 +241,9 @@ table! {
         connected_at -> Timestamptz,
         last_heartbeat_at -> Timestamptz,
         disconnected_at -> Nullable<Timestamptz>,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        app_version -> Nullable<Text>,
     }
 }
 
@@ -108,6 +261,13 @@ table! {
         resend_from_email -> Nullable<Text>,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        webhook_url -> Nullable<Text>,
+        webhook_secret -> Nullable<Text>,
+        apns_environment -> Nullable<Text>,
+        email_logo_url -> Nullable<Text>,
+        email_brand_color -> Nullable<Text>,
+        email_footer -> Nullable<Text>,
+        email_template -> Nullable<Text>,
     }
 }
 
@@ -124,10 +284,21 @@ table! {
 allow_tables_to_appear_in_same_query!(
     relay_outbox,
     relay_notifications,
+    relay_notification_deliveries,
+    relay_notification_actions,
+    relay_notification_templates,
     relay_messages,
+    relay_message_reactions,
     relay_conversations,
     relay_user_preferences,
     relay_device_tokens,
+    relay_sessions,
+    relay_audit_log,
+    relay_blocks,
+    relay_conversation_mutes,
+    relay_conversation_archives,
+    relay_conversation_pins,
+    relay_user_keys,
     relay_ws_connections,
     platform_delivery_config,
     profiles,
@@ -0,0 +1,189 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
+
+use crate::db::DbConnection;
+use crate::redis::{RedisConnection, RedisKeys};
+use crate::schema::relay_sessions;
+
+/// `last_seen_at` is only rewritten once it's this old, so busy clients don't update it on every request
+const LAST_SEEN_RESOLUTION_SECONDS: i64 = 60;
+
+/// Where a token was issued, recorded when it's handed out
+pub struct NewSession<'a> {
+    pub jti: &'a str,
+    pub user_address: &'a str,
+    pub ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Queryable, Serialize)]
+pub struct Session {
+    pub id: i64,
+    #[serde(skip)]
+    pub jti: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub issued_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+type SessionColumns = (
+    relay_sessions::id,
+    relay_sessions::jti,
+    relay_sessions::ip,
+    relay_sessions::user_agent,
+    relay_sessions::issued_at,
+    relay_sessions::last_seen_at,
+    relay_sessions::expires_at,
+);
+
+const SESSION_COLUMNS: SessionColumns = (
+    relay_sessions::id,
+    relay_sessions::jti,
+    relay_sessions::ip,
+    relay_sessions::user_agent,
+    relay_sessions::issued_at,
+    relay_sessions::last_seen_at,
+    relay_sessions::expires_at,
+);
+
+pub async fn create(conn: &mut DbConnection, session: NewSession<'_>) -> Result<i64> {
+    let id = diesel::insert_into(relay_sessions::table)
+        .values((
+            relay_sessions::jti.eq(session.jti),
+            relay_sessions::user_address.eq(session.user_address),
+            relay_sessions::ip.eq(session.ip),
+            relay_sessions::user_agent.eq(session.user_agent),
+            relay_sessions::expires_at.eq(session.expires_at),
+        ))
+        .returning(relay_sessions::id)
+        .get_result(conn)
+        .await?;
+
+    Ok(id)
+}
+
+/// The user's unexpired, unrevoked sessions, most recently seen first
+pub async fn list_active(conn: &mut DbConnection, user_address: &str) -> Result<Vec<Session>> {
+    let sessions = relay_sessions::table
+        .filter(relay_sessions::user_address.eq(user_address))
+        .filter(relay_sessions::revoked_at.is_null())
+        .filter(relay_sessions::expires_at.gt(Utc::now()))
+        .order(relay_sessions::last_seen_at.desc())
+        .select(SESSION_COLUMNS)
+        .load(conn)
+        .await?;
+
+    Ok(sessions)
+}
+
+/// Mark one of the user's sessions revoked, returning it; None if it isn't theirs. Revoking again
+/// returns the session, so a failed denylist write can be retried
+pub async fn revoke(conn: &mut DbConnection, user_address: &str, id: i64) -> Result<Option<Session>> {
+    let session = diesel::update(
+        relay_sessions::table
+            .filter(relay_sessions::id.eq(id))
+            .filter(relay_sessions::user_address.eq(user_address)),
+    )
+    .set(relay_sessions::revoked_at.eq(Utc::now()))
+    .returning(SESSION_COLUMNS)
+    .get_result(conn)
+    .await
+    .optional()?;
+
+    Ok(session)
+}
+
+/// Add a token to the denylist until it would have expired anyway
+pub async fn deny(redis_conn: &mut RedisConnection, keys: RedisKeys<'_>, jti: &str, expires_at: DateTime<Utc>) -> Result<()> {
+    let ttl = (expires_at - Utc::now()).num_seconds().max(1);
+    redis::cmd("SET")
+        .arg(keys.revoked_token(jti))
+        .arg(1)
+        .arg("EX")
+        .arg(ttl)
+        .query_async::<()>(redis_conn)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn is_denied(redis_conn: &mut RedisConnection, keys: RedisKeys<'_>, jti: &str) -> Result<bool> {
+    Ok(redis::cmd("EXISTS").arg(keys.revoked_token(jti)).query_async(redis_conn).await?)
+}
+
+/// Whether the session was revoked, from `relay_sessions`, for when the denylist can't be read. Every token
+/// with a `jti` had its row created before it was handed out, so a missing row (account deleted) counts as revoked
+pub async fn is_revoked(conn: &mut DbConnection, jti: &str) -> Result<bool> {
+    let active: bool = diesel::select(diesel::dsl::exists(
+        relay_sessions::table
+            .filter(relay_sessions::jti.eq(jti))
+            .filter(relay_sessions::revoked_at.is_null()),
+    ))
+    .get_result(conn)
+    .await?;
+
+    Ok(!active)
+}
+
+/// Claim the session's next `last_seen_at` update; false if a request already did within the last
+/// `LAST_SEEN_RESOLUTION_SECONDS`, so busy tokens reach Postgres once per window instead of on every request
+pub async fn claim_touch(redis_conn: &mut RedisConnection, keys: RedisKeys<'_>, jti: &str) -> Result<bool> {
+    let claimed: Option<String> = redis::cmd("SET")
+        .arg(keys.session_touched(jti))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(LAST_SEEN_RESOLUTION_SECONDS)
+        .query_async(redis_conn)
+        .await?;
+
+    Ok(claimed.is_some())
+}
+
+/// Record that the session's token was just used
+pub async fn touch(conn: &mut DbConnection, jti: &str) -> Result<()> {
+    let now = Utc::now();
+    diesel::update(
+        relay_sessions::table
+            .filter(relay_sessions::jti.eq(jti))
+            .filter(relay_sessions::last_seen_at.lt(now - Duration::seconds(LAST_SEEN_RESOLUTION_SECONDS))),
+    )
+    .set(relay_sessions::last_seen_at.eq(now))
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis at REDIS_URL"]
+    async fn test_touch_claimed_once_per_window() {
+        let config = crate::Config::from_env();
+        let pool = crate::redis::create_pool(&config.redis).await.unwrap();
+        let mut conn = crate::redis::get_connection(&pool).await.unwrap();
+        let keys = config.redis.keys();
+        let (jti, other) = (uuid::Uuid::new_v4().to_string(), uuid::Uuid::new_v4().to_string());
+
+        let first = claim_touch(&mut conn, keys, &jti).await.unwrap();
+        let repeat = claim_touch(&mut conn, keys, &jti).await.unwrap();
+        let other_token = claim_touch(&mut conn, keys, &other).await.unwrap();
+
+        for jti in [&jti, &other] {
+            redis::cmd("DEL").arg(keys.session_touched(jti)).query_async::<()>(&mut conn).await.unwrap();
+        }
+
+        assert!(first);
+        assert!(!repeat);
+        assert!(other_token);
+    }
+}
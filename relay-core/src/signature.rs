@@ -1,4 +1,5 @@
 use anyhow::{Result, anyhow};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use mys_sdk::verify_personal_message_signature::verify_personal_message_signature;
 use mys_types::{
     Address,
@@ -7,38 +8,90 @@ use mys_types::{
 use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Why a signature was rejected; malformed input is a client error, a mismatch is an auth failure
+#[derive(Debug, thiserror::Error)]
+pub enum SignatureError {
+    #[error("Signature is neither GenericSignature JSON nor base64 serialized bytes: {0}")]
+    Malformed(String),
+    #[error("Signature scheme {0} is not allowed")]
+    SchemeNotAllowed(String),
+    #[error("Failed to parse wallet address: {0}")]
+    AddressParse(String),
+    #[error("Signature does not match wallet address")]
+    VerificationFailed,
+}
+
 /// Verify MySocial signature using mys-sdk
-/// This uses the custom MySocial signature format, not Ethereum's
+/// This uses the custom MySocial signature format, not Ethereum's. Schemes missing from a non-empty
+/// `allowed_schemes` (lowercase names such as `ed25519` or `zklogin`) are rejected before verifying
 pub async fn verify_mysocial_signature(
     message: &str,
     signature: &str,
     expected_address: &str,
-) -> Result<bool> {
-    // Parse signature string to GenericSignature (expects JSON format)
-    let generic_sig: GenericSignature = serde_json::from_str(signature)
-        .map_err(|e| anyhow!("Failed to parse signature as JSON: {}", e))?;
+    allowed_schemes: &[String],
+) -> std::result::Result<(), SignatureError> {
+    let generic_sig = parse_signature(signature)?;
+
+    let scheme = generic_sig.scheme().name();
+    if !allowed_schemes.is_empty() && !allowed_schemes.iter().any(|s| s == scheme) {
+        return Err(SignatureError::SchemeNotAllowed(scheme.to_string()));
+    }
 
     // Parse wallet address to Address
     let mys_address = Address::from_str(expected_address)
-        .map_err(|e| anyhow!("Failed to parse wallet address: {}", e))?;
+        .map_err(|e| SignatureError::AddressParse(e.to_string()))?;
 
     // Convert message string to bytes
     let message_bytes = message.as_bytes();
 
     // Verify signature using mys-sdk
     // Note: For zkLogin signatures, we would need a MysClient, but for standard signatures we can pass None
-    match verify_personal_message_signature(generic_sig, message_bytes, mys_address, None).await {
-        Ok(_) => Ok(true),
-        Err(e) => {
+    verify_personal_message_signature(generic_sig, message_bytes, mys_address, None)
+        .await
+        .map_err(|e| {
             tracing::debug!("Signature verification failed: {}", e);
-            Ok(false)
-        }
+            SignatureError::VerificationFailed
+        })
+}
+
+/// Parse a signature given either as GenericSignature JSON or as base64 of its serialized bytes
+/// (flag || signature || public key), which is what most wallets hand back
+fn parse_signature(signature: &str) -> std::result::Result<GenericSignature, SignatureError> {
+    let json_err = match serde_json::from_str(signature) {
+        Ok(sig) => return Ok(sig),
+        Err(e) => e,
+    };
+
+    let bytes = STANDARD
+        .decode(signature.trim())
+        .map_err(|_| SignatureError::Malformed(format!("invalid JSON ({})", json_err)))?;
+    GenericSignature::from_bytes(&bytes).map_err(|e| SignatureError::Malformed(e.to_string()))
+}
+
+/// Shortest and longest nonce accepted in a sign-in message
+const MIN_NONCE_LEN: usize = 8;
+const MAX_NONCE_LEN: usize = 128;
+
+/// How strictly sign-in messages are checked against replay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthMessagePolicy {
+    /// Messages signed longer ago than this are rejected
+    pub max_age_seconds: u64,
+    /// Reject messages without a `Nonce:` line instead of only warning
+    pub require_nonce: bool,
+}
+
+impl crate::config::ServerConfig {
+    pub fn auth_message_policy(&self) -> AuthMessagePolicy {
+        AuthMessagePolicy { max_age_seconds: self.auth_message_max_age_seconds, require_nonce: self.auth_require_nonce }
     }
 }
 
 /// Validate message contains nonce/timestamp to prevent replay attacks
 /// Expected format: "Sign in to MySocial Relay\n\nWallet: {address}\nNonce: {nonce}\nTimestamp: {timestamp}"
-pub fn validate_auth_message(message: &str, wallet_address: &str, max_age_seconds: u64) -> Result<()> {
+/// With `require_nonce` the nonce must also be 8-128 ASCII letters, digits, `-` or `_`; otherwise any is accepted
+pub fn validate_auth_message(message: &str, wallet_address: &str, policy: AuthMessagePolicy) -> Result<()> {
+    let max_age_seconds = policy.max_age_seconds;
     // Check message format
     if !message.contains("Sign in to MySocial Relay") {
         return Err(anyhow!("Invalid message format: missing expected prefix"));
@@ -74,14 +127,32 @@ pub fn validate_auth_message(message: &str, wallet_address: &str, max_age_second
         return Err(anyhow!("Message is too old (max age: {} seconds)", max_age_seconds));
     }
 
-    // Extract nonce (optional but recommended)
-    if !message.contains("Nonce:") {
-        tracing::warn!("Message missing nonce - replay protection may be limited");
+    // Extract nonce (optional unless the policy requires it)
+    let nonce = message
+        .lines()
+        .find(|line| line.starts_with("Nonce:"))
+        .and_then(|line| line.split("Nonce:").nth(1))
+        .map(str::trim);
+    match nonce {
+        Some(nonce) if policy.require_nonce => validate_nonce(nonce)?,
+        Some(_) => {}
+        None if policy.require_nonce => return Err(anyhow!("Missing nonce in message")),
+        None => tracing::warn!("Message missing nonce - replay protection may be limited"),
     }
 
     Ok(())
 }
 
+fn validate_nonce(nonce: &str) -> Result<()> {
+    if !(MIN_NONCE_LEN..=MAX_NONCE_LEN).contains(&nonce.len()) {
+        return Err(anyhow!("Nonce must be {}-{} characters", MIN_NONCE_LEN, MAX_NONCE_LEN));
+    }
+    if !nonce.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(anyhow!("Nonce may only contain letters, digits, '-' and '_'"));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,7 +170,118 @@ mod tests {
             wallet, timestamp
         );
 
-        assert!(validate_auth_message(&message, wallet, 300).is_ok());
+        assert!(validate_auth_message(&message, wallet, LENIENT).is_ok());
     }
-}
 
+    const LENIENT: AuthMessagePolicy = AuthMessagePolicy { max_age_seconds: 300, require_nonce: false };
+    const STRICT: AuthMessagePolicy = AuthMessagePolicy { max_age_seconds: 300, require_nonce: true };
+
+    fn signed_ago(seconds: u64, nonce_line: &str) -> String {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() - seconds;
+        format!("Sign in to MySocial Relay\n\nWallet: {}\n{}Timestamp: {}", WALLET, nonce_line, timestamp)
+    }
+
+    #[test]
+    fn test_expired_message() {
+        let message = signed_ago(600, "Nonce: abc123\n");
+        let err = validate_auth_message(&message, WALLET, LENIENT).unwrap_err();
+        assert!(err.to_string().contains("too old"));
+
+        // A longer configured max age accepts it
+        let patient = AuthMessagePolicy { max_age_seconds: 900, ..LENIENT };
+        assert!(validate_auth_message(&message, WALLET, patient).is_ok());
+    }
+
+    #[test]
+    fn test_missing_nonce_rejected_when_required() {
+        let message = signed_ago(0, "");
+        assert!(validate_auth_message(&message, WALLET, LENIENT).is_ok());
+
+        let err = validate_auth_message(&message, WALLET, STRICT).unwrap_err();
+        assert!(err.to_string().contains("Missing nonce"));
+    }
+
+    #[test]
+    fn test_nonce_format() {
+        let malformed = [
+            "Nonce: abc123\n".to_string(),
+            "Nonce: \n".to_string(),
+            "Nonce: abc 123 def\n".to_string(),
+            format!("Nonce: {}\n", "a".repeat(129)),
+        ];
+        // Only checked when nonces are required, so existing clients' nonces keep working otherwise
+        for nonce_line in &malformed {
+            assert!(validate_auth_message(&signed_ago(0, nonce_line), WALLET, LENIENT).is_ok());
+            assert!(validate_auth_message(&signed_ago(0, nonce_line), WALLET, STRICT).is_err());
+        }
+        assert!(validate_auth_message(&signed_ago(0, "Nonce: 6f1c2d3e-aa_01\n"), WALLET, STRICT).is_ok());
+    }
+
+    /// A structurally valid Ed25519 signature that can't verify against anything
+    fn zeroed_signature() -> String {
+        serde_json::to_string(&GenericSignature::Simple(mys_types::SimpleSignature::Ed25519 {
+            signature: mys_types::Ed25519Signature::new([0; 64]),
+            public_key: mys_types::Ed25519PublicKey::new([0; 32]),
+        }))
+        .unwrap()
+    }
+
+    const WALLET: &str = "0x0000000000000000000000000000000000000000000000000000000000000abc";
+
+    #[test]
+    fn test_parse_json_and_base64_signatures() {
+        let sig = GenericSignature::Simple(mys_types::SimpleSignature::Ed25519 {
+            signature: mys_types::Ed25519Signature::new([7; 64]),
+            public_key: mys_types::Ed25519PublicKey::new([9; 32]),
+        });
+
+        let from_json = parse_signature(&serde_json::to_string(&sig).unwrap()).unwrap();
+        assert_eq!(from_json, sig);
+
+        let from_base64 = parse_signature(&STANDARD.encode(sig.to_bytes())).unwrap();
+        assert_eq!(from_base64, sig);
+
+        // Valid base64 that isn't a serialized signature is still malformed
+        assert!(matches!(parse_signature(&STANDARD.encode([0xff; 8])), Err(SignatureError::Malformed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_signature() {
+        let err = verify_mysocial_signature("hello", "not json", WALLET, &[]).await.unwrap_err();
+        assert!(matches!(err, SignatureError::Malformed(_)));
+
+        let err = verify_mysocial_signature("hello", r#"{"scheme":"ed25519"}"#, WALLET, &[]).await.unwrap_err();
+        assert!(matches!(err, SignatureError::Malformed(_)));
+    }
+
+    #[tokio::test]
+    async fn test_unparseable_address() {
+        let err = verify_mysocial_signature("hello", &zeroed_signature(), "not-an-address", &[]).await.unwrap_err();
+        assert!(matches!(err, SignatureError::AddressParse(_)));
+    }
+
+    #[tokio::test]
+    async fn test_signature_mismatch() {
+        let err = verify_mysocial_signature("hello", &zeroed_signature(), WALLET, &[]).await.unwrap_err();
+        assert!(matches!(err, SignatureError::VerificationFailed));
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_scheme_rejected() {
+        use mys_sdk::{ed25519::Ed25519PrivateKey, Signer};
+
+        let key = Ed25519PrivateKey::new([1; 32]);
+        let signature: GenericSignature = key.sign(b"hello");
+        let signature = serde_json::to_string(&signature).unwrap();
+        let allow = |schemes: &[&str]| schemes.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        // An allowed scheme gets past the scheme check, whatever verification then decides
+        for allowed in [vec![], allow(&["ed25519", "secp256k1"])] {
+            let result = verify_mysocial_signature("hello", &signature, WALLET, &allowed).await;
+            assert!(!matches!(result, Err(SignatureError::SchemeNotAllowed(_))));
+        }
+
+        let err = verify_mysocial_signature("hello", &signature, WALLET, &allow(&["secp256k1", "zklogin"])).await.unwrap_err();
+        assert!(matches!(err, SignatureError::SchemeNotAllowed(ref scheme) if scheme == "ed25519"));
+    }
+}
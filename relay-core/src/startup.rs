@@ -0,0 +1,210 @@
+use anyhow::{anyhow, bail, Result};
+use diesel_async::RunQueryDsl;
+use rdkafka::consumer::Consumer;
+use rdkafka::producer::FutureRecord;
+use rdkafka::{Message, Offset, TopicPartitionList};
+use serde::Serialize;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::context::RelayContext;
+use crate::db::DbPool;
+use crate::redis::{get_connection, RedisPool};
+use crate::redpanda::{RedpandaConsumer, RedpandaProducer};
+use crate::topics;
+
+/// One dependency's outcome in the startup report
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub name: &'static str,
+    /// None when the check passed
+    pub error: Option<String>,
+    pub elapsed_ms: u64,
+}
+
+/// Every startup check, in the order they ran
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StartupReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl StartupReport {
+    /// Run `check` and record how it went under `name`
+    pub async fn record(&mut self, name: &'static str, check: impl Future<Output = Result<()>>) {
+        let started = Instant::now();
+        let error = check.await.err().map(|e| format!("{:#}", e));
+        self.checks.push(CheckResult { name, error, elapsed_ms: started.elapsed().as_millis() as u64 });
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.checks.iter().all(|c| c.error.is_none())
+    }
+
+    /// One line per check; failures at error level
+    pub fn log(&self) {
+        for check in &self.checks {
+            match &check.error {
+                None => tracing::info!("Startup check {}: ok ({}ms)", check.name, check.elapsed_ms),
+                Some(e) => tracing::error!("Startup check {}: FAILED after {}ms: {}", check.name, check.elapsed_ms, e),
+            }
+        }
+    }
+
+    /// An error naming each failed check, for refusing to start
+    pub fn into_result(self) -> Result<()> {
+        let failures: Vec<String> = self
+            .checks
+            .into_iter()
+            .filter_map(|c| c.error.map(|e| format!("{}: {}", c.name, e)))
+            .collect();
+        if failures.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!("Startup self-test failed ({})", failures.join("; ")))
+    }
+}
+
+pub async fn check_database(pool: &DbPool) -> Result<()> {
+    let mut conn = pool.get().await?;
+    diesel::sql_query("SELECT 1").execute(&mut conn).await?;
+    Ok(())
+}
+
+pub async fn check_redis(pool: &RedisPool) -> Result<()> {
+    let mut conn = get_connection(pool).await?;
+    redis::cmd("PING").query_async::<String>(&mut conn).await?;
+    Ok(())
+}
+
+/// Writes healthcheck messages and reads them back; Redpanda in production
+pub trait ProbeBroker {
+    /// Produce `payload` to the healthcheck topic, returning the partition and offset it landed at
+    fn produce(&self, payload: &[u8]) -> impl Future<Output = Result<(i32, i64)>> + Send;
+    /// The payload stored at `offset` of `partition`
+    fn read(&self, partition: i32, offset: i64) -> impl Future<Output = Result<Vec<u8>>> + Send;
+}
+
+/// Produce a unique message and consume it back within `timeout`
+pub async fn probe_broker<B: ProbeBroker>(broker: &B, timeout: Duration) -> Result<()> {
+    let token = uuid::Uuid::new_v4().to_string();
+    let round_trip = async {
+        let (partition, offset) = broker.produce(token.as_bytes()).await?;
+        let payload = broker.read(partition, offset).await?;
+        if payload != token.as_bytes() {
+            bail!("read back a different message at partition {} offset {}", partition, offset);
+        }
+        Ok(())
+    };
+
+    tokio::time::timeout(timeout, round_trip)
+        .await
+        .map_err(|_| anyhow!("no round trip through {} within {}ms", topics::HEALTHCHECK, timeout.as_millis()))?
+}
+
+/// The context's producer plus a consumer that reads single offsets of the healthcheck topic
+pub struct RedpandaProbe {
+    producer: RedpandaProducer,
+    consumer: RedpandaConsumer,
+    topic: String,
+}
+
+impl RedpandaProbe {
+    pub fn new(ctx: &RelayContext) -> Result<Self> {
+        Ok(Self {
+            producer: ctx.redpanda_producer.clone(),
+            consumer: ctx.create_consumer(Some("relay-healthcheck"))?,
+            topic: ctx.config.redpanda.topics().healthcheck(),
+        })
+    }
+}
+
+impl ProbeBroker for RedpandaProbe {
+    async fn produce(&self, payload: &[u8]) -> Result<(i32, i64)> {
+        let record = FutureRecord::<(), [u8]>::to(&self.topic).payload(payload);
+        self.producer
+            .send(record, Duration::from_secs(5))
+            .await
+            .map_err(|(e, _)| anyhow!("failed to produce to {}: {}", self.topic, e))
+    }
+
+    async fn read(&self, partition: i32, offset: i64) -> Result<Vec<u8>> {
+        let mut assignment = TopicPartitionList::new();
+        assignment.add_partition_offset(&self.topic, partition, Offset::Offset(offset))?;
+        self.consumer.assign(&assignment)?;
+
+        let message = self.consumer.recv().await?;
+        Ok(message.payload().unwrap_or_default().to_vec())
+    }
+}
+
+/// Check Postgres and Redis and round-trip a message through Redpanda, each reported separately
+pub async fn self_test(ctx: &RelayContext) -> StartupReport {
+    let timeout = Duration::from_millis(ctx.config.redpanda.startup_probe_timeout_ms);
+
+    let mut report = StartupReport::default();
+    report.record("database", check_database(&ctx.db_pool)).await;
+    report.record("redis", check_redis(&ctx.redis_pool)).await;
+    report.record("redpanda", async { probe_broker(&RedpandaProbe::new(ctx)?, timeout).await }).await;
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// An in-memory single-partition topic; `misread` returns the wrong message and `stall` never answers reads
+    #[derive(Default)]
+    struct MockBroker {
+        log: Mutex<Vec<Vec<u8>>>,
+        misread: bool,
+        stall: bool,
+    }
+
+    impl ProbeBroker for MockBroker {
+        fn produce(&self, payload: &[u8]) -> impl Future<Output = Result<(i32, i64)>> + Send {
+            let mut log = self.log.lock().unwrap();
+            log.push(payload.to_vec());
+            let offset = log.len() as i64 - 1;
+            async move { Ok((0, offset)) }
+        }
+
+        fn read(&self, _partition: i32, offset: i64) -> impl Future<Output = Result<Vec<u8>>> + Send {
+            let payload = if self.misread { b"stale".to_vec() } else { self.log.lock().unwrap()[offset as usize].clone() };
+            let stall = self.stall;
+            async move {
+                if stall {
+                    std::future::pending::<()>().await;
+                }
+                Ok(payload)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probe_round_trip() {
+        let timeout = Duration::from_millis(200);
+
+        assert!(probe_broker(&MockBroker::default(), timeout).await.is_ok());
+
+        let misread = probe_broker(&MockBroker { misread: true, ..Default::default() }, timeout).await.unwrap_err();
+        assert!(misread.to_string().contains("different message"));
+
+        let stalled = probe_broker(&MockBroker { stall: true, ..Default::default() }, timeout).await.unwrap_err();
+        assert!(stalled.to_string().contains("within 200ms"));
+    }
+
+    #[tokio::test]
+    async fn test_report_names_every_failure() {
+        let mut report = StartupReport::default();
+        report.record("database", async { Ok(()) }).await;
+        report.record("redis", async { Err(anyhow!("connection refused")) }).await;
+        report.record("redpanda", probe_broker(&MockBroker { stall: true, ..Default::default() }, Duration::from_millis(10))).await;
+
+        assert!(!report.is_healthy());
+        let error = report.into_result().unwrap_err().to_string();
+        assert!(error.contains("redis: connection refused"));
+        assert!(error.contains("redpanda: no round trip"));
+        assert!(!error.contains("database"));
+    }
+}
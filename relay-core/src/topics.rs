@@ -0,0 +1,147 @@
+use crate::config::RedpandaConfig;
+
+/// Notification events, consumed by relay-notify
+pub const NOTIFICATION_EVENTS: &[&str] = &[
+    // Post-related events
+    "events.post.reaction",
+    "events.post.repost",
+    "events.post.tip",
+    "events.post.created",
+    "events.post.ownership",
+    // Comment events
+    "events.comment.created",
+    // Social proof token events
+    "events.spt.created",
+    // Governance events
+    "events.governance.created",
+    // Prediction events
+    "events.prediction.created",
+    // Social graph events
+    "events.follow.created",
+    "events.unfollow.created",
+    // Platform events
+    "events.platform.created",
+];
+/// Direct messages, consumed by relay-messaging
+pub const MESSAGE_EVENTS: &str = "events.message.created";
+/// Outbox events whose type matches no category; nothing consumes them
+pub const UNKNOWN_EVENTS: &str = "events.unknown";
+/// Delivery jobs, consumed by relay-delivery
+pub const DELIVERY: &str = "notifications.delivery";
+/// Round-tripped by the startup probe
+pub const HEALTHCHECK: &str = "relay.healthcheck";
+
+/// Unprefixed topic an outbox event of `event_type` belongs on, by category prefix; None for unknown types
+pub fn event_topic(event_type: &str) -> Option<&'static str> {
+    let topic = match event_type {
+        // Post-related events
+        t if t.starts_with("reaction.") => "events.post.reaction",
+        t if t.starts_with("repost.") => "events.post.repost",
+        t if t.starts_with("tip.") => "events.post.tip",
+        t if t.starts_with("post.created") => "events.post.created",
+        t if t.starts_with("ownership.transferred") => "events.post.ownership",
+        // Comment events (keep separate)
+        t if t.starts_with("comment.") => "events.comment.created",
+        // Social proof token events
+        t if t.starts_with("spt.") => "events.spt.created",
+        // Governance events
+        t if t.starts_with("governance.") => "events.governance.created",
+        // Prediction events
+        t if t.starts_with("prediction.") => "events.prediction.created",
+        // Social graph events
+        t if t.starts_with("follow.") => "events.follow.created",
+        t if t.starts_with("unfollow.") => "events.unfollow.created",
+        // Messaging (handled separately by messaging service)
+        t if t.starts_with("message.") => MESSAGE_EVENTS,
+        // Platform events
+        t if t.starts_with("platform.") => "events.platform.created",
+        _ => return None,
+    };
+    Some(topic)
+}
+
+/// Every Redpanda topic the relay produces to or subscribes to. Naming them here keeps `TOPIC_PREFIX`
+/// applied the same way on both sides
+#[derive(Debug, Clone, Copy)]
+pub struct Topics<'a> {
+    prefix: &'a str,
+}
+
+impl RedpandaConfig {
+    pub fn topics(&self) -> Topics<'_> {
+        Topics { prefix: &self.topic_prefix }
+    }
+}
+
+impl Topics<'_> {
+    /// `topic` with the deployment's prefix
+    pub fn name(&self, topic: &str) -> String {
+        format!("{}{}", self.prefix, topic)
+    }
+
+    /// Where an outbox event of `event_type` is published; `events.unknown` for unrecognised types
+    pub fn for_event(&self, event_type: &str) -> String {
+        self.name(event_topic(event_type).unwrap_or(UNKNOWN_EVENTS))
+    }
+
+    pub fn notification_events(&self) -> Vec<String> {
+        NOTIFICATION_EVENTS.iter().map(|t| self.name(t)).collect()
+    }
+
+    pub fn message_events(&self) -> String {
+        self.name(MESSAGE_EVENTS)
+    }
+
+    pub fn delivery(&self) -> String {
+        self.name(DELIVERY)
+    }
+
+    pub fn healthcheck(&self) -> String {
+        self.name(HEALTHCHECK)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(topic_prefix: &str) -> RedpandaConfig {
+        RedpandaConfig {
+            brokers: "localhost:9092".to_string(),
+            consumer_group: "test".to_string(),
+            handler_max_attempts: 3,
+            handler_retry_delay_ms: 0,
+            dead_letter_enabled: true,
+            lag_interval_seconds: 30,
+            lag_warn_threshold: 1000,
+            consumer_concurrency: 1,
+            startup_probe: false,
+            startup_probe_timeout_ms: 10000,
+            topic_prefix: topic_prefix.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_prefix_applied_to_produced_and_subscribed_topics() {
+        let staging = config("staging.");
+        let topics = staging.topics();
+        let subscribed = topics.notification_events();
+
+        // Each notification event lands on a topic relay-notify subscribes to
+        for event_type in ["reaction.created", "tip.created", "comment.created", "follow.created", "platform.user_joined"] {
+            let produced = topics.for_event(event_type);
+            assert!(produced.starts_with("staging.events."), "{}", produced);
+            assert!(subscribed.contains(&produced), "{} is not subscribed", produced);
+        }
+        assert_eq!(topics.for_event("message.created"), topics.message_events());
+        assert_eq!(topics.message_events(), "staging.events.message.created");
+        assert_eq!(topics.delivery(), "staging.notifications.delivery");
+        assert_eq!(topics.for_event("mystery.created"), "staging.events.unknown");
+        assert!(subscribed.iter().all(|t| t.starts_with("staging.")));
+
+        // Without a prefix the topics keep their plain names
+        let plain = config("");
+        assert_eq!(plain.topics().for_event("tip.created"), "events.post.tip");
+        assert_eq!(plain.topics().healthcheck(), "relay.healthcheck");
+    }
+}
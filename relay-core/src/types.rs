@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use std::fmt;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutboxEvent {
@@ -11,6 +13,69 @@ pub struct OutboxEvent {
     pub created_at: DateTime<Utc>,
 }
 
+/// Envelope version written by this build; consumers skip events with any other version
+pub const RELAY_EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// Payloads published before the envelope was versioned have no `schema_version` and are v1
+fn unversioned_schema() -> u32 {
+    1
+}
+
+/// Envelope of every event published to the `events.*` topics
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelayEvent {
+    #[serde(default = "unversioned_schema")]
+    pub schema_version: u32,
+    pub event_type: String,
+    pub event_data: serde_json::Value,
+    /// Outbox event id, used by consumers to skip re-published events
+    #[serde(default)]
+    pub event_id: Option<String>,
+    #[serde(default)]
+    pub transaction_id: Option<String>,
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl RelayEvent {
+    pub fn new(event_type: impl Into<String>, event_data: serde_json::Value) -> Self {
+        Self {
+            schema_version: RELAY_EVENT_SCHEMA_VERSION,
+            event_type: event_type.into(),
+            event_data,
+            event_id: None,
+            transaction_id: None,
+            timestamp: Some(Utc::now()),
+        }
+    }
+
+    /// Parse a published payload; None (logged) for a schema version this build doesn't understand
+    pub fn decode(payload: &[u8]) -> anyhow::Result<Option<Self>> {
+        #[derive(Deserialize)]
+        struct Version {
+            #[serde(default = "unversioned_schema")]
+            schema_version: u32,
+        }
+
+        // Check the version first, since a future envelope may not match this struct at all
+        let version: Version = serde_json::from_slice(payload)?;
+        if version.schema_version != RELAY_EVENT_SCHEMA_VERSION {
+            tracing::warn!(
+                "Skipping event with unsupported schema_version {} (expected {})",
+                version.schema_version,
+                RELAY_EVENT_SCHEMA_VERSION
+            );
+            return Ok(None);
+        }
+
+        Ok(Some(serde_json::from_slice(payload)?))
+    }
+
+    pub fn event_id(&self) -> Option<&str> {
+        self.event_id.as_deref().filter(|id| !id.is_empty())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Notification {
     pub id: i64,
@@ -23,6 +88,113 @@ pub struct Notification {
     pub created_at: DateTime<Utc>,
 }
 
+/// Client-facing `data` of a notification created from an event. Each event type exposes only the
+/// fields clients need; the raw event stays in `relay_notifications.event_data`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum NotificationData {
+    Post(PostNotificationData),
+    Payment(PaymentNotificationData),
+    Follow(FollowNotificationData),
+    Proposal(ProposalNotificationData),
+    Platform(PlatformNotificationData),
+    Message(MessageNotificationData),
+}
+
+/// Reactions, reposts, comments and other activity on a post
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PostNotificationData {
+    pub post_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reaction: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment_id: Option<String>,
+}
+
+/// Tips, pool trades and prediction bets and payouts
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PaymentNotificationData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+    /// In base units, as a string since amounts can exceed what JSON numbers hold exactly
+    pub amount: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FollowNotificationData {
+    pub actor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProposalNotificationData {
+    pub proposal_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlatformNotificationData {
+    pub platform_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MessageNotificationData {
+    pub conversation_id: Option<String>,
+    pub message_id: Option<i64>,
+    pub actor: Option<String>,
+}
+
+impl NotificationData {
+    /// Project an event onto the fields of its type; `actor` is whoever caused it. None for unknown types
+    pub fn from_event(event_type: &str, event_data: &serde_json::Value, actor: Option<&str>) -> Option<Self> {
+        let text = |field: &str| match event_data.get(field)? {
+            serde_json::Value::String(s) => Some(s.clone()),
+            serde_json::Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        };
+        let actor = actor.map(str::to_string);
+
+        let data = match event_type {
+            "reaction.created" | "repost.created" | "comment.created" | "post.created"
+            | "ownership.transferred" | "prediction.resolved" => Self::Post(PostNotificationData {
+                post_id: text("post_id"),
+                actor,
+                reaction: (event_type == "reaction.created").then(|| text("reaction")).flatten(),
+                comment_id: (event_type == "comment.created").then(|| text("comment_id")).flatten(),
+            }),
+            "tip.created" | "spt.token_bought" | "spt.token_sold" | "spt.tokens_added"
+            | "spt.reservation_created" | "prediction.bet_placed" | "prediction.payout" => {
+                Self::Payment(PaymentNotificationData {
+                    post_id: text("post_id"),
+                    actor,
+                    amount: text("amount"),
+                })
+            }
+            "follow.created" | "unfollow.created" => Self::Follow(FollowNotificationData { actor }),
+            t if t.starts_with("governance.") => Self::Proposal(ProposalNotificationData {
+                proposal_id: text("proposal_id"),
+            }),
+            t if t.starts_with("platform.") => Self::Platform(PlatformNotificationData {
+                platform_id: text("platform_id"),
+            }),
+            "message.created" => Self::Message(MessageNotificationData {
+                conversation_id: text("conversation_id"),
+                message_id: event_data.get("message_id").and_then(|v| v.as_i64()),
+                actor,
+            }),
+            _ => return None,
+        };
+
+        Some(data)
+    }
+
+    pub fn to_value(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_else(|_| serde_json::json!({}))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub id: i64,
@@ -36,6 +208,7 @@ pub struct Message {
     pub created_at: DateTime<Utc>,
     pub delivered_at: Option<DateTime<Utc>>,
     pub read_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,12 +233,152 @@ pub struct UserPreferences {
     pub updated_at: DateTime<Utc>,
 }
 
+/// Platform a device token belongs to; decides which push provider delivers to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DevicePlatform {
+    Ios,
+    Android,
+    Web,
+}
+
+impl DevicePlatform {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ios => "ios",
+            Self::Android => "android",
+            Self::Web => "web",
+        }
+    }
+}
+
+impl FromStr for DevicePlatform {
+    type Err = anyhow::Error;
+
+    /// Case-insensitive, so tokens registered as e.g. `iOS` still route to APNs
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ios" => Ok(Self::Ios),
+            "android" => Ok(Self::Android),
+            "web" => Ok(Self::Web),
+            _ => Err(anyhow::anyhow!("unknown device platform {:?}, expected ios, android or web", s)),
+        }
+    }
+}
+
+impl fmt::Display for DevicePlatform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a message carries. Only `text` has (encrypted) `content`; media types are described by the
+/// message's `media_urls` and a `card` by the payload in its `metadata`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageContentType {
+    #[default]
+    Text,
+    Image,
+    Video,
+    Audio,
+    File,
+    Card,
+}
+
+impl MessageContentType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Image => "image",
+            Self::Video => "video",
+            Self::Audio => "audio",
+            Self::File => "file",
+            Self::Card => "card",
+        }
+    }
+
+    /// Image, video, audio and file messages need at least one media URL
+    pub fn is_media(&self) -> bool {
+        matches!(self, Self::Image | Self::Video | Self::Audio | Self::File)
+    }
+}
+
+impl FromStr for MessageContentType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "image" => Ok(Self::Image),
+            "video" => Ok(Self::Video),
+            "audio" => Ok(Self::Audio),
+            "file" => Ok(Self::File),
+            "card" => Ok(Self::Card),
+            _ => Err(anyhow::anyhow!(
+                "unknown content type {:?}, expected text, image, video, audio, file or card",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MessageContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// APNs gateway: development builds get sandbox device tokens, which production rejects and vice versa
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApnsEnvironment {
+    Sandbox,
+    Production,
+}
+
+impl ApnsEnvironment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sandbox => "sandbox",
+            Self::Production => "production",
+        }
+    }
+
+    /// Fallback for configs that don't say: bundle ids containing `sandbox` or `dev` are taken as sandbox
+    pub fn guess_from_bundle_id(bundle_id: &str) -> Self {
+        if bundle_id.contains("sandbox") || bundle_id.contains("dev") {
+            Self::Sandbox
+        } else {
+            Self::Production
+        }
+    }
+}
+
+impl FromStr for ApnsEnvironment {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "sandbox" => Ok(Self::Sandbox),
+            "production" => Ok(Self::Production),
+            _ => Err(anyhow::anyhow!("unknown APNs environment {:?}, expected sandbox or production", s)),
+        }
+    }
+}
+
+impl fmt::Display for ApnsEnvironment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceToken {
     pub id: i64,
     pub user_address: String,
     pub device_token: String,
-    pub platform: String,
+    pub platform: String, // DevicePlatform::as_str
     pub device_id: Option<String>,
     pub app_version: Option<String>,
     pub created_at: DateTime<Utc>,
@@ -83,3 +396,122 @@ pub struct WebSocketConnection {
     pub disconnected_at: Option<DateTime<Utc>>,
 }
 
+
+/// How API tokens are signed: HS256 with a shared secret, or RS256/ES256 with a key pair so other
+/// services can verify tokens holding only the public key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum JwtAlgorithm {
+    #[default]
+    Hs256,
+    Rs256,
+    Es256,
+}
+
+impl JwtAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hs256 => "HS256",
+            Self::Rs256 => "RS256",
+            Self::Es256 => "ES256",
+        }
+    }
+}
+
+impl FromStr for JwtAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_uppercase().as_str() {
+            "HS256" => Ok(Self::Hs256),
+            "RS256" => Ok(Self::Rs256),
+            "ES256" => Ok(Self::Es256),
+            _ => Err(anyhow::anyhow!("unknown JWT algorithm {:?}, expected HS256, RS256 or ES256", s)),
+        }
+    }
+}
+
+impl fmt::Display for JwtAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_content_type_parse() {
+        assert_eq!("card".parse::<MessageContentType>().unwrap(), MessageContentType::Card);
+        assert_eq!(" Image ".parse::<MessageContentType>().unwrap(), MessageContentType::Image);
+        assert!("sticker".parse::<MessageContentType>().is_err());
+        assert!(MessageContentType::File.is_media());
+        assert!(!MessageContentType::Card.is_media());
+    }
+
+    #[test]
+    fn test_device_platform_parse_normalizes_case() {
+        assert_eq!("ios".parse::<DevicePlatform>().unwrap(), DevicePlatform::Ios);
+        assert_eq!(" iOS ".parse::<DevicePlatform>().unwrap(), DevicePlatform::Ios);
+        assert_eq!("ANDROID".parse::<DevicePlatform>().unwrap(), DevicePlatform::Android);
+        assert_eq!("web".parse::<DevicePlatform>().unwrap(), DevicePlatform::Web);
+        assert!("apple".parse::<DevicePlatform>().is_err());
+        assert!("".parse::<DevicePlatform>().is_err());
+
+        for platform in [DevicePlatform::Ios, DevicePlatform::Android, DevicePlatform::Web] {
+            assert_eq!(platform.to_string().parse::<DevicePlatform>().unwrap(), platform);
+        }
+    }
+
+    #[test]
+    fn test_reaction_data_exposes_only_whitelisted_fields() {
+        let event = serde_json::json!({
+            "post_id": "0xpost",
+            "post_owner": "0xowner",
+            "reactor": "0xfan",
+            "reaction": "like",
+            "platform_id": "mysocial",
+            "tx_digest": "9xKq",
+            "checkpoint": 1234,
+            "indexer_node": "idx-3",
+        });
+
+        let data = NotificationData::from_event("reaction.created", &event, Some("0xfan")).unwrap().to_value();
+        assert_eq!(data, serde_json::json!({"post_id": "0xpost", "actor": "0xfan", "reaction": "like"}));
+
+        // Large amounts stay exact
+        let tip = serde_json::json!({"recipient": "0xb", "tipper": "0xa", "amount": "340282366920938463463374607431768211455"});
+        let data = NotificationData::from_event("tip.created", &tip, Some("0xa")).unwrap().to_value();
+        assert_eq!(data, serde_json::json!({"actor": "0xa", "amount": "340282366920938463463374607431768211455"}));
+
+        assert_eq!(NotificationData::from_event("indexer.reorg", &event, None), None);
+    }
+
+    #[test]
+    fn test_relay_event_round_trips() {
+        let mut event = RelayEvent::new("tip.created", serde_json::json!({"recipient": "0xb", "amount": 5}));
+        event.event_id = Some("evt-1".to_string());
+
+        let payload = serde_json::to_vec(&event).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(value["schema_version"], 1);
+
+        assert_eq!(RelayEvent::decode(&payload).unwrap(), Some(event));
+    }
+
+    #[test]
+    fn test_relay_event_versions() {
+        // Published before versioning: read as v1
+        let legacy = br#"{"event_type": "follow.created", "event_data": {}, "event_id": "", "transaction_id": "tx"}"#;
+        let event = RelayEvent::decode(legacy).unwrap().unwrap();
+        assert_eq!(event.schema_version, 1);
+        assert_eq!(event.event_id(), None);
+
+        // Unknown versions are skipped, even when the rest of the envelope has changed shape
+        let future = br#"{"schema_version": 2, "type": "follow.created", "payload": {}}"#;
+        assert_eq!(RelayEvent::decode(future).unwrap(), None);
+
+        assert!(RelayEvent::decode(b"not json").is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
+use crate::db::DbConnection;
+use crate::schema::relay_user_keys;
+
+/// A public key a user published for end-to-end encrypted messaging; the relay never sees private keys
+#[derive(Debug, Clone, Queryable, Selectable, Serialize)]
+#[diesel(table_name = relay_user_keys)]
+pub struct UserKey {
+    #[serde(skip)]
+    pub id: i64,
+    #[serde(skip)]
+    pub user_address: String,
+    pub key_id: String,
+    /// Client-defined, e.g. `x25519`
+    pub algorithm: String,
+    /// Base64
+    pub public_key: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Publish a key, replacing the user's key with the same `key_id`
+pub async fn publish_user_key(
+    conn: &mut DbConnection,
+    user_address: &str,
+    key_id: &str,
+    algorithm: &str,
+    public_key: &str,
+) -> anyhow::Result<UserKey> {
+    let saved = diesel::insert_into(relay_user_keys::table)
+        .values((
+            relay_user_keys::user_address.eq(user_address),
+            relay_user_keys::key_id.eq(key_id),
+            relay_user_keys::algorithm.eq(algorithm),
+            relay_user_keys::public_key.eq(public_key),
+        ))
+        .on_conflict((relay_user_keys::user_address, relay_user_keys::key_id))
+        .do_update()
+        .set((
+            relay_user_keys::algorithm.eq(algorithm),
+            relay_user_keys::public_key.eq(public_key),
+            relay_user_keys::updated_at.eq(Utc::now()),
+        ))
+        .returning(UserKey::as_returning())
+        .get_result(conn)
+        .await?;
+
+    Ok(saved)
+}
+
+/// A user's published keys, most recently updated first
+pub async fn user_keys(conn: &mut DbConnection, user_address: &str) -> anyhow::Result<Vec<UserKey>> {
+    let keys = relay_user_keys::table
+        .filter(relay_user_keys::user_address.eq(user_address))
+        .order(relay_user_keys::updated_at.desc())
+        .select(UserKey::as_select())
+        .load(conn)
+        .await?;
+
+    Ok(keys)
+}
@@ -1,10 +1,23 @@
 use anyhow::{Result, anyhow};
-use a2::{Client, NotificationBuilder, PlainNotificationBuilder, NotificationOptions};
+use a2::request::payload::{Payload, PayloadLike};
+use a2::{Client, ClientConfig, DefaultNotificationBuilder, ErrorReason, NotificationBuilder, NotificationOptions};
 use relay_core::config::DeliveryConfig;
-use serde_json::Value;
+use relay_core::types::ApnsEnvironment;
+use crate::outcome::{InvalidDeviceToken, SendOutcome};
+use crate::payload::{category, custom_data, thread_id};
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
 use std::fs;
+use std::future::Future;
+use std::time::Duration;
 use tracing;
 
+/// Tries per push; only throttling, APNs outages and connection problems are retried
+const MAX_ATTEMPTS: u32 = 3;
+/// First retry waits this long; each further one doubles it. a2 doesn't expose `Retry-After`, and APNs
+/// rarely sends one, so this backoff stands in for it
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
 pub struct ApnsDelivery {
     client: Option<Client>,
     bundle_id: String,
@@ -36,17 +49,7 @@ impl ApnsDelivery {
                 return Err(anyhow!("Either apns_key_path or apns_key_content must be provided"));
             };
             
-            // Create APNs client
-            let client = Client::token(
-                key_content.as_bytes(),
-                key_id,
-                team_id,
-                if bundle_id.contains("sandbox") || bundle_id.contains("dev") {
-                    a2::Endpoint::Sandbox
-                } else {
-                    a2::Endpoint::Production
-                },
-            )
+            let client = Client::token(key_content.as_bytes(), key_id, team_id, ClientConfig::new(endpoint(config)))
             .map_err(|e| anyhow!("Failed to create APNs client: {}", e))?;
             
             tracing::info!("APNs client initialized successfully");
@@ -62,49 +65,34 @@ impl ApnsDelivery {
         })
     }
 
-    pub async fn send(&self, device_token: &str, notification: &Value) -> Result<()> {
+    pub async fn send(&self, device_token: &str, notification: &Value) -> Result<SendOutcome> {
         let client = match &self.client {
             Some(c) => c,
             None => {
                 tracing::debug!("APNs not configured, skipping");
-                return Ok(());
+                return Ok(SendOutcome::Skipped);
             }
         };
 
-        // Extract notification fields from the JSON value
-        let body = notification
-            .get("body")
-            .and_then(|v| v.as_str())
-            .unwrap_or("You have a new notification");
-
-        // Build the notification payload using PlainNotificationBuilder
-        let mut builder = PlainNotificationBuilder::new(body);
-        
-        // Optionally set badge, sound, category if present in notification data
-        if let Some(badge) = notification.get("badge").and_then(|v| v.as_u64()) {
-            builder.set_badge(badge as u32);
-        }
-        
-        if let Some(sound) = notification.get("sound").and_then(|v| v.as_str()) {
-            builder.set_sound(sound);
-        }
-        
-        if let Some(category) = notification.get("category").and_then(|v| v.as_str()) {
-            builder.set_category(category);
-        }
-        
         // Set notification options with topic (bundle ID) - required for token-based auth
         let mut options = NotificationOptions::default();
         if !self.bundle_id.is_empty() {
             options.apns_topic = Some(&self.bundle_id);
         }
-        
-        // Build the notification payload
-        let payload = builder.build(device_token, options);
+
+        let data = custom_data(notification);
+        let thread_id = thread_id(notification);
+        let category = category(notification);
+        let payload = build_payload(device_token, notification, &data, thread_id.as_deref(), category.as_deref(), options)?;
 
         // Send the notification
-        let response = client.send(payload).await
-            .map_err(|e| anyhow!("Failed to send APNs notification: {}", e))?;
+        let response = match send_with_retry(|| client.send(payload.clone()), RETRY_BASE_DELAY).await {
+            Ok(response) => response,
+            Err(e) if classify(&e) == Failure::InvalidToken => {
+                return Err(InvalidDeviceToken { provider: "apns", reason: e.to_string() }.into());
+            }
+            Err(e) => return Err(anyhow!("Failed to send APNs notification: {}", e)),
+        };
 
         tracing::debug!(
             "APNs notification sent successfully to device {}: {:?}",
@@ -112,6 +100,258 @@ impl ApnsDelivery {
             response
         );
         
-        Ok(())
+        Ok(SendOutcome::Sent { provider_id: response.apns_id })
+    }
+}
+
+/// What a failed send means for the next one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    /// Throttled (429), APNs unavailable (5xx) or the connection failed; worth another try
+    Retryable,
+    /// The device token is malformed, unregistered or belongs to another app
+    InvalidToken,
+    /// Anything else, e.g. a bad payload or rejected provider credentials; retrying won't help
+    Permanent,
+}
+
+fn classify(error: &a2::Error) -> Failure {
+    match error {
+        a2::Error::ResponseError(response) => match (response.code, response.error.as_ref().map(|e| &e.reason)) {
+            (410, _) | (_, Some(ErrorReason::BadDeviceToken | ErrorReason::Unregistered | ErrorReason::DeviceTokenNotForTopic)) => {
+                Failure::InvalidToken
+            }
+            (429 | 500 | 503, _) => Failure::Retryable,
+            _ => Failure::Permanent,
+        },
+        a2::Error::ConnectionError(_) | a2::Error::ClientError(_) | a2::Error::RequestTimeout(_) => Failure::Retryable,
+        _ => Failure::Permanent,
+    }
+}
+
+/// Call `send` until it succeeds, fails for a reason retrying won't fix, or `MAX_ATTEMPTS` is reached,
+/// doubling the wait from `base_delay` between tries
+async fn send_with_retry<F, Fut>(mut send: F, base_delay: Duration) -> Result<a2::Response, a2::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<a2::Response, a2::Error>>,
+{
+    let mut attempt = 1;
+    loop {
+        match send().await {
+            Ok(response) => return Ok(response),
+            Err(e) if attempt < MAX_ATTEMPTS && classify(&e) == Failure::Retryable => {
+                tracing::warn!("APNs attempt {}/{} failed, retrying: {}", attempt, MAX_ATTEMPTS, e);
+                tokio::time::sleep(base_delay * 2u32.pow(attempt - 1)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Gateway for `config`: its `apns_environment`, or a guess from the bundle id when that's unset
+fn endpoint(config: &DeliveryConfig) -> a2::Endpoint {
+    let environment = config
+        .apns_environment
+        .unwrap_or_else(|| ApnsEnvironment::guess_from_bundle_id(config.apns_bundle_id.as_deref().unwrap_or_default()));
+    match environment {
+        ApnsEnvironment::Sandbox => a2::Endpoint::Sandbox,
+        ApnsEnvironment::Production => a2::Endpoint::Production,
+    }
+}
+
+/// An APNs payload with a `thread-id` in `aps`, which a2's builders don't expose
+#[derive(Debug, Clone)]
+pub struct ApnsPayload<'a> {
+    payload: Payload<'a>,
+    thread_id: Option<&'a str>,
+}
+
+impl Serialize for ApnsPayload<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut value = serde_json::to_value(&self.payload).map_err(serde::ser::Error::custom)?;
+        if let (Some(thread_id), Some(aps)) = (self.thread_id, value.get_mut("aps").and_then(|a| a.as_object_mut())) {
+            aps.insert("thread-id".to_string(), Value::from(thread_id));
+        }
+        value.serialize(serializer)
+    }
+}
+
+impl PayloadLike for ApnsPayload<'_> {
+    fn get_device_token(&self) -> &str {
+        self.payload.device_token
+    }
+
+    fn get_options(&self) -> &NotificationOptions<'_> {
+        &self.payload.options
+    }
+}
+
+/// Alert from the notification's title/body/badge/sound, with its `data` as custom keys
+pub fn build_payload<'a>(
+    device_token: &'a str,
+    notification: &'a Value,
+    data: &'a Map<String, Value>,
+    thread_id: Option<&'a str>,
+    category: Option<&'a str>,
+    options: NotificationOptions<'a>,
+) -> Result<ApnsPayload<'a>> {
+    let str_field = |key: &str| notification.get(key).and_then(|v| v.as_str());
+
+    let mut builder = DefaultNotificationBuilder::new()
+        .set_body(str_field("body").unwrap_or("You have a new notification"));
+
+    if let Some(title) = str_field("title") {
+        builder = builder.set_title(title);
+    }
+    if let Some(badge) = notification.get("badge").and_then(|v| v.as_u64()) {
+        builder = builder.set_badge(badge as u32);
+    }
+    if let Some(sound) = str_field("sound") {
+        builder = builder.set_sound(sound);
+    }
+    if let Some(category) = category {
+        builder = builder.set_category(category);
+    }
+
+    let mut payload = builder.build(device_token, options);
+    for (key, value) in data.iter().filter(|(key, _)| key.as_str() != "aps") {
+        payload
+            .add_custom_data(key, value)
+            .map_err(|e| anyhow!("Invalid APNs custom data {}: {}", key, e))?;
+    }
+
+    Ok(ApnsPayload { payload, thread_id })
+}
+
+/// The JSON body APNs would receive for `notification`, for previews; the device token and options
+/// travel outside the body
+pub fn preview_payload(notification: &Value) -> Result<Value> {
+    let data = custom_data(notification);
+    let thread_id = thread_id(notification);
+    let category = category(notification);
+    let payload = build_payload("", notification, &data, thread_id.as_deref(), category.as_deref(), NotificationOptions::default())?;
+
+    Ok(serde_json::from_str(&payload.to_json_string()?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deep_link_survives_into_payload() {
+        let notification = serde_json::json!({
+            "id": 7,
+            "notification_type": "message.created",
+            "title": "New message",
+            "body": "bob sent you a message",
+            "data": {"conversation_id": "0xabc_0xdef", "message_id": 99},
+        });
+        let data = custom_data(&notification);
+        let thread_id = thread_id(&notification);
+        let category = category(&notification);
+
+        let payload = build_payload(
+            "token",
+            &notification,
+            &data,
+            thread_id.as_deref(),
+            category.as_deref(),
+            NotificationOptions::default(),
+        )
+        .unwrap();
+        let json: Value = serde_json::from_str(&payload.to_json_string().unwrap()).unwrap();
+
+        assert_eq!(json["conversation_id"], "0xabc_0xdef");
+        assert_eq!(json["message_id"], 99);
+        assert_eq!(json["notification_id"], 7);
+        assert_eq!(json["aps"]["thread-id"], "0xabc_0xdef");
+        assert_eq!(json["aps"]["category"], "MESSAGE_CREATED");
+        assert_eq!(json["aps"]["alert"]["title"], "New message");
+        assert_eq!(json["aps"]["alert"]["body"], "bob sent you a message");
+    }
+
+    fn config(bundle_id: &str, apns_environment: Option<ApnsEnvironment>) -> DeliveryConfig {
+        DeliveryConfig {
+            apns_bundle_id: Some(bundle_id.to_string()),
+            apns_key_id: None,
+            apns_team_id: None,
+            apns_key_path: None,
+            apns_key_content: None,
+            apns_environment,
+            fcm_server_key: None,
+            resend_api_key: None,
+            resend_from_email: None,
+            webhook_url: None,
+            webhook_secret: None,
+            email_logo_url: None,
+            email_brand_color: None,
+            email_footer: None,
+            email_template_path: None,
+            email_template: None,
+        }
+    }
+
+    #[test]
+    fn test_explicit_environment_overrides_bundle_id_guess() {
+        // A production app whose bundle id happens to contain "dev"
+        assert!(matches!(endpoint(&config("com.acme.devtools", None)), a2::Endpoint::Sandbox));
+        assert!(matches!(
+            endpoint(&config("com.acme.devtools", Some(ApnsEnvironment::Production))),
+            a2::Endpoint::Production
+        ));
+
+        assert!(matches!(endpoint(&config("com.acme.app", None)), a2::Endpoint::Production));
+        assert!(matches!(endpoint(&config("com.acme.app", Some(ApnsEnvironment::Sandbox))), a2::Endpoint::Sandbox));
+    }
+
+    fn rejected(code: u16, reason: ErrorReason) -> a2::Error {
+        a2::Error::ResponseError(a2::Response {
+            error: Some(a2::ErrorBody { reason, timestamp: None }),
+            apns_id: None,
+            code,
+        })
+    }
+
+    /// Replays `responses` in order, counting the calls
+    async fn send_sequence(responses: Vec<Result<a2::Response, a2::Error>>) -> (Result<a2::Response, a2::Error>, usize) {
+        let responses = std::sync::Mutex::new(responses.into_iter());
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let result = send_with_retry(
+            || {
+                calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let next = responses.lock().unwrap().next().expect("no more responses");
+                async move { next }
+            },
+            Duration::ZERO,
+        )
+        .await;
+        (result, calls.into_inner())
+    }
+
+    #[tokio::test]
+    async fn test_throttled_push_is_retried() {
+        let accepted = a2::Response { error: None, apns_id: Some("apns-1".to_string()), code: 200 };
+        let (result, calls) = send_sequence(vec![Err(rejected(429, ErrorReason::TooManyRequests)), Ok(accepted)]).await;
+
+        assert_eq!(result.unwrap().apns_id.as_deref(), Some("apns-1"));
+        assert_eq!(calls, 2);
+    }
+
+    #[tokio::test]
+    async fn test_retries_stop_at_limit_and_on_permanent_failures() {
+        let unavailable = || Err(rejected(503, ErrorReason::ServiceUnavailable));
+        let (result, calls) = send_sequence(vec![unavailable(), unavailable(), unavailable(), unavailable()]).await;
+        assert!(result.is_err());
+        assert_eq!(calls, MAX_ATTEMPTS as usize);
+
+        let (result, calls) = send_sequence(vec![Err(rejected(410, ErrorReason::Unregistered))]).await;
+        assert_eq!(classify(&result.unwrap_err()), Failure::InvalidToken);
+        assert_eq!(calls, 1);
+
+        assert_eq!(classify(&rejected(400, ErrorReason::BadDeviceToken)), Failure::InvalidToken);
+        assert_eq!(classify(&rejected(413, ErrorReason::PayloadTooLarge)), Failure::Permanent);
     }
 }
@@ -0,0 +1,117 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use relay_core::config::DeliveryConfig;
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::{apns::ApnsDelivery, fcm::FcmDelivery, email::EmailDelivery, webhook::WebhookDelivery};
+
+/// The set of delivery clients built from one `DeliveryConfig`
+pub struct DeliveryClients {
+    pub apns: ApnsDelivery,
+    pub fcm: FcmDelivery,
+    pub email: EmailDelivery,
+    pub webhook: WebhookDelivery,
+}
+
+impl DeliveryClients {
+    pub fn new(config: &DeliveryConfig) -> Result<Self> {
+        Ok(Self {
+            apns: ApnsDelivery::new(config)?,
+            fcm: FcmDelivery::new(config)?,
+            email: EmailDelivery::new(config)?,
+            webhook: WebhookDelivery::new(config)?,
+        })
+    }
+}
+
+/// Per-platform clients, reused across jobs so keys are read and HTTP/2 connections set up once
+/// Entries are rebuilt when the platform's config row has a newer `updated_at`
+pub struct ClientCache<C = DeliveryClients> {
+    entries: HashMap<String, (DateTime<Utc>, Arc<C>)>,
+}
+
+impl<C> Default for ClientCache<C> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl<C> ClientCache<C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the cached clients for `platform_id`, building them if missing or built from an older config
+    pub fn get_or_build(
+        &mut self,
+        platform_id: &str,
+        updated_at: DateTime<Utc>,
+        build: impl FnOnce() -> Result<C>,
+    ) -> Result<Arc<C>> {
+        if let Some((cached_at, clients)) = self.entries.get(platform_id) {
+            if *cached_at == updated_at {
+                return Ok(clients.clone());
+            }
+            tracing::info!("Delivery config for platform {} changed, rebuilding clients", platform_id);
+        }
+
+        let clients = Arc::new(build()?);
+        self.entries.insert(platform_id.to_string(), (updated_at, clients.clone()));
+        Ok(clients)
+    }
+
+    /// Drop the clients for a platform whose config was deleted
+    pub fn remove(&mut self, platform_id: &str) {
+        if self.entries.remove(platform_id).is_some() {
+            tracing::info!("Delivery config for platform {} removed, dropping cached clients", platform_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_clients_reused_until_config_changes() {
+        let mut cache: ClientCache<u32> = ClientCache::new();
+        let builds = Cell::new(0);
+        let build = || {
+            builds.set(builds.get() + 1);
+            Ok(builds.get())
+        };
+
+        let v1 = Utc::now();
+        let first = cache.get_or_build("platform-a", v1, build).unwrap();
+        let second = cache.get_or_build("platform-a", v1, build).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(builds.get(), 1);
+
+        // Another platform gets its own clients
+        cache.get_or_build("platform-b", v1, build).unwrap();
+        assert_eq!(builds.get(), 2);
+
+        // A newer config invalidates the cached clients
+        let v2 = v1 + chrono::Duration::seconds(1);
+        let rebuilt = cache.get_or_build("platform-a", v2, build).unwrap();
+        assert!(!Arc::ptr_eq(&first, &rebuilt));
+        assert_eq!(builds.get(), 3);
+
+        // A deleted config drops the entry, so a recreated one is built fresh
+        cache.remove("platform-a");
+        cache.get_or_build("platform-a", v2, build).unwrap();
+        assert_eq!(builds.get(), 4);
+    }
+
+    #[test]
+    fn test_failed_build_is_not_cached() {
+        let mut cache: ClientCache<u32> = ClientCache::new();
+        let now = Utc::now();
+
+        assert!(cache.get_or_build("platform-a", now, || Err(anyhow::anyhow!("bad key"))).is_err());
+        assert_eq!(*cache.get_or_build("platform-a", now, || Ok(7)).unwrap(), 7);
+    }
+}
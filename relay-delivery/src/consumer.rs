@@ -1,55 +1,108 @@
 use anyhow::{Result, anyhow};
 use rdkafka::consumer::Consumer;
+use rdkafka::message::OwnedMessage;
 use rdkafka::Message;
-use relay_core::{RelayContext, redpanda::create_consumer, get_platform_delivery_config};
-use crate::{apns::ApnsDelivery, fcm::FcmDelivery, email::EmailDelivery};
-use std::time::Duration;
+use relay_core::backoff::Backoff;
+use relay_core::consumer_pool::WorkerPool;
+use relay_core::dead_letter::{handle_or_dead_letter, SourceMessage};
+use relay_core::{RelayContext, consumer_lag, processed_events, redpanda::create_consumer, get_platform_delivery_config};
+use relay_core::redis::{counts, get_connection, RedisKeys, RedisPool};
+use crate::clients::{ClientCache, DeliveryClients};
+use crate::dnd;
+use crate::preferences::{self, DeliveryPreferences};
+use crate::outcome::{mark_message_delivered, record_deliveries, DeliveryOutcome, DeliveryRecord, DeliverySummary};
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use futures::future::{join_all, BoxFuture};
+use relay_core::db::DbConnection;
+use relay_core::schema::relay_device_tokens;
+use relay_core::types::DevicePlatform;
+use std::sync::{Arc, Mutex};
 use tracing;
 
-const TOPIC: &str = "notifications.delivery";
+const CONSUMER: &str = "relay-delivery";
 
 pub async fn run(ctx: RelayContext) -> Result<()> {
     tracing::info!("Starting delivery consumer");
 
-    let consumer = create_consumer(&ctx.config.redpanda, Some("relay-delivery"))?;
+    let consumer = create_consumer(&ctx.config.redpanda, Some(CONSUMER))?;
     
     // Global fallback delivery clients (for MySocial platform or when platform config not found)
-    let global_apns = ApnsDelivery::new(&ctx.config.delivery)?;
-    let global_fcm = FcmDelivery::new(&ctx.config.delivery)?;
-    let global_email = EmailDelivery::new(&ctx.config.delivery)?;
+    let global_clients = Arc::new(DeliveryClients::new(&ctx.config.delivery)?);
+    // Only locked around cache lookups, never across an await
+    let platform_clients = Arc::new(Mutex::new(ClientCache::new()));
 
-    consumer.subscribe(&[TOPIC])?;
+    // Summary pushes for users whose quiet hours have ended
+    tokio::spawn(dnd::run_digests(ctx.clone(), global_clients.clone()));
 
-    tracing::info!("Subscribed to topic: {}", TOPIC);
+    let topic = ctx.config.redpanda.topics().delivery();
+    consumer.subscribe(&[&topic])?;
 
-    let mut error_count = 0u32;
+    tracing::info!("Subscribed to topic: {}", topic);
+
+    // Lag per partition, for /metrics and /health/ready
+    tokio::spawn(consumer_lag::report(ctx.clone(), consumer.clone(), CONSUMER));
+
+    // Handle messages on concurrent workers; offsets are only stored below the oldest one still in flight
+    let store_consumer = consumer.clone();
+    let pool = WorkerPool::new(
+        ctx.config.redpanda.consumer_concurrency,
+        {
+            let ctx = ctx.clone();
+            move |message: OwnedMessage| {
+                let ctx = ctx.clone();
+                let global_clients = global_clients.clone();
+                let platform_clients = platform_clients.clone();
+                async move {
+                    if let Some(payload) = message.payload() {
+                        let source = SourceMessage::from_message(&message);
+                        let handled = handle_or_dead_letter(&ctx.redpanda_producer, &ctx.config.redpanda, source, || {
+                            handle_delivery(&ctx, &global_clients, &platform_clients, payload)
+                        })
+                        .await;
+                        match handled {
+                            Ok(_) => {
+                                tracing::debug!("Processed delivery job");
+                            }
+                            Err(e) => {
+                                tracing::error!("Error processing delivery job: {}", e);
+                            }
+                        }
+                    }
+                }
+            }
+        },
+        move |topic: &str, partition: i32, offset: i64| {
+            if let Err(e) = store_consumer.store_offset(topic, partition, offset) {
+                tracing::warn!("Failed to store offset for delivery job: {}", e);
+            }
+        },
+    );
+
+    let mut backoff = Backoff::for_consumer();
     let mut last_error_log = std::time::Instant::now();
     
     loop {
         match consumer.recv().await {
             Ok(message) => {
-                error_count = 0; // Reset error count on success
-                if let Some(payload) = message.payload() {
-                    match handle_delivery(&ctx, &global_apns, &global_fcm, &global_email, payload).await {
-                        Ok(_) => {
-                            tracing::debug!("Processed delivery job");
-                        }
-                        Err(e) => {
-                            tracing::error!("Error processing delivery job: {}", e);
-                        }
-                    }
+                if backoff.failures() > 0 {
+                    backoff.reset();
+                    ctx.consumer_errors.record(CONSUMER, 0);
                 }
+                pool.dispatch(message.detach()).await;
             }
             Err(e) => {
-                error_count += 1;
+                let delay = backoff.fail();
+                ctx.consumer_errors.record(CONSUMER, backoff.failures());
                 // Only log errors every 30 seconds to reduce log spam
                 if last_error_log.elapsed().as_secs() >= 30 {
                     tracing::warn!(
                         "Error receiving message from Redpanda (error count: {}): {}",
-                        error_count,
+                        backoff.failures(),
                         e
                     );
-                    if error_count == 1 {
+                    if backoff.failures() == 1 {
                         tracing::warn!("Troubleshooting Redpanda connection:");
                         tracing::warn!("  1. Verify REDPANDA_BROKERS is correct and brokers are accessible");
                         tracing::warn!("  2. Check if SSL/TLS is required (set REDPANDA_SSL_ENABLED=true)");
@@ -58,9 +111,8 @@ pub async fn run(ctx: RelayContext) -> Result<()> {
                     }
                     last_error_log = std::time::Instant::now();
                 }
-                // Exponential backoff: 1s, 2s, 4s, max 30s
-                let backoff = Duration::from_secs(1 << error_count.min(5)).min(Duration::from_secs(30));
-                tokio::time::sleep(backoff).await;
+                // Exponential backoff with jitter: up to 1s, 2s, 4s, max 30s
+                tokio::time::sleep(delay).await;
             }
         }
     }
@@ -68,13 +120,34 @@ pub async fn run(ctx: RelayContext) -> Result<()> {
 
 async fn handle_delivery(
     ctx: &RelayContext,
-    global_apns: &ApnsDelivery,
-    global_fcm: &FcmDelivery,
-    global_email: &EmailDelivery,
+    global_clients: &Arc<DeliveryClients>,
+    platform_clients: &Mutex<ClientCache>,
     payload: &[u8],
 ) -> Result<()> {
     let job: serde_json::Value = serde_json::from_slice(payload)?;
-    
+
+    // Each notification is delivered once, even if its job is replayed
+    let job_id = job
+        .get("notification")
+        .and_then(|n| n.get("id"))
+        .and_then(|v| v.as_i64())
+        .map(|id| format!("notification-{}", id));
+
+    processed_events::process_once(&ctx.redis_pool, ctx.config.redis.keys(), CONSUMER, job_id.as_deref(), async {
+        let summary = deliver(ctx, global_clients, platform_clients, &job).await?;
+        tracing::info!("Delivery job {}: {}", job_id.as_deref().unwrap_or("without id"), summary);
+        Ok(())
+    })
+    .await
+}
+
+async fn deliver(
+    ctx: &RelayContext,
+    global_clients: &Arc<DeliveryClients>,
+    platform_clients: &Mutex<ClientCache>,
+    job: &serde_json::Value,
+) -> Result<DeliverySummary> {
+
     let user_address = job.get("user_address")
         .and_then(|v| v.as_str())
         .ok_or_else(|| anyhow::anyhow!("Missing user_address"))?;
@@ -85,62 +158,33 @@ async fn handle_delivery(
 
     // Get device tokens for user
     let mut conn = ctx.db_pool.get().await?;
-    use relay_core::schema::relay_device_tokens;
-    use diesel::prelude::*;
-    use diesel_async::RunQueryDsl;
-    
-    let tokens: Vec<(String, String)> = relay_device_tokens::table
-        .filter(relay_device_tokens::user_address.eq(user_address))
-        .select((relay_device_tokens::device_token, relay_device_tokens::platform))
-        .load(&mut conn)
-        .await
-        .unwrap_or_default();
+    let tokens = load_device_tokens(&mut conn, user_address).await;
 
     let notification = job.get("notification")
         .ok_or_else(|| anyhow::anyhow!("Missing notification"))?;
 
-    // Get platform-specific delivery config if platform_id is provided
+    // Use platform-specific clients if platform_id is provided and configured, otherwise global
+    let mut clients = global_clients.clone();
     if let Some(pid) = platform_id {
         match get_platform_delivery_config(&mut conn, pid).await {
             Ok(Some(platform_config)) => {
                 tracing::debug!("Using platform-specific delivery config for platform: {}", pid);
                 let delivery_config = relay_core::config::DeliveryConfig::from(&platform_config);
-                
-                // Create platform-specific clients
-                if let (Ok(platform_apns), Ok(platform_fcm), Ok(platform_email)) = (
-                    ApnsDelivery::new(&delivery_config),
-                    FcmDelivery::new(&delivery_config),
-                    EmailDelivery::new(&delivery_config),
-                ) {
-                    // Use platform-specific clients
-                    for (token, platform) in &tokens {
-                        match platform.as_str() {
-                            "ios" => {
-                                if let Err(e) = platform_apns.send(token, notification).await {
-                                    tracing::error!("Failed to send platform APNs notification: {}", e);
-                                }
-                            }
-                            "android" => {
-                                if let Err(e) = platform_fcm.send(token, notification).await {
-                                    tracing::error!("Failed to send platform FCM notification: {}", e);
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                    
-                    // Send email if enabled
-                    if let Err(e) = platform_email.send(user_address, notification).await {
-                        tracing::error!("Failed to send platform email notification: {}", e);
+
+                let built = platform_clients
+                    .lock()
+                    .unwrap()
+                    .get_or_build(pid, platform_config.updated_at, || DeliveryClients::new(&delivery_config));
+                match built {
+                    Ok(platform) => clients = platform,
+                    Err(e) => {
+                        tracing::warn!("Failed to create platform delivery clients, falling back to global: {}", e);
                     }
-                    
-                    return Ok(());
-                } else {
-                    tracing::warn!("Failed to create platform delivery clients, falling back to global");
                 }
             }
             Ok(None) => {
                 tracing::debug!("No platform-specific config found for platform: {}, using global", pid);
+                platform_clients.lock().unwrap().remove(pid);
             }
             Err(e) => {
                 tracing::warn!("Error fetching platform config, using global: {}", e);
@@ -148,28 +192,258 @@ async fn handle_delivery(
         }
     }
 
-    // Use global clients (fallback or when no platform_id)
-    for (token, platform) in tokens {
-        match platform.as_str() {
-            "ios" => {
-                if let Err(e) = global_apns.send(&token, notification).await {
-                    tracing::error!("Failed to send APNs notification: {}", e);
-                }
+    // During quiet hours push is held back; the notification is already stored and counted
+    let now = Utc::now();
+    let prefs = preferences::load(&mut conn, user_address).await.unwrap_or_else(|e| {
+        tracing::warn!("Failed to load delivery preferences for {}, delivering normally: {}", user_address, e);
+        DeliveryPreferences::default()
+    });
+    let quiet_until = prefs
+        .quiet_hours
+        .and_then(|q| q.ends_at(now))
+        .map(|end| (end, prefs.dnd_digest_enabled));
+
+    let mut records = Vec::new();
+    let mut outcomes = Vec::new();
+    if let Some((end, digest_enabled)) = quiet_until {
+        records.push(DeliveryRecord::skipped("push", "quiet hours"));
+        if digest_enabled && !tokens.is_empty() {
+            if let Err(e) = dnd::queue_for_digest(ctx, user_address, notification, end).await {
+                tracing::warn!("Failed to queue quiet-hours digest for {}: {}", user_address, e);
             }
-            "android" => {
-                if let Err(e) = global_fcm.send(&token, notification).await {
-                    tracing::error!("Failed to send FCM notification: {}", e);
-                }
+        }
+    } else if tokens.is_empty() {
+        records.push(DeliveryRecord::skipped("push", "no registered device tokens"));
+    } else {
+        // The icon badge follows UNREAD with each push
+        let badge = current_badge(&ctx.redis_pool, ctx.config.redis.keys(), user_address).await;
+        let push = with_badge(notification, badge);
+
+        // Send to all of the user's devices concurrently
+        outcomes = send_to_devices(&tokens, |token, platform| send_to_device(&clients, token, platform, &push)).await;
+        records.extend(outcomes.iter().map(|o| o.record.clone()));
+    }
+    prune_invalid_tokens(&mut conn, user_address, &outcomes).await;
+    let summary = DeliverySummary::from_outcomes(&outcomes);
+
+    // Digest users get a periodic summary email from relay-notify instead
+    if prefs.email_digest.is_enabled() {
+        records.push(DeliveryRecord::skipped("email", "email digest"));
+    } else {
+        let email = clients.email.send(user_address, notification).await;
+        if let Err(e) = &email {
+            tracing::error!("Failed to send email notification: {}", e);
+        }
+        records.push(DeliveryRecord::from_result("email", email));
+    }
+
+    // Platforms with a webhook get every notification, quiet hours or not; it's their server, not a device
+    if clients.webhook.is_configured() {
+        let webhook = clients.webhook.send(user_address, notification).await;
+        if let Err(e) = &webhook {
+            tracing::error!("Failed to send webhook notification: {}", e);
+        }
+        records.push(DeliveryRecord::from_result("webhook", webhook));
+    }
+
+    // Keep a record of every attempt so missing pushes can be traced
+    let notification_id = notification.get("id").and_then(|v| v.as_i64());
+    if let Err(e) = record_deliveries(&mut conn, notification_id, user_address, &records).await {
+        tracing::warn!("Failed to record delivery results for {}: {}", user_address, e);
+    }
+
+    // Message notifications mark the message delivered once any channel got through
+    let message_id = notification
+        .get("data")
+        .and_then(|d| d.get("message_id"))
+        .and_then(|v| v.as_i64());
+    if let Some(message_id) = message_id {
+        if records.iter().any(DeliveryRecord::is_sent) {
+            if let Err(e) = mark_message_delivered(&mut conn, message_id).await {
+                tracing::warn!("Failed to mark message {} delivered: {}", message_id, e);
             }
-            _ => {}
         }
     }
 
-    // Send email if enabled
-    if let Err(e) = global_email.send(user_address, notification).await {
-        tracing::error!("Failed to send email notification: {}", e);
+    Ok(summary)
+}
+
+/// The recipient's unread count for the app badge; None when Redis can't be read, so pushes leave the badge alone
+pub(crate) async fn current_badge(pool: &RedisPool, keys: RedisKeys<'_>, user_address: &str) -> Option<u32> {
+    let total = async {
+        let mut redis_conn = get_connection(pool).await?;
+        counts::get_total(&mut redis_conn, keys, user_address).await
+    };
+
+    match total.await {
+        Ok(total) => Some(total.min(u32::MAX as i64) as u32),
+        Err(e) => {
+            tracing::warn!("Sending pushes to {} without a badge, unread count unavailable: {}", user_address, e);
+            None
+        }
+    }
+}
+
+/// `notification` with `badge` set, as APNs and FCM read it
+pub(crate) fn with_badge(notification: &serde_json::Value, badge: Option<u32>) -> serde_json::Value {
+    let mut notification = notification.clone();
+    if let (Some(badge), Some(fields)) = (badge, notification.as_object_mut()) {
+        fields.insert("badge".to_string(), badge.into());
+    }
+    notification
+}
+
+/// A user's device tokens; rows with an unrecognised platform can't be routed and are skipped
+pub(crate) async fn load_device_tokens(conn: &mut DbConnection, user_address: &str) -> Vec<(String, DevicePlatform)> {
+    let rows: Vec<(String, String)> = relay_device_tokens::table
+        .filter(relay_device_tokens::user_address.eq(user_address))
+        .select((relay_device_tokens::device_token, relay_device_tokens::platform))
+        .load(conn)
+        .await
+        .unwrap_or_default();
+
+    rows.into_iter()
+        .filter_map(|(token, platform)| match platform.parse() {
+            Ok(platform) => Some((token, platform)),
+            Err(e) => {
+                tracing::debug!("Skipping device token for {}: {}", user_address, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Remove the tokens the provider rejected, since sends to them will never work again
+pub(crate) async fn prune_invalid_tokens(conn: &mut DbConnection, user_address: &str, outcomes: &[DeliveryOutcome]) {
+    let invalid: Vec<&str> = outcomes.iter().filter(|o| o.invalid_token).map(|o| o.device_token.as_str()).collect();
+    if invalid.is_empty() {
+        return;
     }
 
-    Ok(())
+    let pruned = diesel::delete(
+        relay_device_tokens::table
+            .filter(relay_device_tokens::user_address.eq(user_address))
+            .filter(relay_device_tokens::device_token.eq_any(&invalid)),
+    )
+    .execute(conn)
+    .await;
+    if let Err(e) = pruned {
+        tracing::warn!("Failed to prune invalid device tokens for {}: {}", user_address, e);
+    }
+}
+
+/// Send to every token concurrently with `send`, which returns None for platforms it can't push to; each
+/// token gets an outcome, so one failing device doesn't hide how the others went
+pub(crate) async fn send_to_devices<'a, F>(tokens: &'a [(String, DevicePlatform)], send: F) -> Vec<DeliveryOutcome>
+where
+    F: Fn(&'a str, DevicePlatform) -> Option<BoxFuture<'a, DeliveryOutcome>>,
+{
+    let attempts = tokens.iter().map(|(token, platform)| {
+        let sending = send(token, *platform);
+        async move {
+            match sending {
+                Some(sending) => sending.await,
+                None => DeliveryOutcome::skipped(token, *platform, "no push provider for this platform"),
+            }
+        }
+    });
+    join_all(attempts).await
 }
 
+pub(crate) fn send_to_device<'a>(
+    clients: &'a DeliveryClients,
+    token: &'a str,
+    platform: DevicePlatform,
+    notification: &'a serde_json::Value,
+) -> Option<BoxFuture<'a, DeliveryOutcome>> {
+    match platform {
+        DevicePlatform::Ios => Some(Box::pin(async move {
+            let result = clients.apns.send(token, notification).await;
+            if let Err(e) = &result {
+                tracing::error!("Failed to send APNs notification: {}", e);
+            }
+            DeliveryOutcome::new(token, platform, "apns", result)
+        })),
+        DevicePlatform::Android => Some(Box::pin(async move {
+            let result = clients.fcm.send(token, notification).await;
+            if let Err(e) = &result {
+                tracing::error!("Failed to send FCM notification: {}", e);
+            }
+            DeliveryOutcome::new(token, platform, "fcm", result)
+        })),
+        // No web push provider yet
+        DevicePlatform::Web => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outcome::{InvalidDeviceToken, SendOutcome};
+    use std::cell::Cell;
+
+    #[tokio::test]
+    async fn test_one_failing_device_does_not_stop_the_other() {
+        let tokens = vec![
+            ("ios-bad".to_string(), DevicePlatform::Ios),
+            ("android".to_string(), DevicePlatform::Android),
+        ];
+        let attempts = Cell::new(0);
+
+        // The first token is rejected by the provider, the second accepted
+        let outcomes = send_to_devices(&tokens, |token, platform| {
+            attempts.set(attempts.get() + 1);
+            let (channel, result) = match token {
+                "ios-bad" => ("apns", Err(InvalidDeviceToken { provider: "apns", reason: "Unregistered".to_string() }.into())),
+                _ => ("fcm", Ok(SendOutcome::Sent { provider_id: Some("fcm-1".to_string()) })),
+            };
+            Some(Box::pin(async move { DeliveryOutcome::new(token, platform, channel, result) }) as BoxFuture<'_, _>)
+        })
+        .await;
+
+        assert_eq!(attempts.get(), 2);
+        assert!(outcomes[0].invalid_token);
+        assert!(outcomes[1].record.is_sent());
+
+        let summary = DeliverySummary::from_outcomes(&outcomes);
+        assert_eq!(summary, DeliverySummary { devices: 2, delivered: 1, failed: 0, skipped: 0, invalid_tokens: 1 });
+        assert_eq!(summary.to_string(), "1/2 devices delivered, 1 invalid token pruned");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis at REDIS_URL"]
+    async fn test_push_badge_is_unread_count() {
+        let config = relay_core::Config::from_env();
+        let keys = config.redis.keys();
+        let pool = relay_core::redis::create_pool(&config.redis).await.unwrap();
+        let mut redis_conn = get_connection(&pool).await.unwrap();
+        let user = format!("0xbadge-{}", uuid::Uuid::new_v4());
+        let notification = serde_json::json!({"id": 1, "title": "New follower", "body": "0xfan followed you"});
+
+        redis::cmd("SET").arg(keys.unread_total(&user)).arg(7).query_async::<()>(&mut redis_conn).await.unwrap();
+        let badge = current_badge(&pool, keys, &user).await;
+        redis::cmd("DEL").arg(keys.unread_total(&user)).query_async::<()>(&mut redis_conn).await.unwrap();
+
+        assert_eq!(badge, Some(7));
+        let push = with_badge(&notification, badge);
+        assert_eq!(crate::apns::preview_payload(&push).unwrap()["aps"]["badge"], 7);
+        assert_eq!(crate::fcm::build_message("token", &push).badge, Some(7));
+
+        // Nothing unread clears the badge
+        assert_eq!(current_badge(&pool, keys, &user).await, Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_badge_omitted_without_redis() {
+        let config = relay_core::Config::from_env();
+        let unreachable = Arc::new(redis::Client::open("redis://127.0.0.1:1").unwrap());
+        let notification = serde_json::json!({"id": 1, "title": "New follower", "body": "0xfan followed you"});
+
+        let badge = current_badge(&unreachable, config.redis.keys(), "0xuser").await;
+        let push = with_badge(&notification, badge);
+
+        assert_eq!(badge, None);
+        assert!(crate::apns::preview_payload(&push).unwrap()["aps"].get("badge").is_none());
+        assert_eq!(crate::fcm::build_message("token", &push).badge, None);
+    }
+}
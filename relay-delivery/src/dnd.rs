@@ -0,0 +1,145 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use relay_core::{RelayContext, lock, redis::get_connection};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::clients::DeliveryClients;
+use crate::consumer::{load_device_tokens, prune_invalid_tokens, send_to_device, send_to_devices};
+use crate::outcome::{record_deliveries, DeliverySummary};
+
+/// Held-back notifications are dropped if no digest goes out within this long
+const DIGEST_TTL_SECONDS: u64 = 2 * 24 * 60 * 60;
+
+const DIGEST_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Hold a suppressed notification for the digest sent when quiet hours end at `due`
+pub async fn queue_for_digest(
+    ctx: &RelayContext,
+    user_address: &str,
+    notification: &serde_json::Value,
+    due: DateTime<Utc>,
+) -> Result<()> {
+    let keys = ctx.config.redis.keys();
+    let key = keys.dnd_digest(user_address);
+    let mut conn = get_connection(&ctx.redis_pool).await?;
+
+    redis::pipe()
+        .atomic()
+        .cmd("RPUSH").arg(&key).arg(serde_json::to_string(notification)?).ignore()
+        .cmd("EXPIRE").arg(&key).arg(DIGEST_TTL_SECONDS).ignore()
+        // NX keeps the first due time if several notifications arrive in one window
+        .cmd("ZADD").arg(keys.dnd_digest_due()).arg("NX").arg(due.timestamp()).arg(user_address).ignore()
+        .query_async::<()>(&mut conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Send one summary push per user whose quiet hours have ended, from the instance holding the `dnd-digest` lock
+pub async fn run_digests(ctx: RelayContext, clients: Arc<DeliveryClients>) {
+    lock::with_leader_lock(&ctx, "dnd-digest", || send_digests_forever(&ctx, &clients)).await
+}
+
+async fn send_digests_forever(ctx: &RelayContext, clients: &DeliveryClients) {
+    let mut interval = tokio::time::interval(DIGEST_POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = send_due_digests(ctx, clients).await {
+            tracing::warn!("Failed to send quiet-hours digests: {}", e);
+        }
+    }
+}
+
+async fn send_due_digests(ctx: &RelayContext, clients: &DeliveryClients) -> Result<()> {
+    let mut redis_conn = get_connection(&ctx.redis_pool).await?;
+    let keys = ctx.config.redis.keys();
+
+    // Users with a pending digest, scored by when their quiet hours end
+    let due: Vec<String> = redis::cmd("ZRANGEBYSCORE")
+        .arg(keys.dnd_digest_due())
+        .arg("-inf")
+        .arg(Utc::now().timestamp())
+        .arg("LIMIT")
+        .arg(0)
+        .arg(100)
+        .query_async(&mut redis_conn)
+        .await?;
+
+    for user_address in due {
+        // Only the worker that removes the entry sends the digest
+        let claimed: i64 = redis::cmd("ZREM")
+            .arg(keys.dnd_digest_due())
+            .arg(&user_address)
+            .query_async(&mut redis_conn)
+            .await?;
+        if claimed == 0 {
+            continue;
+        }
+
+        let (held,): (Vec<String>,) = redis::pipe()
+            .atomic()
+            .cmd("LRANGE").arg(keys.dnd_digest(&user_address)).arg(0).arg(-1)
+            .cmd("DEL").arg(keys.dnd_digest(&user_address)).ignore()
+            .query_async(&mut redis_conn)
+            .await?;
+
+        if held.is_empty() {
+            continue;
+        }
+
+        if let Err(e) = send_digest(ctx, clients, &user_address, held.len()).await {
+            tracing::warn!("Failed to send quiet-hours digest to {}: {}", user_address, e);
+        }
+    }
+
+    Ok(())
+}
+
+async fn send_digest(ctx: &RelayContext, clients: &DeliveryClients, user_address: &str, count: usize) -> Result<()> {
+    let notification = digest_notification(count);
+
+    let mut conn = ctx.db_pool.get().await?;
+    let tokens = load_device_tokens(&mut conn, user_address).await;
+
+    let outcomes = send_to_devices(&tokens, |token, platform| send_to_device(clients, token, platform, &notification)).await;
+    let records: Vec<_> = outcomes.iter().map(|o| o.record.clone()).collect();
+    prune_invalid_tokens(&mut conn, user_address, &outcomes).await;
+
+    record_deliveries(&mut conn, None, user_address, &records).await?;
+    tracing::debug!(
+        "Sent quiet-hours digest of {} notifications to {}: {}",
+        count,
+        user_address,
+        DeliverySummary::from_outcomes(&outcomes)
+    );
+
+    Ok(())
+}
+
+fn digest_notification(count: usize) -> serde_json::Value {
+    let body = if count == 1 {
+        "You have 1 new notification".to_string()
+    } else {
+        format!("You have {} new notifications", count)
+    };
+
+    serde_json::json!({
+        "title": "While you were away",
+        "body": body,
+        "badge": count,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest_notification_summarizes_count() {
+        assert_eq!(digest_notification(1)["body"], "You have 1 new notification");
+        assert_eq!(digest_notification(5)["body"], "You have 5 new notifications");
+        assert_eq!(digest_notification(5)["badge"], 5);
+    }
+}
@@ -1,12 +1,15 @@
 use anyhow::{Result, anyhow};
+use handlebars::Handlebars;
 use relay_core::config::DeliveryConfig;
+use crate::outcome::SendOutcome;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
+use std::fs;
 use std::sync::Arc;
 use tracing;
 
 /// Simple HTML escaping function
-fn html_escape(text: &str) -> String {
+pub fn html_escape(text: &str) -> String {
     text.chars()
         .map(|c| match c {
             '<' => "&lt;".to_string(),
@@ -19,6 +22,104 @@ fn html_escape(text: &str) -> String {
         .collect()
 }
 
+/// Built-in notification email; platforms can replace it with their own `email_template`
+const DEFAULT_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+</head>
+<body style="font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, 'Helvetica Neue', Arial, sans-serif; line-height: 1.6; color: #333; max-width: 600px; margin: 0 auto; padding: 20px;">
+    {{#if logo_url}}
+    <img src="{{logo_url}}" alt="" style="display: block; max-height: 48px; margin-bottom: 20px;">
+    {{/if}}
+    <div style="background-color: #f8f9fa; border-top: 4px solid {{brand_color}}; border-radius: 8px; padding: 24px; margin-bottom: 20px;">
+        <h1 style="margin: 0 0 16px 0; font-size: 24px; color: #212529;">{{title}}</h1>
+        <p style="margin: 0; font-size: 16px; color: #495057;">{{body}}</p>
+    </div>
+    <p style="font-size: 14px; color: #6c757d; margin-top: 20px;">
+        {{footer}}
+    </p>
+</body>
+</html>"#;
+
+const DEFAULT_BRAND_COLOR: &str = "#212529";
+const DEFAULT_FOOTER: &str = "This is a notification from MySocial.";
+
+const DEFAULT_TEMPLATE_NAME: &str = "default";
+const PLATFORM_TEMPLATE_NAME: &str = "platform";
+
+/// Notification email templates plus the branding they're rendered with
+pub struct EmailTemplates {
+    registry: Handlebars<'static>,
+    has_platform_template: bool,
+    logo_url: Option<String>,
+    brand_color: String,
+    footer: String,
+}
+
+impl EmailTemplates {
+    /// A template that can't be read or doesn't compile is logged and the built-in one used instead,
+    /// so a bad template never stops emails going out
+    pub fn new(config: &DeliveryConfig) -> Self {
+        let mut registry = Handlebars::new();
+        // Same escaping as the rest of the relay's HTML, for every `{{value}}`
+        registry.register_escape_fn(html_escape);
+        registry
+            .register_template_string(DEFAULT_TEMPLATE_NAME, DEFAULT_TEMPLATE)
+            .expect("built-in email template compiles");
+
+        let source = match (&config.email_template, &config.email_template_path) {
+            (Some(template), _) => Some(template.clone()),
+            (None, Some(path)) => fs::read_to_string(path)
+                .map_err(|e| tracing::warn!("Failed to read email template {}, using the default: {}", path, e))
+                .ok(),
+            (None, None) => None,
+        };
+        let has_platform_template = source.is_some_and(|source| {
+            registry
+                .register_template_string(PLATFORM_TEMPLATE_NAME, source)
+                .map_err(|e| tracing::warn!("Invalid email template, using the default: {}", e))
+                .is_ok()
+        });
+
+        Self {
+            registry,
+            has_platform_template,
+            logo_url: config.email_logo_url.clone(),
+            brand_color: config
+                .email_brand_color
+                .clone()
+                .filter(|color| relay_core::platform_delivery_config::is_hex_color(color))
+                .unwrap_or_else(|| DEFAULT_BRAND_COLOR.to_string()),
+            footer: config.email_footer.clone().unwrap_or_else(|| DEFAULT_FOOTER.to_string()),
+        }
+    }
+
+    /// Render `notification` as an HTML email. Templates see `title`, `body`, `logo_url`, `brand_color`,
+    /// `footer` and the whole `notification`
+    pub fn render(&self, title: &str, body: &str, notification: &Value) -> String {
+        let data = json!({
+            "title": title,
+            "body": body,
+            "logo_url": self.logo_url,
+            "brand_color": self.brand_color,
+            "footer": self.footer,
+            "notification": notification,
+        });
+
+        if self.has_platform_template {
+            match self.registry.render(PLATFORM_TEMPLATE_NAME, &data) {
+                Ok(html) => return html,
+                Err(e) => tracing::warn!("Failed to render email template, using the default: {}", e),
+            }
+        }
+        self.registry
+            .render(DEFAULT_TEMPLATE_NAME, &data)
+            .expect("built-in email template renders")
+    }
+}
+
 const RESEND_API_URL: &str = "https://api.resend.com/emails";
 
 #[derive(Debug, Serialize)]
@@ -40,6 +141,7 @@ pub struct EmailDelivery {
     client: Option<Arc<reqwest::Client>>,
     api_key: Option<String>,
     from_email: Option<String>,
+    templates: EmailTemplates,
 }
 
 impl EmailDelivery {
@@ -67,18 +169,11 @@ impl EmailDelivery {
             client,
             api_key,
             from_email,
+            templates: EmailTemplates::new(config),
         })
     }
 
-    pub async fn send(&self, user_address: &str, notification: &Value) -> Result<()> {
-        let (client, api_key, from_email) = match (&self.client, &self.api_key, &self.from_email) {
-            (Some(c), Some(k), Some(f)) => (c, k, f),
-            _ => {
-                tracing::debug!("Email not configured, skipping");
-                return Ok(());
-            }
-        };
-
+    pub async fn send(&self, user_address: &str, notification: &Value) -> Result<SendOutcome> {
         // Extract notification fields from the JSON value
         let subject = notification
             .get("title")
@@ -90,35 +185,28 @@ impl EmailDelivery {
             .and_then(|v| v.as_str())
             .unwrap_or("You have a new notification");
 
-        // Build HTML email content
-        let html_content = format!(
-            r#"<!DOCTYPE html>
-<html>
-<head>
-    <meta charset="UTF-8">
-    <meta name="viewport" content="width=device-width, initial-scale=1.0">
-</head>
-<body style="font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, 'Helvetica Neue', Arial, sans-serif; line-height: 1.6; color: #333; max-width: 600px; margin: 0 auto; padding: 20px;">
-    <div style="background-color: #f8f9fa; border-radius: 8px; padding: 24px; margin-bottom: 20px;">
-        <h1 style="margin: 0 0 16px 0; font-size: 24px; color: #212529;">{}</h1>
-        <p style="margin: 0; font-size: 16px; color: #495057;">{}</p>
-    </div>
-    <p style="font-size: 14px; color: #6c757d; margin-top: 20px;">
-        This is a notification from MySocial.
-    </p>
-</body>
-</html>"#,
-            html_escape(subject),
-            html_escape(body)
-        );
+        let html_content = self.templates.render(subject, body, notification);
+
+        self.send_email(user_address, subject, html_content, body).await
+    }
+
+    /// Send an already-rendered email; `text` is the plain-text alternative
+    pub async fn send_email(&self, user_address: &str, subject: &str, html: String, text: &str) -> Result<SendOutcome> {
+        let (client, api_key, from_email) = match (&self.client, &self.api_key, &self.from_email) {
+            (Some(c), Some(k), Some(f)) => (c, k, f),
+            _ => {
+                tracing::debug!("Email not configured, skipping");
+                return Ok(SendOutcome::Skipped);
+            }
+        };
 
         // Build the Resend API request
         let email_request = ResendEmailRequest {
             from: from_email.clone(),
             to: vec![user_address.to_string()],
             subject: subject.to_string(),
-            html: html_content,
-            text: Some(body.to_string()),
+            html,
+            text: Some(text.to_string()),
         };
 
         // Send the email via Resend API
@@ -157,6 +245,76 @@ impl EmailDelivery {
             email_response.id
         );
 
-        Ok(())
+        Ok(SendOutcome::Sent { provider_id: Some(email_response.id) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> DeliveryConfig {
+        DeliveryConfig {
+            apns_bundle_id: None,
+            apns_key_id: None,
+            apns_team_id: None,
+            apns_key_path: None,
+            apns_key_content: None,
+            apns_environment: None,
+            fcm_server_key: None,
+            resend_api_key: None,
+            resend_from_email: None,
+            webhook_url: None,
+            webhook_secret: None,
+            email_logo_url: None,
+            email_brand_color: None,
+            email_footer: None,
+            email_template_path: None,
+            email_template: None,
+        }
+    }
+
+    fn notification() -> Value {
+        json!({"type": "tip.created", "title": "New tip", "body": "<b>alice</b> tipped you", "data": {"amount": 5}})
+    }
+
+    #[test]
+    fn test_platform_branded_template() {
+        let config = DeliveryConfig {
+            email_logo_url: Some("https://cdn.acme.example/logo.png".into()),
+            email_brand_color: Some("#ff5500".into()),
+            email_footer: Some("Sent by Acme".into()),
+            email_template: Some(
+                r#"<img src="{{logo_url}}"><h1 style="color: {{brand_color}}">{{title}}</h1><p>{{body}}</p><p>{{notification.data.amount}}</p><footer>{{footer}}</footer>"#.into(),
+            ),
+            ..config()
+        };
+        let html = EmailTemplates::new(&config).render("New tip", "<b>alice</b> tipped you", &notification());
+
+        assert!(html.contains(r#"<img src="https://cdn.acme.example/logo.png">"#));
+        assert!(html.contains("color: #ff5500"));
+        assert!(html.contains("<p>5</p>"));
+        assert!(html.contains("<footer>Sent by Acme</footer>"));
+        // User-supplied values are escaped
+        assert!(html.contains("&lt;b&gt;alice&lt;/b&gt; tipped you"));
+    }
+
+    #[test]
+    fn test_default_template_and_fallbacks() {
+        let html = EmailTemplates::new(&config()).render("New tip", "alice tipped you", &notification());
+        assert!(html.contains(DEFAULT_FOOTER));
+        assert!(!html.contains("<img"));
+
+        // A template that doesn't compile, and a brand color that isn't one, fall back to the defaults
+        let broken = DeliveryConfig {
+            email_template: Some("{{#if title}}unclosed".into()),
+            email_brand_color: Some("red; background: url(x)".into()),
+            email_logo_url: Some("https://cdn.acme.example/logo.png".into()),
+            ..config()
+        };
+        let html = EmailTemplates::new(&broken).render("New tip", "alice tipped you", &notification());
+        assert!(html.contains("alice tipped you"));
+        assert!(html.contains(DEFAULT_BRAND_COLOR));
+        assert!(html.contains("https://cdn.acme.example/logo.png"));
     }
 }
@@ -1,9 +1,42 @@
 use anyhow::{Result, anyhow};
 use fcm::Client;
 use relay_core::config::DeliveryConfig;
+use crate::outcome::SendOutcome;
+use crate::payload::{category, collapse_key, fcm_data};
+use serde::Serialize;
 use serde_json::Value;
+use std::collections::HashMap;
 use tracing;
 
+/// What is sent to FCM for one device: the alert, its grouping and action category, and string data
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FcmMessage {
+    pub token: String,
+    pub title: Option<String>,
+    pub body: String,
+    /// Pushes with the same key replace each other, like an APNs `thread-id` groups them
+    pub collapse_key: Option<String>,
+    /// The notification's category, for the app's intent filter
+    pub click_action: Option<String>,
+    /// App icon badge, the recipient's unread count when it was known
+    pub badge: Option<u32>,
+    pub data: HashMap<String, String>,
+}
+
+pub fn build_message(device_token: &str, notification: &Value) -> FcmMessage {
+    let str_field = |key: &str| notification.get(key).and_then(|v| v.as_str());
+
+    FcmMessage {
+        token: device_token.to_string(),
+        title: str_field("title").map(str::to_string),
+        body: str_field("body").unwrap_or("You have a new notification").to_string(),
+        collapse_key: collapse_key(notification),
+        click_action: category(notification),
+        badge: notification.get("badge").and_then(|v| v.as_u64()).map(|b| b as u32),
+        data: fcm_data(notification),
+    }
+}
+
 pub struct FcmDelivery {
     client: Option<Client>,
     server_key: Option<String>,
@@ -26,15 +59,39 @@ impl FcmDelivery {
         Ok(Self { client, server_key })
     }
 
-    pub async fn send(&self, device_token: &str, notification: &Value) -> Result<()> {
+    pub async fn send(&self, device_token: &str, notification: &Value) -> Result<SendOutcome> {
         if self.client.is_none() || self.server_key.is_none() {
             tracing::debug!("FCM not configured, skipping");
-            return Ok(());
+            return Ok(SendOutcome::Skipped);
         }
 
         // TODO: Implement actual FCM delivery
         // The fcm 0.9 crate API needs to be checked for the correct usage
-        tracing::debug!("Would send FCM notification to device: {}", device_token);
-        Ok(())
+        // Until then report the send as skipped so delivery records don't claim it went out
+        let message = build_message(device_token, notification);
+        tracing::debug!("Would send FCM notification {:?}", message);
+        Ok(SendOutcome::Skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_carries_collapse_key_and_category() {
+        let notification = serde_json::json!({
+            "id": 3,
+            "notification_type": "comment.created",
+            "title": "New comment",
+            "body": "alice commented on your post",
+            "data": {"post_id": "0xpost"},
+        });
+
+        let message = build_message("token", &notification);
+        assert_eq!(message.collapse_key.as_deref(), Some("0xpost"));
+        assert_eq!(message.click_action.as_deref(), Some("COMMENT_CREATED"));
+        assert_eq!(message.data["post_id"], "0xpost");
+        assert_eq!(message.title.as_deref(), Some("New comment"));
     }
 }
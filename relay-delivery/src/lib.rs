@@ -2,6 +2,13 @@ pub mod consumer;
 pub mod apns;
 pub mod fcm;
 pub mod email;
+pub mod webhook;
+pub mod clients;
+pub mod outcome;
+pub mod payload;
+pub mod dnd;
+pub mod preferences;
+pub mod test_push;
 
 pub use consumer::run;
 
@@ -0,0 +1,213 @@
+use anyhow::Result;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use relay_core::db::DbConnection;
+use relay_core::schema::{relay_messages, relay_notification_deliveries};
+use relay_core::types::DevicePlatform;
+use std::fmt;
+
+/// What a channel did with a single send
+#[derive(Debug, Clone, PartialEq)]
+pub enum SendOutcome {
+    /// Accepted by the provider, with the provider's id for the send when it returns one
+    Sent { provider_id: Option<String> },
+    /// The channel isn't configured, so nothing was sent
+    Skipped,
+}
+
+/// The provider rejected the device token itself (malformed, unregistered or for another app), so sending to
+/// it again won't work; callers can downcast to this to prune the token
+#[derive(Debug, thiserror::Error)]
+#[error("invalid device token ({provider}): {reason}")]
+pub struct InvalidDeviceToken {
+    pub provider: &'static str,
+    pub reason: String,
+}
+
+/// A delivery attempt to be written to `relay_notification_deliveries`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeliveryRecord {
+    pub channel: &'static str,
+    pub status: &'static str,
+    pub provider_id: Option<String>,
+    pub error: Option<String>,
+}
+
+impl DeliveryRecord {
+    pub fn from_result(channel: &'static str, result: Result<SendOutcome>) -> Self {
+        match result {
+            Ok(SendOutcome::Sent { provider_id }) => Self {
+                channel,
+                status: "sent",
+                provider_id,
+                error: None,
+            },
+            Ok(SendOutcome::Skipped) => Self::skipped(channel, "channel not configured"),
+            Err(e) => Self {
+                channel,
+                status: "failed",
+                provider_id: None,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+
+    pub fn skipped(channel: &'static str, reason: &str) -> Self {
+        Self {
+            channel,
+            status: "skipped",
+            provider_id: None,
+            error: Some(reason.to_string()),
+        }
+    }
+
+    pub fn is_sent(&self) -> bool {
+        self.status == "sent"
+    }
+}
+
+/// What happened to one of the user's device tokens in a delivery
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeliveryOutcome {
+    pub device_token: String,
+    pub platform: DevicePlatform,
+    pub record: DeliveryRecord,
+    /// The provider rejected the token itself, so it should be pruned
+    pub invalid_token: bool,
+}
+
+impl DeliveryOutcome {
+    pub fn new(device_token: &str, platform: DevicePlatform, channel: &'static str, result: Result<SendOutcome>) -> Self {
+        let invalid_token = result.as_ref().is_err_and(|e| e.downcast_ref::<InvalidDeviceToken>().is_some());
+        Self {
+            device_token: device_token.to_string(),
+            platform,
+            record: DeliveryRecord::from_result(channel, result),
+            invalid_token,
+        }
+    }
+
+    pub fn skipped(device_token: &str, platform: DevicePlatform, reason: &str) -> Self {
+        Self {
+            device_token: device_token.to_string(),
+            platform,
+            record: DeliveryRecord::skipped("push", reason),
+            invalid_token: false,
+        }
+    }
+}
+
+/// How a push to all of a user's devices went, e.g. `3/4 devices delivered, 1 invalid token pruned`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeliverySummary {
+    pub devices: usize,
+    pub delivered: usize,
+    /// Failures other than invalid tokens
+    pub failed: usize,
+    pub skipped: usize,
+    pub invalid_tokens: usize,
+}
+
+impl DeliverySummary {
+    pub fn from_outcomes(outcomes: &[DeliveryOutcome]) -> Self {
+        let mut summary = Self { devices: outcomes.len(), ..Default::default() };
+        for outcome in outcomes {
+            match outcome.record.status {
+                _ if outcome.invalid_token => summary.invalid_tokens += 1,
+                "sent" => summary.delivered += 1,
+                "skipped" => summary.skipped += 1,
+                _ => summary.failed += 1,
+            }
+        }
+        summary
+    }
+}
+
+impl fmt::Display for DeliverySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{} devices delivered", self.delivered, self.devices)?;
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+        if self.skipped > 0 {
+            write!(f, ", {} skipped", self.skipped)?;
+        }
+        match self.invalid_tokens {
+            0 => Ok(()),
+            1 => write!(f, ", 1 invalid token pruned"),
+            n => write!(f, ", {} invalid tokens pruned", n),
+        }
+    }
+}
+
+pub async fn record_deliveries(
+    conn: &mut DbConnection,
+    notification_id: Option<i64>,
+    user_address: &str,
+    records: &[DeliveryRecord],
+) -> Result<()> {
+    let rows: Vec<_> = records
+        .iter()
+        .map(|r| (
+            relay_notification_deliveries::notification_id.eq(notification_id),
+            relay_notification_deliveries::user_address.eq(user_address),
+            relay_notification_deliveries::channel.eq(r.channel),
+            relay_notification_deliveries::status.eq(r.status),
+            relay_notification_deliveries::provider_id.eq(r.provider_id.as_deref()),
+            relay_notification_deliveries::error.eq(r.error.as_deref()),
+        ))
+        .collect();
+
+    diesel::insert_into(relay_notification_deliveries::table)
+        .values(rows)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Stamp `delivered_at` on a message the first time any channel reaches the recipient
+pub async fn mark_message_delivered(conn: &mut DbConnection, message_id: i64) -> Result<()> {
+    diesel::update(
+        relay_messages::table
+            .filter(relay_messages::id.eq(message_id))
+            .filter(relay_messages::delivered_at.is_null()),
+    )
+    .set(relay_messages::delivered_at.eq(Utc::now()))
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_from_result() {
+        let sent = DeliveryRecord::from_result("apns", Ok(SendOutcome::Sent { provider_id: Some("abc".to_string()) }));
+        assert!(sent.is_sent());
+        assert_eq!(sent.provider_id.as_deref(), Some("abc"));
+
+        let skipped = DeliveryRecord::from_result("fcm", Ok(SendOutcome::Skipped));
+        assert_eq!(skipped.status, "skipped");
+        assert!(!skipped.is_sent());
+
+        let failed = DeliveryRecord::from_result("email", Err(anyhow::anyhow!("Resend API returned error status 500")));
+        assert_eq!(failed.status, "failed");
+        assert_eq!(failed.error.as_deref(), Some("Resend API returned error status 500"));
+    }
+
+    #[test]
+    fn test_invalid_token_outcome() {
+        let rejected = InvalidDeviceToken { provider: "apns", reason: "BadDeviceToken".to_string() };
+        let outcome = DeliveryOutcome::new("ios-bad", DevicePlatform::Ios, "apns", Err(rejected.into()));
+        assert!(outcome.invalid_token);
+        assert_eq!(outcome.record.status, "failed");
+
+        let outage = DeliveryOutcome::new("ios", DevicePlatform::Ios, "apns", Err(anyhow::anyhow!("connection reset")));
+        assert!(!outage.invalid_token);
+    }
+}
@@ -0,0 +1,148 @@
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Keys apps use to deep-link from a push into the right screen
+const THREAD_KEYS: &[&str] = &["conversation_id", "post_id"];
+
+/// App-specific keys carried alongside the alert: the notification's `data` object plus its id, type
+/// and the quick actions (`reply`, `mark_read`, ...) the app shows under the type's category
+pub fn custom_data(notification: &Value) -> Map<String, Value> {
+    let mut data = notification
+        .get("data")
+        .and_then(|d| d.as_object())
+        .cloned()
+        .unwrap_or_default();
+
+    for (key, field) in [("notification_id", "id"), ("notification_type", "notification_type")] {
+        if let Some(value) = notification.get(field).filter(|v| !v.is_null()) {
+            data.entry(key).or_insert_with(|| value.clone());
+        }
+    }
+
+    if let Some(actions) = notification.get("actions").filter(|a| a.as_array().is_some_and(|a| !a.is_empty())) {
+        data.entry("actions").or_insert_with(|| actions.clone());
+    }
+
+    data
+}
+
+/// Groups pushes about the same conversation or post, falling back to the notification type
+pub fn thread_id(notification: &Value) -> Option<String> {
+    let data = notification.get("data");
+
+    THREAD_KEYS
+        .iter()
+        .find_map(|key| data.and_then(|d| d.get(*key)).and_then(value_to_string))
+        .or_else(|| notification.get("notification_type").and_then(value_to_string))
+}
+
+/// FCM `collapse_key`, grouping the same pushes as the APNs `thread-id`
+pub fn collapse_key(notification: &Value) -> Option<String> {
+    thread_id(notification)
+}
+
+/// Category clients register actions for: an explicit `category`, otherwise derived from the
+/// notification type, e.g. `comment.created` -> `COMMENT_CREATED`
+pub fn category(notification: &Value) -> Option<String> {
+    if let Some(category) = notification.get("category").and_then(value_to_string) {
+        return Some(category);
+    }
+
+    let notification_type = notification.get("notification_type")?.as_str()?.trim();
+    (!notification_type.is_empty()).then(|| {
+        notification_type
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect()
+    })
+}
+
+/// FCM data payloads only accept string values, so nested values are sent as JSON
+pub fn fcm_data(notification: &Value) -> HashMap<String, String> {
+    custom_data(notification)
+        .into_iter()
+        .filter_map(|(key, value)| match value {
+            Value::Null => None,
+            Value::String(s) => Some((key, s)),
+            other => Some((key, other.to_string())),
+        })
+        .collect()
+}
+
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) if !s.is_empty() => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification() -> Value {
+        serde_json::json!({
+            "id": 42,
+            "notification_type": "comment.created",
+            "title": "New comment",
+            "body": "alice commented on your post",
+            "data": {"post_id": "0xpost", "comment_id": "0xcomment", "platform_id": null},
+        })
+    }
+
+    #[test]
+    fn test_custom_data_keeps_deep_link_fields() {
+        let data = custom_data(&notification());
+        assert_eq!(data["post_id"], "0xpost");
+        assert_eq!(data["notification_id"], 42);
+        assert_eq!(data["notification_type"], "comment.created");
+    }
+
+    #[test]
+    fn test_thread_id_prefers_conversation_then_post() {
+        assert_eq!(thread_id(&notification()).as_deref(), Some("0xpost"));
+
+        let message = serde_json::json!({"notification_type": "message.created", "data": {"conversation_id": "c1", "post_id": "p1"}});
+        assert_eq!(thread_id(&message).as_deref(), Some("c1"));
+
+        let follow = serde_json::json!({"notification_type": "follow.created", "data": {}});
+        assert_eq!(thread_id(&follow).as_deref(), Some("follow.created"));
+    }
+
+    #[test]
+    fn test_same_post_shares_collapse_key() {
+        let comment = notification();
+        let reaction = serde_json::json!({"notification_type": "reaction.created", "data": {"post_id": "0xpost"}});
+        let other_post = serde_json::json!({"notification_type": "comment.created", "data": {"post_id": "0xother"}});
+
+        assert_eq!(collapse_key(&comment), collapse_key(&reaction));
+        assert_ne!(collapse_key(&comment), collapse_key(&other_post));
+
+        assert_eq!(category(&comment).as_deref(), Some("COMMENT_CREATED"));
+        assert_eq!(category(&serde_json::json!({"notification_type": "tip.created", "category": "TIP"})).as_deref(), Some("TIP"));
+        assert_eq!(category(&serde_json::json!({"data": {}})), None);
+    }
+
+    #[test]
+    fn test_message_push_carries_reply_action() {
+        let message = serde_json::json!({
+            "notification_type": "message.created",
+            "data": {"conversation_id": "c1"},
+            "actions": ["reply", "mark_read"],
+        });
+
+        assert_eq!(custom_data(&message)["actions"], serde_json::json!(["reply", "mark_read"]));
+        assert_eq!(category(&message).as_deref(), Some("MESSAGE_CREATED"));
+        assert_eq!(fcm_data(&message)["actions"], r#"["reply","mark_read"]"#);
+        assert!(!custom_data(&notification()).contains_key("actions"));
+    }
+
+    #[test]
+    fn test_fcm_data_is_stringly_typed() {
+        let data = fcm_data(&notification());
+        assert_eq!(data["post_id"], "0xpost");
+        assert_eq!(data["notification_id"], "42");
+        assert!(!data.contains_key("platform_id"));
+    }
+}
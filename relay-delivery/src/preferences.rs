@@ -0,0 +1,43 @@
+use anyhow::Result;
+use chrono::NaiveTime;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use relay_core::db::DbConnection;
+use relay_core::email_digest::EmailDigest;
+use relay_core::quiet_hours::QuietHours;
+use relay_core::schema::relay_user_preferences;
+
+/// (dnd_start, dnd_end, timezone, dnd_digest_enabled, email_digest)
+type PreferencesRow = (Option<NaiveTime>, Option<NaiveTime>, Option<String>, bool, String);
+
+/// The parts of a user's preferences that change how a notification is delivered
+#[derive(Debug, Default)]
+pub struct DeliveryPreferences {
+    pub quiet_hours: Option<QuietHours>,
+    pub dnd_digest_enabled: bool,
+    pub email_digest: EmailDigest,
+}
+
+pub async fn load(conn: &mut DbConnection, user_address: &str) -> Result<DeliveryPreferences> {
+    let prefs: Option<PreferencesRow> = relay_user_preferences::table
+        .filter(relay_user_preferences::user_address.eq(user_address))
+        .select((
+            relay_user_preferences::dnd_start,
+            relay_user_preferences::dnd_end,
+            relay_user_preferences::timezone,
+            relay_user_preferences::dnd_digest_enabled,
+            relay_user_preferences::email_digest,
+        ))
+        .first(conn)
+        .await
+        .optional()?;
+
+    Ok(match prefs {
+        Some((start, end, timezone, dnd_digest_enabled, email_digest)) => DeliveryPreferences {
+            quiet_hours: QuietHours::from_preferences(start, end, timezone.as_deref()),
+            dnd_digest_enabled,
+            email_digest: EmailDigest::parse(&email_digest).unwrap_or_default(),
+        },
+        None => DeliveryPreferences::default(),
+    })
+}
@@ -0,0 +1,118 @@
+use anyhow::Result;
+use chrono::Utc;
+use futures::future::BoxFuture;
+use relay_core::config::DeliveryConfig;
+use relay_core::types::DevicePlatform;
+use relay_core::{get_platform_delivery_config, RelayContext};
+use serde::Serialize;
+use serde_json::Value;
+use crate::clients::DeliveryClients;
+use crate::consumer::{load_device_tokens, send_to_device, send_to_devices};
+use crate::outcome::DeliveryOutcome;
+
+/// The canned push sent when a user asks to check their devices; it isn't stored or counted as unread
+pub fn test_notification() -> Value {
+    serde_json::json!({
+        "notification_type": "test",
+        "title": "Test notification",
+        "body": "Push notifications are working on this device",
+        "data": {"test": true},
+        "created_at": Utc::now(),
+    })
+}
+
+/// What happened to the test push for one device token
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TestPushResult {
+    pub device_token: String,
+    pub platform: DevicePlatform,
+    /// `sent`, `skipped` or `failed`, as in `relay_notification_deliveries`
+    pub status: &'static str,
+    pub provider_id: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Send to every token concurrently with `send`, which returns None for platforms it can't push to
+pub async fn send_to_tokens<'a, F>(tokens: &'a [(String, DevicePlatform)], send: F) -> Vec<TestPushResult>
+where
+    F: Fn(&'a str, DevicePlatform) -> Option<BoxFuture<'a, DeliveryOutcome>>,
+{
+    send_to_devices(tokens, send)
+        .await
+        .into_iter()
+        .map(|outcome| TestPushResult {
+            device_token: outcome.device_token,
+            platform: outcome.platform,
+            status: outcome.record.status,
+            provider_id: outcome.record.provider_id,
+            error: outcome.record.error,
+        })
+        .collect()
+}
+
+/// Push `test_notification` to each of the user's registered devices through the same clients and
+/// routing as real deliveries, using `platform_id`'s delivery config when it has one
+pub async fn send_test_notification(
+    ctx: &RelayContext,
+    user_address: &str,
+    platform_id: Option<&str>,
+) -> Result<Vec<TestPushResult>> {
+    let mut conn = ctx.db_pool.get().await?;
+    let tokens = load_device_tokens(&mut conn, user_address).await;
+    if tokens.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let platform_config = match platform_id {
+        Some(pid) => get_platform_delivery_config(&mut conn, pid).await?,
+        None => None,
+    };
+    drop(conn);
+    let config = platform_config
+        .as_ref()
+        .map(DeliveryConfig::from)
+        .unwrap_or_else(|| ctx.config.delivery.clone());
+    let clients = DeliveryClients::new(&config)?;
+
+    let notification = test_notification();
+    let results = send_to_tokens(&tokens, |token, platform| send_to_device(&clients, token, platform, &notification)).await;
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outcome::SendOutcome;
+
+    #[tokio::test]
+    async fn test_results_per_token() {
+        let tokens = vec![
+            ("ios-ok".to_string(), DevicePlatform::Ios),
+            ("ios-bad".to_string(), DevicePlatform::Ios),
+            ("android".to_string(), DevicePlatform::Android),
+            ("browser".to_string(), DevicePlatform::Web),
+        ];
+
+        // Stands in for the provider clients: one accepted push, one rejected token, one unconfigured provider
+        let results = send_to_tokens(&tokens, |token, platform| {
+            let result = match (token, platform) {
+                (_, DevicePlatform::Web) => return None,
+                ("ios-ok", _) => Ok(SendOutcome::Sent { provider_id: Some("apns-1".to_string()) }),
+                ("ios-bad", _) => Err(anyhow::anyhow!("invalid device token (apns): BadDeviceToken")),
+                _ => Ok(SendOutcome::Skipped),
+            };
+            let channel = if platform == DevicePlatform::Ios { "apns" } else { "fcm" };
+            Some(Box::pin(async move { DeliveryOutcome::new(token, platform, channel, result) }) as BoxFuture<'_, _>)
+        })
+        .await;
+
+        let statuses: Vec<_> = results.iter().map(|r| (r.device_token.as_str(), r.status)).collect();
+        assert_eq!(
+            statuses,
+            [("ios-ok", "sent"), ("ios-bad", "failed"), ("android", "skipped"), ("browser", "skipped")]
+        );
+        assert_eq!(results[0].provider_id.as_deref(), Some("apns-1"));
+        assert_eq!(results[1].error.as_deref(), Some("invalid device token (apns): BadDeviceToken"));
+        assert_eq!(results[3].error.as_deref(), Some("no push provider for this platform"));
+    }
+}
@@ -0,0 +1,217 @@
+use anyhow::{Result, anyhow};
+use hmac::{Hmac, Mac};
+use relay_core::config::DeliveryConfig;
+use crate::outcome::SendOutcome;
+use serde_json::Value;
+use sha2::Sha256;
+use std::time::Duration;
+use tracing;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SIGNATURE_HEADER: &str = "X-Relay-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Relay-Timestamp";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_ATTEMPTS: u32 = 3;
+/// First retry waits this long; each further one doubles it
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// `sha256=` + hex HMAC-SHA256 of `{timestamp}.{body}`; the timestamp lets receivers reject replays
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// POSTs notifications to a platform's own endpoint
+pub struct WebhookDelivery {
+    client: Option<reqwest::Client>,
+    url: String,
+    secret: String,
+}
+
+impl WebhookDelivery {
+    pub fn new(config: &DeliveryConfig) -> Result<Self> {
+        let (client, url, secret) = match (&config.webhook_url, &config.webhook_secret) {
+            (Some(url), Some(secret)) => {
+                let client = reqwest::Client::builder()
+                    .timeout(REQUEST_TIMEOUT)
+                    .build()
+                    .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?;
+                tracing::info!("Webhook delivery enabled for {}", url);
+                (Some(client), url.clone(), secret.clone())
+            }
+            _ => (None, String::new(), String::new()),
+        };
+
+        Ok(Self { client, url, secret })
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Returns the endpoint's HTTP status as the provider id; timeouts, 429s and 5xx responses are retried
+    pub async fn send(&self, user_address: &str, notification: &Value) -> Result<SendOutcome> {
+        let client = match &self.client {
+            Some(c) => c,
+            None => {
+                tracing::debug!("Webhook not configured, skipping");
+                return Ok(SendOutcome::Skipped);
+            }
+        };
+
+        let body = serde_json::to_vec(&serde_json::json!({
+            "user_address": user_address,
+            "notification": notification,
+        }))?;
+
+        let mut attempt = 1;
+        loop {
+            let timestamp = chrono::Utc::now().timestamp();
+            let result = client
+                .post(&self.url)
+                .header("Content-Type", "application/json")
+                .header(TIMESTAMP_HEADER, timestamp.to_string())
+                .header(SIGNATURE_HEADER, signature(&self.secret, timestamp, &body))
+                .body(body.clone())
+                .send()
+                .await;
+
+            let error = match result {
+                Ok(response) if response.status().is_success() => {
+                    let status = response.status().as_u16();
+                    tracing::debug!("Webhook delivered to {} for {} (HTTP {})", self.url, user_address, status);
+                    return Ok(SendOutcome::Sent { provider_id: Some(status.to_string()) });
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let retryable = status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+                    let error = anyhow!("Webhook returned HTTP {}", status.as_u16());
+                    if !retryable {
+                        return Err(error);
+                    }
+                    error
+                }
+                Err(e) => anyhow!("Webhook request failed: {}", e),
+            };
+
+            if attempt >= MAX_ATTEMPTS {
+                return Err(error);
+            }
+            tracing::warn!("Webhook attempt {}/{} to {} failed, retrying: {}", attempt, MAX_ATTEMPTS, self.url, error);
+            tokio::time::sleep(RETRY_BASE_DELAY * 2u32.pow(attempt - 1)).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers each connection with the next status in `statuses`, returning the raw requests it received
+    async fn mock_server(statuses: Vec<u16>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/relay", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for status in statuses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                // Read until the full body named by Content-Length has arrived
+                loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().to_string()))
+                            .and_then(|v| v.parse::<usize>().ok())
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            break;
+                        }
+                    }
+                    if n == 0 {
+                        break;
+                    }
+                }
+                let response = format!("HTTP/1.1 {} Mock\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+                socket.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+
+        (url, server)
+    }
+
+    fn header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+        request
+            .lines()
+            .find_map(|l| l.split_once(':').filter(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.trim()))
+    }
+
+    fn delivery(url: &str) -> WebhookDelivery {
+        let config = DeliveryConfig {
+            apns_bundle_id: None,
+            apns_key_id: None,
+            apns_team_id: None,
+            apns_key_path: None,
+            apns_key_content: None,
+            apns_environment: None,
+            fcm_server_key: None,
+            resend_api_key: None,
+            resend_from_email: None,
+            webhook_url: Some(url.to_string()),
+            webhook_secret: Some("whsec_test".to_string()),
+            email_logo_url: None,
+            email_brand_color: None,
+            email_footer: None,
+            email_template_path: None,
+            email_template: None,
+        };
+        WebhookDelivery::new(&config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_webhook_is_signed() {
+        let (url, server) = mock_server(vec![200]).await;
+        let notification = serde_json::json!({"id": 7, "title": "New follower"});
+
+        let outcome = delivery(&url).send("0xabc", &notification).await.unwrap();
+        let requests = server.await.unwrap();
+
+        assert_eq!(outcome, SendOutcome::Sent { provider_id: Some("200".to_string()) });
+        let request = &requests[0];
+        let body = request.split_once("\r\n\r\n").unwrap().1;
+        let timestamp: i64 = header(request, TIMESTAMP_HEADER).unwrap().parse().unwrap();
+
+        assert_eq!(header(request, SIGNATURE_HEADER), Some(signature("whsec_test", timestamp, body.as_bytes()).as_str()));
+        assert_ne!(header(request, SIGNATURE_HEADER), Some(signature("other", timestamp, body.as_bytes()).as_str()));
+        let payload: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(payload["user_address"], "0xabc");
+        assert_eq!(payload["notification"], notification);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_retries_server_errors_only() {
+        let (url, server) = mock_server(vec![503, 200]).await;
+        assert!(delivery(&url).send("0xabc", &serde_json::json!({})).await.is_ok());
+        assert_eq!(server.await.unwrap().len(), 2);
+
+        let (url, server) = mock_server(vec![400]).await;
+        let err = delivery(&url).send("0xabc", &serde_json::json!({})).await.unwrap_err();
+        assert!(err.to_string().contains("HTTP 400"));
+        assert_eq!(server.await.unwrap().len(), 1);
+    }
+}
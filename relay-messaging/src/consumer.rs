@@ -1,51 +1,93 @@
 use anyhow::{Result, anyhow};
 use rdkafka::consumer::Consumer;
+use rdkafka::message::OwnedMessage;
 use rdkafka::Message;
-use relay_core::{RelayContext, redpanda::create_consumer};
+use relay_core::backoff::Backoff;
+use relay_core::consumer_pool::WorkerPool;
+use relay_core::dead_letter::{handle_or_dead_letter, SourceMessage};
+use relay_core::{RelayContext, consumer_lag, key_rotation, message_retention, processed_events, redpanda::create_consumer, types::RelayEvent};
 use crate::service::MessagingService;
-use std::time::Duration;
+use std::sync::Arc;
 use tracing;
 
-const TOPIC: &str = "events.message.created";
+const CONSUMER: &str = "relay-messaging";
 
 pub async fn run(ctx: RelayContext) -> Result<()> {
     tracing::info!("Starting messaging consumer");
 
-    let consumer = create_consumer(&ctx.config.redpanda, Some("relay-messaging"))?;
-    let service = MessagingService::new(ctx.clone());
+    let consumer = create_consumer(&ctx.config.redpanda, Some(CONSUMER))?;
+    let service = Arc::new(MessagingService::new(ctx.clone()));
 
-    consumer.subscribe(&[TOPIC])?;
+    let topic = ctx.config.redpanda.topics().message_events();
+    consumer.subscribe(&[&topic])?;
 
-    tracing::info!("Subscribed to topic: {}", TOPIC);
+    tracing::info!("Subscribed to topic: {}", topic);
 
-    let mut error_count = 0u32;
+    // Lag per partition, for /metrics and /health/ready
+    tokio::spawn(consumer_lag::report(ctx.clone(), consumer.clone(), CONSUMER));
+
+    // Delete messages past MESSAGE_RETENTION_DAYS
+    tokio::spawn(message_retention::run(ctx.clone()));
+    tokio::spawn(key_rotation::run(ctx.clone()));
+
+    // Handle messages on concurrent workers; offsets are only stored below the oldest one still in flight
+    let store_consumer = consumer.clone();
+    let pool = WorkerPool::new(
+        ctx.config.redpanda.consumer_concurrency,
+        {
+            let ctx = ctx.clone();
+            move |message: OwnedMessage| {
+                let ctx = ctx.clone();
+                let service = service.clone();
+                async move {
+                    if let Some(payload) = message.payload() {
+                        let source = SourceMessage::from_message(&message);
+                        let handled = handle_or_dead_letter(&ctx.redpanda_producer, &ctx.config.redpanda, source, || {
+                            handle_message(&ctx, &service, payload)
+                        })
+                        .await;
+                        match handled {
+                            Ok(_) => {
+                                tracing::debug!("Processed message event");
+                            }
+                            Err(e) => {
+                                tracing::error!("Error processing message event: {}", e);
+                            }
+                        }
+                    }
+                }
+            }
+        },
+        move |topic: &str, partition: i32, offset: i64| {
+            if let Err(e) = store_consumer.store_offset(topic, partition, offset) {
+                tracing::warn!("Failed to store offset for message event: {}", e);
+            }
+        },
+    );
+
+    let mut backoff = Backoff::for_consumer();
     let mut last_error_log = std::time::Instant::now();
     
     loop {
         match consumer.recv().await {
             Ok(message) => {
-                error_count = 0; // Reset error count on success
-                if let Some(payload) = message.payload() {
-                    match handle_message(&service, payload).await {
-                        Ok(_) => {
-                            tracing::debug!("Processed message event");
-                        }
-                        Err(e) => {
-                            tracing::error!("Error processing message event: {}", e);
-                        }
-                    }
+                if backoff.failures() > 0 {
+                    backoff.reset();
+                    ctx.consumer_errors.record(CONSUMER, 0);
                 }
+                pool.dispatch(message.detach()).await;
             }
             Err(e) => {
-                error_count += 1;
+                let delay = backoff.fail();
+                ctx.consumer_errors.record(CONSUMER, backoff.failures());
                 // Only log errors every 30 seconds to reduce log spam
                 if last_error_log.elapsed().as_secs() >= 30 {
                     tracing::warn!(
                         "Error receiving message from Redpanda (error count: {}): {}",
-                        error_count,
+                        backoff.failures(),
                         e
                     );
-                    if error_count == 1 {
+                    if backoff.failures() == 1 {
                         tracing::warn!("Troubleshooting Redpanda connection:");
                         tracing::warn!("  1. Verify REDPANDA_BROKERS is correct and brokers are accessible");
                         tracing::warn!("  2. Check if SSL/TLS is required (set REDPANDA_SSL_ENABLED=true)");
@@ -54,22 +96,25 @@ pub async fn run(ctx: RelayContext) -> Result<()> {
                     }
                     last_error_log = std::time::Instant::now();
                 }
-                // Exponential backoff: 1s, 2s, 4s, max 30s
-                let backoff = Duration::from_secs(1 << error_count.min(5)).min(Duration::from_secs(30));
-                tokio::time::sleep(backoff).await;
+                // Exponential backoff with jitter: up to 1s, 2s, 4s, max 30s
+                tokio::time::sleep(delay).await;
             }
         }
     }
 }
 
-async fn handle_message(service: &MessagingService, payload: &[u8]) -> Result<()> {
-    let event: serde_json::Value = serde_json::from_slice(payload)?;
-    
-    let event_data = event.get("event_data")
-        .ok_or_else(|| anyhow::anyhow!("Missing event_data"))?;
-
-    service.process_message(event_data).await?;
+async fn handle_message(ctx: &RelayContext, service: &MessagingService, payload: &[u8]) -> Result<()> {
+    let event = match RelayEvent::decode(payload)? {
+        Some(event) => event,
+        None => return Ok(()),
+    };
 
-    Ok(())
+    processed_events::process_once(
+        &ctx.redis_pool,
+        ctx.config.redis.keys(),
+        CONSUMER,
+        event.event_id(),
+        service.process_message(&event.event_data),
+    )
+    .await
 }
-
@@ -3,7 +3,15 @@ use chrono::Utc;
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
 use relay_core::schema::{relay_messages, relay_conversations};
-use relay_core::{RelayContext, redis::get_connection, encrypt_message};
+use relay_core::blocks;
+use relay_core::conversation_archives;
+use relay_core::conversation_mutes;
+use relay_core::message_references;
+use relay_core::moderation::Verdict;
+use relay_core::notification_actions;
+use relay_core::conversation_previews::{self, ConversationPreview};
+use relay_core::types::MessageContentType;
+use relay_core::{RelayContext, redis::{chat_cache, get_connection, streams}, encrypt_message};
 use serde_json::Value;
 use tracing;
 use base64::{engine::general_purpose::STANDARD, Engine};
@@ -30,30 +38,96 @@ impl MessagingService {
             .and_then(|v| v.as_str())
             .ok_or_else(|| anyhow::anyhow!("Missing content"))?;
 
+        // Events from before content types existed are text
+        let content_type: MessageContentType = match event_data.get("content_type").and_then(|v| v.as_str()) {
+            Some(t) => t.parse()?,
+            None => MessageContentType::Text,
+        };
+        let media_urls = event_data.get("media_urls").filter(|v| !v.is_null()).cloned();
+        let metadata = event_data.get("metadata").filter(|v| !v.is_null()).cloned();
+        // Client-encrypted content is base64 ciphertext, stored as-is and never decrypted here
+        let e2ee = event_data.get("e2ee").and_then(|v| v.as_bool()).unwrap_or(self.ctx.config.server.e2ee_mode);
+        let key_exchange = event_data.get("key_exchange").and_then(|v| v.as_str());
+
+        // The API stores its messages, after checking blocks, moderation and references, and sends their id
+        // along; those only need delivering, since storing them again would leave a second copy
+        if let Some(message_id) = event_data.get("message_id").and_then(|v| v.as_i64()) {
+            let body = serde_json::json!({
+                "content": content,
+                "content_type": content_type,
+                "media_urls": media_urls,
+                "metadata": metadata,
+                "e2ee": e2ee,
+                "key_exchange": key_exchange,
+                "reply_to_message_id": event_data.get("reply_to_message_id").and_then(|v| v.as_i64()),
+                "forwarded_from_message_id": event_data.get("forwarded_from_message_id").and_then(|v| v.as_i64()),
+            });
+            let conversation_id = conversation_id_for(sender, recipient);
+            return self.deliver(sender, recipient, &conversation_id, message_id, content, &body).await;
+        }
+
+        // Blocked senders' messages are dropped without storing or delivering anything
+        if self.is_blocked(recipient, sender).await? {
+            tracing::debug!("Dropping message from {} to {}: sender is blocked", sender, recipient);
+            return Ok(());
+        }
+
+        // Events from the API carry its verdict; others are checked here. Ciphertext can't be classified
+        let flagged = match event_data.get("flagged").and_then(|v| v.as_bool()) {
+            Some(flagged) => flagged,
+            None if content_type == MessageContentType::Text && !e2ee => {
+                match self.ctx.moderator.check(sender, recipient, content).await {
+                    Verdict::Block => {
+                        tracing::info!("Dropping message from {} to {}: rejected by moderation", sender, recipient);
+                        return Ok(());
+                    }
+                    verdict => verdict == Verdict::Flag,
+                }
+            }
+            None => false,
+        };
+
         let conversation_id = self.get_or_create_conversation(sender, recipient).await?;
+        let (reply_to, forwarded_from) = self.valid_references(&conversation_id, sender, event_data).await?;
+
+        // Encrypt text before storing; other types carry no content
+        let encrypted_bytes = if content_type == MessageContentType::Text && e2ee {
+            STANDARD.decode(content.trim())
+                .map_err(|e| anyhow!("Invalid end-to-end encrypted content: {}", e))?
+        } else if content_type == MessageContentType::Text {
+            let encrypted_content = encrypt_message(
+                content,
+                &conversation_id,
+                self.ctx.config.server.encryption_keys(),
+                self.ctx.config.server.encryption_algorithm,
+            )?;
 
-        // Encrypt message content before storing
-        let encrypted_content = encrypt_message(
-            content,
-            &conversation_id,
-            &self.ctx.config.server.encryption_key,
-        )?;
-        
-        // Convert encrypted string to bytes for BYTEA storage
-        let encrypted_bytes = STANDARD.decode(&encrypted_content)
-            .map_err(|e| anyhow!("Failed to decode encrypted content: {}", e))?;
+            // Convert encrypted string to bytes for BYTEA storage
+            STANDARD.decode(&encrypted_content)
+                .map_err(|e| anyhow!("Failed to decode encrypted content: {}", e))?
+        } else {
+            Vec::new()
+        };
 
         // Store encrypted message in Postgres
         let mut conn = self.ctx.db_pool.get().await?;
-        diesel::insert_into(relay_messages::table)
+        let message_id: i64 = diesel::insert_into(relay_messages::table)
             .values((
                 relay_messages::conversation_id.eq(&conversation_id),
                 relay_messages::sender_address.eq(sender),
                 relay_messages::recipient_address.eq(recipient),
                 relay_messages::content.eq(encrypted_bytes),
-                relay_messages::content_type.eq("text"),
+                relay_messages::content_type.eq(content_type.as_str()),
+                relay_messages::media_urls.eq(&media_urls),
+                relay_messages::metadata.eq(&metadata),
+                relay_messages::e2ee.eq(e2ee),
+                relay_messages::key_exchange.eq(key_exchange),
+                relay_messages::flagged.eq(flagged),
+                relay_messages::reply_to_message_id.eq(reply_to),
+                relay_messages::forwarded_from_message_id.eq(forwarded_from),
             ))
-            .execute(&mut conn)
+            .returning(relay_messages::id)
+            .get_result(&mut conn)
             .await?;
 
         // Update conversation
@@ -61,24 +135,120 @@ impl MessagingService {
             .set(relay_conversations::last_message_at.eq(Utc::now()))
             .execute(&mut conn)
             .await?;
+        // A new message brings an archived conversation back to the recipient's inbox
+        conversation_archives::unarchive_conversation(&mut conn, recipient, &conversation_id).await?;
 
-        // Cache in Redis
-        self.cache_message(&conversation_id, sender, recipient, content).await?;
+        let preview = ConversationPreview::new(message_id, sender, content_type.as_str(), Some(content), Utc::now(), e2ee);
+        if let Err(e) = self.cache_preview(&conversation_id, &preview).await {
+            tracing::warn!("Failed to cache preview for conversation {}: {}", conversation_id, e);
+        }
 
-        // Emit WebSocket event
-        self.emit_ws_event(recipient, &conversation_id, content).await?;
+        let body = serde_json::json!({
+            "content": content,
+            "content_type": content_type,
+            "media_urls": media_urls,
+            "metadata": metadata,
+            "e2ee": e2ee,
+            "key_exchange": key_exchange,
+            "reply_to_message_id": reply_to,
+            "forwarded_from_message_id": forwarded_from,
+        });
+        self.deliver(sender, recipient, &conversation_id, message_id, content, &body).await
+    }
+
+    /// Cache a stored message, stream it to the recipient's sockets and queue its push
+    async fn deliver(
+        &self,
+        sender: &str,
+        recipient: &str,
+        conversation_id: &str,
+        message_id: i64,
+        content: &str,
+        body: &Value,
+    ) -> Result<()> {
+        let mut conn = get_connection(&self.ctx.redis_pool).await?;
+        chat_cache::push_message(&mut conn, self.ctx.config.redis.keys(), conversation_id, message_id, sender, recipient, content).await?;
+
+        self.emit_ws_event(recipient, conversation_id, message_id, body).await?;
+
+        // Push and email, unless the recipient muted the conversation; the message is stored and streamed either way
+        match self.delivery_job(recipient, conversation_id, message_id, sender).await? {
+            Some(job) => {
+                if let Err(e) = self.emit_delivery_job(recipient, &job).await {
+                    tracing::warn!("Failed to emit delivery job for message {}: {}", message_id, e);
+                }
+            }
+            None => tracing::debug!("Conversation {} is muted for {}, skipping push", conversation_id, recipient),
+        }
 
         Ok(())
     }
 
+    /// The delivery job for a new message, or None when the recipient muted the conversation
+    async fn delivery_job(
+        &self,
+        recipient: &str,
+        conversation_id: &str,
+        message_id: i64,
+        sender: &str,
+    ) -> Result<Option<Value>> {
+        let mut conn = self.ctx.db_pool.get().await?;
+        if conversation_mutes::is_muted(&mut conn, recipient, conversation_id).await? {
+            return Ok(None);
+        }
+
+        Ok(Some(serde_json::json!({
+            "user_address": recipient,
+            "notification": message_notification(conversation_id, message_id, sender),
+        })))
+    }
+
+    async fn emit_delivery_job(&self, recipient: &str, job: &Value) -> Result<()> {
+        relay_core::redpanda::produce_message(
+            &self.ctx.redpanda_producer,
+            &self.ctx.config.redpanda.topics().delivery(),
+            Some(recipient),
+            &serde_json::to_vec(job)?,
+        )
+        .await
+    }
+
+    /// The event's `reply_to_message_id` and `forwarded_from_message_id`, each kept only if the sender may
+    /// reference that message; the API has already checked its own events, so a reference that fails here
+    /// points at a message deleted since and the message is stored without it
+    async fn valid_references(&self, conversation_id: &str, sender: &str, event_data: &Value) -> Result<(Option<i64>, Option<i64>)> {
+        let mut conn = self.ctx.db_pool.get().await?;
+
+        let mut reply_to = event_data.get("reply_to_message_id").and_then(|v| v.as_i64());
+        if let Some(id) = reply_to {
+            if !message_references::can_reply_to(&mut conn, conversation_id, id).await? {
+                tracing::debug!("Dropping reply_to_message_id {} from {}: not in {}", id, sender, conversation_id);
+                reply_to = None;
+            }
+        }
+        let mut forwarded_from = event_data.get("forwarded_from_message_id").and_then(|v| v.as_i64());
+        if let Some(id) = forwarded_from {
+            if !message_references::can_forward(&mut conn, sender, id).await? {
+                tracing::debug!("Dropping forwarded_from_message_id {} from {}: not theirs to forward", id, sender);
+                forwarded_from = None;
+            }
+        }
+
+        Ok((reply_to, forwarded_from))
+    }
+
+    async fn is_blocked(&self, blocker: &str, blocked: &str) -> Result<bool> {
+        let mut conn = self.ctx.db_pool.get().await?;
+        blocks::is_blocked(&mut conn, blocker, blocked).await
+    }
+
     async fn get_or_create_conversation(&self, user1: &str, user2: &str) -> Result<String> {
-        // Create deterministic conversation ID
         let (p1, p2) = if user1 < user2 {
             (user1, user2)
         } else {
             (user2, user1)
         };
-        let conversation_id = format!("{}:{}", p1, p2);
+        let conversation_id = conversation_id_for(user1, user2);
 
         let mut conn = self.ctx.db_pool.get().await?;
 
@@ -96,6 +266,7 @@ impl MessagingService {
                     relay_conversations::conversation_id.eq(&conversation_id),
                     relay_conversations::participant1_address.eq(p1),
                     relay_conversations::participant2_address.eq(p2),
+                    relay_conversations::conversation_key_version.eq(self.ctx.config.server.encryption_key_version),
                 ))
                 .execute(&mut conn)
                 .await?;
@@ -104,60 +275,182 @@ impl MessagingService {
         Ok(conversation_id)
     }
 
-    async fn cache_message(
-        &self,
-        conversation_id: &str,
-        sender: &str,
-        recipient: &str,
-        content: &str,
-    ) -> Result<()> {
+    async fn cache_preview(&self, conversation_id: &str, preview: &ConversationPreview) -> Result<()> {
         let mut conn = get_connection(&self.ctx.redis_pool).await?;
-        let key = format!("CHAT:{}", conversation_id);
+        conversation_previews::store(&mut conn, self.ctx.config.redis.keys(), conversation_id, preview).await
+    }
 
-        let message = serde_json::json!({
-            "sender": sender,
-            "recipient": recipient,
-            "content": content,
-            "created_at": Utc::now(),
+    /// `body` holds the message's `content`, `content_type`, `media_urls`, `metadata` and references
+    async fn emit_ws_event(&self, user_address: &str, conversation_id: &str, message_id: i64, body: &Value) -> Result<()> {
+        let mut payload = serde_json::json!({
+            "type": "message",
+            "message_id": message_id,
+            "conversation_id": conversation_id,
         });
+        if let (Some(payload), Some(body)) = (payload.as_object_mut(), body.as_object()) {
+            payload.extend(body.clone());
+        }
 
-        redis::cmd("LPUSH")
-            .arg(&key)
-            .arg(serde_json::to_string(&message)?)
-            .query_async(&mut conn)
-            .await?;
-
-        // Keep only last 50 messages
-        redis::cmd("LTRIM")
-            .arg(&key)
-            .arg(0)
-            .arg(49)
-            .query_async(&mut conn)
-            .await?;
+        let mut conn = get_connection(&self.ctx.redis_pool).await?;
+        streams::push_chat_event(&mut conn, &self.ctx.config.redis, user_address, &payload).await?;
 
         Ok(())
     }
+}
 
-    async fn emit_ws_event(&self, user_address: &str, conversation_id: &str, content: &str) -> Result<()> {
-        let payload = serde_json::json!({
-            "type": "message",
+/// Deterministic id of the direct conversation between two users, whichever of them sends
+fn conversation_id_for(user1: &str, user2: &str) -> String {
+    if user1 < user2 {
+        format!("{}:{}", user1, user2)
+    } else {
+        format!("{}:{}", user2, user1)
+    }
+}
+
+/// The push for a new message; its actions let the recipient reply or mark it read from the notification
+fn message_notification(conversation_id: &str, message_id: i64, sender: &str) -> Value {
+    let notification_type = "message.created";
+    serde_json::json!({
+        "notification_type": notification_type,
+        "title": "New Message",
+        "body": "You have a new message",
+        "data": {
             "conversation_id": conversation_id,
-            "content": content,
-        });
+            "message_id": message_id,
+            "sender_address": sender,
+        },
+        "actions": notification_actions::for_type(notification_type),
+    })
+}
 
-        let payload_bytes = serde_json::to_vec(&payload)?;
-        let stream_key = format!("STREAM:CHAT:{}", user_address);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use relay_core::Config;
 
-        let mut conn = get_connection(&self.ctx.redis_pool).await?;
-        redis::cmd("XADD")
-            .arg(&stream_key)
-            .arg("*")
-            .arg("data")
-            .arg(String::from_utf8_lossy(&payload_bytes))
-            .query_async(&mut conn)
-            .await?;
+    #[test]
+    fn test_message_notification_offers_reply() {
+        let notification = message_notification("0xa:0xb", 7, "0xa");
 
-        Ok(())
+        assert_eq!(notification["actions"][0], notification_actions::REPLY);
+        assert_eq!(notification["data"]["conversation_id"], "0xa:0xb");
     }
-}
 
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables, and Redis at REDIS_URL"]
+    async fn test_blocked_sender_message_never_reaches_recipient() {
+        let ctx = RelayContext::new(Config::from_env()).await.unwrap();
+        let service = MessagingService::new(ctx.clone());
+        let sender = format!("0xsender-{}", uuid::Uuid::new_v4());
+        let recipient = format!("0xrecipient-{}", uuid::Uuid::new_v4());
+
+        let mut conn = ctx.db_pool.get().await.unwrap();
+        blocks::block_user(&mut conn, &recipient, &sender).await.unwrap();
+
+        let event = serde_json::json!({"sender_address": sender, "recipient_address": recipient, "content": "hi"});
+        service.process_message(&event).await.unwrap();
+
+        let stored: i64 = relay_messages::table
+            .filter(relay_messages::sender_address.eq(&sender))
+            .count()
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let mut redis_conn = get_connection(&ctx.redis_pool).await.unwrap();
+        let stream_len: usize = redis::cmd("XLEN")
+            .arg(ctx.config.redis.keys().chat_stream(&recipient))
+            .query_async(&mut redis_conn)
+            .await
+            .unwrap();
+        let conversation_id = if sender < recipient {
+            format!("{}:{}", sender, recipient)
+        } else {
+            format!("{}:{}", recipient, sender)
+        };
+        let cached: usize = redis::cmd("LLEN")
+            .arg(ctx.config.redis.keys().chat_cache(&conversation_id))
+            .query_async(&mut redis_conn)
+            .await
+            .unwrap();
+
+        blocks::unblock_user(&mut conn, &recipient, &sender).await.unwrap();
+
+        assert_eq!(stored, 0);
+        assert_eq!(stream_len, 0);
+        assert_eq!(cached, 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables, and Redis at REDIS_URL"]
+    async fn test_muted_conversation_produces_no_delivery_job() {
+        let ctx = RelayContext::new(Config::from_env()).await.unwrap();
+        let service = MessagingService::new(ctx.clone());
+        let sender = format!("0xsender-{}", uuid::Uuid::new_v4());
+        let recipient = format!("0xrecipient-{}", uuid::Uuid::new_v4());
+        let conversation_id = service.get_or_create_conversation(&sender, &recipient).await.unwrap();
+
+        let job = service.delivery_job(&recipient, &conversation_id, 1, &sender).await.unwrap().unwrap();
+        assert_eq!(job["notification"]["data"]["conversation_id"], conversation_id.as_str());
+
+        let mut conn = ctx.db_pool.get().await.unwrap();
+        conversation_mutes::mute_conversation(&mut conn, &recipient, &conversation_id).await.unwrap();
+        let muted_job = service.delivery_job(&recipient, &conversation_id, 1, &sender).await.unwrap();
+
+        // The sender didn't mute it, so replies to them are still pushed
+        let reply_job = service.delivery_job(&sender, &conversation_id, 2, &recipient).await.unwrap();
+
+        conversation_mutes::unmute_conversation(&mut conn, &recipient, &conversation_id).await.unwrap();
+
+        assert!(muted_job.is_none());
+        assert!(reply_job.is_some());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables, and Redis at REDIS_URL"]
+    async fn test_sending_updates_cached_preview() {
+        let ctx = RelayContext::new(Config::from_env()).await.unwrap();
+        let service = MessagingService::new(ctx.clone());
+        let sender = format!("0xsender-{}", uuid::Uuid::new_v4());
+        let recipient = format!("0xrecipient-{}", uuid::Uuid::new_v4());
+        let conversation_id = service.get_or_create_conversation(&sender, &recipient).await.unwrap();
+        let mut redis_conn = get_connection(&ctx.redis_pool).await.unwrap();
+
+        for content in ["first", &"x".repeat(conversation_previews::PREVIEW_CHARS + 50)] {
+            let event = serde_json::json!({"sender_address": sender, "recipient_address": recipient, "content": content});
+            service.process_message(&event).await.unwrap();
+        }
+
+        let keys = ctx.config.redis.keys();
+        let cached = conversation_previews::load_many(&mut redis_conn, keys, std::slice::from_ref(&conversation_id)).await.unwrap();
+        conversation_previews::invalidate(&mut redis_conn, keys, &conversation_id).await.unwrap();
+
+        let preview = &cached[&conversation_id];
+        assert_eq!(preview.sender_address, sender);
+        assert_eq!(preview.preview, Some("x".repeat(conversation_previews::PREVIEW_CHARS)));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables, and Redis at REDIS_URL"]
+    async fn test_new_message_unarchives_for_recipient() {
+        let ctx = RelayContext::new(Config::from_env()).await.unwrap();
+        let service = MessagingService::new(ctx.clone());
+        let sender = format!("0xsender-{}", uuid::Uuid::new_v4());
+        let recipient = format!("0xrecipient-{}", uuid::Uuid::new_v4());
+        let conversation_id = service.get_or_create_conversation(&sender, &recipient).await.unwrap();
+        let mut conn = ctx.db_pool.get().await.unwrap();
+        conversation_archives::archive_conversation(&mut conn, &sender, &conversation_id).await.unwrap();
+        conversation_archives::archive_conversation(&mut conn, &recipient, &conversation_id).await.unwrap();
+
+        let event = serde_json::json!({"sender_address": sender, "recipient_address": recipient, "content": "wake up"});
+        service.process_message(&event).await.unwrap();
+
+        let for_recipient = conversation_archives::is_archived(&mut conn, &recipient, &conversation_id).await.unwrap();
+        let for_sender = conversation_archives::is_archived(&mut conn, &sender, &conversation_id).await.unwrap();
+        conversation_archives::unarchive_conversation(&mut conn, &sender, &conversation_id).await.unwrap();
+
+        assert!(!for_recipient);
+        // Sending doesn't unarchive it for the sender
+        assert!(for_sender);
+    }
+}
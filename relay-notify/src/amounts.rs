@@ -0,0 +1,115 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+pub const MYSO: &str = "MYSO";
+/// Social proof tokens bought, sold and reserved from a creator's pool
+pub const SPT: &str = "SPT";
+
+/// Formats base-unit event amounts as whole tokens, using the decimals configured per token symbol
+pub struct AmountFormatter<'a> {
+    decimals: &'a BTreeMap<String, u32>,
+}
+
+impl<'a> AmountFormatter<'a> {
+    pub fn new(decimals: &'a BTreeMap<String, u32>) -> Self {
+        Self { decimals }
+    }
+
+    /// `amount` of `token` in whole tokens, e.g. `1,250.5`; unknown tokens are shown as-is
+    pub fn format(&self, token: &str, amount: u128) -> String {
+        format_units(amount, self.decimals.get(token).copied().unwrap_or(0))
+    }
+
+    /// The event's `amount` as e.g. `1.5 MYSO`
+    pub fn myso(&self, event_data: &Value) -> String {
+        format!("{} {}", self.format(MYSO, raw_amount(event_data)), MYSO)
+    }
+
+    /// The event's `amount` of social proof tokens as e.g. `1 token` or `2,000 tokens`
+    pub fn tokens(&self, event_data: &Value) -> String {
+        count_noun(&self.format(SPT, raw_amount(event_data)), "token", "tokens")
+    }
+}
+
+/// The event's `amount` in base units; indexers send large amounts as strings. Missing or invalid is 0
+pub fn raw_amount(event_data: &Value) -> u128 {
+    match event_data.get("amount") {
+        Some(Value::Number(n)) => n.as_u64().map(u128::from).unwrap_or(0),
+        Some(Value::String(s)) => s.trim().parse().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// `amount / 10^decimals` with thousands separators and no trailing fractional zeros
+pub fn format_units(amount: u128, decimals: u32) -> String {
+    let digits = format!("{:0>width$}", amount, width = decimals as usize + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals as usize);
+    let fraction = fraction.trim_end_matches('0');
+
+    let mut out = group_thousands(whole);
+    if !fraction.is_empty() {
+        out.push('.');
+        out.push_str(fraction);
+    }
+    out
+}
+
+fn group_thousands(digits: &str) -> String {
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// `1 token`, `0 tokens`, `1.5 tokens`
+pub fn count_noun(amount: &str, singular: &str, plural: &str) -> String {
+    format!("{} {}", amount, if amount == "1" { singular } else { plural })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn decimals() -> BTreeMap<String, u32> {
+        BTreeMap::from([(MYSO.to_string(), 9), (SPT.to_string(), 0)])
+    }
+
+    #[test]
+    fn test_zero_one_and_large_amounts() {
+        let decimals = decimals();
+        let amounts = AmountFormatter::new(&decimals);
+
+        assert_eq!(amounts.myso(&json!({"amount": 0})), "0 MYSO");
+        assert_eq!(amounts.myso(&json!({"amount": 1_000_000_000u64})), "1 MYSO");
+        assert_eq!(amounts.myso(&json!({"amount": 1_500_000_000u64})), "1.5 MYSO");
+        assert_eq!(amounts.myso(&json!({"amount": 1})), "0.000000001 MYSO");
+        // Beyond u64, sent as a string
+        assert_eq!(amounts.myso(&json!({"amount": "123456789000000000000"})), "123,456,789,000 MYSO");
+
+        assert_eq!(amounts.tokens(&json!({"amount": 0})), "0 tokens");
+        assert_eq!(amounts.tokens(&json!({"amount": 1})), "1 token");
+        assert_eq!(amounts.tokens(&json!({"amount": 2})), "2 tokens");
+        assert_eq!(amounts.tokens(&json!({"amount": 1_234_567})), "1,234,567 tokens");
+        assert_eq!(amounts.tokens(&json!({})), "0 tokens");
+    }
+
+    #[test]
+    fn test_decimals_per_token() {
+        let decimals = BTreeMap::from([(SPT.to_string(), 6)]);
+        let amounts = AmountFormatter::new(&decimals);
+
+        assert_eq!(amounts.tokens(&json!({"amount": 1_000_000})), "1 token");
+        assert_eq!(amounts.tokens(&json!({"amount": 2_500_000})), "2.5 tokens");
+        // Unconfigured tokens are shown in base units
+        assert_eq!(amounts.myso(&json!({"amount": 1_000})), "1,000 MYSO");
+
+        assert_eq!(format_units(999, 0), "999");
+        assert_eq!(format_units(1_000, 0), "1,000");
+        assert_eq!(format_units(100_000, 6), "0.1");
+    }
+}
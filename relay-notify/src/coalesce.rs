@@ -0,0 +1,143 @@
+use serde_json::Value;
+
+/// Most actors remembered on a coalesced notification; `count` keeps the true total
+const MAX_ACTORS: usize = 20;
+
+/// How one event type groups: notifications for the same user and `target_field` value are merged
+#[derive(Debug)]
+pub struct CoalesceRule {
+    pub target_field: &'static str,
+    actor_fields: &'static [&'static str],
+    verb: &'static str,
+}
+
+/// Event types that are merged when they arrive in bursts
+pub fn rule_for(event_type: &str) -> Option<CoalesceRule> {
+    let rule = match event_type {
+        "reaction.created" => CoalesceRule {
+            target_field: "post_id",
+            actor_fields: &["reactor", "user_address", "user"],
+            verb: "reacted to your post",
+        },
+        "repost.created" => CoalesceRule {
+            target_field: "post_id",
+            actor_fields: &["reposter"],
+            verb: "reposted your post",
+        },
+        "comment.created" => CoalesceRule {
+            target_field: "post_id",
+            actor_fields: &["commenter"],
+            verb: "commented on your post",
+        },
+        "follow.created" => CoalesceRule {
+            target_field: "following_address",
+            actor_fields: &["follower_address", "follower"],
+            verb: "started following you",
+        },
+        _ => return None,
+    };
+
+    Some(rule)
+}
+
+impl CoalesceRule {
+    /// The grouping key for an event, if it carries one
+    pub fn target<'a>(&self, event_data: &'a Value) -> Option<&'a str> {
+        event_data.get(self.target_field).and_then(|v| v.as_str())
+    }
+
+    fn actor(&self, event_data: &Value) -> Option<String> {
+        self.actor_fields
+            .iter()
+            .find_map(|f| event_data.get(*f).and_then(|v| v.as_str()))
+            .map(|s| s.to_string())
+    }
+
+    /// Data for the first notification in a group: its client `data` plus `count` and `actors`
+    pub fn initial_data(&self, data: Value, event_data: &Value) -> Value {
+        let mut data = data;
+        if let Some(obj) = data.as_object_mut() {
+            obj.insert("count".to_string(), Value::from(1));
+            obj.insert("actors".to_string(), Value::from(self.actor(event_data).into_iter().collect::<Vec<_>>()));
+        }
+        data
+    }
+
+    /// Fold another event into an existing group's data
+    pub fn merge(&self, existing: &Value, event_data: &Value) -> Value {
+        let mut data = existing.clone();
+        let count = existing.get("count").and_then(|v| v.as_u64()).unwrap_or(1) + 1;
+
+        let mut actors: Vec<String> = existing
+            .get("actors")
+            .and_then(|v| v.as_array())
+            .map(|a| a.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect())
+            .unwrap_or_default();
+
+        if let Some(actor) = self.actor(event_data) {
+            // Most recent actor first
+            actors.retain(|a| a != &actor);
+            actors.insert(0, actor);
+            actors.truncate(MAX_ACTORS);
+        }
+
+        if let Some(obj) = data.as_object_mut() {
+            obj.insert("count".to_string(), Value::from(count));
+            obj.insert("actors".to_string(), Value::from(actors));
+        }
+        data
+    }
+
+    /// Title and body summarizing a group, e.g. "12 people reacted to your post"
+    pub fn summary(&self, data: &Value) -> (String, String) {
+        let count = data.get("count").and_then(|v| v.as_u64()).unwrap_or(1);
+        let who = if count == 1 {
+            "1 person".to_string()
+        } else {
+            format!("{} people", count)
+        };
+
+        ("New Activity".to_string(), format!("{} {}", who, self.verb))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_three_reactions_coalesce_into_one() {
+        let rule = rule_for("reaction.created").unwrap();
+        let events: Vec<Value> = ["0xa", "0xb", "0xc"]
+            .iter()
+            .map(|actor| serde_json::json!({"post_id": "post-1", "post_owner": "0xowner", "reactor": actor, "reaction": "like"}))
+            .collect();
+
+        // All three share a grouping key
+        assert!(events.iter().all(|e| rule.target(e) == Some("post-1")));
+
+        let mut data = rule.initial_data(serde_json::json!({"post_id": "post-1"}), &events[0]);
+        for event in &events[1..] {
+            data = rule.merge(&data, event);
+        }
+
+        assert_eq!(data["count"], 3);
+        assert_eq!(data["actors"], serde_json::json!(["0xc", "0xb", "0xa"]));
+        assert_eq!(rule.summary(&data).1, "3 people reacted to your post");
+    }
+
+    #[test]
+    fn test_repeat_actor_counted_once_in_actors() {
+        let rule = rule_for("follow.created").unwrap();
+        let event = serde_json::json!({"following_address": "0xme", "follower_address": "0xa"});
+
+        let data = rule.merge(&rule.initial_data(serde_json::json!({}), &event), &event);
+        assert_eq!(data["count"], 2);
+        assert_eq!(data["actors"], serde_json::json!(["0xa"]));
+    }
+
+    #[test]
+    fn test_tips_are_not_coalesced() {
+        assert!(rule_for("tip.created").is_none());
+    }
+}
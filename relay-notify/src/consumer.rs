@@ -1,72 +1,115 @@
 use anyhow::{Result, anyhow};
 use rdkafka::consumer::Consumer;
+use rdkafka::message::OwnedMessage;
 use rdkafka::Message;
-use relay_core::{RelayContext, redpanda::create_consumer};
+use relay_core::backoff::Backoff;
+use relay_core::consumer_pool::WorkerPool;
+use relay_core::dead_letter::{handle_or_dead_letter, SourceMessage};
+use relay_core::{RelayContext, consumer_lag, processed_events, redpanda::create_consumer, types::RelayEvent};
+use crate::digest;
 use crate::service::NotificationService;
+use std::sync::Arc;
 use std::time::Duration;
 use tracing;
 
-const TOPICS: &[&str] = &[
-    // Post-related events
-    "events.post.reaction",
-    "events.post.repost",
-    "events.post.tip",
-    "events.post.created",
-    "events.post.ownership",
-    // Comment events
-    "events.comment.created",
-    // Social proof token events
-    "events.spt.created",
-    // Governance events
-    "events.governance.created",
-    // Prediction events
-    "events.prediction.created",
-    // Social graph events
-    "events.follow.created",
-    "events.unfollow.created",
-    // Platform events
-    "events.platform.created",
-    // Note: events.message.created is handled by relay-messaging service, not here
-];
+const CONSUMER: &str = "relay-notify";
+
+const TEMPLATE_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
 
 pub async fn run(ctx: RelayContext) -> Result<()> {
     tracing::info!("Starting notification consumer");
 
-    let consumer = create_consumer(&ctx.config.redpanda, Some("relay-notify"))?;
-    let service = NotificationService::new(ctx.clone());
+    let consumer = create_consumer(&ctx.config.redpanda, Some(CONSUMER))?;
+    let service = Arc::new(NotificationService::new(ctx.clone()));
+
+    match service.reload_templates().await {
+        Ok(count) => tracing::info!("Loaded {} notification templates", count),
+        Err(e) => tracing::warn!("Failed to load notification templates, using built-in copy: {}", e),
+    }
+
+    // Pick up templates changed through the admin API
+    let refresh_service = service.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TEMPLATE_REFRESH_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = refresh_service.reload_templates().await {
+                tracing::warn!("Failed to refresh notification templates: {}", e);
+            }
+        }
+    });
+
+    // Summary emails for users who opted out of per-notification email
+    tokio::spawn(digest::run(ctx.clone()));
 
-    consumer.subscribe(TOPICS)?;
+    // events.message.created is handled by relay-messaging, not here
+    let topics = ctx.config.redpanda.topics().notification_events();
+    consumer.subscribe(&topics.iter().map(String::as_str).collect::<Vec<_>>())?;
 
-    tracing::info!("Subscribed to topics: {:?}", TOPICS);
+    tracing::info!("Subscribed to topics: {:?}", topics);
+
+    // Lag per partition, for /metrics and /health/ready
+    tokio::spawn(consumer_lag::report(ctx.clone(), consumer.clone(), CONSUMER));
+
+    // Handle messages on concurrent workers; offsets are only stored below the oldest one still in flight
+    let store_consumer = consumer.clone();
+    let pool = WorkerPool::new(
+        ctx.config.redpanda.consumer_concurrency,
+        {
+            let ctx = ctx.clone();
+            move |message: OwnedMessage| {
+                let ctx = ctx.clone();
+                let service = service.clone();
+                async move {
+                    if let Some(payload) = message.payload() {
+                        let source = SourceMessage::from_message(&message);
+                        let handled = handle_or_dead_letter(&ctx.redpanda_producer, &ctx.config.redpanda, source, || {
+                            handle_event(&ctx, &service, payload)
+                        })
+                        .await;
+                        match handled {
+                            Ok(_) => {
+                                tracing::debug!("Processed notification event");
+                            }
+                            Err(e) => {
+                                tracing::error!("Error processing notification event: {}", e);
+                            }
+                        }
+                    }
+                }
+            }
+        },
+        move |topic: &str, partition: i32, offset: i64| {
+            if let Err(e) = store_consumer.store_offset(topic, partition, offset) {
+                tracing::warn!("Failed to store offset for notification event: {}", e);
+            }
+        },
+    );
 
-    let mut error_count = 0u32;
+    let mut backoff = Backoff::for_consumer();
     let mut last_error_log = std::time::Instant::now();
     
     loop {
         match consumer.recv().await {
             Ok(message) => {
-                error_count = 0; // Reset error count on success
-                if let Some(payload) = message.payload() {
-                    match handle_event(&service, payload).await {
-                        Ok(_) => {
-                            tracing::debug!("Processed notification event");
-                        }
-                        Err(e) => {
-                            tracing::error!("Error processing notification event: {}", e);
-                        }
-                    }
+                if backoff.failures() > 0 {
+                    backoff.reset();
+                    ctx.consumer_errors.record(CONSUMER, 0);
                 }
+                pool.dispatch(message.detach()).await;
             }
             Err(e) => {
-                error_count += 1;
+                let delay = backoff.fail();
+                ctx.consumer_errors.record(CONSUMER, backoff.failures());
                 // Only log errors every 30 seconds to reduce log spam
                 if last_error_log.elapsed().as_secs() >= 30 {
                     tracing::warn!(
                         "Error receiving message from Redpanda (error count: {}): {}",
-                        error_count,
+                        backoff.failures(),
                         e
                     );
-                    if error_count == 1 {
+                    if backoff.failures() == 1 {
                         tracing::warn!("Troubleshooting Redpanda connection:");
                         tracing::warn!("  1. Verify REDPANDA_BROKERS is correct and brokers are accessible");
                         tracing::warn!("  2. Check if SSL/TLS is required (set REDPANDA_SSL_ENABLED=true)");
@@ -75,26 +118,65 @@ pub async fn run(ctx: RelayContext) -> Result<()> {
                     }
                     last_error_log = std::time::Instant::now();
                 }
-                // Exponential backoff: 1s, 2s, 4s, max 30s
-                let backoff = Duration::from_secs(1 << error_count.min(5)).min(Duration::from_secs(30));
-                tokio::time::sleep(backoff).await;
+                // Exponential backoff with jitter: up to 1s, 2s, 4s, max 30s
+                tokio::time::sleep(delay).await;
             }
         }
     }
 }
 
-async fn handle_event(service: &NotificationService, payload: &[u8]) -> Result<()> {
-    let event: serde_json::Value = serde_json::from_slice(payload)?;
-    
-    let event_type = event.get("event_type")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| anyhow::anyhow!("Missing event_type"))?;
+async fn handle_event(ctx: &RelayContext, service: &NotificationService, payload: &[u8]) -> Result<()> {
+    let event = match RelayEvent::decode(payload)? {
+        Some(event) => event,
+        None => return Ok(()),
+    };
 
-    let event_data = event.get("event_data")
-        .ok_or_else(|| anyhow::anyhow!("Missing event_data"))?;
+    // The outbox may re-publish an event after a crash; don't notify twice
+    processed_events::process_once(
+        &ctx.redis_pool,
+        ctx.config.redis.keys(),
+        CONSUMER,
+        event.event_id(),
+        service.process_event(&event.event_type, &event.event_data),
+    )
+    .await
+}
 
-    service.process_event(event_type, event_data).await?;
 
-    Ok(())
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::prelude::*;
+    use diesel_async::RunQueryDsl;
+    use relay_core::schema::relay_notifications;
+    use relay_core::Config;
 
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables, Redis at REDIS_URL and Redpanda"]
+    async fn test_duplicate_event_creates_one_notification() {
+        let ctx = RelayContext::new(Config::from_env()).await.unwrap();
+        let service = NotificationService::new(ctx.clone());
+        let owner = format!("0xowner-{}", uuid::Uuid::new_v4());
+
+        // The same outbox row published twice, e.g. after a crash before processed_at was set
+        let payload = serde_json::to_vec(&serde_json::json!({
+            "event_type": "tip.created",
+            "event_id": format!("evt-{}", uuid::Uuid::new_v4()),
+            "event_data": {"recipient": owner, "tipper": "0xfan", "amount": 5, "post_id": "post-1"},
+        }))
+        .unwrap();
+
+        handle_event(&ctx, &service, &payload).await.unwrap();
+        handle_event(&ctx, &service, &payload).await.unwrap();
+
+        let mut conn = ctx.db_pool.get().await.unwrap();
+        let count: i64 = relay_notifications::table
+            .filter(relay_notifications::user_address.eq(&owner))
+            .count()
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        assert_eq!(count, 1);
+    }
+}
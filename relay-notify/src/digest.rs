@@ -0,0 +1,306 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use relay_core::db::DbConnection;
+use relay_core::email_digest::EmailDigest;
+use relay_core::schema::{relay_notifications, relay_user_preferences};
+use relay_core::{lock, RelayContext};
+use relay_delivery::email::{html_escape, EmailDelivery};
+use relay_delivery::outcome::{record_deliveries, DeliveryRecord};
+use std::time::Duration;
+
+const DIGEST_POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Most notifications pulled into one digest; section counts still reflect what was loaded
+const MAX_DIGEST_NOTIFICATIONS: i64 = 200;
+
+/// Notifications listed per section before it collapses into "and N more"
+const MAX_ITEMS_PER_SECTION: usize = 10;
+
+/// One unread notification as it appears in a digest
+#[derive(Debug, Clone)]
+pub struct DigestItem {
+    pub notification_type: String,
+    pub title: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub struct DigestSection {
+    pub heading: &'static str,
+    pub items: Vec<DigestItem>,
+}
+
+/// Email heading for a notification type; related types (e.g. all `spt.*` events) share a section
+fn section_heading(notification_type: &str) -> &'static str {
+    match notification_type {
+        "reaction.created" => "Reactions",
+        "comment.created" => "Comments",
+        "repost.created" => "Reposts",
+        "tip.created" => "Tips",
+        "follow.created" => "New followers",
+        "post.created" => "New posts",
+        t if t.starts_with("spt.") => "Social proof tokens",
+        t if t.starts_with("governance.") => "Governance",
+        t if t.starts_with("prediction.") => "Predictions",
+        t if t.starts_with("platform.") => "Platforms",
+        t if t.starts_with("message.") => "Messages",
+        _ => "Other activity",
+    }
+}
+
+/// Group notifications into sections, ordered by each section's first (most recent) notification
+pub fn group_by_type(items: Vec<DigestItem>) -> Vec<DigestSection> {
+    let mut sections: Vec<DigestSection> = Vec::new();
+
+    for item in items {
+        let heading = section_heading(&item.notification_type);
+        match sections.iter_mut().find(|s| s.heading == heading) {
+            Some(section) => section.items.push(item),
+            None => sections.push(DigestSection { heading, items: vec![item] }),
+        }
+    }
+
+    sections
+}
+
+fn digest_subject(digest: EmailDigest, total: usize) -> String {
+    let noun = if total == 1 { "notification" } else { "notifications" };
+    format!("Your {} MySocial digest: {} new {}", digest.as_str(), total, noun)
+}
+
+/// Render the digest as (html, text)
+pub fn render_digest(subject: &str, sections: &[DigestSection]) -> (String, String) {
+    let mut html_sections = String::new();
+    let mut text = format!("{}\n", subject);
+
+    for section in sections {
+        html_sections.push_str(&format!(
+            r#"<h2 style="font-size: 18px; color: #212529; margin: 24px 0 8px 0;">{} ({})</h2><ul style="padding-left: 20px; margin: 0;">"#,
+            html_escape(section.heading),
+            section.items.len()
+        ));
+        text.push_str(&format!("\n{} ({})\n", section.heading, section.items.len()));
+
+        for item in section.items.iter().take(MAX_ITEMS_PER_SECTION) {
+            html_sections.push_str(&format!(
+                r#"<li style="margin-bottom: 6px;"><strong>{}</strong> {}</li>"#,
+                html_escape(&item.title),
+                html_escape(&item.body)
+            ));
+            text.push_str(&format!("- {}: {}\n", item.title, item.body));
+        }
+
+        let hidden = section.items.len().saturating_sub(MAX_ITEMS_PER_SECTION);
+        if hidden > 0 {
+            html_sections.push_str(&format!(r#"<li style="color: #6c757d;">and {} more</li>"#, hidden));
+            text.push_str(&format!("- and {} more\n", hidden));
+        }
+
+        html_sections.push_str("</ul>");
+    }
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+</head>
+<body style="font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, 'Helvetica Neue', Arial, sans-serif; line-height: 1.6; color: #333; max-width: 600px; margin: 0 auto; padding: 20px;">
+    <div style="background-color: #f8f9fa; border-radius: 8px; padding: 24px; margin-bottom: 20px;">
+        <h1 style="margin: 0 0 16px 0; font-size: 24px; color: #212529;">{}</h1>
+        {}
+    </div>
+    <p style="font-size: 14px; color: #6c757d; margin-top: 20px;">
+        You are receiving this digest because of your MySocial email preferences.
+    </p>
+</body>
+</html>"#,
+        html_escape(subject),
+        html_sections
+    );
+
+    (html, text)
+}
+
+/// Periodically email each opted-in user a summary of their unread notifications. Only the instance holding
+/// the `email-digest` lock sends them, so nobody gets one per instance
+pub async fn run(ctx: RelayContext) {
+    let email = match EmailDelivery::new(&ctx.config.delivery) {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::error!("Failed to create email client, digests disabled: {}", e);
+            return;
+        }
+    };
+
+    lock::with_leader_lock(&ctx, "email-digest", || send_digests_forever(&ctx, &email)).await
+}
+
+async fn send_digests_forever(ctx: &RelayContext, email: &EmailDelivery) {
+    let mut interval = tokio::time::interval(DIGEST_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = send_due_digests(ctx, email).await {
+            tracing::warn!("Failed to send email digests: {}", e);
+        }
+    }
+}
+
+async fn send_due_digests(ctx: &RelayContext, email: &EmailDelivery) -> Result<()> {
+    let mut conn = ctx.db_pool.get().await?;
+    let now = Utc::now();
+
+    let subscribers: Vec<(String, String, Option<DateTime<Utc>>)> = relay_user_preferences::table
+        .filter(relay_user_preferences::email_digest.ne(EmailDigest::Off.as_str()))
+        .select((
+            relay_user_preferences::user_address,
+            relay_user_preferences::email_digest,
+            relay_user_preferences::last_digest_at,
+        ))
+        .load(&mut conn)
+        .await?;
+
+    for (user_address, digest, last_digest_at) in subscribers {
+        let digest = match EmailDigest::parse(&digest) {
+            Some(digest) if digest.is_due(last_digest_at, now) => digest,
+            _ => continue,
+        };
+
+        // Only the worker that advances last_digest_at sends this round's digest
+        if !claim_digest(&mut conn, &user_address, last_digest_at, Some(now)).await? {
+            continue;
+        }
+
+        if let Err(e) = send_digest(&mut conn, email, &user_address, digest, last_digest_at, now).await {
+            tracing::warn!("Failed to send email digest to {}: {}", user_address, e);
+            // Put the marker back so the next run retries this window
+            if let Err(e) = claim_digest(&mut conn, &user_address, Some(now), last_digest_at).await {
+                tracing::warn!("Failed to reset last_digest_at for {}: {}", user_address, e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Move `last_digest_at` from `from` to `to`; false if another worker already moved it
+async fn claim_digest(
+    conn: &mut DbConnection,
+    user_address: &str,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<bool> {
+    let updated = diesel::update(
+        relay_user_preferences::table
+            .filter(relay_user_preferences::user_address.eq(user_address))
+            .filter(relay_user_preferences::last_digest_at.is_not_distinct_from(from)),
+    )
+    .set(relay_user_preferences::last_digest_at.eq(to))
+    .execute(conn)
+    .await?;
+
+    Ok(updated == 1)
+}
+
+async fn send_digest(
+    conn: &mut DbConnection,
+    email: &EmailDelivery,
+    user_address: &str,
+    digest: EmailDigest,
+    last_digest_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    // A first digest covers one period back
+    let since = last_digest_at
+        .or_else(|| digest.period().map(|p| now - p))
+        .unwrap_or(now);
+
+    let rows: Vec<(String, String, String)> = relay_notifications::table
+        .filter(relay_notifications::user_address.eq(user_address))
+        .filter(relay_notifications::read_at.is_null())
+        .filter(relay_notifications::deleted_at.is_null())
+        .filter(relay_notifications::created_at.gt(since))
+        .filter(relay_notifications::created_at.le(now))
+        .order(relay_notifications::created_at.desc())
+        .limit(MAX_DIGEST_NOTIFICATIONS)
+        .select((
+            relay_notifications::notification_type,
+            relay_notifications::title,
+            relay_notifications::body,
+        ))
+        .load(conn)
+        .await?;
+
+    if rows.is_empty() {
+        tracing::debug!("No unread notifications for {}, skipping digest", user_address);
+        return Ok(());
+    }
+
+    let total = rows.len();
+    let sections = group_by_type(
+        rows.into_iter()
+            .map(|(notification_type, title, body)| DigestItem { notification_type, title, body })
+            .collect(),
+    );
+
+    let subject = digest_subject(digest, total);
+    let (html, text) = render_digest(&subject, &sections);
+    let result = email.send_email(user_address, &subject, html, &text).await;
+    let record = DeliveryRecord::from_result("email", result);
+
+    if let Err(e) = record_deliveries(conn, None, user_address, std::slice::from_ref(&record)).await {
+        tracing::warn!("Failed to record digest delivery for {}: {}", user_address, e);
+    }
+
+    if record.status == "failed" {
+        anyhow::bail!(record.error.unwrap_or_default());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(notification_type: &str, title: &str) -> DigestItem {
+        DigestItem {
+            notification_type: notification_type.to_string(),
+            title: title.to_string(),
+            body: format!("{} body", title),
+        }
+    }
+
+    #[test]
+    fn test_groups_by_type_in_order_of_first_appearance() {
+        let sections = group_by_type(vec![
+            item("tip.created", "tip 1"),
+            item("reaction.created", "reaction 1"),
+            item("spt.token_bought", "bought"),
+            item("tip.created", "tip 2"),
+            item("spt.token_sold", "sold"),
+        ]);
+
+        let headings: Vec<_> = sections.iter().map(|s| (s.heading, s.items.len())).collect();
+        assert_eq!(headings, vec![("Tips", 2), ("Reactions", 1), ("Social proof tokens", 2)]);
+    }
+
+    #[test]
+    fn test_render_truncates_long_sections_and_escapes() {
+        let items = (0..12).map(|i| item("follow.created", &format!("<b>follower {}</b>", i))).collect();
+        let sections = group_by_type(items);
+        let subject = digest_subject(EmailDigest::Daily, 12);
+
+        let (html, text) = render_digest(&subject, &sections);
+        assert_eq!(subject, "Your daily MySocial digest: 12 new notifications");
+        assert!(html.contains("New followers (12)"));
+        assert!(html.contains("&lt;b&gt;follower 0&lt;/b&gt;"));
+        assert!(!html.contains("<b>follower"));
+        assert!(text.contains("- and 2 more"));
+        assert!(!text.contains("follower 10"));
+    }
+}
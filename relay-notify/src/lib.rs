@@ -1,6 +1,10 @@
+pub mod amounts;
+pub mod coalesce;
 pub mod consumer;
+pub mod digest;
 pub mod service;
+pub mod templates;
 
 pub use consumer::run;
-pub use service::NotificationService;
+pub use service::{DirectNotification, NotificationPreview, NotificationService};
 
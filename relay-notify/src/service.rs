@@ -2,54 +2,214 @@ use anyhow::{Result, anyhow};
 use chrono::Utc;
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
+use relay_core::{blocks, notification_actions};
 use relay_core::schema::relay_notifications;
-use relay_core::{RelayContext, redis::get_connection};
+use relay_core::types::NotificationData;
+use relay_core::{RelayContext, redis::{counts, get_connection, inbox, streams, RedisKeys}};
+use serde::Serialize;
 use serde_json::Value;
+use relay_core::notification_templates::load_notification_templates;
+use std::sync::RwLock;
+use crate::amounts::AmountFormatter;
+use crate::coalesce::rule_for;
+use crate::templates::TemplateStore;
 use tracing;
 
+/// A notification whose copy is given by the sender rather than rendered from an event
+#[derive(Debug, Clone)]
+pub struct DirectNotification {
+    pub notification_type: String,
+    pub title: String,
+    pub body: String,
+    pub data: Option<Value>,
+    pub platform_id: Option<String>,
+}
+
+/// What a notification for an event would look like
+#[derive(Debug, Clone, Serialize)]
+pub struct NotificationPreview {
+    /// Who the event would notify, before dropping users who blocked the actor or opted out
+    pub recipients: Vec<String>,
+    /// `platform` when one of the platform's templates rendered the copy, otherwise `built_in`
+    pub template: &'static str,
+    /// As stored and handed to delivery, without the id and recipient
+    pub notification: Value,
+}
+
 pub struct NotificationService {
     ctx: RelayContext,
+    templates: RwLock<TemplateStore>,
 }
 
 impl NotificationService {
     pub fn new(ctx: RelayContext) -> Self {
-        Self { ctx }
+        Self {
+            ctx,
+            templates: RwLock::new(TemplateStore::default()),
+        }
+    }
+
+    /// Replace the cached platform templates with the current contents of the table
+    pub async fn reload_templates(&self) -> Result<usize> {
+        let mut conn = self.ctx.db_pool.get().await?;
+        let store = TemplateStore::new(load_notification_templates(&mut conn).await?);
+        let count = store.len();
+
+        *self.templates.write().map_err(|_| anyhow!("Template cache lock poisoned"))? = store;
+
+        Ok(count)
     }
 
     pub async fn process_event(&self, event_type: &str, event_data: &Value) -> Result<()> {
         tracing::debug!("Processing notification event: {}", event_type);
 
         // Extract user addresses from event data
-        let recipients = self.extract_recipients(event_type, event_data)?;
+        let recipients = Self::extract_recipients(event_type, event_data)?;
+        let recipients = self.without_blockers(event_type, event_data, recipients).await?;
 
+        let mut created = Vec::new();
         for recipient in recipients {
             // Check user preferences
             if !self.should_notify(&recipient, event_type).await? {
                 continue;
             }
 
+            // Fold bursts of the same activity into the recipient's existing unread notification
+            if let Some(notification) = self.coalesce_into_existing(event_type, event_data, &recipient).await? {
+                self.replace_in_redis_inbox(&recipient, &notification).await?;
+
+                if let Err(e) = self.emit_ws_event(&recipient, &notification).await {
+                    tracing::warn!("Failed to emit WebSocket notification for {}: {}", recipient, e);
+                }
+
+                // Already counted as unread and already pushed once
+                continue;
+            }
+
             // Create notification
             let notification = self.create_notification(event_type, event_data, &recipient).await?;
-            
-            // Extract platform_id for counting
-            let platform_id = notification
-                .get("platform_id")
-                .and_then(|v| v.as_str());
+            created.push((recipient, notification));
+        }
+
+        self.publish_new(&created).await
+    }
+
+    /// Send a notification with caller-supplied copy to each recipient, bypassing templates and
+    /// coalescing; returns the new notification ids in recipient order
+    pub async fn send_direct(&self, recipients: &[String], direct: &DirectNotification) -> Result<Vec<i64>> {
+        let data = direct.data.clone().unwrap_or_else(|| Value::Object(Default::default()));
+        let mut created = Vec::with_capacity(recipients.len());
 
-            // Store in Redis inbox
-            self.add_to_redis_inbox(&recipient, &notification).await?;
+        for recipient in recipients {
+            let notification = self
+                .insert_notification(
+                    recipient,
+                    &direct.notification_type,
+                    (&direct.title, &direct.body),
+                    &data,
+                    None,
+                    direct.platform_id.as_deref(),
+                )
+                .await?;
+            created.push((recipient.clone(), notification));
+        }
+
+        self.publish_new(&created).await?;
+        Ok(created.iter().map(|(_, notification)| notification["id"].as_i64().unwrap_or_default()).collect())
+    }
+
+    /// Fan freshly stored notifications out to the inboxes, unread counts, WebSocket and push/email.
+    /// Inboxes and counts for the whole batch are written in one Redis round trip
+    async fn publish_new(&self, created: &[(String, Value)]) -> Result<()> {
+        if created.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = get_connection(&self.ctx.redis_pool).await?;
+        fan_out_pipeline(self.ctx.config.redis.keys(), created)?
+            .query_async::<()>(&mut conn)
+            .await?;
+        drop(conn);
 
-            // Increment unread count (total and platform-specific)
-            self.increment_unread_count(&recipient, platform_id).await?;
+        for (recipient, notification) in created {
+            // Push to connected WebSocket clients; they can still fetch it from the inbox if this fails
+            if let Err(e) = self.emit_ws_event(recipient, notification).await {
+                tracing::warn!("Failed to emit WebSocket notification for {}: {}", recipient, e);
+            }
 
             // Emit delivery job to Redpanda
-            self.emit_delivery_job(&recipient, &notification).await?;
+            self.emit_delivery_job(recipient, notification).await?;
         }
 
         Ok(())
     }
 
-    fn extract_recipients(&self, event_type: &str, event_data: &Value) -> Result<Vec<String>> {
+    /// How `event_type` would be rendered and who would get it, without storing or delivering anything.
+    /// `locale` and `platform_id` default to the ones in `event_data`, as for real events
+    pub fn preview(
+        &self,
+        event_type: &str,
+        event_data: &Value,
+        locale: Option<&str>,
+        platform_id: Option<&str>,
+    ) -> Result<NotificationPreview> {
+        let templates = self.templates.read().map_err(|_| anyhow!("Template cache lock poisoned"))?;
+        let amounts = AmountFormatter::new(&self.ctx.config.notify.token_decimals);
+        Self::render_preview(&templates, &amounts, event_type, event_data, locale, platform_id)
+    }
+
+    fn render_preview(
+        templates: &TemplateStore,
+        amounts: &AmountFormatter,
+        event_type: &str,
+        event_data: &Value,
+        locale: Option<&str>,
+        platform_id: Option<&str>,
+    ) -> Result<NotificationPreview> {
+        let platform_id = platform_id.or_else(|| event_data.get("platform_id").and_then(|v| v.as_str()));
+        let locale = locale.or_else(|| event_data.get("locale").and_then(|v| v.as_str()));
+
+        let rendered = templates.render(platform_id, event_type, locale, event_data);
+        let template = if rendered.is_some() { "platform" } else { "built_in" };
+        let (title, body) = rendered.unwrap_or_else(|| Self::format_notification(amounts, event_type, event_data));
+
+        let data = client_data(event_type, event_data);
+        let data = match rule_for(event_type) {
+            Some(rule) => rule.initial_data(data, event_data),
+            None => data,
+        };
+
+        Ok(NotificationPreview {
+            recipients: Self::extract_recipients(event_type, event_data)?,
+            template,
+            notification: serde_json::json!({
+                "notification_type": event_type,
+                "title": title,
+                "body": body,
+                "data": data,
+                "actions": notification_actions::for_type(event_type),
+                "platform_id": platform_id,
+            }),
+        })
+    }
+
+    /// Drop recipients who have blocked the user that triggered the event
+    async fn without_blockers(&self, event_type: &str, event_data: &Value, recipients: Vec<String>) -> Result<Vec<String>> {
+        let actor = match actor_address(event_type, event_data) {
+            Some(actor) if !recipients.is_empty() => actor,
+            _ => return Ok(recipients),
+        };
+
+        let mut conn = self.ctx.db_pool.get().await?;
+        let blockers = blocks::blockers_among(&mut conn, &recipients, actor).await?;
+        if !blockers.is_empty() {
+            tracing::debug!("Skipping {} notification for {} recipients who blocked {}", event_type, blockers.len(), actor);
+        }
+
+        Ok(recipients.into_iter().filter(|r| !blockers.contains(r)).collect())
+    }
+
+    fn extract_recipients(event_type: &str, event_data: &Value) -> Result<Vec<String>> {
         match event_type {
             // Post-related events
             "reaction.created" | "comment.created" => {
@@ -178,49 +338,160 @@ impl NotificationService {
         Ok(true)
     }
 
+    /// Merge this event into an unread notification of the same type and target created within the
+    /// coalescing window; returns the updated notification, or None if a new one should be created
+    async fn coalesce_into_existing(
+        &self,
+        event_type: &str,
+        event_data: &Value,
+        user_address: &str,
+    ) -> Result<Option<Value>> {
+        let window = self.ctx.config.notify.coalesce_window_seconds;
+        if window == 0 {
+            return Ok(None);
+        }
+
+        let rule = match rule_for(event_type) {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+        let target = match rule.target(event_data) {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+
+        let mut target_filter = serde_json::Map::new();
+        target_filter.insert(rule.target_field.to_string(), Value::from(target));
+
+        let mut conn = self.ctx.db_pool.get().await?;
+        let since = Utc::now() - chrono::Duration::seconds(window as i64);
+        let existing: Option<(i64, Option<Value>, Option<String>)> = relay_notifications::table
+            .filter(relay_notifications::user_address.eq(user_address))
+            .filter(relay_notifications::notification_type.eq(event_type))
+            .filter(relay_notifications::read_at.is_null())
+            .filter(relay_notifications::deleted_at.is_null())
+            .filter(relay_notifications::created_at.gt(since))
+            .filter(relay_notifications::event_data.contains(Value::Object(target_filter)))
+            .order(relay_notifications::created_at.desc())
+            .select((
+                relay_notifications::id,
+                relay_notifications::data,
+                relay_notifications::platform_id,
+            ))
+            .first(&mut conn)
+            .await
+            .optional()?;
+
+        let (id, data, platform_id) = match existing {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let data = data.unwrap_or_else(|| rule.initial_data(client_data(event_type, event_data), event_data));
+        let data = rule.merge(&data, event_data);
+        let (title, body) = rule.summary(&data);
+        let now = Utc::now();
+
+        // Bump created_at so the group sorts with the latest activity
+        diesel::update(relay_notifications::table.filter(relay_notifications::id.eq(id)))
+            .set((
+                relay_notifications::title.eq(&title),
+                relay_notifications::body.eq(&body),
+                relay_notifications::data.eq(&data),
+                relay_notifications::created_at.eq(now),
+            ))
+            .execute(&mut conn)
+            .await?;
+
+        tracing::debug!("Coalesced {} into notification {} for {}", event_type, id, user_address);
+
+        Ok(Some(serde_json::json!({
+            "id": id,
+            "user_address": user_address,
+            "notification_type": event_type,
+            "title": title,
+            "body": body,
+            "data": data,
+            "actions": notification_actions::for_type(event_type),
+            "platform_id": platform_id,
+            "created_at": now,
+        })))
+    }
+
     async fn create_notification(
         &self,
         event_type: &str,
         event_data: &Value,
         user_address: &str,
     ) -> Result<Value> {
-        let (title, body) = self.format_notification(event_type, event_data);
-        
         // Extract platform_id from event data if available
         let platform_id = event_data
             .get("platform_id")
             .and_then(|v| v.as_str())
             .map(|s| s.to_string());
 
-        let notification = serde_json::json!({
-            "id": uuid::Uuid::new_v4().to_string(),
-            "user_address": user_address,
-            "notification_type": event_type,
-            "title": title,
-            "body": body,
-            "data": event_data,
-            "platform_id": platform_id,
-            "created_at": Utc::now(),
-        });
+        // Platform-specific template if one is configured, otherwise the built-in copy
+        let locale = event_data.get("locale").and_then(|v| v.as_str());
+        let (title, body) = self.templates
+            .read()
+            .ok()
+            .and_then(|t| t.render(platform_id.as_deref(), event_type, locale, event_data))
+            .unwrap_or_else(|| {
+                Self::format_notification(&AmountFormatter::new(&self.ctx.config.notify.token_decimals), event_type, event_data)
+            });
+
+        // Groupable events start with a count and actor list so later events can be merged in
+        let data = client_data(event_type, event_data);
+        let data = match rule_for(event_type) {
+            Some(rule) => rule.initial_data(data, event_data),
+            None => data,
+        };
 
+        self.insert_notification(user_address, event_type, (&title, &body), &data, Some(event_data), platform_id.as_deref()).await
+    }
+
+    async fn insert_notification(
+        &self,
+        user_address: &str,
+        event_type: &str,
+        (title, body): (&str, &str),
+        data: &Value,
+        event_data: Option<&Value>,
+        platform_id: Option<&str>,
+    ) -> Result<Value> {
         // Store in Postgres
         let mut conn = self.ctx.db_pool.get().await?;
-        diesel::insert_into(relay_notifications::table)
+        let id: i64 = diesel::insert_into(relay_notifications::table)
             .values((
                 relay_notifications::user_address.eq(user_address),
                 relay_notifications::notification_type.eq(event_type),
-                relay_notifications::title.eq(&title),
-                relay_notifications::body.eq(&body),
-                relay_notifications::data.eq(event_data),
-                relay_notifications::platform_id.eq(platform_id.as_deref()),
+                relay_notifications::title.eq(title),
+                relay_notifications::body.eq(body),
+                relay_notifications::data.eq(data),
+                relay_notifications::event_data.eq(event_data),
+                relay_notifications::platform_id.eq(platform_id),
             ))
-            .execute(&mut conn)
+            .returning(relay_notifications::id)
+            .get_result(&mut conn)
             .await?;
 
+        // Use the database id so clients can mark it read and deliveries can be traced back to it
+        let notification = serde_json::json!({
+            "id": id,
+            "user_address": user_address,
+            "notification_type": event_type,
+            "title": title,
+            "body": body,
+            "data": data,
+            "actions": notification_actions::for_type(event_type),
+            "platform_id": platform_id,
+            "created_at": Utc::now(),
+        });
+
         Ok(notification)
     }
 
-    fn format_notification(&self, event_type: &str, event_data: &Value) -> (String, String) {
+    fn format_notification(amounts: &AmountFormatter, event_type: &str, event_data: &Value) -> (String, String) {
         match event_type {
             // Post-related events
             "reaction.created" => {
@@ -238,11 +509,10 @@ impl NotificationService {
                 )
             }
             "tip.created" => {
-                let amount = event_data.get("amount").and_then(|v| v.as_u64()).unwrap_or(0);
                 let tipper = event_data.get("tipper").and_then(|v| v.as_str()).unwrap_or("Someone");
                 (
                     "New Tip".to_string(),
-                    format!("{} tipped you {} MYSO", tipper, amount),
+                    format!("{} tipped you {}", tipper, amounts.myso(event_data)),
                 )
             }
             "post.created" => {
@@ -281,33 +551,29 @@ impl NotificationService {
             // Social proof token events
             "spt.token_bought" => {
                 let buyer = event_data.get("buyer").and_then(|v| v.as_str()).unwrap_or("Someone");
-                let amount = event_data.get("amount").and_then(|v| v.as_u64()).unwrap_or(0);
                 (
                     "Token Bought".to_string(),
-                    format!("{} bought {} tokens from your pool", buyer, amount),
+                    format!("{} bought {} from your pool", buyer, amounts.tokens(event_data)),
                 )
             }
             "spt.token_sold" => {
                 let seller = event_data.get("seller").and_then(|v| v.as_str()).unwrap_or("Someone");
-                let amount = event_data.get("amount").and_then(|v| v.as_u64()).unwrap_or(0);
                 (
                     "Token Sold".to_string(),
-                    format!("{} sold {} tokens from your pool", seller, amount),
+                    format!("{} sold {} from your pool", seller, amounts.tokens(event_data)),
                 )
             }
             "spt.tokens_added" => {
-                let amount = event_data.get("amount").and_then(|v| v.as_u64()).unwrap_or(0);
                 (
                     "Tokens Added".to_string(),
-                    format!("{} tokens were added to your pool", amount),
+                    format!("{} added to your pool", amounts.tokens(event_data)),
                 )
             }
             "spt.reservation_created" => {
                 let reserver = event_data.get("reserver").and_then(|v| v.as_str()).unwrap_or("Someone");
-                let amount = event_data.get("amount").and_then(|v| v.as_u64()).unwrap_or(0);
                 (
                     "New Reservation".to_string(),
-                    format!("{} reserved {} tokens", reserver, amount),
+                    format!("{} reserved {}", reserver, amounts.tokens(event_data)),
                 )
             }
             // Governance events
@@ -344,10 +610,9 @@ impl NotificationService {
             // Prediction events
             "prediction.bet_placed" => {
                 let bettor = event_data.get("bettor").and_then(|v| v.as_str()).unwrap_or("Someone");
-                let amount = event_data.get("amount").and_then(|v| v.as_u64()).unwrap_or(0);
                 (
                     "New Bet".to_string(),
-                    format!("{} placed a bet of {} MYSO on your prediction", bettor, amount),
+                    format!("{} placed a bet of {} on your prediction", bettor, amounts.myso(event_data)),
                 )
             }
             "prediction.resolved" => {
@@ -357,10 +622,9 @@ impl NotificationService {
                 )
             }
             "prediction.payout" => {
-                let amount = event_data.get("amount").and_then(|v| v.as_u64()).unwrap_or(0);
                 (
                     "Prediction Payout".to_string(),
-                    format!("You received {} MYSO from your prediction bet", amount),
+                    format!("You received {} from your prediction bet", amounts.myso(event_data)),
                 )
             }
             // Platform events
@@ -405,54 +669,31 @@ impl NotificationService {
         }
     }
 
-    async fn add_to_redis_inbox(&self, user_address: &str, notification: &Value) -> Result<()> {
-        let mut conn = get_connection(&self.ctx.redis_pool).await?;
-        let key = format!("INBOX:{}", user_address);
-        
-        redis::cmd("LPUSH")
-            .arg(&key)
-            .arg(serde_json::to_string(notification)?)
-            .query_async(&mut conn)
-            .await?;
+    async fn emit_ws_event(&self, user_address: &str, notification: &Value) -> Result<()> {
+        let payload = serde_json::json!({
+            "type": "notification",
+            "notification": notification,
+        });
 
-        // Keep only last 100 notifications
-        redis::cmd("LTRIM")
-            .arg(&key)
-            .arg(0)
-            .arg(99)
-            .query_async(&mut conn)
-            .await?;
+        let mut conn = get_connection(&self.ctx.redis_pool).await?;
+        streams::push_notify_event(&mut conn, &self.ctx.config.redis, user_address, notification_platform(notification), &payload).await?;
 
         Ok(())
     }
 
-    async fn increment_unread_count(&self, user_address: &str, platform_id: Option<&str>) -> Result<()> {
+    /// Swap the inbox entry for an updated notification and move it to the front
+    async fn replace_in_redis_inbox(&self, user_address: &str, notification: &Value) -> Result<()> {
         let mut conn = get_connection(&self.ctx.redis_pool).await?;
-        
-        // Increment total unread count
-        let total_key = format!("UNREAD:{}", user_address);
-        redis::cmd("INCR")
-            .arg(&total_key)
-            .query_async(&mut conn)
-            .await?;
-        
-        // Increment platform-specific unread count if platform_id is provided
-        if let Some(pid) = platform_id {
-            let platform_key = format!("UNREAD:{}:{}", user_address, pid);
-            redis::cmd("INCR")
-                .arg(&platform_key)
-                .query_async(&mut conn)
-                .await?;
-        }
-
-        Ok(())
+        let keys = self.ctx.config.redis.keys();
+        inbox::replace_in_inbox(&mut conn, keys, user_address, notification_platform(notification), notification).await
     }
 
     async fn emit_delivery_job(&self, user_address: &str, notification: &Value) -> Result<()> {
-        // Extract platform_id from notification data if available
+        // The notification's platform, or one named in its data
         let platform_id = notification
-            .get("data")
-            .and_then(|d| d.get("platform_id"))
+            .get("platform_id")
+            .filter(|v| !v.is_null())
+            .or_else(|| notification.get("data").and_then(|d| d.get("platform_id")))
             .and_then(|v| v.as_str())
             .map(|s| s.to_string());
 
@@ -468,7 +709,7 @@ impl NotificationService {
         let payload_bytes = serde_json::to_vec(&payload)?;
         relay_core::redpanda::produce_message(
             &self.ctx.redpanda_producer,
-            "notifications.delivery",
+            &self.ctx.config.redpanda.topics().delivery(),
             Some(user_address),
             &payload_bytes,
         )
@@ -478,3 +719,199 @@ impl NotificationService {
     }
 }
 
+/// For each recipient, a MULTI block that pushes the notification onto their inbox and increments
+/// their total unread count, plus the platform's count when the notification has one
+fn fan_out_pipeline(keys: RedisKeys<'_>, created: &[(String, Value)]) -> Result<redis::Pipeline> {
+    let mut pipe = redis::pipe();
+    for (recipient, notification) in created {
+        pipe.cmd("MULTI").ignore();
+        let platform_id = notification_platform(notification);
+        inbox::push_inbox(&mut pipe, keys, recipient, platform_id, notification)?;
+        counts::incr_unread(&mut pipe, keys, recipient, platform_id);
+        pipe.cmd("EXEC").ignore();
+    }
+    Ok(pipe)
+}
+
+/// What clients see as a notification's `data`: the event's typed projection, or `{}` for unknown types
+fn client_data(event_type: &str, event_data: &Value) -> Value {
+    NotificationData::from_event(event_type, event_data, actor_address(event_type, event_data))
+        .map(|data| data.to_value())
+        .unwrap_or_else(|| Value::Object(Default::default()))
+}
+
+/// The platform a stored notification belongs to, which picks its inbox and stream under `REDIS_PLATFORM_NAMESPACES`
+fn notification_platform(notification: &Value) -> Option<&str> {
+    notification.get("platform_id").and_then(|p| p.as_str())
+}
+
+/// The user whose action triggered the event, for events caused by a person rather than the system
+fn actor_address<'a>(event_type: &str, event_data: &'a Value) -> Option<&'a str> {
+    let fields: &[&str] = match event_type {
+        "reaction.created" => &["reactor", "user_address", "user"],
+        "comment.created" => &["commenter"],
+        "repost.created" => &["reposter"],
+        "tip.created" => &["tipper"],
+        "follow.created" | "unfollow.created" => &["follower_address", "follower"],
+        "spt.token_bought" => &["buyer"],
+        "spt.token_sold" => &["seller"],
+        "spt.reservation_created" => &["reserver"],
+        "prediction.bet_placed" => &["bettor"],
+        "message.created" => &["sender_address"],
+        _ => return None,
+    };
+
+    fields.iter().find_map(|f| event_data.get(*f).and_then(|v| v.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_actor_address_for_user_actions() {
+        let follow = serde_json::json!({"follower_address": "0xa", "following_address": "0xb"});
+        assert_eq!(actor_address("follow.created", &follow), Some("0xa"));
+
+        let reaction = serde_json::json!({"post_owner": "0xb", "user_address": "0xc"});
+        assert_eq!(actor_address("reaction.created", &reaction), Some("0xc"));
+
+        // System events have no actor to block
+        let approved = serde_json::json!({"submitter": "0xb"});
+        assert_eq!(actor_address("governance.proposal_approved", &approved), None);
+    }
+
+    #[test]
+    fn test_preview_renders_without_delivering() {
+        let now = chrono::Utc::now();
+        let templates = TemplateStore::new(vec![relay_core::notification_templates::NotificationTemplate {
+            id: 1,
+            platform_id: "platform-a".to_string(),
+            event_type: "tip.created".to_string(),
+            locale: relay_core::notification_templates::DEFAULT_LOCALE.to_string(),
+            title_template: "You got tipped!".to_string(),
+            body_template: "{tipper} sent you a tip".to_string(),
+            created_at: now,
+            updated_at: now,
+        }]);
+        let decimals = Default::default();
+        let amounts = AmountFormatter::new(&decimals);
+
+        let tip = serde_json::json!({"tipper": "0xa", "recipient": "0xb", "platform_id": "platform-a"});
+        let preview = NotificationService::render_preview(&templates, &amounts, "tip.created", &tip, None, None).unwrap();
+        assert_eq!(preview.recipients, vec!["0xb"]);
+        assert_eq!(preview.template, "platform");
+        assert_eq!(preview.notification["body"], "0xa sent you a tip");
+        assert_eq!(preview.notification["platform_id"], "platform-a");
+
+        // Another platform has no override, so the built-in copy is used
+        let elsewhere = NotificationService::render_preview(&templates, &amounts, "tip.created", &tip, None, Some("platform-b")).unwrap();
+        assert_eq!(elsewhere.template, "built_in");
+        assert_eq!(elsewhere.notification["title"], "New Tip");
+
+        let follow = serde_json::json!({"follower_address": "0xa", "following_address": "0xc"});
+        let preview = NotificationService::render_preview(&templates, &amounts, "follow.created", &follow, None, None).unwrap();
+        assert_eq!(preview.recipients, vec!["0xc"]);
+        assert_eq!(preview.notification["title"], "New Follower");
+        assert_eq!(preview.notification["data"]["count"], 1);
+    }
+
+    #[test]
+    fn test_fan_out_pipeline_counts_each_recipient_once() {
+        let config = relay_core::Config::from_env();
+        let keys = config.redis.keys();
+        let created = vec![
+            ("0xa".to_string(), serde_json::json!({"id": 1, "platform_id": "app"})),
+            ("0xb".to_string(), serde_json::json!({"id": 2, "platform_id": null})),
+        ];
+        let packed = fan_out_pipeline(keys, &created).unwrap().get_packed_pipeline();
+        let packed = String::from_utf8_lossy(&packed);
+
+        assert_eq!(packed.matches("MULTI").count(), 2);
+        assert_eq!(packed.matches("LPUSH").count(), 2);
+        assert_eq!(packed.matches("LTRIM").count(), 2);
+        assert!(packed.contains(&format!("LTRIM\r\n${}\r\n{}\r\n$1\r\n0\r\n$2\r\n99", keys.inbox("0xa", Some("app")).len(), keys.inbox("0xa", Some("app")))));
+        assert!(packed.contains(&keys.unread_total("0xa")));
+        assert!(packed.contains(&keys.unread_platform("0xa", "app")));
+        assert!(packed.contains(&keys.unread_total("0xb")));
+        // Only notifications with a platform bump a platform count
+        assert_eq!(packed.matches("INCR").count(), 3);
+        assert!(packed.contains(&keys.unread_platforms("0xa")));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running Redis at REDIS_URL"]
+    async fn test_pipelined_fan_out_keeps_inbox_and_counts() {
+        let config = relay_core::Config::from_env();
+        let keys = config.redis.keys();
+        let pool = relay_core::redis::create_pool(&config.redis).await.unwrap();
+        let mut conn = get_connection(&pool).await.unwrap();
+        let user = format!("0xuser-{}", uuid::Uuid::new_v4());
+
+        // A full inbox stays full: the oldest entry makes room for the new one
+        for i in 0..inbox::INBOX_LEN {
+            let _: () = redis::cmd("RPUSH").arg(keys.inbox(&user, Some("app"))).arg(i).query_async(&mut conn).await.unwrap();
+        }
+        let created = vec![
+            (user.clone(), serde_json::json!({"id": 1, "platform_id": "app"})),
+            (user.clone(), serde_json::json!({"id": 2, "platform_id": "app"})),
+            (user.clone(), serde_json::json!({"id": 3})),
+        ];
+        fan_out_pipeline(keys, &created).unwrap().query_async::<()>(&mut conn).await.unwrap();
+
+        let platform_len: isize = redis::cmd("LLEN").arg(keys.inbox(&user, Some("app"))).query_async(&mut conn).await.unwrap();
+        let newest: String = redis::cmd("LINDEX").arg(keys.inbox(&user, Some("app"))).arg(0).query_async(&mut conn).await.unwrap();
+        let total: i64 = redis::cmd("GET").arg(keys.unread_total(&user)).query_async(&mut conn).await.unwrap();
+        let platform: i64 = redis::cmd("GET").arg(keys.unread_platform(&user, "app")).query_async(&mut conn).await.unwrap();
+        let _: () = redis::cmd("DEL")
+            .arg(keys.inbox(&user, Some("app")))
+            .arg(keys.inbox(&user, None))
+            .arg(keys.unread_total(&user))
+            .arg(keys.unread_platform(&user, "app"))
+            .arg(keys.unread_platforms(&user))
+            .query_async(&mut conn)
+            .await
+            .unwrap();
+
+        assert_eq!(platform_len, inbox::INBOX_LEN);
+        assert_eq!(serde_json::from_str::<Value>(&newest).unwrap()["id"], 2);
+        assert_eq!(total, 3);
+        assert_eq!(platform, 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables, Redis at REDIS_URL and Redpanda"]
+    async fn test_direct_notification_fans_out() {
+        let ctx = RelayContext::new(relay_core::Config::from_env()).await.unwrap();
+        let service = NotificationService::new(ctx.clone());
+        let recipients: Vec<String> = (0..2).map(|i| format!("0xuser-{}-{}", i, uuid::Uuid::new_v4())).collect();
+        let direct = DirectNotification {
+            notification_type: "system.announcement".to_string(),
+            title: "Maintenance".to_string(),
+            body: "Back soon".to_string(),
+            data: Some(serde_json::json!({"url": "https://status.example.com"})),
+            platform_id: None,
+        };
+
+        let ids = service.send_direct(&recipients, &direct).await.unwrap();
+
+        let mut conn = ctx.db_pool.get().await.unwrap();
+        let stored: Vec<(String, String)> = relay_notifications::table
+            .filter(relay_notifications::id.eq_any(&ids))
+            .select((relay_notifications::user_address, relay_notifications::title))
+            .load(&mut conn)
+            .await
+            .unwrap();
+        let mut redis_conn = get_connection(&ctx.redis_pool).await.unwrap();
+        let unread: Option<i64> = redis::cmd("GET")
+            .arg(ctx.config.redis.keys().unread_total(&recipients[0]))
+            .query_async(&mut redis_conn)
+            .await
+            .unwrap();
+
+        assert_eq!(ids.len(), 2);
+        assert_eq!(stored.len(), 2);
+        assert!(stored.iter().all(|(address, title)| recipients.contains(address) && title == "Maintenance"));
+        assert_eq!(unread, Some(1));
+    }
+}
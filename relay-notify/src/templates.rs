@@ -0,0 +1,140 @@
+use relay_core::notification_templates::{NotificationTemplate, DEFAULT_LOCALE};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// In-memory copy of `relay_notification_templates`, keyed by (platform_id, event_type, locale)
+#[derive(Debug, Default)]
+pub struct TemplateStore {
+    templates: HashMap<(String, String, String), (String, String)>,
+}
+
+impl TemplateStore {
+    pub fn new(rows: Vec<NotificationTemplate>) -> Self {
+        let templates = rows
+            .into_iter()
+            .map(|t| ((t.platform_id, t.event_type, t.locale), (t.title_template, t.body_template)))
+            .collect();
+
+        Self { templates }
+    }
+
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    /// Render the platform's template for `event_type`, trying `locale` then the default locale
+    /// Returns None when the platform has no override, so the caller uses the built-in copy
+    pub fn render(
+        &self,
+        platform_id: Option<&str>,
+        event_type: &str,
+        locale: Option<&str>,
+        event_data: &Value,
+    ) -> Option<(String, String)> {
+        let platform_id = platform_id?;
+        let lookup = |locale: &str| {
+            self.templates
+                .get(&(platform_id.to_string(), event_type.to_string(), locale.to_string()))
+        };
+
+        let (title, body) = locale
+            .and_then(lookup)
+            .or_else(|| lookup(DEFAULT_LOCALE))?;
+
+        Some((render(title, event_data), render(body, event_data)))
+    }
+}
+
+/// Substitute `{field}` placeholders with top-level fields of `event_data`
+/// `{field|fallback}` uses `fallback` when the field is missing; missing fields without one render empty,
+/// and an unterminated `{` is kept as literal text
+pub fn render(template: &str, event_data: &Value) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let end = match after.find('}') {
+            Some(end) => end,
+            None => {
+                out.push_str(&rest[start..]);
+                return out;
+            }
+        };
+
+        let placeholder = &after[..end];
+        let (field, fallback) = match placeholder.split_once('|') {
+            Some((field, fallback)) => (field.trim(), fallback),
+            None => (placeholder.trim(), ""),
+        };
+
+        match event_data.get(field) {
+            Some(Value::String(s)) => out.push_str(s),
+            Some(Value::Number(n)) => out.push_str(&n.to_string()),
+            Some(Value::Bool(b)) => out.push_str(&b.to_string()),
+            _ => out.push_str(fallback),
+        }
+
+        rest = &after[end + 1..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn template(platform_id: &str, event_type: &str, title: &str, body: &str) -> NotificationTemplate {
+        NotificationTemplate {
+            id: 1,
+            platform_id: platform_id.to_string(),
+            event_type: event_type.to_string(),
+            locale: DEFAULT_LOCALE.to_string(),
+            title_template: title.to_string(),
+            body_template: body.to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_platform_override_for_tip_created() {
+        let store = TemplateStore::new(vec![template(
+            "platform-a",
+            "tip.created",
+            "You got tipped!",
+            "{tipper|A fan} sent you {amount} MYSO",
+        )]);
+        let data = serde_json::json!({"tipper": "alice", "amount": 5});
+
+        let (title, body) = store.render(Some("platform-a"), "tip.created", None, &data).unwrap();
+        assert_eq!(title, "You got tipped!");
+        assert_eq!(body, "alice sent you 5 MYSO");
+
+        // Unknown locale falls back to the default-locale template
+        assert!(store.render(Some("platform-a"), "tip.created", Some("fr"), &data).is_some());
+
+        // Other platforms and events keep the built-in copy
+        assert!(store.render(Some("platform-b"), "tip.created", None, &data).is_none());
+        assert!(store.render(None, "tip.created", None, &data).is_none());
+        assert!(store.render(Some("platform-a"), "follow.created", None, &data).is_none());
+    }
+
+    #[test]
+    fn test_render_handles_missing_placeholders() {
+        let data = serde_json::json!({"amount": 5});
+
+        assert_eq!(render("{tipper|Someone} tipped {amount}", &data), "Someone tipped 5");
+        assert_eq!(render("{tipper} tipped", &data), " tipped");
+        assert_eq!(render("unterminated {amount", &data), "unterminated {amount");
+    }
+}
@@ -1,9 +1,12 @@
 use anyhow::{Result, anyhow};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
 use relay_core::schema::relay_outbox;
-use relay_core::{RelayContext, redpanda::produce_message};
+use relay_core::config::OutboxConfig;
+use relay_core::db::DbConnection;
+use relay_core::types::RelayEvent;
+use relay_core::{RelayContext, lock, redpanda::produce_message, topics};
 use std::time::Duration;
 use tracing;
 
@@ -16,19 +19,27 @@ struct OutboxRow {
     event_data: serde_json::Value,
     event_id: Option<String>,
     transaction_id: Option<String>,
+    retry_count: i32,
 }
 
-const POLL_INTERVAL_MS: u64 = 150;
-const BATCH_SIZE: usize = 100;
-const MAX_RETRIES: i32 = 3;
-
 pub async fn run(ctx: RelayContext) -> Result<()> {
-    tracing::info!("Starting outbox poller");
+    let config = ctx.config.outbox.clone();
+    tracing::info!(
+        "Starting outbox poller (interval {}ms, batch size {}, max retries {})",
+        config.poll_interval_ms,
+        config.batch_size,
+        config.max_retries
+    );
+
+    // With several instances, only the lock holder polls so each event is published once
+    lock::with_leader_lock(&ctx, "outbox-poller", || poll_forever(&ctx, &config)).await
+}
 
+async fn poll_forever(ctx: &RelayContext, config: &OutboxConfig) -> Result<()> {
     loop {
-        match poll_and_publish(&ctx).await {
+        match poll_and_publish(ctx, config).await {
             Ok(_) => {
-                tokio::time::sleep(Duration::from_millis(POLL_INTERVAL_MS)).await;
+                tokio::time::sleep(Duration::from_millis(config.poll_interval_ms)).await;
             }
             Err(e) => {
                 tracing::error!("Error in outbox poller: {}", e);
@@ -38,19 +49,40 @@ pub async fn run(ctx: RelayContext) -> Result<()> {
     }
 }
 
-async fn poll_and_publish(ctx: &RelayContext) -> Result<()> {
+/// First retry waits about this long; each further failure doubles it
+const RETRY_BASE_DELAY_MS: u64 = 1_000;
+const RETRY_MAX_DELAY_MS: u64 = 5 * 60 * 1_000;
+
+/// Backoff before retry number `retry_count` (1 after the first failure): half the exponential delay
+/// plus `jitter` (0.0-1.0) of the other half, so rows that failed together don't retry together
+fn retry_delay(retry_count: i32, jitter: f64) -> chrono::Duration {
+    let exponent = retry_count.saturating_sub(1).clamp(0, 20) as u32;
+    let delay_ms = RETRY_BASE_DELAY_MS.saturating_mul(1 << exponent).min(RETRY_MAX_DELAY_MS);
+    let half = delay_ms / 2;
+
+    chrono::Duration::milliseconds((half + (half as f64 * jitter.clamp(0.0, 1.0)) as u64) as i64)
+}
+
+/// Unprocessed events that still have retries left and whose backoff has elapsed
+async fn load_due_events(conn: &mut DbConnection, config: &OutboxConfig, now: DateTime<Utc>) -> Result<Vec<OutboxRow>> {
+    let events = relay_outbox::table
+        .filter(relay_outbox::processed_at.is_null())
+        .filter(relay_outbox::retry_count.lt(config.max_retries))
+        .filter(relay_outbox::next_retry_at.is_null().or(relay_outbox::next_retry_at.le(now)))
+        .order(relay_outbox::created_at.asc())
+        .limit(config.batch_size as i64)
+        .select(OutboxRow::as_select())
+        .load(conn)
+        .await?;
+
+    Ok(events)
+}
+
+async fn poll_and_publish(ctx: &RelayContext, config: &OutboxConfig) -> Result<()> {
     let mut conn = ctx.db_pool.get().await?;
 
     // Query unprocessed events
-    let events: Vec<OutboxRow> = 
-        relay_outbox::table
-            .filter(relay_outbox::processed_at.is_null())
-            .filter(relay_outbox::retry_count.lt(&MAX_RETRIES))
-            .order(relay_outbox::created_at.asc())
-            .limit(BATCH_SIZE as i64)
-            .select(OutboxRow::as_select())
-            .load(&mut conn)
-            .await?;
+    let events = load_due_events(&mut conn, config, Utc::now()).await?;
 
     if events.is_empty() {
         return Ok(());
@@ -73,16 +105,19 @@ async fn poll_and_publish(ctx: &RelayContext) -> Result<()> {
                 tracing::debug!("Published and marked event {} as processed", event.id);
             }
             Err(e) => {
-                // Increment retry count
+                // Increment retry count and back off before the next attempt
+                let retry_count = event.retry_count + 1;
+                let next_retry_at = Utc::now() + retry_delay(retry_count, rand::random::<f64>());
                 diesel::update(relay_outbox::table.filter(relay_outbox::id.eq(event.id)))
                     .set((
-                        relay_outbox::retry_count.eq(relay_outbox::retry_count + 1),
+                        relay_outbox::retry_count.eq(retry_count),
                         relay_outbox::error_message.eq(Some(format!("{}", e))),
+                        relay_outbox::next_retry_at.eq(next_retry_at),
                     ))
                     .execute(&mut conn)
                     .await?;
 
-                tracing::warn!("Failed to publish event {}: {}", event.id, e);
+                tracing::warn!("Failed to publish event {} (attempt {}, next retry at {}): {}", event.id, retry_count, next_retry_at, e);
             }
         }
     }
@@ -98,53 +133,121 @@ async fn publish_event(
     transaction_id: Option<&str>,
 ) -> Result<()> {
     // Determine topic from event type using category-based routing
-    let topic = match event_type {
-        // Post-related events
-        t if t.starts_with("reaction.") => "events.post.reaction",
-        t if t.starts_with("repost.") => "events.post.repost",
-        t if t.starts_with("tip.") => "events.post.tip",
-        t if t.starts_with("post.created") => "events.post.created",
-        t if t.starts_with("ownership.transferred") => "events.post.ownership",
-        // Comment events (keep separate)
-        t if t.starts_with("comment.") => "events.comment.created",
-        // Social proof token events
-        t if t.starts_with("spt.") => "events.spt.created",
-        // Governance events
-        t if t.starts_with("governance.") => "events.governance.created",
-        // Prediction events
-        t if t.starts_with("prediction.") => "events.prediction.created",
-        // Social graph events
-        t if t.starts_with("follow.") => "events.follow.created",
-        t if t.starts_with("unfollow.") => "events.unfollow.created",
-        // Messaging (handled separately by messaging service)
-        t if t.starts_with("message.") => "events.message.created",
-        // Platform events
-        t if t.starts_with("platform.") => "events.platform.created",
-        // Fallback for unknown events
-        _ => {
-            tracing::warn!("Unknown event type: {}, routing to events.unknown", event_type);
-            "events.unknown"
-        },
+    let names = ctx.config.redpanda.topics();
+    let topic = match topics::event_topic(event_type) {
+        Some(topic) => names.name(topic),
+        None => {
+            tracing::warn!("Unknown event type: {}, routing to {}", event_type, topics::UNKNOWN_EVENTS);
+            names.name(topics::UNKNOWN_EVENTS)
+        }
     };
 
-    // Create message payload
-    let payload = serde_json::json!({
-        "event_type": event_type,
-        "event_data": event_data,
-        "event_id": event_id,
-        "transaction_id": transaction_id,
-        "timestamp": Utc::now(),
-    });
+    let payload = RelayEvent {
+        event_id: event_id.map(str::to_string),
+        transaction_id: transaction_id.map(str::to_string),
+        ..RelayEvent::new(event_type, event_data.clone())
+    };
 
     let payload_bytes = serde_json::to_vec(&payload)?;
 
     // Use event_id as key if available, otherwise use transaction_id
     let key = event_id.or(transaction_id);
 
-    produce_message(&ctx.redpanda_producer, topic, key, &payload_bytes).await?;
+    produce_message(&ctx.redpanda_producer, &topic, key, &payload_bytes).await?;
 
     tracing::debug!("Published event {} to topic {}", event_type, topic);
 
     Ok(())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay_grows_with_jitter_and_is_capped() {
+        assert_eq!(retry_delay(1, 0.0), chrono::Duration::milliseconds(500));
+        assert_eq!(retry_delay(1, 1.0), chrono::Duration::milliseconds(1_000));
+        assert_eq!(retry_delay(3, 0.0), chrono::Duration::milliseconds(2_000));
+        assert_eq!(retry_delay(30, 1.0), chrono::Duration::milliseconds(RETRY_MAX_DELAY_MS as i64));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables"]
+    async fn test_failed_event_not_retried_before_backoff() {
+        let config = relay_core::Config::from_env();
+        let pool = relay_core::db::create_pool(&config.database).await.unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let event_id = format!("evt-{}", uuid::Uuid::new_v4());
+
+        // A row that just failed its first publish
+        let failed_at = Utc::now();
+        let next_retry_at = failed_at + retry_delay(1, 0.5);
+        let id: i64 = diesel::insert_into(relay_outbox::table)
+            .values((
+                relay_outbox::event_type.eq("tip.created"),
+                relay_outbox::event_data.eq(serde_json::json!({})),
+                relay_outbox::event_id.eq(&event_id),
+                relay_outbox::retry_count.eq(1),
+                relay_outbox::next_retry_at.eq(next_retry_at),
+            ))
+            .returning(relay_outbox::id)
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let polled = |events: Vec<OutboxRow>| events.iter().any(|e| e.id == id);
+        let outbox = relay_core::config::OutboxConfig { batch_size: 10_000, ..config.outbox.clone() };
+
+        let before = load_due_events(&mut conn, &outbox, failed_at).await.unwrap();
+        let after = load_due_events(&mut conn, &outbox, next_retry_at).await.unwrap();
+
+        diesel::delete(relay_outbox::table.filter(relay_outbox::id.eq(id))).execute(&mut conn).await.unwrap();
+
+        assert!(!polled(before));
+        assert!(polled(after));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Postgres at DATABASE_URL with the relay tables"]
+    async fn test_enqueued_events_are_polled() {
+        use diesel_async::scoped_futures::ScopedFutureExt;
+        use diesel_async::AsyncConnection;
+        use relay_core::outbox::{enqueue_event, enqueue_event_in_transaction, NewOutboxEvent};
+
+        let config = relay_core::Config::from_env();
+        let pool = relay_core::db::create_pool(&config.database).await.unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let event_id = format!("evt-{}", uuid::Uuid::new_v4());
+        let data = serde_json::json!({"post_id": "0x1"});
+
+        let id = enqueue_event(&mut conn, "tip.created", &data, Some(&event_id), Some("tx-1")).await.unwrap();
+
+        // Enqueued alongside writes that roll back, so it must never be published
+        let rolled_back_id = format!("{}-rolled-back", event_id);
+        let rolled_back = conn
+            .transaction::<(), anyhow::Error, _>(|conn| {
+                async {
+                    let event = NewOutboxEvent { event_id: Some(&rolled_back_id), ..NewOutboxEvent::new("tip.created", &data) };
+                    enqueue_event_in_transaction(conn, &event).await?;
+                    Err(anyhow!("caller's own write failed"))
+                }
+                .scope_boxed()
+            })
+            .await;
+
+        let outbox = relay_core::config::OutboxConfig { batch_size: 10_000, ..config.outbox.clone() };
+        let due = load_due_events(&mut conn, &outbox, Utc::now()).await.unwrap();
+
+        diesel::delete(relay_outbox::table.filter(relay_outbox::id.eq(id))).execute(&mut conn).await.unwrap();
+
+        assert!(rolled_back.is_err());
+        let polled = due.iter().find(|e| e.id == id).expect("enqueued event is due");
+        assert_eq!(polled.event_type, "tip.created");
+        assert_eq!(polled.event_data, data);
+        assert_eq!(polled.event_id.as_deref(), Some(event_id.as_str()));
+        assert_eq!(polled.transaction_id.as_deref(), Some("tx-1"));
+        assert!(!due.iter().any(|e| e.event_id.as_deref() == Some(rolled_back_id.as_str())));
+    }
+}
@@ -1,6 +1,8 @@
 use anyhow::Result;
 use relay_core::Config;
+use relay_core::migrations::run_pending_migrations;
 use relay_core::RelayContext;
+use relay_core::startup;
 use relay_outbox::run as run_outbox;
 use relay_notify::run as run_notify;
 use relay_messaging::run as run_messaging;
@@ -28,10 +30,27 @@ async fn main() -> Result<()> {
 
     // Load configuration
     let config = Config::from_env();
+
+    if config.database.run_migrations {
+        let applied = run_pending_migrations(&config.database).await?;
+        if applied.is_empty() {
+            tracing::info!("Database schema is up to date");
+        } else {
+            tracing::info!("Applied {} migrations: {}", applied.len(), applied.join(", "));
+        }
+    }
+
     let ctx = RelayContext::new(config).await?;
 
     tracing::info!("Relay context initialized");
 
+    // The producer only connects on first use, so a bad broker address would otherwise go unnoticed
+    if ctx.config.redpanda.startup_probe {
+        let report = startup::self_test(&ctx).await;
+        report.log();
+        report.into_result()?;
+    }
+
     // Create shutdown signal
     let (shutdown_tx, mut shutdown_rx) = tokio::sync::broadcast::channel::<()>(1);
 
@@ -158,7 +177,9 @@ fn validate_production_secrets() {
         || env::var("PRODUCTION").is_ok();
     
     if is_production {
-        if jwt_secret.is_empty() || jwt_secret == "your-secret-key-change-in-production" {
+        // RS256/ES256 sign with JWT_PRIVATE_KEY instead
+        let uses_jwt_secret = env::var("JWT_ALGORITHM").map_or(true, |alg| alg.trim().eq_ignore_ascii_case("HS256"));
+        if uses_jwt_secret && (jwt_secret.is_empty() || jwt_secret == "your-secret-key-change-in-production") {
             tracing::error!("JWT_SECRET is not set or using default value in production!");
             tracing::error!("This is a security risk. Please set JWT_SECRET environment variable.");
             // Don't panic, but log strongly